@@ -1,20 +1,34 @@
+use futures::future::Shared;
 use futures::FutureExt;
 use gpui::prelude::*;
 use gpui::{
     actions, canvas, div, px, rems, App, Application, Bounds, Context, Entity, FocusHandle,
-    Focusable, KeyBinding, Render, Subscription, Task, Window, WindowOptions,
+    Focusable, KeyBinding, PathPromptOptions, Render, Subscription, Task, Window, WindowOptions,
 };
 use gpuikit::elements::icon_button::icon_button;
 use gpuikit::layout::{h_stack, v_stack};
-use gpuikit::DefaultIcons;
 use gpuikit::theme::{ActiveTheme, Themeable};
+use gpuikit::DefaultIcons;
 use player_core::{
-    ensure_directories, import_all_pending, problem_path, repair_problem_files_with_progress,
-    save_library, AudioPlayer, AudioPlayerEvent, Library, LibraryReader, LoadedEntry,
-    MediaControlsHandler, MediaKeyEvent, PlaybackState, RepairProgress, Song,
+    append_play, apply_fix, delete_problem, edit_problem_tags, edit_songs, export_library,
+    force_import_problem, generate_token, import_all_pending_with_events, list_backups,
+    load_history, load_library, load_library_roots, load_session, log_scrobble,
+    migrate_jsonl_to_sqlite, needs_recovery, open_storage, path_from_file_uri, problem_files,
+    rate_songs, rekey_song_files, repair_problem_duration, repair_problem_files_with_progress,
+    rescan_roots, restore_backup, retry_problem, save_library, save_library_roots, save_session,
+    verify_library, AudioPlayer, AudioPlayerEvent, Backup, CancelToken, ImportEvent, ImportJournal,
+    ImportOptions, ImportStage, ImportWatcher, IntegrityFix, Library, LibraryRoot, LoadedEntry,
+    MediaControlsHandler, MediaKeyEvent, MetadataEdit, MpdServer, MpdSubsystem, Paths, PlayRecord,
+    PlayTracker, PlaybackState, ProblemFile, RatingEdit, RemoteCall, RemoteRequest, RemoteServer,
+    RemoteSong, RemoteStatus, RepairProgress, RepeatMode, Session, SessionItem, Settings, Song,
+    SongFilter, SongId, SortOrder, StorageBackend, StorageError, VerifyOptions, DEFAULT_DEBOUNCE,
+};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use ui::{
+    BackupsView, BackupsViewEvent, IntegrityView, IntegrityViewEvent, ListView, ListViewEvent,
+    ProblemAction, ProblemsView, ProblemsViewEvent, TagEditTarget, TagEditor, TagEditorEvent,
 };
-use std::time::Duration;
-use ui::{ListView, ListViewEvent};
 
 actions!(
     player,
@@ -24,6 +38,10 @@ actions!(
         SkipPrevious,
         ToggleShuffle,
         ToggleRepeat,
+        AddLibraryFolder,
+        VerifyLibrary,
+        ShowProblems,
+        ShowBackups,
     ]
 );
 
@@ -34,27 +52,53 @@ pub fn init(cx: &mut App) {
         KeyBinding::new("cmd-left", SkipPrevious, None),
         KeyBinding::new("cmd-s", ToggleShuffle, None),
         KeyBinding::new("cmd-r", ToggleRepeat, None),
+        KeyBinding::new("cmd-o", AddLibraryFolder, None),
+        KeyBinding::new("cmd-shift-v", VerifyLibrary, None),
+        KeyBinding::new("cmd-shift-p", ShowProblems, None),
+        KeyBinding::new("cmd-shift-b", ShowBackups, None),
     ]);
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum RepeatMode {
-    Off,
-    All,
-    One,
-}
-
 struct Player {
+    settings: Settings,
+    paths: Paths,
     library: Entity<Library>,
     list_view: Entity<ListView>,
     audio_player: Entity<AudioPlayer>,
+    tag_editor: Option<Entity<TagEditor>>,
+    integrity_view: Option<Entity<IntegrityView>>,
+    problems_view: Option<Entity<ProblemsView>>,
+    backups_view: Option<Entity<BackupsView>>,
     focus_handle: FocusHandle,
     status_message: Option<String>,
     is_syncing: bool,
+    /// Set when a sync is requested while one is already running
+    sync_requested: bool,
     sync_task: Option<Task<()>>,
+    /// The latest library save. Each save waits for the one before it.
+    library_save: Option<Shared<Task<Result<(), String>>>>,
+    import_cancel: Option<CancelToken>,
+    _import_watcher: Option<ImportWatcher>,
     shuffle: bool,
     repeat: RepeatMode,
+    sort_order: SortOrder,
+    filter: SongFilter,
+    /// Turns audio player events into plays for the listening history
+    play_tracker: PlayTracker,
     media_controls: Option<MediaControlsHandler>,
+    remote_server: Option<RemoteServer>,
+    mpd_server: Option<MpdServer>,
+    /// Songs queued from the remote control API, played before the rest of the list
+    up_next: Vec<SongId>,
+    /// The session from the last run, until it's restored once the library has
+    /// loaded. Nothing is saved before then, so an early quit doesn't lose it.
+    pending_session: Option<Session>,
+    /// A session save waiting out a burst of seeks
+    session_save: Option<Task<()>>,
+    _tag_editor_subscription: Option<Subscription>,
+    _integrity_view_subscription: Option<Subscription>,
+    _problems_view_subscription: Option<Subscription>,
+    _backups_view_subscription: Option<Subscription>,
     _subscriptions: Vec<Subscription>,
 }
 
@@ -65,46 +109,83 @@ impl Focusable for Player {
 }
 
 impl Player {
-    fn new(cx: &mut Context<Self>) -> Self {
-        if let Err(e) = ensure_directories() {
+    fn new(
+        mut settings: Settings,
+        paths: Paths,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        if let Err(e) = paths.ensure_directories() {
             eprintln!("Failed to create directories: {}", e);
         }
 
         let library = cx.new(|_cx| Library::new());
 
-        Self::stream_load_library(library.clone(), cx);
+        let session = load_session(&paths).unwrap_or_else(|e| {
+            eprintln!("Failed to load session: {}", e);
+            Session::default()
+        });
+
+        Self::stream_load_library(library.clone(), paths.clone(), cx);
 
-        let audio_player =
-            cx.new(|cx| AudioPlayer::new(cx).expect("Failed to create audio player"));
+        let audio_player = cx.new(|cx| {
+            let mut player = AudioPlayer::new(cx).expect("Failed to create audio player");
+            player.set_volume(settings.playback.volume, cx);
+            player
+        });
 
-        let list_view = cx.new(|cx| ListView::new(library.clone(), cx));
+        let list_view =
+            cx.new(|cx| ListView::new(library.clone(), cx).sort_order(session.sort_order));
 
         let subscriptions = vec![
-            cx.subscribe(&list_view, Self::handle_list_view_event),
+            cx.subscribe_in(&list_view, window, Self::handle_list_view_event),
             cx.subscribe(&audio_player, Self::handle_audio_player_event),
+            cx.on_app_quit(|this, cx| {
+                this.persist_session(cx);
+                async {}
+            }),
         ];
 
-        let media_controls = match MediaControlsHandler::new() {
-            Ok(controls) => Some(controls),
-            Err(e) => {
-                eprintln!("Failed to initialize media controls: {}", e);
-                None
-            }
-        };
-
-        Player {
+        let media_controls = Self::start_media_controls(cx);
+        let (remote_server, mpd_server) = Self::start_control_servers(&mut settings, cx);
+
+        let mut player = Player {
+            shuffle: settings.playback.shuffle,
+            repeat: settings.playback.repeat,
+            sort_order: session.sort_order,
+            filter: SongFilter::default(),
+            play_tracker: PlayTracker::new(),
+            _import_watcher: Self::watch_import_folder(&paths.import, cx),
+            settings,
+            paths,
             library,
             list_view,
             audio_player,
+            tag_editor: None,
+            integrity_view: None,
+            problems_view: None,
+            backups_view: None,
             focus_handle: cx.focus_handle(),
             status_message: None,
             is_syncing: false,
+            sync_requested: false,
             sync_task: None,
-            shuffle: false,
-            repeat: RepeatMode::Off,
+            library_save: None,
+            import_cancel: None,
             media_controls,
+            remote_server,
+            mpd_server,
+            up_next: Vec::new(),
+            pending_session: Some(session),
+            session_save: None,
+            _tag_editor_subscription: None,
+            _integrity_view_subscription: None,
+            _problems_view_subscription: None,
+            _backups_view_subscription: None,
             _subscriptions: subscriptions,
-        }
+        };
+        player.update_media_controls_modes();
+        player
     }
 
     fn action_toggle_playback(
@@ -147,43 +228,731 @@ impl Player {
         self.toggle_repeat(cx);
     }
 
+    fn action_add_library_folder(
+        &mut self,
+        _: &AddLibraryFolder,
+        _window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.add_library_folder(cx);
+    }
+
+    fn action_verify_library(
+        &mut self,
+        _: &VerifyLibrary,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.verify_library(window, cx);
+    }
+
+    fn action_show_problems(
+        &mut self,
+        _: &ShowProblems,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.show_problems(window, cx);
+    }
+
+    fn action_show_backups(
+        &mut self,
+        _: &ShowBackups,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.show_backups(window, cx);
+    }
+
     fn handle_list_view_event(
         &mut self,
-        _list_view: Entity<ListView>,
+        _list_view: &Entity<ListView>,
         event: &ListViewEvent,
+        window: &mut Window,
         cx: &mut Context<Self>,
     ) {
         match event {
-            ListViewEvent::SongSelected(_song) => {}
+            ListViewEvent::SongSelected(_song) => {
+                self.persist_session(cx);
+            }
             ListViewEvent::SongDoubleClicked(song) | ListViewEvent::PlaySelected(song) => {
                 self.play_song(song.clone(), cx);
             }
             ListViewEvent::TogglePlayback => {
                 self.toggle_playback(cx);
             }
+            ListViewEvent::EditSelected(songs) => {
+                self.open_tag_editor(songs.clone(), window, cx);
+            }
+            ListViewEvent::RateSelected(songs, edit) => {
+                let song_ids = songs.iter().map(|song| song.id).collect();
+                self.rate_songs(song_ids, *edit, cx);
+            }
         }
     }
 
+    fn handle_tag_editor_event(
+        &mut self,
+        _tag_editor: &Entity<TagEditor>,
+        event: &TagEditorEvent,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        match event {
+            TagEditorEvent::Save {
+                target,
+                edit,
+                refile,
+            } => {
+                match target {
+                    TagEditTarget::Songs(song_ids) => {
+                        self.save_tag_edits(song_ids.clone(), edit.clone(), *refile, cx);
+                    }
+                    TagEditTarget::ProblemFile {
+                        problem,
+                        force_import,
+                    } => {
+                        let action = if *force_import {
+                            ProblemAction::ForceImport
+                        } else {
+                            ProblemAction::EditTags
+                        };
+                        self.resolve_problem(problem.clone(), action, edit.clone(), cx);
+                    }
+                }
+                self.close_tag_editor(window, cx);
+            }
+            TagEditorEvent::Cancel => {
+                self.close_tag_editor(window, cx);
+            }
+        }
+    }
+
+    fn open_tag_editor(&mut self, songs: Vec<Song>, window: &mut Window, cx: &mut Context<Self>) {
+        let tag_editor = cx.new(|cx| TagEditor::new(songs, cx));
+        self.show_tag_editor(tag_editor, window, cx);
+    }
+
+    fn show_tag_editor(
+        &mut self,
+        tag_editor: Entity<TagEditor>,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        tag_editor.update(cx, |tag_editor, cx| tag_editor.focus(window, cx));
+
+        self._tag_editor_subscription =
+            Some(cx.subscribe_in(&tag_editor, window, Self::handle_tag_editor_event));
+        self.tag_editor = Some(tag_editor);
+        cx.notify();
+    }
+
+    fn close_tag_editor(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.tag_editor = None;
+        self._tag_editor_subscription = None;
+        self.list_view
+            .update(cx, |list_view, cx| list_view.focus(window, cx));
+        cx.notify();
+    }
+
+    /// Check the library against the files on disk and show what's wrong
+    fn verify_library(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        self.set_status("Checking library...", cx);
+
+        let lib = self.library.read(cx).clone();
+        let paths = self.paths.clone();
+        cx.spawn_in(window, async move |this, cx| {
+            let (lib, report) = cx
+                .background_executor()
+                .spawn(async move {
+                    let options = VerifyOptions {
+                        check_durations: true,
+                    };
+                    let report = verify_library(&lib, &paths, options);
+                    (lib, report)
+                })
+                .await;
+
+            let _ = this.update_in(cx, |this, window, cx| {
+                let issue_count = report.issues.len();
+                let integrity_view = cx.new(|cx| IntegrityView::new(report, &lib, cx));
+                integrity_view.update(cx, |integrity_view, cx| integrity_view.focus(window, cx));
+
+                this._integrity_view_subscription = Some(cx.subscribe_in(
+                    &integrity_view,
+                    window,
+                    Self::handle_integrity_view_event,
+                ));
+                this.integrity_view = Some(integrity_view);
+                this.set_status(format!("Library check found {} issues", issue_count), cx);
+            });
+        })
+        .detach();
+    }
+
+    fn handle_integrity_view_event(
+        &mut self,
+        _integrity_view: &Entity<IntegrityView>,
+        event: &IntegrityViewEvent,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        match event {
+            IntegrityViewEvent::ApplyFixes(fixes) => {
+                self.apply_integrity_fixes(fixes.clone(), cx);
+            }
+            IntegrityViewEvent::Close => {
+                self.integrity_view = None;
+                self._integrity_view_subscription = None;
+                self.list_view
+                    .update(cx, |list_view, cx| list_view.focus(window, cx));
+                cx.notify();
+            }
+        }
+    }
+
+    fn apply_integrity_fixes(&mut self, fixes: Vec<IntegrityFix>, cx: &mut Context<Self>) {
+        self.set_status("Fixing library...", cx);
+
+        let mut lib = self.library.read(cx).clone();
+        cx.spawn(async move |this, cx| {
+            let (fixed_songs, removed, fixed, failed) = cx
+                .background_executor()
+                .spawn(async move {
+                    let mut fixed_songs = Vec::new();
+                    let mut removed = Vec::new();
+                    let mut failed = 0;
+                    for fix in &fixes {
+                        match apply_fix(&mut lib, fix) {
+                            Ok(Some(song)) => fixed_songs.push(song),
+                            Ok(None) => {
+                                if let IntegrityFix::RemoveEntry { song_id } = fix {
+                                    removed.push(*song_id);
+                                }
+                            }
+                            Err(e) => {
+                                eprintln!("Failed to apply {:?}: {}", fix, e);
+                                failed += 1;
+                            }
+                        }
+                    }
+                    (fixed_songs, removed, fixes.len() - failed, failed)
+                })
+                .await;
+
+            // Only touch the songs that were fixed, then save the live library, so
+            // changes made to it while fixing aren't undone
+            let Ok(save) = this.update(cx, |this, cx| {
+                this.library.update(cx, |current_lib, cx| {
+                    for song in fixed_songs {
+                        replace_song(current_lib, song);
+                    }
+                    for id in removed {
+                        current_lib.remove_song(id);
+                    }
+                    cx.notify();
+                });
+                this.save_live_library(cx)
+            }) else {
+                return;
+            };
+
+            let message = match save.await {
+                Ok(()) if failed > 0 => format!("Fixed {} issues, {} failed", fixed, failed),
+                Ok(()) => format!("Fixed {} issues", fixed),
+                Err(e) => {
+                    eprintln!("Failed to save library: {}", e);
+                    "Failed to save library".to_string()
+                }
+            };
+
+            let _ = this.update(cx, |this, cx| {
+                this.set_status(message, cx);
+            });
+        })
+        .detach();
+    }
+
+    /// Save the live library, then clear the import journal. The journal is kept
+    /// if the save fails, so the imported songs can be recovered at the next launch.
+    fn clear_journal_after_save(&mut self, cx: &mut Context<Self>) {
+        let save = self.save_live_library(cx);
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            if let Err(e) = save.await {
+                eprintln!("Failed to save library: {}", e);
+                let _ = this.update(cx, |this, cx| {
+                    this.set_status("Failed to save library", cx);
+                });
+                return;
+            }
+            let cleared = cx
+                .background_executor()
+                .spawn(async move { ImportJournal::clear(&paths) })
+                .await;
+            if let Err(e) = cleared {
+                eprintln!("Failed to clear import journal: {}", e);
+            }
+        })
+        .detach();
+    }
+
+    /// Save the live library in the background. Saves land in the order they were
+    /// made, so a save of an older library can't overwrite a newer one on disk.
+    fn save_live_library(&mut self, cx: &mut Context<Self>) -> Shared<Task<Result<(), String>>> {
+        let lib = self.library.read(cx).clone();
+        let paths = self.paths.clone();
+        let previous = self.library_save.take();
+        let save = cx
+            .background_executor()
+            .spawn(async move {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+                save_library(&lib, &paths).map_err(|e| e.to_string())
+            })
+            .shared();
+        self.library_save = Some(save.clone());
+        save
+    }
+
+    /// List the files that failed to import
+    fn show_problems(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let paths = self.paths.clone();
+        cx.spawn_in(window, async move |this, cx| {
+            let problems = cx
+                .background_executor()
+                .spawn(async move { problem_files(&paths) })
+                .await;
+
+            let problems = match problems {
+                Ok(problems) => problems,
+                Err(e) => {
+                    eprintln!("Failed to read problem log: {}", e);
+                    Vec::new()
+                }
+            };
+
+            let _ = this.update_in(cx, |this, window, cx| {
+                let problems_view = cx.new(|cx| ProblemsView::new(problems, cx));
+                problems_view.update(cx, |problems_view, cx| problems_view.focus(window, cx));
+
+                this._problems_view_subscription =
+                    Some(cx.subscribe_in(&problems_view, window, Self::handle_problems_view_event));
+                this.problems_view = Some(problems_view);
+                cx.notify();
+            });
+        })
+        .detach();
+    }
+
+    fn handle_problems_view_event(
+        &mut self,
+        _problems_view: &Entity<ProblemsView>,
+        event: &ProblemsViewEvent,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        match event {
+            ProblemsViewEvent::Action { problem, action } => match action {
+                ProblemAction::EditTags | ProblemAction::ForceImport => {
+                    let force_import = *action == ProblemAction::ForceImport;
+                    let problem = problem.clone();
+                    let tag_editor = cx.new(|cx| TagEditor::for_problem(problem, force_import, cx));
+                    self.show_tag_editor(tag_editor, window, cx);
+                }
+                _ => {
+                    self.resolve_problem(problem.clone(), *action, MetadataEdit::default(), cx);
+                }
+            },
+            ProblemsViewEvent::Close => {
+                self.problems_view = None;
+                self._problems_view_subscription = None;
+                self.list_view
+                    .update(cx, |list_view, cx| list_view.focus(window, cx));
+                cx.notify();
+            }
+        }
+    }
+
+    /// List the automatic backups of the library
+    fn show_backups(&mut self, window: &mut Window, cx: &mut Context<Self>) {
+        let paths = self.paths.clone();
+        cx.spawn_in(window, async move |this, cx| {
+            let backups = cx
+                .background_executor()
+                .spawn(async move { list_backups(&paths) })
+                .await;
+
+            let backups = match backups {
+                Ok(backups) => backups,
+                Err(e) => {
+                    eprintln!("Failed to list backups: {}", e);
+                    Vec::new()
+                }
+            };
+
+            let _ = this.update_in(cx, |this, window, cx| {
+                let backups_view = cx.new(|cx| BackupsView::new(backups, cx));
+                backups_view.update(cx, |backups_view, cx| backups_view.focus(window, cx));
+
+                this._backups_view_subscription =
+                    Some(cx.subscribe_in(&backups_view, window, Self::handle_backups_view_event));
+                this.backups_view = Some(backups_view);
+                cx.notify();
+            });
+        })
+        .detach();
+    }
+
+    fn handle_backups_view_event(
+        &mut self,
+        _backups_view: &Entity<BackupsView>,
+        event: &BackupsViewEvent,
+        window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        match event {
+            BackupsViewEvent::Restore(backup) => {
+                self.restore_library_backup(backup.clone(), cx);
+            }
+            BackupsViewEvent::Export => self.export_library(cx),
+            BackupsViewEvent::Close => {
+                self.backups_view = None;
+                self._backups_view_subscription = None;
+                self.list_view
+                    .update(cx, |list_view, cx| list_view.focus(window, cx));
+                cx.notify();
+            }
+        }
+    }
+
+    /// Put the library files back as they were in a backup, then reload the
+    /// library and settings from them
+    fn restore_library_backup(&mut self, backup: Backup, cx: &mut Context<Self>) {
+        if self.is_syncing {
+            self.set_status("Wait for the sync to finish before restoring", cx);
+            return;
+        }
+        self.set_status("Restoring backup...", cx);
+
+        // Wait for saves already queued, so they can't land on top of the restored
+        // files, and make saves queued from now on wait for the restore
+        let previous = self.library_save.take();
+        let restore_paths = self.paths.clone();
+        let restore = cx
+            .background_executor()
+            .spawn(async move {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+                // The open library's folders and storage backend stay in use until
+                // a restart, so a backup whose settings change them can't be
+                // restored in place
+                let settings = backup
+                    .settings()
+                    .map_err(|e| e.to_string())?
+                    .unwrap_or_default();
+                if settings.paths().map_err(|e| e.to_string())? != restore_paths {
+                    return Err("the backup uses other folders or storage; restore it \
+                                with `player-cli restore` and restart the player"
+                        .to_string());
+                }
+                restore_backup(&backup, &restore_paths).map_err(|e| e.to_string())
+            })
+            .shared();
+        self.library_save = Some(restore.clone());
+
+        let library = self.library.clone();
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            let result = match restore.await {
+                Ok(()) => cx
+                    .background_executor()
+                    .spawn(async move {
+                        let mut lib = load_library(&paths)?;
+                        lib.apply_history(&load_history(&paths)?);
+                        Ok::<_, StorageError>((lib, list_backups(&paths)?))
+                    })
+                    .await
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e),
+            };
+
+            let _ = this.update(cx, |this, cx| match result {
+                Ok((lib, backups)) => {
+                    library.update(cx, |current_lib, cx| {
+                        *current_lib = lib;
+                        cx.notify();
+                    });
+                    match Settings::load() {
+                        Ok(settings) => {
+                            this.shuffle = settings.playback.shuffle;
+                            this.repeat = settings.playback.repeat;
+                            this.settings = settings;
+                            this.update_media_controls_modes();
+                        }
+                        Err(e) => eprintln!("Failed to reload settings: {}", e),
+                    }
+                    if let Some(backups_view) = &this.backups_view {
+                        backups_view
+                            .update(cx, |backups_view, cx| backups_view.set_backups(backups, cx));
+                    }
+                    this.set_status("Restored backup", cx);
+                }
+                Err(e) => {
+                    eprintln!("Failed to restore backup: {}", e);
+                    this.set_status(format!("Restore failed: {}", e), cx);
+                }
+            });
+        })
+        .detach();
+    }
+
+    /// Ask for a folder, then export the whole library state to a file in it
+    fn export_library(&mut self, cx: &mut Context<Self>) {
+        let selected = cx.prompt_for_paths(PathPromptOptions {
+            files: false,
+            directories: true,
+            multiple: false,
+            prompt: Some("Export Library".into()),
+        });
+
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            let Ok(Ok(Some(selected))) = selected.await else {
+                return;
+            };
+            let Some(folder) = selected.into_iter().next() else {
+                return;
+            };
+
+            let destination = folder.join("player-library-export.json");
+            let result = cx
+                .background_executor()
+                .spawn(async move { export_library(&paths, &destination) })
+                .await;
+
+            let _ = this.update(cx, |this, cx| match result {
+                Ok(export) => this.set_status(format!("Exported {} songs", export.songs.len()), cx),
+                Err(e) => {
+                    eprintln!("Failed to export library: {}", e);
+                    this.set_status(format!("Export failed: {}", e), cx);
+                }
+            });
+        })
+        .detach();
+    }
+
+    /// Act on a problem file. Files sent back to Import are picked up by a sync;
+    /// force-imported files are added to the library directly.
+    fn resolve_problem(
+        &mut self,
+        problem: ProblemFile,
+        action: ProblemAction,
+        edit: MetadataEdit,
+        cx: &mut Context<Self>,
+    ) {
+        let library = self.library.clone();
+        let mut lib = self.library.read(cx).clone();
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            let result = cx
+                .background_executor()
+                .spawn(async move {
+                    match action {
+                        ProblemAction::Retry => retry_problem(&problem, &paths).map(|_| None),
+                        ProblemAction::RepairDuration => {
+                            repair_problem_duration(&problem, &paths).map(|_| None)
+                        }
+                        ProblemAction::EditTags => {
+                            edit_problem_tags(&problem, &edit, &paths).map(|_| None)
+                        }
+                        ProblemAction::Delete => delete_problem(&problem, &paths).map(|()| None),
+                        ProblemAction::ForceImport => {
+                            let options = ImportOptions::default();
+                            let result =
+                                force_import_problem(&problem, &mut lib, &edit, &options, &paths)?;
+                            Ok(Some(result.song))
+                        }
+                    }
+                })
+                .await;
+
+            let _ = this.update(cx, |this, cx| match result {
+                Ok(Some(song)) => {
+                    library.update(cx, |current_lib, cx| {
+                        replace_song(current_lib, song);
+                        cx.notify();
+                    });
+                    this.set_status("Imported problem file", cx);
+                    this.clear_journal_after_save(cx);
+                }
+                Ok(None) if action == ProblemAction::Delete => {
+                    this.set_status("Deleted problem file", cx);
+                }
+                Ok(None) => this.sync_library(cx),
+                Err(e) => {
+                    eprintln!("Failed to resolve problem file: {}", e);
+                    this.set_status(format!("Problem file: {}", e), cx);
+                }
+            });
+        })
+        .detach();
+    }
+
+    fn save_tag_edits(
+        &mut self,
+        song_ids: Vec<SongId>,
+        edit: MetadataEdit,
+        refile: bool,
+        cx: &mut Context<Self>,
+    ) {
+        if edit.is_empty() {
+            return;
+        }
+
+        self.set_status("Saving tags...", cx);
+
+        let mut lib = self.library.read(cx).clone();
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            let file_edit = edit.clone();
+            let result = cx
+                .background_executor()
+                .spawn(async move { edit_songs(&mut lib, &song_ids, &file_edit, refile, &paths) })
+                .await;
+
+            for failure in &result.failed {
+                eprintln!("Failed to edit song {}: {}", failure.id.0, failure.error);
+            }
+            let edited_count = result.edited.len();
+            let failed_count = result.failed.len();
+
+            // Apply the edit to the live songs rather than replacing them with the
+            // edited copies, so changes made while the tags were written are kept
+            let Ok(save) = this.update(cx, |this, cx| {
+                this.library.update(cx, |current_lib, cx| {
+                    for edited in result.edited {
+                        if let Some(song) = current_lib.songs.get_mut(&edited.song.id) {
+                            edit.apply_to(song);
+                            song.file.path = edited.song.file.path;
+                        }
+                    }
+                    cx.notify();
+                });
+                this.save_live_library(cx)
+            }) else {
+                return;
+            };
+
+            let message = match save.await {
+                Ok(()) if failed_count > 0 => {
+                    format!("Edited {} songs, {} failed", edited_count, failed_count)
+                }
+                Ok(()) => format!("Edited {} songs", edited_count),
+                Err(e) => {
+                    eprintln!("Failed to save library: {}", e);
+                    "Failed to save tags".to_string()
+                }
+            };
+
+            let _ = this.update(cx, |this, cx| {
+                this.set_status(message, cx);
+            });
+        })
+        .detach();
+    }
+
+    /// Save a rating change, writing it to the files' tags too if that's enabled.
+    /// Only failures are reported, so rating from the keyboard stays quiet.
+    fn rate_songs(&mut self, song_ids: Vec<SongId>, edit: RatingEdit, cx: &mut Context<Self>) {
+        let mut lib = self.library.read(cx).clone();
+        let write_to_tags = self.settings.write_ratings_to_tags;
+        cx.spawn(async move |this, cx| {
+            let result = cx
+                .background_executor()
+                .spawn(async move { rate_songs(&mut lib, &song_ids, edit, write_to_tags) })
+                .await;
+
+            for failure in &result.failed {
+                eprintln!("Failed to rate song {}: {}", failure.id.0, failure.error);
+            }
+            let rated_count = result.edited.len();
+            let failed_count = result.failed.len();
+
+            // Apply the rating to the live songs rather than replacing them with the
+            // rated copies, so a rating made meanwhile isn't undone
+            let Ok(save) = this.update(cx, |this, cx| {
+                this.library.update(cx, |current_lib, cx| {
+                    for rated in result.edited {
+                        if let Some(song) = current_lib.songs.get_mut(&rated.song.id) {
+                            edit.apply_to(song);
+                        }
+                    }
+                    cx.notify();
+                });
+                this.save_live_library(cx)
+            }) else {
+                return;
+            };
+
+            let message = match save.await {
+                Ok(()) => (failed_count > 0)
+                    .then(|| format!("Rated {} songs, {} failed", rated_count, failed_count)),
+                Err(e) => {
+                    eprintln!("Failed to save library: {}", e);
+                    Some("Failed to save rating".to_string())
+                }
+            };
+
+            if let Some(message) = message {
+                let _ = this.update(cx, |this, cx| {
+                    this.set_status(message, cx);
+                });
+            }
+        })
+        .detach();
+    }
+
     fn handle_audio_player_event(
         &mut self,
         _audio_player: Entity<AudioPlayer>,
         event: &AudioPlayerEvent,
         cx: &mut Context<Self>,
     ) {
+        if let Some(server) = &self.remote_server {
+            server.broadcast(event);
+        }
+        if let Some(server) = &self.mpd_server {
+            server.broadcast(event);
+        }
         match event {
             AudioPlayerEvent::StateChanged(state) => {
+                self.play_tracker.state_changed(*state, Instant::now());
                 self.update_media_controls_playback(*state, cx);
+                self.persist_session(cx);
                 cx.notify();
             }
             AudioPlayerEvent::SongChanged(song) => {
+                if let Some(record) = self
+                    .play_tracker
+                    .song_changed(song.as_deref(), Instant::now())
+                {
+                    self.record_play(record, cx);
+                }
                 let song_id = song.as_ref().map(|s| s.id);
                 self.list_view.update(cx, |list_view, cx| {
                     list_view.set_playing_song(song_id, cx);
                 });
-                self.update_media_controls_metadata(song.as_ref());
+                self.update_media_controls_metadata(song.as_deref(), cx);
+                self.persist_session(cx);
                 cx.notify();
             }
             AudioPlayerEvent::PlaybackFinished => {
+                if let Some(record) = self.play_tracker.finished(Instant::now()) {
+                    self.record_play(record, cx);
+                }
                 match self.repeat {
                     RepeatMode::One => {
                         if let Some(song) = self.audio_player.read(cx).current_song().cloned() {
@@ -191,12 +960,7 @@ impl Player {
                         }
                     }
                     RepeatMode::All => {
-                        let next_song = if self.shuffle {
-                            self.list_view.read(cx).random_song(cx)
-                        } else {
-                            self.list_view.read(cx).next_song(cx)
-                        };
-                        if let Some(song) = next_song {
+                        if let Some(song) = self.following_song(cx) {
                             self.play_song(song, cx);
                         } else if !self.shuffle {
                             let first_song = self.list_view.read(cx).first_song(cx);
@@ -206,143 +970,643 @@ impl Player {
                         }
                     }
                     RepeatMode::Off => {
-                        let next_song = if self.shuffle {
-                            self.list_view.read(cx).random_song(cx)
-                        } else {
-                            self.list_view.read(cx).next_song(cx)
-                        };
-                        if let Some(song) = next_song {
+                        if let Some(song) = self.following_song(cx) {
                             self.play_song(song, cx);
                         }
                     }
                 }
-                cx.notify();
+                cx.notify();
+            }
+        }
+    }
+
+    fn play_song(&mut self, song: Song, cx: &mut Context<Self>) {
+        self.audio_player.update(cx, |player, cx| {
+            if let Err(e) = player.play_song(song, cx) {
+                eprintln!("Failed to play song: {}", e);
+            }
+        });
+    }
+
+    fn toggle_playback(&mut self, cx: &mut Context<Self>) {
+        self.audio_player.update(cx, |player, cx| {
+            player.toggle_playback(cx);
+        });
+    }
+
+    fn skip_next(&mut self, cx: &mut Context<Self>) {
+        if let Some(song) = self.following_song(cx) {
+            self.play_song(song, cx);
+        }
+    }
+
+    /// The song to play after the current one: the next queued song, or else
+    /// a random or the next song in the list
+    fn following_song(&mut self, cx: &mut Context<Self>) -> Option<Song> {
+        while !self.up_next.is_empty() {
+            let song_id = self.up_next.remove(0);
+            if let Some(song) = self.library.read(cx).songs.get(&song_id) {
+                return Some(song.clone());
+            }
+        }
+        if self.shuffle {
+            self.list_view.read(cx).random_song(cx)
+        } else {
+            self.list_view.read(cx).next_song(cx)
+        }
+    }
+
+    fn skip_previous(&mut self, cx: &mut Context<Self>) {
+        let position = self.audio_player.read(cx).position();
+        if position > Duration::from_secs(3) {
+            if let Some(song) = self.audio_player.read(cx).current_song().cloned() {
+                self.play_song(song, cx);
+            }
+        } else {
+            let prev_song = if self.shuffle {
+                self.list_view.read(cx).random_song(cx)
+            } else {
+                self.list_view.read(cx).previous_song(cx)
+            };
+            if let Some(song) = prev_song {
+                self.play_song(song, cx);
+            }
+        }
+    }
+
+    /// Count a finished play towards the song's stats and add it to the listening history
+    fn record_play(&mut self, record: PlayRecord, cx: &mut Context<Self>) {
+        let song = self.library.update(cx, |library, cx| {
+            library.record_play(&record);
+            cx.notify();
+            library.songs.get(&SongId(record.song_id)).cloned()
+        });
+        // Scrobbles go to a local log and queue only; nothing is submitted yet
+        let scrobble_song = song.filter(|_| self.settings.scrobbling);
+
+        let paths = self.paths.clone();
+        cx.background_executor()
+            .spawn(async move {
+                if let Err(e) = append_play(&record, &paths) {
+                    eprintln!("Failed to record play: {}", e);
+                }
+                if let Some(song) = scrobble_song {
+                    if let Err(e) = log_scrobble(&song, &record, &paths) {
+                        eprintln!("Failed to log scrobble: {}", e);
+                    }
+                }
+            })
+            .detach();
+    }
+
+    fn cycle_sort_order(&mut self, cx: &mut Context<Self>) {
+        let ix = SortOrder::ALL
+            .iter()
+            .position(|order| *order == self.sort_order)
+            .unwrap_or(0);
+        self.sort_order = SortOrder::ALL[(ix + 1) % SortOrder::ALL.len()];
+        let sort_order = self.sort_order;
+        self.list_view
+            .update(cx, |list_view, cx| list_view.set_sort_order(sort_order, cx));
+        self.update_media_controls_tracks(cx);
+        self.persist_session(cx);
+        cx.notify();
+    }
+
+    fn cycle_filter(&mut self, cx: &mut Context<Self>) {
+        let ix = SongFilter::PRESETS
+            .iter()
+            .position(|filter| *filter == self.filter)
+            .unwrap_or(0);
+        self.filter = SongFilter::PRESETS[(ix + 1) % SongFilter::PRESETS.len()];
+        let filter = self.filter;
+        self.list_view
+            .update(cx, |list_view, cx| list_view.set_filter(filter, cx));
+        self.update_media_controls_tracks(cx);
+        cx.notify();
+    }
+
+    fn toggle_shuffle(&mut self, cx: &mut Context<Self>) {
+        self.set_shuffle(!self.shuffle, cx);
+    }
+
+    fn set_shuffle(&mut self, shuffle: bool, cx: &mut Context<Self>) {
+        self.shuffle = shuffle;
+        self.settings.playback.shuffle = shuffle;
+        self.save_settings();
+        self.update_media_controls_modes();
+        self.notify_mpd(MpdSubsystem::Options);
+        cx.notify();
+    }
+
+    fn toggle_repeat(&mut self, cx: &mut Context<Self>) {
+        let repeat = match self.repeat {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        };
+        self.set_repeat(repeat, cx);
+    }
+
+    fn set_repeat(&mut self, repeat: RepeatMode, cx: &mut Context<Self>) {
+        self.repeat = repeat;
+        self.settings.playback.repeat = repeat;
+        self.save_settings();
+        self.update_media_controls_modes();
+        self.notify_mpd(MpdSubsystem::Options);
+        cx.notify();
+    }
+
+    fn set_volume(&mut self, volume: f32, cx: &mut Context<Self>) {
+        self.audio_player
+            .update(cx, |player, cx| player.set_volume(volume, cx));
+        self.settings.playback.volume = self.audio_player.read(cx).volume();
+        self.save_settings();
+        self.update_media_controls_modes();
+        self.notify_mpd(MpdSubsystem::Mixer);
+        cx.notify();
+    }
+
+    /// Put back what was loaded when the player last ran, paused where it was left
+    fn restore_session(&mut self, cx: &mut Context<Self>) {
+        let Some(session) = self.pending_session.take() else {
+            return;
+        };
+
+        let library = self.library.read(cx);
+        self.up_next = session
+            .queue
+            .iter()
+            .map(|id| SongId(*id))
+            .filter(|id| library.songs.contains_key(id))
+            .collect();
+        // Audiobooks are M4B songs, so they resume at the exact position like any song
+        let song = session
+            .current
+            .and_then(|SessionItem::Song(id)| library.songs.get(&SongId(id)).cloned());
+
+        if let Some(song) = song {
+            self.audio_player.update(cx, |player, cx| {
+                if let Err(e) = player.load_song(song, session.position, cx) {
+                    eprintln!("Failed to restore song: {}", e);
+                }
+            });
+        }
+        // After the restored song's events, which select the playing song
+        if let Some(selected) = session.selected {
+            cx.spawn(async move |this, cx| {
+                let _ = this.update(cx, |this, cx| {
+                    this.list_view.update(cx, |list_view, cx| {
+                        list_view.select_song(SongId(selected), cx)
+                    });
+                });
+            })
+            .detach();
+        }
+        if !self.up_next.is_empty() {
+            self.notify_mpd(MpdSubsystem::Playlist);
+        }
+        cx.notify();
+    }
+
+    fn current_session(&self, cx: &App) -> Session {
+        let player = self.audio_player.read(cx);
+        Session {
+            current: player
+                .current_song()
+                .map(|song| SessionItem::Song(song.id.0)),
+            position: player.position(),
+            queue: self.up_next.iter().map(|id| id.0).collect(),
+            sort_order: self.sort_order,
+            selected: self.list_view.read(cx).selected_song_id(cx).map(|id| id.0),
+        }
+    }
+
+    /// Write the session file, once the last session has been restored
+    fn persist_session(&self, cx: &App) {
+        if self.pending_session.is_some() {
+            return;
+        }
+        if let Err(e) = save_session(&self.current_session(cx), &self.paths) {
+            eprintln!("Failed to save session: {}", e);
+        }
+    }
+
+    /// Save the session a moment after a seek, so a long audiobook resumes where it
+    /// was left even if the app is killed before playback stops. Seeks come in
+    /// bursts while a seek key is held; only the last one is written.
+    fn persist_session_soon(&mut self, cx: &mut Context<Self>) {
+        const SESSION_SAVE_DELAY: Duration = Duration::from_secs(1);
+
+        self.session_save = Some(cx.spawn(async move |this, cx| {
+            cx.background_executor().timer(SESSION_SAVE_DELAY).await;
+            let _ = this.update(cx, |this, cx| this.persist_session(cx));
+        }));
+    }
+
+    fn save_settings(&self) {
+        if let Err(e) = self.settings.save() {
+            eprintln!("Failed to save settings: {}", e);
+        }
+    }
+
+    /// Start the local control API and the MPD server if they're enabled.
+    /// Calls from both are answered here, on the main thread.
+    fn start_control_servers(
+        settings: &mut Settings,
+        cx: &mut Context<Self>,
+    ) -> (Option<RemoteServer>, Option<MpdServer>) {
+        let (call_tx, call_rx) = smol::channel::unbounded::<RemoteCall>();
+        let remote_server = Self::start_remote_server(settings, call_tx.clone());
+        let mpd_server = Self::start_mpd_server(settings, call_tx);
+        if remote_server.is_none() && mpd_server.is_none() {
+            return (None, None);
+        }
+
+        cx.spawn(async move |this, cx| {
+            while let Ok(call) = call_rx.recv().await {
+                if this
+                    .update(cx, |this, cx| this.handle_remote_call(call, cx))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        })
+        .detach();
+
+        (remote_server, mpd_server)
+    }
+
+    /// The local control API, generating its token the first time
+    fn start_remote_server(
+        settings: &mut Settings,
+        call_tx: smol::channel::Sender<RemoteCall>,
+    ) -> Option<RemoteServer> {
+        if !settings.remote.enabled {
+            return None;
+        }
+
+        let token = match &settings.remote.token {
+            Some(token) => token.clone(),
+            None => {
+                let token = generate_token();
+                settings.remote.token = Some(token.clone());
+                if let Err(e) = settings.save() {
+                    eprintln!("Failed to save remote control token: {}", e);
+                }
+                token
+            }
+        };
+
+        let server = match RemoteServer::start(&settings.remote.address, &token, move |call| {
+            let _ = call_tx.send_blocking(call);
+        }) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Failed to start remote control server: {}", e);
+                return None;
+            }
+        };
+        println!("Remote control listening on {}", server.local_addr());
+        Some(server)
+    }
+
+    fn start_mpd_server(
+        settings: &Settings,
+        call_tx: smol::channel::Sender<RemoteCall>,
+    ) -> Option<MpdServer> {
+        if !settings.mpd.enabled {
+            return None;
+        }
+
+        let server = match MpdServer::start(
+            &settings.mpd.address,
+            settings.mpd.password.as_deref(),
+            move |call| {
+                let _ = call_tx.send_blocking(call);
+            },
+        ) {
+            Ok(server) => server,
+            Err(e) => {
+                eprintln!("Failed to start MPD server: {}", e);
+                return None;
+            }
+        };
+        println!("MPD server listening on {}", server.local_addr());
+        Some(server)
+    }
+
+    fn notify_mpd(&self, subsystem: MpdSubsystem) {
+        if let Some(server) = &self.mpd_server {
+            server.changed(subsystem);
+        }
+    }
+
+    /// Carry out a remote control call. Queue calls answer with the queue,
+    /// `search` with the matching songs and the rest with the player's status.
+    fn handle_remote_call(&mut self, call: RemoteCall, cx: &mut Context<Self>) {
+        let result = match &call.request {
+            RemoteRequest::Status | RemoteRequest::QueueList | RemoteRequest::Search { .. } => {
+                Ok(())
+            }
+            RemoteRequest::Play => {
+                self.handle_media_key_event(MediaKeyEvent::Play, cx);
+                Ok(())
+            }
+            RemoteRequest::Pause => {
+                self.handle_media_key_event(MediaKeyEvent::Pause, cx);
+                Ok(())
+            }
+            RemoteRequest::Toggle => {
+                self.toggle_playback(cx);
+                Ok(())
+            }
+            RemoteRequest::Stop => {
+                self.handle_media_key_event(MediaKeyEvent::Stop, cx);
+                Ok(())
+            }
+            RemoteRequest::Next => {
+                self.skip_next(cx);
+                Ok(())
+            }
+            RemoteRequest::Previous => {
+                self.skip_previous(cx);
+                Ok(())
+            }
+            RemoteRequest::Seek { position } => Duration::try_from_secs_f64(*position)
+                .map(|position| {
+                    self.handle_media_key_event(MediaKeyEvent::SetPosition(position), cx)
+                })
+                .map_err(|_| format!("Invalid position {}", position)),
+            RemoteRequest::SeekBy { offset } => Duration::try_from_secs_f64(offset.abs())
+                .map(|delta| self.seek_by(delta, *offset >= 0.0, cx))
+                .map_err(|_| format!("Invalid offset {}", offset)),
+            RemoteRequest::SetVolume { volume } => {
+                self.set_volume(*volume, cx);
+                Ok(())
+            }
+            RemoteRequest::SetShuffle { shuffle } => {
+                self.set_shuffle(*shuffle, cx);
+                Ok(())
+            }
+            RemoteRequest::SetRepeat { repeat } => {
+                self.set_repeat(*repeat, cx);
+                Ok(())
+            }
+            RemoteRequest::PlaySong { id } => {
+                match self.library.read(cx).songs.get(&SongId(*id)).cloned() {
+                    Some(song) => {
+                        self.play_song(song, cx);
+                        Ok(())
+                    }
+                    None => Err(format!("No song with id {}", id)),
+                }
+            }
+            RemoteRequest::QueueAdd { ids } => {
+                let library = self.library.read(cx);
+                match ids
+                    .iter()
+                    .find(|id| !library.songs.contains_key(&SongId(**id)))
+                {
+                    Some(id) => Err(format!("No song with id {}", id)),
+                    None => {
+                        self.up_next.extend(ids.iter().copied().map(SongId));
+                        Ok(())
+                    }
+                }
+            }
+            RemoteRequest::QueueRemove { index } => {
+                if *index < self.up_next.len() {
+                    self.up_next.remove(*index);
+                    Ok(())
+                } else {
+                    Err(format!("No queue entry {}", index))
+                }
+            }
+            RemoteRequest::QueueClear => {
+                self.up_next.clear();
+                Ok(())
+            }
+        };
+
+        if let Err(message) = result {
+            call.fail(message);
+            return;
+        }
+        match &call.request {
+            RemoteRequest::Search { query, limit } => {
+                let songs: Vec<RemoteSong> = self
+                    .library
+                    .read(cx)
+                    .search(query, self.sort_order)
+                    .iter()
+                    .take(*limit)
+                    .map(RemoteSong::from)
+                    .collect();
+                call.respond(songs);
+            }
+            RemoteRequest::QueueList
+            | RemoteRequest::QueueAdd { .. }
+            | RemoteRequest::QueueRemove { .. }
+            | RemoteRequest::QueueClear => {
+                if !matches!(call.request, RemoteRequest::QueueList) {
+                    self.notify_mpd(MpdSubsystem::Playlist);
+                    self.persist_session(cx);
+                }
+                let queue = self.queued_songs(cx);
+                call.respond(queue);
+            }
+            _ => {
+                let status = self.remote_status(cx);
+                call.respond(status);
+            }
+        }
+    }
+
+    fn queued_songs(&self, cx: &App) -> Vec<RemoteSong> {
+        let library = self.library.read(cx);
+        self.up_next
+            .iter()
+            .filter_map(|song_id| library.songs.get(song_id))
+            .map(RemoteSong::from)
+            .collect()
+    }
+
+    fn remote_status(&self, cx: &App) -> RemoteStatus {
+        let player = self.audio_player.read(cx);
+        RemoteStatus {
+            state: player.state(),
+            song: player.current_song().map(RemoteSong::from),
+            position: player.position().as_secs_f64(),
+            volume: player.volume(),
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+            queue: self.queued_songs(cx),
+        }
+    }
+
+    /// Start the system media controls. Their requests arrive on the
+    /// controls' own thread and are handed to the app here.
+    fn start_media_controls(cx: &mut Context<Self>) -> Option<MediaControlsHandler> {
+        let (event_tx, event_rx) = smol::channel::unbounded::<MediaKeyEvent>();
+        let controls = match MediaControlsHandler::new(move |event| {
+            let _ = event_tx.send_blocking(event);
+        }) {
+            Ok(controls) => controls,
+            Err(e) => {
+                eprintln!("Failed to initialize media controls: {}", e);
+                return None;
+            }
+        };
+
+        cx.spawn(async move |this, cx| {
+            while let Ok(event) = event_rx.recv().await {
+                if this
+                    .update(cx, |this, cx| this.handle_media_key_event(event, cx))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        })
+        .detach();
+
+        Some(controls)
+    }
+
+    fn handle_media_key_event(&mut self, event: MediaKeyEvent, cx: &mut Context<Self>) {
+        match event {
+            MediaKeyEvent::Play => {
+                self.audio_player.update(cx, |player, cx| {
+                    player.play(cx);
+                });
+            }
+            MediaKeyEvent::Pause => {
+                self.audio_player.update(cx, |player, cx| {
+                    player.pause(cx);
+                });
+            }
+            MediaKeyEvent::Toggle => {
+                self.toggle_playback(cx);
+            }
+            MediaKeyEvent::Next => {
+                self.skip_next(cx);
+            }
+            MediaKeyEvent::Previous => {
+                self.skip_previous(cx);
+            }
+            MediaKeyEvent::Stop => {
+                self.audio_player.update(cx, |player, cx| {
+                    player.stop(cx);
+                });
+            }
+            MediaKeyEvent::SeekForward => {
+                self.seek_by(Duration::from_secs(10), true, cx);
+            }
+            MediaKeyEvent::SeekBackward => {
+                self.seek_by(Duration::from_secs(10), false, cx);
+            }
+            MediaKeyEvent::SeekBy { offset, forward } => {
+                self.seek_by(offset, forward, cx);
+            }
+            MediaKeyEvent::SetPosition(position) => {
+                self.audio_player.update(cx, |player, cx| {
+                    player.seek_to(position, cx);
+                });
+                self.update_media_controls_position(cx);
+                self.persist_session_soon(cx);
+            }
+            MediaKeyEvent::SetVolume(volume) => {
+                self.set_volume(volume as f32, cx);
+            }
+            MediaKeyEvent::SetShuffle(shuffle) => {
+                self.set_shuffle(shuffle, cx);
+            }
+            MediaKeyEvent::SetRepeat(repeat) => {
+                self.set_repeat(repeat, cx);
+            }
+            MediaKeyEvent::GoTo(song_id) => {
+                if let Some(song) = self.library.read(cx).songs.get(&song_id).cloned() {
+                    self.play_song(song, cx);
+                }
             }
+            MediaKeyEvent::OpenUri(uri) => self.open_uri(&uri, cx),
+            MediaKeyEvent::Raise => cx.activate(true),
+            MediaKeyEvent::Quit => cx.quit(),
         }
     }
 
-    fn play_song(&mut self, song: Song, cx: &mut Context<Self>) {
+    fn seek_by(&mut self, offset: Duration, forward: bool, cx: &mut Context<Self>) {
         self.audio_player.update(cx, |player, cx| {
-            if let Err(e) = player.play_song(song, cx) {
-                eprintln!("Failed to play song: {}", e);
-            }
+            player.seek_by(offset, forward, cx);
         });
+        self.update_media_controls_position(cx);
+        self.persist_session_soon(cx);
     }
 
-    fn toggle_playback(&mut self, cx: &mut Context<Self>) {
-        self.audio_player.update(cx, |player, cx| {
-            player.toggle_playback(cx);
+    /// Play a `file://` URI, if it's a song in the library
+    fn open_uri(&mut self, uri: &str, cx: &mut Context<Self>) {
+        let song = path_from_file_uri(uri).and_then(|path| {
+            self.library
+                .read(cx)
+                .songs
+                .values()
+                .find(|song| song.file.path == path)
+                .cloned()
         });
-    }
-
-    fn skip_next(&mut self, cx: &mut Context<Self>) {
-        let next_song = if self.shuffle {
-            self.list_view.read(cx).random_song(cx)
-        } else {
-            self.list_view.read(cx).next_song(cx)
-        };
-        if let Some(song) = next_song {
-            self.play_song(song, cx);
+        match song {
+            Some(song) => self.play_song(song, cx),
+            None => self.set_status(format!("Not in the library: {}", uri), cx),
         }
     }
 
-    fn skip_previous(&mut self, cx: &mut Context<Self>) {
-        let position = self.audio_player.read(cx).position();
-        if position > Duration::from_secs(3) {
-            if let Some(song) = self.audio_player.read(cx).current_song().cloned() {
-                self.play_song(song, cx);
-            }
-        } else {
-            let prev_song = if self.shuffle {
-                self.list_view.read(cx).random_song(cx)
-            } else {
-                self.list_view.read(cx).previous_song(cx)
+    fn update_media_controls_metadata(&mut self, song: Option<&Song>, cx: &mut Context<Self>) {
+        if let Some(controls) = &mut self.media_controls {
+            let result = match song {
+                Some(song) => controls.set_metadata(Some(song)),
+                None => controls.clear(),
             };
-            if let Some(song) = prev_song {
-                self.play_song(song, cx);
+            if let Err(e) = result {
+                eprintln!("Failed to update media controls metadata: {}", e);
             }
         }
+        self.update_media_controls_tracks(cx);
     }
 
-    fn toggle_shuffle(&mut self, cx: &mut Context<Self>) {
-        self.shuffle = !self.shuffle;
-        cx.notify();
-    }
+    /// Offer the songs that play next to the media controls' track list
+    fn update_media_controls_tracks(&mut self, cx: &mut Context<Self>) {
+        const TRACK_LIST_LENGTH: usize = 50;
 
-    fn toggle_repeat(&mut self, cx: &mut Context<Self>) {
-        self.repeat = match self.repeat {
-            RepeatMode::Off => RepeatMode::All,
-            RepeatMode::All => RepeatMode::One,
-            RepeatMode::One => RepeatMode::Off,
-        };
-        cx.notify();
+        if let Some(controls) = &mut self.media_controls {
+            let tracks = self
+                .list_view
+                .read(cx)
+                .upcoming_songs(TRACK_LIST_LENGTH, cx);
+            if let Err(e) = controls.set_tracks(&tracks) {
+                eprintln!("Failed to update media controls track list: {}", e);
+            }
+        }
     }
 
-    fn poll_media_key_events(&mut self, cx: &mut Context<Self>) {
-        let events: Vec<MediaKeyEvent> = self
-            .media_controls
-            .as_ref()
-            .map(|c| c.poll_events())
-            .unwrap_or_default();
-
-        for event in events {
-            match event {
-                MediaKeyEvent::Play => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.play(cx);
-                    });
-                }
-                MediaKeyEvent::Pause => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.pause(cx);
-                    });
-                }
-                MediaKeyEvent::Toggle => {
-                    self.toggle_playback(cx);
-                }
-                MediaKeyEvent::Next => {
-                    self.skip_next(cx);
-                }
-                MediaKeyEvent::Previous => {
-                    self.skip_previous(cx);
-                }
-                MediaKeyEvent::Stop => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.stop(cx);
-                    });
-                }
-                MediaKeyEvent::SeekForward => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.seek_by(Duration::from_secs(10), true, cx);
-                    });
-                }
-                MediaKeyEvent::SeekBackward => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.seek_by(Duration::from_secs(10), false, cx);
-                    });
-                }
-                MediaKeyEvent::SetPosition(position) => {
-                    self.audio_player.update(cx, |player, cx| {
-                        player.seek_to(position, cx);
-                    });
-                }
+    fn update_media_controls_modes(&mut self) {
+        if let Some(controls) = &mut self.media_controls {
+            let result = controls
+                .set_volume(f64::from(self.settings.playback.volume))
+                .and_then(|()| controls.set_shuffle(self.shuffle))
+                .and_then(|()| controls.set_repeat(self.repeat));
+            if let Err(e) = result {
+                eprintln!("Failed to update media controls: {}", e);
             }
         }
     }
 
-    fn update_media_controls_metadata(&mut self, song: Option<&Song>) {
+    /// Tell the media controls the position jumped
+    fn update_media_controls_position(&mut self, cx: &mut Context<Self>) {
         if let Some(controls) = &mut self.media_controls {
-            if let Some(song) = song {
-                if let Err(e) = controls.set_metadata(
-                    Some(&song.title),
-                    song.artist.as_deref(),
-                    song.album.as_deref(),
-                    Some(song.duration),
-                ) {
-                    eprintln!("Failed to update media controls metadata: {}", e);
-                }
-            } else if let Err(e) = controls.clear() {
-                eprintln!("Failed to clear media controls: {}", e);
+            let position = self.audio_player.read(cx).position();
+            if let Err(e) = controls.seeked(position) {
+                eprintln!("Failed to update media controls position: {}", e);
             }
         }
     }
@@ -361,29 +1625,45 @@ impl Player {
         }
     }
 
-    fn stream_load_library(library: Entity<Library>, cx: &mut Context<Self>) {
-        cx.spawn(async move |_this, cx| {
-            let reader = match LibraryReader::open() {
-                Ok(Some(reader)) => reader,
-                Ok(None) => {
-                    println!("No existing library found");
-                    return;
+    fn stream_load_library(library: Entity<Library>, paths: Paths, cx: &mut Context<Self>) {
+        cx.spawn(async move |this, cx| {
+            if needs_recovery(&paths) {
+                let recovery_paths = paths.clone();
+                cx.background_executor()
+                    .spawn(async move { Self::recover_interrupted_imports(&recovery_paths) })
+                    .await;
+            }
+
+            if paths.backend == StorageBackend::Sqlite {
+                let migration_paths = paths.clone();
+                let migrated = cx
+                    .background_executor()
+                    .spawn(async move { migrate_jsonl_to_sqlite(&migration_paths) })
+                    .await;
+                match migrated {
+                    Ok(Some(count)) => println!("Migrated {} entries to SQLite", count),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Failed to migrate library to SQLite: {}", e),
                 }
+            }
+
+            let entries = match open_storage(&paths).and_then(|storage| storage.load_entries()) {
+                Ok(entries) => entries,
                 Err(e) => {
                     eprintln!("Failed to open library: {}", e);
+                    // There's nothing to restore into, so save sessions from here on
+                    let _ = this.update(cx, |this, _cx| this.pending_session = None);
                     return;
                 }
             };
 
-            let mut song_count = 0;
             let mut batch: Vec<player_core::Song> = Vec::new();
             const BATCH_SIZE: usize = 100;
 
-            for entry in reader {
+            for entry in entries {
                 match entry {
                     LoadedEntry::Song(song) => {
                         batch.push(song);
-                        song_count += 1;
 
                         if batch.len() >= BATCH_SIZE {
                             let songs_to_add = std::mem::take(&mut batch);
@@ -401,6 +1681,23 @@ impl Player {
                             cx.notify();
                         });
                     }
+                    LoadedEntry::RemovedSong(id) => {
+                        // Apply songs read before the removal first, since they may include it
+                        let songs_to_add = std::mem::take(&mut batch);
+                        let _ = library.update(cx, |lib, cx| {
+                            for song in songs_to_add {
+                                lib.add_song(song);
+                            }
+                            lib.remove_song(id);
+                            cx.notify();
+                        });
+                    }
+                    LoadedEntry::RemovedAudiobook(id) => {
+                        let _ = library.update(cx, |lib, cx| {
+                            lib.audiobooks.remove(&id);
+                            cx.notify();
+                        });
+                    }
                     LoadedEntry::Meta(_) => {}
                     LoadedEntry::Skipped { line_number, error } => {
                         eprintln!("Warning: Skipped line {}: {}", line_number, error);
@@ -417,11 +1714,81 @@ impl Player {
                 });
             }
 
+            // Songs from older libraries get content-derived ids, saved so they stick.
+            // The history, session and backfill failures are pointed at the new ids
+            // before the history is read below.
+            let rekeyed = library.update(cx, |lib, cx| {
+                let remap = lib.rekey_songs();
+                if remap.is_empty() {
+                    return None;
+                }
+                cx.notify();
+                Some((lib.clone(), remap))
+            });
+            if let Ok(Some((lib, remap))) = rekeyed {
+                let _ = this.update(cx, |this, _cx| {
+                    if let Some(session) = &mut this.pending_session {
+                        session.rekey(&remap);
+                    }
+                });
+                let save_paths = paths.clone();
+                let saved = cx
+                    .background_executor()
+                    .spawn(async move {
+                        save_library(&lib, &save_paths)?;
+                        rekey_song_files(&remap, &save_paths)
+                    })
+                    .await;
+                if let Err(e) = saved {
+                    eprintln!("Failed to save library with new song ids: {}", e);
+                }
+            }
+
+            let history_paths = paths.clone();
+            let history = cx
+                .background_executor()
+                .spawn(async move { load_history(&history_paths) })
+                .await;
+            match history {
+                Ok(history) => {
+                    let _ = library.update(cx, |lib, cx| {
+                        lib.apply_history(&history);
+                        cx.notify();
+                    });
+                }
+                Err(e) => eprintln!("Failed to load listening history: {}", e),
+            }
+
+            let song_count = library
+                .read_with(cx, |lib, _cx| lib.songs.len())
+                .unwrap_or(0);
             println!("Loaded {} songs from library", song_count);
+
+            let _ = this.update(cx, |this, cx| this.restore_session(cx));
         })
         .detach();
     }
 
+    /// Finish or undo imports that were cut short last time, before the library is shown
+    fn recover_interrupted_imports(paths: &Paths) {
+        let report = match player_core::recover_interrupted_imports(paths) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Failed to recover interrupted imports: {}", e);
+                return;
+            }
+        };
+        println!(
+            "Recovered {} imports, rolled back {}, adopted {} files",
+            report.completed.len(),
+            report.rolled_back.len(),
+            report.adopted.len()
+        );
+        for (path, error) in &report.unresolved {
+            eprintln!("  - could not recover {:?}: {}", path, error);
+        }
+    }
+
     fn set_status(&mut self, message: impl Into<String>, cx: &mut Context<Self>) {
         self.status_message = Some(message.into());
         cx.notify();
@@ -432,15 +1799,85 @@ impl Player {
         cx.notify();
     }
 
+    fn watch_import_folder(import_dir: &Path, cx: &mut Context<Self>) -> Option<ImportWatcher> {
+        let (ready_tx, ready_rx) = smol::channel::unbounded::<()>();
+
+        let watcher = match ImportWatcher::new(import_dir, DEFAULT_DEBOUNCE, move || {
+            let _ = ready_tx.send_blocking(());
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                eprintln!("Failed to watch Import folder: {}", e);
+                return None;
+            }
+        };
+
+        cx.spawn(async move |this, cx| {
+            while ready_rx.recv().await.is_ok() {
+                if this.update(cx, |this, cx| this.sync_library(cx)).is_err() {
+                    break;
+                }
+            }
+        })
+        .detach();
+
+        Some(watcher)
+    }
+
+    /// Ask for a folder to index in place, then sync to pick up its files
+    fn add_library_folder(&mut self, cx: &mut Context<Self>) {
+        let selected = cx.prompt_for_paths(PathPromptOptions {
+            files: false,
+            directories: true,
+            multiple: false,
+            prompt: Some("Add to Library".into()),
+        });
+
+        let paths = self.paths.clone();
+        cx.spawn(async move |this, cx| {
+            let Ok(Ok(Some(selected))) = selected.await else {
+                return;
+            };
+
+            let mut roots = load_library_roots(&paths).unwrap_or_default();
+            for path in selected {
+                if !roots.iter().any(|root| root.path == path) {
+                    roots.push(LibraryRoot::new(path));
+                }
+            }
+            if let Err(e) = save_library_roots(&roots, &paths) {
+                eprintln!("Failed to save library roots: {}", e);
+                return;
+            }
+
+            let _ = this.update(cx, |this, cx| this.sync_library(cx));
+        })
+        .detach();
+    }
+
+    /// Stop the running import after the file in progress
+    fn cancel_sync(&mut self, cx: &mut Context<Self>) {
+        if let Some(cancel) = &self.import_cancel {
+            cancel.cancel();
+            self.sync_requested = false;
+            self.set_status("Cancelling...", cx);
+        }
+    }
+
     fn sync_library(&mut self, cx: &mut Context<Self>) {
         if self.is_syncing {
+            self.sync_requested = true;
             return;
         }
 
         self.is_syncing = true;
         self.set_status("Starting sync...", cx);
 
+        let cancel = CancelToken::new();
+        self.import_cancel = Some(cancel.clone());
+
         let library = self.library.clone();
+        let paths = self.paths.clone();
         let (progress_tx, progress_rx) = smol::channel::unbounded::<RepairProgress>();
 
         let task = cx.spawn(async move |this, cx| {
@@ -458,7 +1895,7 @@ impl Player {
                 lib.audiobooks = current_audiobooks;
             }
 
-            let problem_dir = problem_path();
+            let problem_dir = paths.problem.clone();
             let has_problem_files = problem_dir.exists()
                 && std::fs::read_dir(&problem_dir)
                     .map(|mut d| d.next().is_some())
@@ -466,8 +1903,9 @@ impl Player {
 
             if has_problem_files {
                 let progress_tx_clone = progress_tx.clone();
+                let repair_paths = paths.clone();
                 let repair_task = cx.background_executor().spawn(async move {
-                    repair_problem_files_with_progress(|progress| {
+                    repair_problem_files_with_progress(&repair_paths, |progress| {
                         let _ = progress_tx_clone.send_blocking(progress);
                     })
                 });
@@ -513,43 +1951,190 @@ impl Player {
                 this.set_status("Importing files...", cx);
             });
 
-            let (results, lib) = cx
+            let (import_event_tx, import_event_rx) = smol::channel::unbounded::<ImportEvent>();
+            let options_paths = paths.clone();
+            let options = cx
                 .background_executor()
-                .spawn(async move {
-                    let results = import_all_pending(&mut lib);
-                    (results, lib)
-                })
+                .spawn(async move { ImportOptions::for_library(&options_paths) })
                 .await;
-            let success_count = results.iter().filter(|r| r.is_ok()).count();
+
+            let import_options = options.clone();
+            let import_paths = paths.clone();
+            let import_cancel = cancel.clone();
+            let import_event_tx_clone = import_event_tx.clone();
+            let import_task = cx.background_executor().spawn(async move {
+                let options = import_options;
+                let results = import_all_pending_with_events(
+                    &mut lib,
+                    &options,
+                    &import_paths,
+                    &import_cancel,
+                    |event| {
+                        let _ = import_event_tx_clone.send_blocking(event);
+                    },
+                );
+                (results, lib)
+            });
+
+            let mut import_task = import_task.fuse();
+            let (results, mut lib) = loop {
+                futures::select_biased! {
+                    event = import_event_rx.recv().fuse() => {
+                        match event {
+                            Ok(ImportEvent::Progress(progress)) => {
+                                let filename = progress.current_file
+                                    .file_name()
+                                    .map(|s| s.to_string_lossy().to_string())
+                                    .unwrap_or_default();
+                                let stage = match progress.stage {
+                                    ImportStage::ReadingMetadata => "Reading",
+                                    ImportStage::Copying => "Copying",
+                                    ImportStage::Archiving => "Archiving",
+                                };
+                                let _ = this.update(cx, |this, cx| {
+                                    this.set_status(
+                                        format!("{} {} ({}/{})", stage, filename, progress.current, progress.total),
+                                        cx,
+                                    );
+                                });
+                            }
+                            Ok(ImportEvent::SongImported(song)) => {
+                                let _ = library.update(cx, |current_lib, cx| {
+                                    replace_song(current_lib, *song);
+                                    cx.notify();
+                                });
+                            }
+                            Ok(ImportEvent::Failed { path, error }) => {
+                                eprintln!("Failed to import {:?}: {}", path, error);
+                            }
+                            Ok(ImportEvent::Scanned { .. }) | Err(_) => {}
+                        }
+                    }
+                    result = &mut import_task => break result,
+                }
+            };
+            let cancelled = cancel.is_cancelled();
+
+            // Re-index folders that are indexed in place rather than imported
+            let mut rescans = Vec::new();
+            if !cancelled {
+                let _ = this.update(cx, |this, cx| {
+                    this.set_status("Scanning library folders...", cx);
+                });
+
+                let rescan_paths = paths.clone();
+                (rescans, lib) = cx
+                    .background_executor()
+                    .spawn(async move {
+                        let rescans = rescan_roots(&mut lib, &options, &rescan_paths)
+                            .unwrap_or_else(|e| {
+                                eprintln!("Failed to load library roots: {}", e);
+                                Vec::new()
+                            });
+                        (rescans, lib)
+                    })
+                    .await;
+            }
+            let rescan_changed = rescans.iter().any(|rescan| rescan.changed_library());
+            let rescan_count: usize = rescans
+                .iter()
+                .map(|rescan| rescan.added.len() + rescan.updated.len() + rescan.moved.len())
+                .sum();
+            let removed_count: usize = rescans.iter().map(|rescan| rescan.removed.len()).sum();
+            let changed_songs: Vec<Song> = results
+                .iter()
+                .flatten()
+                .filter(|result| result.outcome.changed_library())
+                .map(|result| result.song.clone())
+                .collect();
+            let success_count = changed_songs.len();
+            let duplicate_count = results
+                .iter()
+                .flatten()
+                .filter(|result| !result.outcome.changed_library())
+                .count();
             let error_count = results.iter().filter(|r| r.is_err()).count();
 
+            if duplicate_count > 0 {
+                let _ = this.update(cx, |this, cx| {
+                    this.set_status(format!("Skipped {} duplicates", duplicate_count), cx);
+                });
+            }
+
+            if rescan_count > 0 || removed_count > 0 {
+                let _ = this.update(cx, |this, cx| {
+                    this.set_status(
+                        format!(
+                            "Indexed {} files, removed {} missing",
+                            rescan_count, removed_count
+                        ),
+                        cx,
+                    );
+                });
+            }
+
             if success_count > 0 {
                 let _ = this.update(cx, |this, cx| {
                     this.set_status(format!("Imported {} files", success_count), cx);
                 });
+            }
 
-                if let Err(e) = save_library(&lib) {
-                    eprintln!("Failed to save library: {}", e);
-                }
-
+            if success_count > 0 || rescan_changed {
                 let new_songs = lib.songs.clone();
-                let _ = library.update(cx, |current_lib, cx| {
-                    for (id, song) in new_songs {
-                        if !current_lib.songs.contains_key(&id) {
-                            current_lib.add_song(song);
+                let _ = this.update(cx, |this, cx| {
+                    library.update(cx, |current_lib, cx| {
+                        // Ids come from song content, so songs added to either copy of the
+                        // library meanwhile can't collide. Replaced duplicates keep their id,
+                        // so they must overwrite the old entry
+                        for song in changed_songs {
+                            replace_song(current_lib, song);
                         }
-                    }
-                    cx.notify();
+                        for rescan in rescans {
+                            for song in rescan
+                                .added
+                                .into_iter()
+                                .chain(rescan.updated)
+                                .chain(rescan.moved)
+                            {
+                                replace_song(current_lib, song);
+                            }
+                            for id in rescan.removed {
+                                current_lib.remove_song(id);
+                            }
+                        }
+                        // Pick up content hashes computed for songs imported before hashing,
+                        // leaving songs removed from the library meanwhile removed
+                        for (id, song) in new_songs {
+                            let missing_hash = current_lib
+                                .songs
+                                .get(&id)
+                                .is_some_and(|current| current.content_hash.is_none());
+                            if missing_hash {
+                                replace_song(current_lib, song);
+                            }
+                        }
+                        cx.notify();
+                    });
+                    // Keep the journal until the imported songs are saved, so they can be recovered
+                    this.clear_journal_after_save(cx);
                 });
+            } else if let Err(e) = ImportJournal::clear(&paths) {
+                eprintln!("Failed to clear import journal: {}", e);
             }
 
             if error_count > 0 {
                 let _ = this.update(cx, |this, cx| {
-                    this.set_status(format!("{} files moved to Problem folder", error_count), cx);
+                    this.set_status(format!("{} files failed to import", error_count), cx);
                 });
             }
 
-            let final_message = if success_count > 0 || error_count > 0 {
+            let final_message = if cancelled {
+                "Import cancelled".to_string()
+            } else if success_count > 0
+                || duplicate_count > 0
+                || error_count > 0
+                || rescan_changed
+            {
                 "Sync complete".to_string()
             } else {
                 "No new files".to_string()
@@ -564,7 +2149,14 @@ impl Player {
             let _ = this.update(cx, |this, cx| {
                 this.is_syncing = false;
                 this.sync_task = None;
+                this.import_cancel = None;
                 this.clear_status(cx);
+
+                // Files arrived while we were busy
+                if this.sync_requested {
+                    this.sync_requested = false;
+                    this.sync_library(cx);
+                }
             });
         });
 
@@ -636,8 +2228,6 @@ impl Render for Player {
             player.check_and_handle_finished(cx);
         });
 
-        self.poll_media_key_events(cx);
-
         let theme = cx.theme();
         let audio_player = self.audio_player.read(cx);
         let playback_state = audio_player.state();
@@ -661,6 +2251,10 @@ impl Render for Player {
             .on_action(cx.listener(Self::action_skip_previous))
             .on_action(cx.listener(Self::action_toggle_shuffle))
             .on_action(cx.listener(Self::action_toggle_repeat))
+            .on_action(cx.listener(Self::action_add_library_folder))
+            .on_action(cx.listener(Self::action_verify_library))
+            .on_action(cx.listener(Self::action_show_problems))
+            .on_action(cx.listener(Self::action_show_backups))
             .bg(theme.bg())
             .size_full()
             .child(
@@ -669,6 +2263,18 @@ impl Render for Player {
                     .overflow_hidden()
                     .child(self.list_view.clone()),
             )
+            .when_some(self.tag_editor.clone(), |el, tag_editor| {
+                el.child(tag_editor)
+            })
+            .when_some(self.integrity_view.clone(), |el, integrity_view| {
+                el.child(integrity_view)
+            })
+            .when_some(self.problems_view.clone(), |el, problems_view| {
+                el.child(problems_view)
+            })
+            .when_some(self.backups_view.clone(), |el, backups_view| {
+                el.child(backups_view)
+            })
             .child(
                 v_stack()
                     .gap(rems(0.5))
@@ -814,31 +2420,131 @@ impl Render for Player {
                             .child(status_message.unwrap_or_default()),
                     )
                     .child(
-                        div()
-                            .id("sync-button")
-                            .text_xs()
-                            .text_color(if self.is_syncing {
-                                theme.fg_disabled()
-                            } else {
-                                theme.fg_muted()
-                            })
-                            .when(!self.is_syncing, |el| {
-                                el.cursor_pointer()
+                        h_stack()
+                            .gap(rems(0.75))
+                            .child(
+                                div()
+                                    .id("add-folder-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .on_click(cx.listener(|this, _event, _window, cx| {
+                                        this.add_library_folder(cx);
+                                    }))
+                                    .child("Add Folder"),
+                            )
+                            .child(
+                                div()
+                                    .id("verify-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .on_click(cx.listener(|this, _event, window, cx| {
+                                        this.verify_library(window, cx);
+                                    }))
+                                    .child("Verify"),
+                            )
+                            .child(
+                                div()
+                                    .id("problems-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .on_click(cx.listener(|this, _event, window, cx| {
+                                        this.show_problems(window, cx);
+                                    }))
+                                    .child("Problems"),
+                            )
+                            .child(
+                                div()
+                                    .id("filter-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .on_click(cx.listener(|this, _event, _window, cx| {
+                                        this.cycle_filter(cx);
+                                    }))
+                                    .child(format!("Show: {}", self.filter.label())),
+                            )
+                            .child(
+                                div()
+                                    .id("sort-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
                                     .hover(|s| s.text_color(theme.fg()))
                                     .on_click(cx.listener(|this, _event, _window, cx| {
-                                        this.sync_library(cx);
+                                        this.cycle_sort_order(cx);
+                                    }))
+                                    .child(format!("Sort: {}", self.sort_order.label())),
+                            )
+                            .child(
+                                div()
+                                    .id("backups-button")
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .on_click(cx.listener(|this, _event, window, cx| {
+                                        this.show_backups(window, cx);
                                     }))
+                                    .child("Backups"),
+                            )
+                            .when(self.import_cancel.is_some(), |el| {
+                                el.child(
+                                    div()
+                                        .id("cancel-sync-button")
+                                        .text_xs()
+                                        .text_color(theme.fg_muted())
+                                        .cursor_pointer()
+                                        .hover(|s| s.text_color(theme.fg()))
+                                        .on_click(cx.listener(|this, _event, _window, cx| {
+                                            this.cancel_sync(cx);
+                                        }))
+                                        .child("Cancel"),
+                                )
                             })
-                            .child(if self.is_syncing {
-                                "Syncing..."
-                            } else {
-                                "Sync"
-                            }),
+                            .child(
+                                div()
+                                    .id("sync-button")
+                                    .text_xs()
+                                    .text_color(if self.is_syncing {
+                                        theme.fg_disabled()
+                                    } else {
+                                        theme.fg_muted()
+                                    })
+                                    .when(!self.is_syncing, |el| {
+                                        el.cursor_pointer()
+                                            .hover(|s| s.text_color(theme.fg()))
+                                            .on_click(cx.listener(|this, _event, _window, cx| {
+                                                this.sync_library(cx);
+                                            }))
+                                    })
+                                    .child(if self.is_syncing {
+                                        "Syncing..."
+                                    } else {
+                                        "Sync"
+                                    }),
+                            ),
                     ),
             )
     }
 }
 
+/// Put a song rebuilt from a copy of the library into the live library, keeping
+/// the rating, loved flag, added date and stats of the song it replaces, which
+/// may have changed since the copy was made
+fn replace_song(library: &mut Library, mut song: Song) {
+    if let Some(existing) = library.songs.get(&song.id) {
+        song.keep_user_data(existing);
+    }
+    library.add_song(song);
+}
+
 fn format_duration(duration: Duration) -> String {
     let total_seconds = duration.as_secs();
     let minutes = total_seconds / 60;
@@ -847,6 +2553,23 @@ fn format_duration(duration: Duration) -> String {
 }
 
 fn main() {
+    // A settings file that can't be read is an error rather than the defaults,
+    // which would be written over it the next time settings are saved
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let paths = match settings.paths() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
     Application::new()
         .with_assets(gpuikit::assets())
         .run(|cx: &mut App| {
@@ -854,7 +2577,7 @@ fn main() {
             ui::init(cx);
             init(cx);
             cx.open_window(WindowOptions::default(), |window, cx| {
-                let player = cx.new(Player::new);
+                let player = cx.new(|cx| Player::new(settings, paths, window, cx));
                 window.focus(&player.read(cx).focus_handle);
                 player
             })
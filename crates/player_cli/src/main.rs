@@ -0,0 +1,375 @@
+//! Library maintenance from the command line, for machines without a display.
+//! Works on the same library, settings and folders as the `player` app.
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use player_core::{
+    export_library, import_all_pending, list_backups, load_history, load_library, needs_recovery,
+    preview_import, recover_interrupted_imports, repair_problem_files_with_progress, rescan_roots,
+    restore_backup, save_library, verify_library, ImportError, ImportJournal, ImportOptions,
+    ImportOutcome, ImportResult, IntegrityIssue, Library, Paths, Settings, Song, SongEntry,
+    SortOrder, StorageError, VerifyOptions,
+};
+
+const USAGE: &str = "\
+Usage: player-cli <command>
+
+Commands:
+  import [--dry-run]            import new files from the Import folder and rescan library folders
+  repair                        fix files in the Problem folder and move them back to Import
+  list [--sort <order>] [--json]
+                                list songs; <order> is one of: artist, album, title, most-played,
+                                recently-played, recently-added, rating
+  search <query>                list songs whose title, artist or album contains <query>
+  verify [--durations]          check the library against the files on disk
+  stats                         show library totals
+  backups                       list backups of the library, newest first
+  restore <backup>              restore the backup with that id
+  export <file>                 export the whole library state to a file";
+
+fn main() -> ExitCode {
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let paths = match settings.paths() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["import"] => import(&paths),
+        ["import", "--dry-run"] => preview(&paths),
+        ["repair"] => repair(&paths),
+        ["list", options @ ..] => match parse_list_options(options) {
+            Some((sort_order, json)) => list(&paths, sort_order, json),
+            None => return usage(),
+        },
+        ["search", query] => search(&paths, query),
+        ["verify"] => verify(&paths, false),
+        ["verify", "--durations"] => verify(&paths, true),
+        ["stats"] => stats(&paths),
+        ["backups"] => list_backups(&paths).map(|backups| {
+            for backup in backups {
+                println!("{}\t{}", backup.created_at, backup.files().join(", "));
+            }
+        }),
+        ["restore", id] => {
+            let backup = list_backups(&paths).map(|backups| {
+                backups
+                    .into_iter()
+                    .find(|backup| backup.created_at.to_string() == *id)
+            });
+            match backup {
+                Ok(Some(backup)) => {
+                    restore_backup(&backup, &paths).map(|()| println!("Restored backup {}", id))
+                }
+                Ok(None) => {
+                    eprintln!("No backup {}; run `player-cli backups` to list them", id);
+                    return ExitCode::FAILURE;
+                }
+                Err(e) => Err(e),
+            }
+        }
+        ["export", file] => export_library(&paths, Path::new(file))
+            .map(|export| println!("Exported {} songs to {}", export.songs.len(), file)),
+        _ => return usage(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+// ============================================================================
+// Import and Repair
+// ============================================================================
+
+/// Import pending files the way the app's sync does: recover interrupted
+/// imports, import everything in Import, then rescan library folders
+fn import(paths: &Paths) -> Result<(), StorageError> {
+    if needs_recovery(paths) {
+        let report = recover_interrupted_imports(paths)?;
+        println!(
+            "Recovered {} imports, rolled back {}, adopted {} files",
+            report.completed.len(),
+            report.rolled_back.len(),
+            report.adopted.len()
+        );
+    }
+
+    let mut library = load_library(paths)?;
+    let options = ImportOptions::for_library(paths);
+    let results = import_all_pending(&mut library, &options, paths);
+    print_import_results(&results);
+
+    let rescans = rescan_roots(&mut library, &options, paths)?;
+    for rescan in &rescans {
+        println!(
+            "Indexed {} files, removed {} missing",
+            rescan.added.len() + rescan.updated.len() + rescan.moved.len(),
+            rescan.removed.len()
+        );
+        for (path, error) in &rescan.errors {
+            eprintln!("Failed to index {}: {}", path.display(), error);
+        }
+    }
+
+    // Keep the journal until the imported songs are saved, so they can be recovered
+    let imported = results
+        .iter()
+        .flatten()
+        .any(|result| result.outcome.changed_library());
+    if imported || rescans.iter().any(|rescan| rescan.changed_library()) {
+        save_library(&library, paths)?;
+    }
+    ImportJournal::clear(paths)?;
+    Ok(())
+}
+
+fn preview(paths: &Paths) -> Result<(), StorageError> {
+    let library = load_library(paths)?;
+    let options = ImportOptions::for_library(paths);
+    let results: Vec<_> = preview_import(&library, &options, paths)
+        .into_iter()
+        .map(|(_, result)| result)
+        .collect();
+    print_import_results(&results);
+    Ok(())
+}
+
+fn print_import_results(results: &[Result<ImportResult, ImportError>]) {
+    for result in results {
+        match result {
+            Ok(result) => {
+                let action = match result.outcome {
+                    ImportOutcome::Imported => "import",
+                    ImportOutcome::Skipped(_) => "skip duplicate",
+                    ImportOutcome::Replaced(_) => "replace",
+                    ImportOutcome::KeptBoth(_) => "import duplicate",
+                };
+                println!(
+                    "{}\t{}\t{}",
+                    action,
+                    result.original_path.display(),
+                    result.library_path.display()
+                );
+            }
+            Err(e) => eprintln!("Failed to import: {}", e),
+        }
+    }
+
+    let imported = results
+        .iter()
+        .flatten()
+        .filter(|result| result.outcome.changed_library())
+        .count();
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    println!(
+        "{} imported, {} duplicates skipped, {} failed",
+        imported,
+        results.len() - imported - failed,
+        failed
+    );
+}
+
+fn repair(paths: &Paths) -> Result<(), StorageError> {
+    let (repaired, failures) = repair_problem_files_with_progress(paths, |progress| {
+        eprintln!(
+            "Repairing {} ({}/{})",
+            progress.current_file.display(),
+            progress.current,
+            progress.total
+        );
+    });
+    for result in &repaired {
+        println!(
+            "{}\t{}",
+            result.path.display(),
+            format_duration(result.duration)
+        );
+    }
+    for failure in &failures {
+        eprintln!(
+            "Could not repair {}: {}",
+            failure.path.display(),
+            failure.reason
+        );
+    }
+    println!(
+        "Repaired {} files, {} failed",
+        repaired.len(),
+        failures.len()
+    );
+    Ok(())
+}
+
+// ============================================================================
+// Listing
+// ============================================================================
+
+/// The library with play counts from the listening history
+fn load_library_with_history(paths: &Paths) -> Result<Library, StorageError> {
+    let mut library = load_library(paths)?;
+    library.apply_history(&load_history(paths)?);
+    Ok(library)
+}
+
+/// `[--sort <order>] [--json]` in any order
+fn parse_list_options(options: &[&str]) -> Option<(SortOrder, bool)> {
+    let mut sort_order = SortOrder::default();
+    let mut json = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "--json" => json = true,
+            "--sort" => sort_order = parse_sort_order(options.next()?)?,
+            _ => return None,
+        }
+    }
+    Some((sort_order, json))
+}
+
+/// A sort order by its label, e.g. "most-played"
+fn parse_sort_order(name: &str) -> Option<SortOrder> {
+    SortOrder::ALL
+        .into_iter()
+        .find(|order| order.label().to_lowercase().replace(' ', "-") == name)
+}
+
+fn list(paths: &Paths, sort_order: SortOrder, json: bool) -> Result<(), StorageError> {
+    let songs = load_library_with_history(paths)?.list(sort_order);
+    print_songs(&songs, json)
+}
+
+fn search(paths: &Paths, query: &str) -> Result<(), StorageError> {
+    let songs = load_library_with_history(paths)?.search(query, SortOrder::default());
+    print_songs(&songs, false)
+}
+
+/// One song per line as artist, album, title and length, tab separated,
+/// or a JSON array of library entries
+fn print_songs(songs: &[Song], json: bool) -> Result<(), StorageError> {
+    if json {
+        let entries: Vec<SongEntry> = songs.iter().map(SongEntry::from_song).collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    for song in songs {
+        println!(
+            "{}\t{}\t{}\t{}",
+            song.artist.as_deref().unwrap_or(""),
+            song.album.as_deref().unwrap_or(""),
+            song.title,
+            format_duration(song.duration)
+        );
+    }
+    Ok(())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+// ============================================================================
+// Verify and Stats
+// ============================================================================
+
+fn verify(paths: &Paths, check_durations: bool) -> Result<(), StorageError> {
+    let library = load_library(paths)?;
+    let report = verify_library(&library, paths, VerifyOptions { check_durations });
+
+    let describe = |id| {
+        library
+            .songs
+            .get(&id)
+            .map(|song: &Song| song.file.path.display().to_string())
+            .unwrap_or_default()
+    };
+    for issue in &report.issues {
+        match issue {
+            IntegrityIssue::MissingFile {
+                path, relink_to, ..
+            } => match relink_to {
+                Some(found) => {
+                    println!("missing\t{}\tfound at {}", path.display(), found.display())
+                }
+                None => println!("missing\t{}", path.display()),
+            },
+            IntegrityIssue::Orphan { path } => println!("orphan\t{}", path.display()),
+            IntegrityIssue::DurationMismatch {
+                song_id,
+                stored,
+                decoded,
+            } => println!(
+                "duration\t{}\tstored {}, decoded {}",
+                describe(*song_id),
+                format_duration(*stored),
+                format_duration(*decoded)
+            ),
+            IntegrityIssue::StaleTags { song_id, fields } => {
+                println!(
+                    "tags\t{}\t{} changed",
+                    describe(*song_id),
+                    fields.join(", ")
+                )
+            }
+        }
+    }
+    println!(
+        "Checked {} songs, found {} issues",
+        report.songs_checked,
+        report.issues.len()
+    );
+    Ok(())
+}
+
+fn stats(paths: &Paths) -> Result<(), StorageError> {
+    let library = load_library_with_history(paths)?;
+    let songs = library.songs.values();
+    let count = |field: fn(&Song) -> Option<&String>| {
+        let mut values: Vec<&String> = library.songs.values().filter_map(field).collect();
+        values.sort();
+        values.dedup();
+        values.len()
+    };
+
+    let total: Duration = songs.clone().map(|song| song.duration).sum();
+    let hours = total.as_secs() / 3600;
+    println!("Songs:      {}", library.songs.len());
+    println!("Artists:    {}", count(|song| song.artist.as_ref()));
+    println!("Albums:     {}", count(|song| song.album.as_ref()));
+    println!("Audiobooks: {}", library.audiobooks.len());
+    println!("Length:     {}h {:02}m", hours, total.as_secs() / 60 % 60);
+    println!(
+        "Plays:      {}",
+        songs.clone().map(|song| song.stats.play_count).sum::<u32>()
+    );
+    println!(
+        "Skips:      {}",
+        songs.clone().map(|song| song.stats.skip_count).sum::<u32>()
+    );
+    println!("Loved:      {}", songs.filter(|song| song.loved).count());
+    Ok(())
+}
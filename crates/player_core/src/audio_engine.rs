@@ -0,0 +1,338 @@
+use std::io::BufReader;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
+
+use crate::library::Song;
+use crate::playback::PlaybackState;
+
+/// Plays songs. Doesn't depend on any UI: changes are sent as
+/// `AudioPlayerEvent`s to each receiver from `subscribe`.
+pub struct AudioEngine {
+    output: Output,
+    sink: Option<Sink>,
+    /// Pauses the thread throwing away a discarding output's samples
+    discard_paused: Option<mpsc::Sender<bool>>,
+    current_song: Option<Song>,
+    state: PlaybackState,
+    volume: f32,
+    playback_started_at: Option<Instant>,
+    paused_position: Duration,
+    subscribers: Vec<mpsc::Sender<AudioPlayerEvent>>,
+}
+
+enum Output {
+    Device {
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+    },
+    /// Each song is decoded on its own thread and thrown away
+    Discard,
+}
+
+#[derive(Debug, Clone)]
+pub enum AudioPlayerEvent {
+    StateChanged(PlaybackState),
+    SongChanged(Option<Box<Song>>),
+    PlaybackFinished,
+}
+
+impl AudioEngine {
+    /// An engine playing on the default output device
+    pub fn new() -> Result<Self, AudioPlayerError> {
+        let (stream, handle) = OutputStream::try_default()
+            .map_err(|e| AudioPlayerError::OutputStreamError(e.to_string()))?;
+        Ok(Self::with_output(Output::Device {
+            _stream: stream,
+            handle,
+        }))
+    }
+
+    /// An engine that decodes songs as fast as it can and throws the samples
+    /// away, so they finish almost at once. For tests.
+    pub fn discarding() -> Self {
+        Self::with_output(Output::Discard)
+    }
+
+    fn with_output(output: Output) -> Self {
+        Self {
+            output,
+            sink: None,
+            discard_paused: None,
+            current_song: None,
+            state: PlaybackState::Stopped,
+            volume: 1.0,
+            playback_started_at: None,
+            paused_position: Duration::ZERO,
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Receive every event from now on, in order. Dropping the receiver unsubscribes.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<AudioPlayerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn emit(&mut self, event: AudioPlayerEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// A sink for the output, and for a discarding output, the sender that pauses it
+    fn new_sink(&self) -> Result<(Sink, Option<mpsc::Sender<bool>>), AudioPlayerError> {
+        match &self.output {
+            Output::Device { handle, .. } => Sink::try_new(handle)
+                .map(|sink| (sink, None))
+                .map_err(|e| AudioPlayerError::SinkError(e.to_string())),
+            Output::Discard => {
+                let (sink, samples) = Sink::new_idle();
+                let (paused_tx, paused_rx) = mpsc::channel();
+                thread::spawn(move || discard_samples(samples, paused_rx));
+                Ok((sink, Some(paused_tx)))
+            }
+        }
+    }
+
+    fn set_discard_paused(&self, paused: bool) {
+        if let Some(discard_paused) = &self.discard_paused {
+            let _ = discard_paused.send(paused);
+        }
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.state
+    }
+
+    pub fn current_song(&self) -> Option<&Song> {
+        self.current_song.as_ref()
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn position(&self) -> Duration {
+        match self.state {
+            PlaybackState::Playing => {
+                if let Some(started_at) = self.playback_started_at {
+                    self.paused_position + started_at.elapsed()
+                } else {
+                    self.paused_position
+                }
+            }
+            PlaybackState::Paused | PlaybackState::Stopped => self.paused_position,
+        }
+    }
+
+    pub fn play_song(&mut self, song: Song) -> Result<(), AudioPlayerError> {
+        self.start_song(song, None)
+    }
+
+    /// Load `song` paused at `position`, ready to resume where it was left
+    pub fn load_song(&mut self, song: Song, position: Duration) -> Result<(), AudioPlayerError> {
+        self.start_song(song, Some(position))
+    }
+
+    /// Play `song` from the start, or load it paused at `paused_at`
+    fn start_song(
+        &mut self,
+        song: Song,
+        paused_at: Option<Duration>,
+    ) -> Result<(), AudioPlayerError> {
+        self.stop_internal();
+
+        let path = &song.file.path;
+        let file =
+            std::fs::File::open(path).map_err(|e| AudioPlayerError::FileError(e.to_string()))?;
+        let reader = BufReader::new(file);
+
+        let source =
+            Decoder::new(reader).map_err(|e| AudioPlayerError::DecodeError(e.to_string()))?;
+
+        let (sink, discard_paused) = self.new_sink()?;
+        self.discard_paused = discard_paused;
+
+        sink.set_volume(self.volume);
+        if paused_at.is_some() {
+            sink.pause();
+        }
+        sink.append(source);
+
+        let position = match paused_at {
+            Some(position) if !position.is_zero() && sink.try_seek(position).is_ok() => position,
+            _ => Duration::ZERO,
+        };
+        let state = if paused_at.is_some() {
+            // Only after seeking, since a seek waits for samples to be pulled
+            self.set_discard_paused(true);
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Playing
+        };
+
+        self.sink = Some(sink);
+        self.current_song = Some(song.clone());
+        self.state = state;
+        self.playback_started_at = (state == PlaybackState::Playing).then(Instant::now);
+        self.paused_position = position;
+
+        self.emit(AudioPlayerEvent::SongChanged(Some(Box::new(song))));
+        self.emit(AudioPlayerEvent::StateChanged(state));
+
+        Ok(())
+    }
+
+    pub fn play(&mut self) {
+        if let Some(sink) = &self.sink {
+            if self.state == PlaybackState::Paused {
+                sink.play();
+                self.set_discard_paused(false);
+                self.state = PlaybackState::Playing;
+                self.playback_started_at = Some(Instant::now());
+                self.emit(AudioPlayerEvent::StateChanged(PlaybackState::Playing));
+            }
+        }
+    }
+
+    pub fn pause(&mut self) {
+        if let Some(sink) = &self.sink {
+            if self.state == PlaybackState::Playing {
+                sink.pause();
+                self.set_discard_paused(true);
+                self.paused_position = self.position();
+                self.playback_started_at = None;
+                self.state = PlaybackState::Paused;
+                self.emit(AudioPlayerEvent::StateChanged(PlaybackState::Paused));
+            }
+        }
+    }
+
+    pub fn toggle_playback(&mut self) {
+        match self.state {
+            PlaybackState::Playing => self.pause(),
+            PlaybackState::Paused => self.play(),
+            PlaybackState::Stopped => {}
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.stop_internal();
+        self.current_song = None;
+        self.state = PlaybackState::Stopped;
+        self.emit(AudioPlayerEvent::SongChanged(None));
+        self.emit(AudioPlayerEvent::StateChanged(PlaybackState::Stopped));
+    }
+
+    fn stop_internal(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self.discard_paused = None;
+        self.playback_started_at = None;
+        self.paused_position = Duration::ZERO;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        if let Some(sink) = &self.sink {
+            sink.set_volume(self.volume);
+        }
+    }
+
+    pub fn seek_to(&mut self, position: Duration) {
+        if let Some(sink) = &self.sink {
+            // A seek waits for samples to be pulled, so let a paused discarding output run
+            let paused = self.state == PlaybackState::Paused;
+            if paused {
+                self.set_discard_paused(false);
+            }
+            let seeked = sink.try_seek(position).is_ok();
+            if paused {
+                self.set_discard_paused(true);
+            }
+            if seeked {
+                self.paused_position = position;
+                if self.state == PlaybackState::Playing {
+                    self.playback_started_at = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    pub fn seek_by(&mut self, delta: Duration, forward: bool) {
+        let current_position = self.position();
+        let new_position = if forward {
+            current_position.saturating_add(delta)
+        } else {
+            current_position.saturating_sub(delta)
+        };
+        self.seek_to(new_position);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sink.as_ref().is_some_and(|s| s.empty())
+    }
+
+    /// Stop if the current song has played to its end. Call this periodically;
+    /// the sink doesn't report when it runs dry.
+    pub fn check_and_handle_finished(&mut self) -> bool {
+        if self.state == PlaybackState::Playing && self.is_finished() {
+            self.stop_internal();
+            self.state = PlaybackState::Stopped;
+            self.emit(AudioPlayerEvent::PlaybackFinished);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Pull a discarding output's samples and throw them away, as fast as they
+/// decode. While paused, wait for the engine to resume rather than pulling
+/// silence. Ends when the engine drops the sender, or the samples run out.
+fn discard_samples(mut samples: impl Iterator, paused: mpsc::Receiver<bool>) {
+    const CHUNK: usize = 4096;
+
+    let mut is_paused = false;
+    loop {
+        let update = if is_paused {
+            paused.recv().map_err(|_| mpsc::TryRecvError::Disconnected)
+        } else {
+            paused.try_recv()
+        };
+        match update {
+            Ok(update) => is_paused = update,
+            Err(mpsc::TryRecvError::Disconnected) => return,
+            Err(mpsc::TryRecvError::Empty) => {
+                if samples.by_ref().take(CHUNK).count() < CHUNK {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AudioPlayerError {
+    OutputStreamError(String),
+    FileError(String),
+    DecodeError(String),
+    SinkError(String),
+}
+
+impl std::fmt::Display for AudioPlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioPlayerError::OutputStreamError(e) => write!(f, "Output stream error: {}", e),
+            AudioPlayerError::FileError(e) => write!(f, "File error: {}", e),
+            AudioPlayerError::DecodeError(e) => write!(f, "Decode error: {}", e),
+            AudioPlayerError::SinkError(e) => write!(f, "Sink error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AudioPlayerError {}
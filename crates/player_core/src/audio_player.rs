@@ -1,71 +1,41 @@
-use std::io::BufReader;
-use std::time::{Duration, Instant};
+use std::sync::mpsc;
+use std::time::Duration;
 
 use gpui::{Context, EventEmitter};
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink};
 
+use crate::audio_engine::{AudioEngine, AudioPlayerError, AudioPlayerEvent};
 use crate::library::Song;
 use crate::playback::PlaybackState;
 
+/// An `AudioEngine` as a gpui entity, emitting its events to subscribers
 pub struct AudioPlayer {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
-    sink: Option<Sink>,
-    current_song: Option<Song>,
-    state: PlaybackState,
-    volume: f32,
-    playback_started_at: Option<Instant>,
-    paused_position: Duration,
-}
-
-pub enum AudioPlayerEvent {
-    StateChanged(PlaybackState),
-    SongChanged(Option<Song>),
-    PlaybackFinished,
+    engine: AudioEngine,
+    events: mpsc::Receiver<AudioPlayerEvent>,
 }
 
 impl EventEmitter<AudioPlayerEvent> for AudioPlayer {}
 
 impl AudioPlayer {
     pub fn new(_cx: &mut Context<Self>) -> Result<Self, AudioPlayerError> {
-        let (stream, stream_handle) = OutputStream::try_default()
-            .map_err(|e| AudioPlayerError::OutputStreamError(e.to_string()))?;
-
-        Ok(Self {
-            _stream: stream,
-            stream_handle,
-            sink: None,
-            current_song: None,
-            state: PlaybackState::Stopped,
-            volume: 1.0,
-            playback_started_at: None,
-            paused_position: Duration::ZERO,
-        })
+        let mut engine = AudioEngine::new()?;
+        let events = engine.subscribe();
+        Ok(Self { engine, events })
     }
 
     pub fn state(&self) -> PlaybackState {
-        self.state
+        self.engine.state()
     }
 
     pub fn current_song(&self) -> Option<&Song> {
-        self.current_song.as_ref()
+        self.engine.current_song()
     }
 
     pub fn volume(&self) -> f32 {
-        self.volume
+        self.engine.volume()
     }
 
     pub fn position(&self) -> Duration {
-        match self.state {
-            PlaybackState::Playing => {
-                if let Some(started_at) = self.playback_started_at {
-                    self.paused_position + started_at.elapsed()
-                } else {
-                    self.paused_position
-                }
-            }
-            PlaybackState::Paused | PlaybackState::Stopped => self.paused_position,
-        }
+        self.engine.position()
     }
 
     pub fn play_song(
@@ -73,149 +43,78 @@ impl AudioPlayer {
         song: Song,
         cx: &mut Context<Self>,
     ) -> Result<(), AudioPlayerError> {
-        self.stop_internal();
-
-        let path = &song.file.path;
-        let file =
-            std::fs::File::open(path).map_err(|e| AudioPlayerError::FileError(e.to_string()))?;
-        let reader = BufReader::new(file);
-
-        let source =
-            Decoder::new(reader).map_err(|e| AudioPlayerError::DecodeError(e.to_string()))?;
-
-        let sink = Sink::try_new(&self.stream_handle)
-            .map_err(|e| AudioPlayerError::SinkError(e.to_string()))?;
-
-        sink.set_volume(self.volume);
-        sink.append(source);
-
-        self.sink = Some(sink);
-        self.current_song = Some(song.clone());
-        self.state = PlaybackState::Playing;
-        self.playback_started_at = Some(Instant::now());
-        self.paused_position = Duration::ZERO;
-
-        cx.emit(AudioPlayerEvent::SongChanged(Some(song)));
-        cx.emit(AudioPlayerEvent::StateChanged(PlaybackState::Playing));
-        cx.notify();
+        let result = self.engine.play_song(song);
+        self.emit_events(cx);
+        result
+    }
 
-        Ok(())
+    /// Load `song` paused at `position`, ready to resume where it was left
+    pub fn load_song(
+        &mut self,
+        song: Song,
+        position: Duration,
+        cx: &mut Context<Self>,
+    ) -> Result<(), AudioPlayerError> {
+        let result = self.engine.load_song(song, position);
+        self.emit_events(cx);
+        result
     }
 
     pub fn play(&mut self, cx: &mut Context<Self>) {
-        if let Some(sink) = &self.sink {
-            if self.state == PlaybackState::Paused {
-                sink.play();
-                self.state = PlaybackState::Playing;
-                self.playback_started_at = Some(Instant::now());
-                cx.emit(AudioPlayerEvent::StateChanged(PlaybackState::Playing));
-                cx.notify();
-            }
-        }
+        self.engine.play();
+        self.emit_events(cx);
     }
 
     pub fn pause(&mut self, cx: &mut Context<Self>) {
-        if let Some(sink) = &self.sink {
-            if self.state == PlaybackState::Playing {
-                sink.pause();
-                self.paused_position = self.position();
-                self.playback_started_at = None;
-                self.state = PlaybackState::Paused;
-                cx.emit(AudioPlayerEvent::StateChanged(PlaybackState::Paused));
-                cx.notify();
-            }
-        }
+        self.engine.pause();
+        self.emit_events(cx);
     }
 
     pub fn toggle_playback(&mut self, cx: &mut Context<Self>) {
-        match self.state {
-            PlaybackState::Playing => self.pause(cx),
-            PlaybackState::Paused => self.play(cx),
-            PlaybackState::Stopped => {}
-        }
+        self.engine.toggle_playback();
+        self.emit_events(cx);
     }
 
     pub fn stop(&mut self, cx: &mut Context<Self>) {
-        self.stop_internal();
-        self.current_song = None;
-        self.state = PlaybackState::Stopped;
-        cx.emit(AudioPlayerEvent::SongChanged(None));
-        cx.emit(AudioPlayerEvent::StateChanged(PlaybackState::Stopped));
-        cx.notify();
-    }
-
-    fn stop_internal(&mut self) {
-        if let Some(sink) = self.sink.take() {
-            sink.stop();
-        }
-        self.playback_started_at = None;
-        self.paused_position = Duration::ZERO;
+        self.engine.stop();
+        self.emit_events(cx);
     }
 
     pub fn set_volume(&mut self, volume: f32, cx: &mut Context<Self>) {
-        self.volume = volume.clamp(0.0, 1.0);
-        if let Some(sink) = &self.sink {
-            sink.set_volume(self.volume);
-        }
+        self.engine.set_volume(volume);
         cx.notify();
     }
 
     pub fn seek_to(&mut self, position: Duration, cx: &mut Context<Self>) {
-        if let Some(sink) = &self.sink {
-            if sink.try_seek(position).is_ok() {
-                self.paused_position = position;
-                if self.state == PlaybackState::Playing {
-                    self.playback_started_at = Some(Instant::now());
-                }
-                cx.notify();
-            }
-        }
+        self.engine.seek_to(position);
+        cx.notify();
     }
 
     pub fn seek_by(&mut self, delta: Duration, forward: bool, cx: &mut Context<Self>) {
-        let current_position = self.position();
-        let new_position = if forward {
-            current_position.saturating_add(delta)
-        } else {
-            current_position.saturating_sub(delta)
-        };
-        self.seek_to(new_position, cx);
+        self.engine.seek_by(delta, forward);
+        cx.notify();
     }
 
     pub fn is_finished(&self) -> bool {
-        self.sink.as_ref().is_some_and(|s| s.empty())
+        self.engine.is_finished()
     }
 
     pub fn check_and_handle_finished(&mut self, cx: &mut Context<Self>) -> bool {
-        if self.state == PlaybackState::Playing && self.is_finished() {
-            self.stop_internal();
-            self.state = PlaybackState::Stopped;
-            cx.emit(AudioPlayerEvent::PlaybackFinished);
-            cx.notify();
-            true
-        } else {
-            false
+        let finished = self.engine.check_and_handle_finished();
+        if finished {
+            self.emit_events(cx);
         }
+        finished
     }
-}
-
-#[derive(Debug, Clone)]
-pub enum AudioPlayerError {
-    OutputStreamError(String),
-    FileError(String),
-    DecodeError(String),
-    SinkError(String),
-}
 
-impl std::fmt::Display for AudioPlayerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AudioPlayerError::OutputStreamError(e) => write!(f, "Output stream error: {}", e),
-            AudioPlayerError::FileError(e) => write!(f, "File error: {}", e),
-            AudioPlayerError::DecodeError(e) => write!(f, "Decode error: {}", e),
-            AudioPlayerError::SinkError(e) => write!(f, "Sink error: {}", e),
+    fn emit_events(&mut self, cx: &mut Context<Self>) {
+        let mut emitted = false;
+        for event in self.events.try_iter() {
+            cx.emit(event);
+            emitted = true;
+        }
+        if emitted {
+            cx.notify();
         }
     }
 }
-
-impl std::error::Error for AudioPlayerError {}
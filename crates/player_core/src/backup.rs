@@ -0,0 +1,254 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::{load_history, PlayRecord};
+use crate::problems::{load_problem_log, ProblemFile};
+use crate::roots::{load_library_roots, LibraryRoot};
+use crate::settings::Settings;
+use crate::sqlite::SqliteStorage;
+use crate::storage::{
+    load_library, AudiobookEntry, Paths, SongEntry, StorageError, MANIFEST_SCHEMA_VERSION,
+};
+
+/// How many backups are kept. Older ones are deleted as new ones are made.
+pub const KEPT_BACKUPS: usize = 10;
+
+/// Saves back up the library at most this often, so a burst of saves doesn't
+/// rotate out the state from before a bad change
+pub const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// ============================================================================
+// Backups
+// ============================================================================
+
+/// A copy of the library files from one point in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    /// Folder holding the copied files, named after when the backup was made
+    pub path: PathBuf,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+}
+
+impl Backup {
+    /// Names of the library files in the backup
+    pub fn files(&self) -> Vec<&'static str> {
+        LIBRARY_FILES
+            .iter()
+            .copied()
+            .filter(|name| self.path.join(name).exists())
+            .collect()
+    }
+
+    /// The settings in the backup, or `None` if it has no settings file, in which
+    /// case restoring it leaves the defaults
+    pub fn settings(&self) -> Result<Option<Settings>, StorageError> {
+        match fs::read_to_string(self.path.join("settings.json")) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// The files that make up the library state, by the name they're backed up under
+const LIBRARY_FILES: &[&str] = &[
+    "library.jsonl",
+    "library.sqlite3",
+    "song-fingerprints.jsonl",
+    "roots.jsonl",
+    "problems.jsonl",
+    "settings.json",
+    "history.jsonl",
+];
+
+fn library_file(name: &str, paths: &Paths) -> PathBuf {
+    match name {
+        "library.jsonl" => paths.manifest(),
+        "library.sqlite3" => paths.database(),
+        "song-fingerprints.jsonl" => paths.song_fingerprints(),
+        "roots.jsonl" => paths.library_roots(),
+        "problems.jsonl" => paths.problem_log(),
+        "settings.json" => paths.settings.clone(),
+        "history.jsonl" => paths.listening_history(),
+        _ => unreachable!("not a library file: {}", name),
+    }
+}
+
+/// Copy the library files into a new backup, then delete backups beyond
+/// `KEPT_BACKUPS`. Returns `None` if there is no library to back up yet.
+pub fn back_up(paths: &Paths) -> Result<Option<Backup>, StorageError> {
+    let backup = make_backup(paths)?;
+    prune_backups(paths)?;
+    Ok(backup)
+}
+
+/// Back up the library files, unless the newest backup was made less than
+/// `BACKUP_INTERVAL` ago. Returns `None` if no backup was made.
+pub fn back_up_if_due(paths: &Paths) -> Result<Option<Backup>, StorageError> {
+    let interval = BACKUP_INTERVAL.as_millis() as u64;
+    let newest = list_backups(paths)?.into_iter().next();
+    if newest.is_some_and(|backup| unix_millis().saturating_sub(backup.created_at) < interval) {
+        return Ok(None);
+    }
+    back_up(paths)
+}
+
+fn make_backup(paths: &Paths) -> Result<Option<Backup>, StorageError> {
+    if !paths.manifest().exists() && !paths.database().exists() {
+        return Ok(None);
+    }
+
+    let mut created_at = unix_millis();
+    while paths.backups().join(created_at.to_string()).exists() {
+        created_at += 1;
+    }
+
+    // Copy into a temporary folder so a partial backup is never listed
+    let temp_path = paths.backups().join(format!("{}.tmp", created_at));
+    fs::create_dir_all(&temp_path)?;
+    for name in LIBRARY_FILES {
+        let source = library_file(name, paths);
+        if !source.exists() {
+            continue;
+        }
+        if source == paths.database() {
+            SqliteStorage::open_at(&source)?.copy_to(&temp_path.join(name))?;
+        } else {
+            fs::copy(&source, temp_path.join(name))?;
+        }
+    }
+
+    let path = paths.backups().join(created_at.to_string());
+    fs::rename(&temp_path, &path)?;
+    Ok(Some(Backup { path, created_at }))
+}
+
+fn prune_backups(paths: &Paths) -> Result<(), StorageError> {
+    for backup in list_backups(paths)?.into_iter().skip(KEPT_BACKUPS) {
+        fs::remove_dir_all(&backup.path)?;
+    }
+    Ok(())
+}
+
+/// Every backup, newest first
+pub fn list_backups(paths: &Paths) -> Result<Vec<Backup>, StorageError> {
+    let entries = match fs::read_dir(paths.backups()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut backups: Vec<Backup> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let created_at = entry.file_name().to_str()?.parse().ok()?;
+            Some(Backup {
+                path: entry.path(),
+                created_at,
+            })
+        })
+        .collect();
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.created_at));
+    Ok(backups)
+}
+
+/// Put the library files back as they were in `backup`. Files the backup doesn't
+/// have are removed. The current files are backed up first, so a restore can be
+/// undone by restoring that backup.
+///
+/// Reload the library and settings afterwards.
+pub fn restore_backup(backup: &Backup, paths: &Paths) -> Result<(), StorageError> {
+    make_backup(paths)?;
+
+    for name in LIBRARY_FILES {
+        let source = backup.path.join(name);
+        let destination = library_file(name, paths);
+        if source.exists() {
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let temp_path = destination.with_extension("restore.tmp");
+            fs::copy(&source, &temp_path)?;
+            fs::rename(&temp_path, &destination)?;
+        } else {
+            match fs::remove_file(&destination) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+    }
+
+    prune_backups(paths)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// ============================================================================
+// Export
+// ============================================================================
+
+/// The whole library state in one JSON document, independent of the storage backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryExport {
+    /// The manifest schema version the song and audiobook entries are written in
+    pub schema_version: u32,
+    /// Milliseconds since the Unix epoch
+    pub exported_at: u64,
+    pub settings: Option<Settings>,
+    pub roots: Vec<LibraryRoot>,
+    pub problems: Vec<ProblemFile>,
+    pub songs: Vec<SongEntry>,
+    pub audiobooks: Vec<AudiobookEntry>,
+    #[serde(default)]
+    pub history: Vec<PlayRecord>,
+}
+
+/// Write the library, its folders, problem log, listening history and settings
+/// to a single file. The remote control token and MPD password are left out.
+/// Returns what was written.
+pub fn export_library(paths: &Paths, destination: &Path) -> Result<LibraryExport, StorageError> {
+    let library = load_library(paths)?;
+    let settings = match fs::read_to_string(&paths.settings) {
+        Ok(contents) => Some(serde_json::from_str::<Settings>(&contents)?.without_secrets()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut songs: Vec<SongEntry> = library.songs.values().map(SongEntry::from_song).collect();
+    songs.sort_by_key(|song| song.id);
+    let mut audiobooks: Vec<AudiobookEntry> = library
+        .audiobooks
+        .values()
+        .map(AudiobookEntry::from_audiobook)
+        .collect();
+    audiobooks.sort_by_key(|audiobook| audiobook.id);
+
+    let export = LibraryExport {
+        schema_version: MANIFEST_SCHEMA_VERSION,
+        exported_at: unix_millis(),
+        settings,
+        roots: load_library_roots(paths)?,
+        problems: load_problem_log(paths)?,
+        songs,
+        audiobooks,
+        history: load_history(paths)?,
+    };
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = destination.with_extension("tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(&export)?)?;
+    fs::rename(&temp_path, destination)?;
+    Ok(export)
+}
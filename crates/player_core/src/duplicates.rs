@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioFormat;
+use crate::fingerprint::{Fingerprint, FINGERPRINT_MATCH_THRESHOLD};
+use crate::import::Metadata;
+use crate::library::{Library, SongId};
+use crate::roots::FileStamp;
+use crate::storage::{Paths, StorageError};
+
+/// How close two durations must be for songs with matching artist and title to count as duplicates
+pub const DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+
+// ============================================================================
+// Duplicate Types
+// ============================================================================
+
+/// What to do when an imported file duplicates a song already in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Leave the library untouched and archive the incoming file
+    #[default]
+    Skip,
+    /// Replace the library copy if the incoming file has a higher bitrate, otherwise skip.
+    /// Files of a different format are always skipped, since the library copy keeps its path.
+    ReplaceIfBetterBitrate,
+    /// Import the file as a separate song
+    KeepBoth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateMatch {
+    /// The audio data is byte-for-byte identical
+    ContentHash,
+    /// The acoustic fingerprints match, e.g. the same recording in another encoding
+    Fingerprint,
+    /// Artist and title match and the durations are within `DURATION_TOLERANCE`
+    Metadata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Duplicate {
+    pub song_id: SongId,
+    pub matched_by: DuplicateMatch,
+}
+
+// ============================================================================
+// Content Hashing
+// ============================================================================
+
+/// Hash the audio data of a file.
+///
+/// For MP3 files the ID3v2 header and ID3v1 trailer are excluded, so the hash
+/// stays the same when tags are edited or a duration is written back to the file.
+pub fn content_hash(path: &Path, format: AudioFormat) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let (start, end) = match format {
+        AudioFormat::Mp3 => mp3_audio_range(&mut file, len)?,
+        AudioFormat::M4b => (0, len),
+    };
+
+    file.seek(SeekFrom::Start(start))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file.take(end - start), &mut hasher)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Find the byte range of an MP3 file that lies between its ID3v2 and ID3v1 tags
+fn mp3_audio_range(file: &mut File, len: u64) -> io::Result<(u64, u64)> {
+    let mut start = 0;
+    let mut header = [0u8; 10];
+    if len >= 10 {
+        file.read_exact(&mut header)?;
+        if &header[0..3] == b"ID3" {
+            let size = header[6..10]
+                .iter()
+                .fold(0u64, |size, byte| (size << 7) | (*byte & 0x7f) as u64);
+            let has_footer = header[5] & 0x10 != 0;
+            start = (10 + size + if has_footer { 10 } else { 0 }).min(len);
+        }
+    }
+
+    let mut end = len;
+    if len >= start + 128 {
+        let mut trailer = [0u8; 3];
+        file.seek(SeekFrom::Start(len - 128))?;
+        file.read_exact(&mut trailer)?;
+        if &trailer == b"TAG" {
+            end = len - 128;
+        }
+    }
+
+    Ok((start, end))
+}
+
+// ============================================================================
+// Backfilling
+// ============================================================================
+
+/// Songs whose content hash or fingerprint couldn't be computed, with the
+/// stamp their file had then (`None` if it was missing). Backfills only retry
+/// a song once its file changes.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackfillFailures {
+    pub content_hash: HashMap<u64, Option<FileStamp>>,
+    pub fingerprint: HashMap<u64, Option<FileStamp>>,
+}
+
+impl BackfillFailures {
+    /// The recorded failures, or none if none were saved yet
+    pub fn load(paths: &Paths) -> Result<Self, StorageError> {
+        match fs::read_to_string(paths.backfill_failures()) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, paths: &Paths) -> Result<(), StorageError> {
+        let path = paths.backfill_failures();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Move failures recorded against old song ids to their new ids.
+    /// Returns whether anything changed.
+    pub fn rekey(&mut self, remap: &HashMap<SongId, SongId>) -> bool {
+        let mut changed = false;
+        for failures in [&mut self.content_hash, &mut self.fingerprint] {
+            for (old_id, new_id) in remap {
+                if let Some(stamp) = failures.remove(&old_id.0) {
+                    failures.insert(new_id.0, stamp);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Whether a song failed before and its file hasn't changed since
+pub(crate) fn failed_before(
+    failures: &HashMap<u64, Option<FileStamp>>,
+    id: SongId,
+    path: &Path,
+) -> bool {
+    failures
+        .get(&id.0)
+        .is_some_and(|stamp| *stamp == FileStamp::read(path).ok())
+}
+
+/// Compute content hashes for library songs that don't have one yet,
+/// so that files imported before hashing existed can still be matched.
+/// Songs that fail are recorded in `failures` and skipped until their file changes.
+pub fn backfill_content_hashes(library: &mut Library, failures: &mut BackfillFailures) {
+    let results: Vec<(SongId, Result<String, Option<FileStamp>>)> = library
+        .songs
+        .values()
+        .filter(|song| song.content_hash.is_none())
+        .filter(|song| !failed_before(&failures.content_hash, song.id, &song.file.path))
+        .map(|song| (song.id, song.file.path.clone(), song.file.format))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(id, path, format)| {
+            let hash = content_hash(&path, format).map_err(|_| FileStamp::read(&path).ok());
+            (id, hash)
+        })
+        .collect();
+
+    for (id, result) in results {
+        match result {
+            Ok(hash) => {
+                failures.content_hash.remove(&id.0);
+                if let Some(song) = library.songs.get_mut(&id) {
+                    song.content_hash = Some(hash);
+                }
+            }
+            Err(stamp) => {
+                failures.content_hash.insert(id.0, stamp);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Duplicate Detection
+// ============================================================================
+
+/// Find a song in the library that duplicates an incoming file, preferring exact
+/// content matches, then acoustic matches, then matching tags. Among acoustic
+/// matches the most similar wins, and among tag matches the closest in duration.
+pub fn find_duplicate(
+    library: &Library,
+    content_hash: Option<&str>,
+    fingerprint: Option<&Fingerprint>,
+    metadata: &Metadata,
+) -> Option<Duplicate> {
+    if let Some(hash) = content_hash {
+        let by_hash = library
+            .songs
+            .values()
+            .find(|song| song.content_hash.as_deref() == Some(hash));
+        if let Some(song) = by_hash {
+            return Some(Duplicate {
+                song_id: song.id,
+                matched_by: DuplicateMatch::ContentHash,
+            });
+        }
+    }
+
+    if let (Some(fingerprint), Some(duration)) = (fingerprint, metadata.duration) {
+        let by_fingerprint = library
+            .songs
+            .values()
+            .filter(|song| song.duration.abs_diff(duration) <= DURATION_TOLERANCE)
+            .filter_map(|song| Some((song, song.fingerprint.as_ref()?.similarity(fingerprint))))
+            .filter(|(_, similarity)| *similarity >= FINGERPRINT_MATCH_THRESHOLD)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((song, _)) = by_fingerprint {
+            return Some(Duplicate {
+                song_id: song.id,
+                matched_by: DuplicateMatch::Fingerprint,
+            });
+        }
+    }
+
+    let artist = metadata
+        .artist
+        .as_deref()
+        .or(metadata.album_artist.as_deref())
+        .map(normalize)?;
+    let title = metadata.title.as_deref().map(normalize)?;
+    let duration = metadata.duration?;
+
+    library
+        .songs
+        .values()
+        .filter(|song| {
+            song.artist.as_deref().map(normalize).as_ref() == Some(&artist)
+                && normalize(&song.title) == title
+                && song.duration.abs_diff(duration) <= DURATION_TOLERANCE
+        })
+        .min_by_key(|song| song.duration.abs_diff(duration))
+        .map(|song| Duplicate {
+            song_id: song.id,
+            matched_by: DuplicateMatch::Metadata,
+        })
+}
+
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase()
+}
+
+/// Approximate bitrate in bits per second from the file size and duration
+pub fn estimated_bitrate(path: &Path, duration: Duration) -> Option<u64> {
+    let size = std::fs::metadata(path).ok()?.len();
+    let secs = duration.as_secs_f64();
+    if secs <= 0.0 {
+        return None;
+    }
+    Some((size as f64 * 8.0 / secs) as u64)
+}
@@ -0,0 +1,390 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use id3::frame::Popularimeter;
+use id3::{Tag, TagLike};
+
+use crate::audio::{AudioFile, AudioFormat};
+use crate::import::{generate_library_path, Metadata};
+use crate::library::{Library, Song, SongId, MAX_RATING};
+use crate::mp4::write_mp4_metadata;
+use crate::storage::Paths;
+
+// ============================================================================
+// Error Types
+// ============================================================================
+
+#[derive(Debug)]
+pub enum EditError {
+    SongNotFound(SongId),
+    UnsupportedFormat(AudioFormat),
+    DestinationExists(PathBuf),
+    IoError(std::io::Error),
+    Id3Error(id3::Error),
+}
+
+impl From<std::io::Error> for EditError {
+    fn from(e: std::io::Error) -> Self {
+        EditError::IoError(e)
+    }
+}
+
+impl From<id3::Error> for EditError {
+    fn from(e: id3::Error) -> Self {
+        EditError::Id3Error(e)
+    }
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::SongNotFound(id) => write!(f, "Song not found: {}", id.0),
+            EditError::UnsupportedFormat(format) => {
+                write!(f, "Writing tags is not supported for {:?} files", format)
+            }
+            EditError::DestinationExists(path) => {
+                write!(f, "A different file already exists at {:?}", path)
+            }
+            EditError::IoError(e) => write!(f, "IO error: {}", e),
+            EditError::Id3Error(e) => write!(f, "ID3 error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+// ============================================================================
+// Edit Types
+// ============================================================================
+
+/// A set of metadata changes to apply to one or more songs.
+///
+/// Each field is `None` to leave the value untouched. For optional tags,
+/// `Some(None)` clears the value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataEdit {
+    pub title: Option<String>,
+    pub artist: Option<Option<String>>,
+    pub album: Option<Option<String>>,
+    pub track_number: Option<Option<u32>>,
+}
+
+impl MetadataEdit {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.album.is_none()
+            && self.track_number.is_none()
+    }
+
+    /// Apply the edit to an in-memory song
+    pub fn apply_to(&self, song: &mut Song) {
+        if let Some(title) = &self.title {
+            song.title = title.clone();
+        }
+        if let Some(artist) = &self.artist {
+            song.artist = artist.clone();
+        }
+        if let Some(album) = &self.album {
+            song.album = album.clone();
+        }
+        if let Some(track_number) = self.track_number {
+            song.track_number = track_number;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EditResult {
+    pub song: Song,
+    pub previous_path: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct EditFailure {
+    pub id: SongId,
+    pub error: EditError,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchEditResult {
+    pub edited: Vec<EditResult>,
+    pub failed: Vec<EditFailure>,
+}
+
+// ============================================================================
+// Tag Writer Trait
+// ============================================================================
+
+pub trait TagWriter {
+    type Error;
+
+    fn write(file: &AudioFile, edit: &MetadataEdit) -> Result<(), Self::Error>;
+}
+
+// ============================================================================
+// MP3 Tag Writer
+// ============================================================================
+
+pub struct Mp3TagWriter;
+
+impl TagWriter for Mp3TagWriter {
+    type Error = EditError;
+
+    fn write(file: &AudioFile, edit: &MetadataEdit) -> Result<(), Self::Error> {
+        let mut tag = Tag::read_from_path(&file.path).unwrap_or_else(|_| Tag::new());
+
+        if let Some(title) = &edit.title {
+            tag.set_title(title.as_str());
+        }
+        match &edit.artist {
+            Some(Some(artist)) => tag.set_artist(artist.as_str()),
+            Some(None) => tag.remove_artist(),
+            None => {}
+        }
+        match &edit.album {
+            Some(Some(album)) => tag.set_album(album.as_str()),
+            Some(None) => tag.remove_album(),
+            None => {}
+        }
+        match edit.track_number {
+            Some(Some(track)) => tag.set_track(track),
+            Some(None) => tag.remove_track(),
+            None => {}
+        }
+
+        tag.write_to_path(&file.path, id3::Version::Id3v24)?;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// M4B Tag Writer
+// ============================================================================
+
+pub struct M4bTagWriter;
+
+impl TagWriter for M4bTagWriter {
+    type Error = EditError;
+
+    fn write(file: &AudioFile, edit: &MetadataEdit) -> Result<(), Self::Error> {
+        write_mp4_metadata(&file.path, edit)?;
+        Ok(())
+    }
+}
+
+/// Write the edited tags back to the audio file, dispatching on its format
+pub fn write_tags(file: &AudioFile, edit: &MetadataEdit) -> Result<(), EditError> {
+    match file.format {
+        AudioFormat::Mp3 => Mp3TagWriter::write(file, edit),
+        AudioFormat::M4b => M4bTagWriter::write(file, edit),
+    }
+}
+
+// ============================================================================
+// Editing Songs
+// ============================================================================
+
+/// Edit a single song's metadata:
+/// 1. Check the refile destination is free, so a failed move can't leave the
+///    file's tags out of step with the library
+/// 2. Write the tags back to the audio file
+/// 3. Optionally move the file to the library path for its new metadata. Only
+///    files in the Music folder are moved; songs indexed in place under a
+///    `LibraryRoot` stay where they are.
+/// 4. Update the song in the library
+///
+/// The manifest is not saved.
+pub fn edit_song(
+    library: &mut Library,
+    id: SongId,
+    edit: &MetadataEdit,
+    refile: bool,
+    paths: &Paths,
+) -> Result<EditResult, EditError> {
+    let song = library.songs.get(&id).ok_or(EditError::SongNotFound(id))?;
+    let previous_path = song.file.path.clone();
+
+    let mut edited = song.clone();
+    edit.apply_to(&mut edited);
+
+    let destination = (refile && previous_path.starts_with(&paths.music))
+        .then(|| generate_library_path(&song_metadata(&edited), edited.file.format, paths))
+        .filter(|destination| *destination != previous_path);
+    if let Some(destination) = &destination {
+        if destination.exists() {
+            return Err(EditError::DestinationExists(destination.clone()));
+        }
+    }
+
+    write_tags(&edited.file, edit)?;
+
+    if let Some(destination) = destination {
+        move_song_file(&previous_path, &destination, &paths.music)?;
+        edited.file.path = destination;
+    }
+
+    library.songs.insert(id, edited.clone());
+
+    Ok(EditResult {
+        song: edited,
+        previous_path,
+    })
+}
+
+/// Apply the same edit to every song in `ids`. The manifest is not saved, so a
+/// caller editing a copy of the library can apply the edited songs to the
+/// library it saves.
+pub fn edit_songs(
+    library: &mut Library,
+    ids: &[SongId],
+    edit: &MetadataEdit,
+    refile: bool,
+    paths: &Paths,
+) -> BatchEditResult {
+    let mut result = BatchEditResult::default();
+
+    for &id in ids {
+        match edit_song(library, id, edit, refile, paths) {
+            Ok(edited) => result.edited.push(edited),
+            Err(error) => result.failed.push(EditFailure { id, error }),
+        }
+    }
+
+    result
+}
+
+fn song_metadata(song: &Song) -> Metadata {
+    Metadata {
+        title: Some(song.title.clone()),
+        artist: song.artist.clone(),
+        album: song.album.clone(),
+        track_number: song.track_number,
+        duration: Some(song.duration),
+        ..Default::default()
+    }
+}
+
+fn move_song_file(from: &Path, to: &Path, music_dir: &Path) -> Result<(), EditError> {
+    if to.exists() {
+        return Err(EditError::DestinationExists(to.to_path_buf()));
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)?;
+
+    remove_empty_parents(from, music_dir);
+    Ok(())
+}
+
+/// Remove the now-empty Artist/Album directories left behind after moving a file
+fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut current = path.parent();
+    while let Some(dir) = current {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+        current = dir.parent();
+    }
+}
+
+// ============================================================================
+// Ratings
+// ============================================================================
+
+/// Who the POPM frames the player writes are attributed to
+const POPM_USER: &str = "player";
+
+/// A change to the rating and loved flag of one or more songs.
+/// Each field is `None` to leave the value untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RatingEdit {
+    pub rating: Option<u8>,
+    pub loved: Option<bool>,
+}
+
+impl RatingEdit {
+    pub fn apply_to(&self, song: &mut Song) {
+        if let Some(rating) = self.rating {
+            song.rating = rating.min(MAX_RATING);
+        }
+        if let Some(loved) = self.loved {
+            song.loved = loved;
+        }
+    }
+}
+
+/// Write a star rating to the file's POPM frame. The loved flag has no
+/// standard frame, so it stays in the library only.
+pub fn write_rating(file: &AudioFile, rating: u8) -> Result<(), EditError> {
+    if file.format != AudioFormat::Mp3 {
+        return Err(EditError::UnsupportedFormat(file.format));
+    }
+
+    let mut tag = Tag::read_from_path(&file.path).unwrap_or_else(|_| Tag::new());
+    let counter = tag
+        .frames()
+        .filter_map(|frame| frame.content().popularimeter())
+        .find(|popm| popm.user == POPM_USER)
+        .map_or(0, |popm| popm.counter);
+    tag.add_frame(Popularimeter {
+        user: POPM_USER.to_string(),
+        rating: popm_rating(rating),
+        counter,
+    });
+    tag.write_to_path(&file.path, id3::Version::Id3v24)?;
+    Ok(())
+}
+
+/// Stars on the 1–255 POPM scale most taggers read, with 0 for unrated
+pub fn popm_rating(stars: u8) -> u8 {
+    match stars {
+        0 => 0,
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+/// Apply the same rating change to every song in `ids`. With `write_to_tags`, the
+/// star rating is also written to each file first; a song whose file can't be
+/// written is left as it was. The manifest is not saved, as with `edit_songs`.
+pub fn rate_songs(
+    library: &mut Library,
+    ids: &[SongId],
+    edit: RatingEdit,
+    write_to_tags: bool,
+) -> BatchEditResult {
+    let mut result = BatchEditResult::default();
+
+    for &id in ids {
+        let Some(song) = library.songs.get(&id) else {
+            result.failed.push(EditFailure {
+                id,
+                error: EditError::SongNotFound(id),
+            });
+            continue;
+        };
+
+        let mut rated = song.clone();
+        edit.apply_to(&mut rated);
+        if write_to_tags && rated.rating != song.rating {
+            if let Err(error) = write_rating(&rated.file, rated.rating) {
+                result.failed.push(EditFailure { id, error });
+                continue;
+            }
+        }
+
+        library.songs.insert(id, rated.clone());
+        result.edited.push(EditResult {
+            previous_path: rated.file.path.clone(),
+            song: rated,
+        });
+    }
+
+    result
+}
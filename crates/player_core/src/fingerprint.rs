@@ -0,0 +1,385 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use rayon::prelude::*;
+use rodio::{Decoder, Source};
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use serde::{Deserialize, Serialize};
+
+use crate::duplicates::{failed_before, BackfillFailures, DURATION_TOLERANCE};
+use crate::import::Metadata;
+use crate::library::{Library, SongId};
+use crate::roots::FileStamp;
+
+/// Audio is downmixed and resampled to this rate before fingerprinting
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const HOP_SIZE: usize = FRAME_SIZE / 3;
+/// Only the start of each file is fingerprinted
+const MAX_LENGTH: Duration = Duration::from_secs(120);
+
+const MIN_FREQUENCY: f32 = 28.0;
+const MAX_FREQUENCY: f32 = 3520.0;
+
+/// How far two fingerprints may be shifted against each other when comparing, in frames
+const MAX_ALIGNMENT_OFFSET: isize = 8;
+/// The fewest overlapping frames needed for a meaningful comparison
+const MIN_OVERLAP: usize = 16;
+
+/// Similarity at or above which two fingerprints are considered the same recording.
+/// Unrelated audio scores around 0.5.
+pub const FINGERPRINT_MATCH_THRESHOLD: f32 = 0.8;
+
+// ============================================================================
+// Fingerprint
+// ============================================================================
+
+/// A Chromaprint-style acoustic fingerprint: one 32-bit sub-fingerprint per
+/// audio frame, derived from how the 12 pitch-class energies compare within
+/// a frame and against the previous frame.
+///
+/// Fingerprints survive re-encoding, so they identify the same recording
+/// across formats and bitrates where content hashes can't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint(pub Vec<u32>);
+
+impl Fingerprint {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|item| format!("{:08x}", item)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.len().is_multiple_of(8) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(8)
+            .map(|i| u32::from_str_radix(hex.get(i..i + 8)?, 16).ok())
+            .collect::<Option<Vec<_>>>()
+            .map(Fingerprint)
+    }
+
+    /// Fraction of matching bits at the best alignment, from 0.0 to 1.0
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        let (a, b) = (&self.0, &other.0);
+        let mut best = 0.0f32;
+
+        for offset in -MAX_ALIGNMENT_OFFSET..=MAX_ALIGNMENT_OFFSET {
+            let (a_start, b_start) = if offset >= 0 {
+                (offset as usize, 0)
+            } else {
+                (0, (-offset) as usize)
+            };
+            if a_start >= a.len() || b_start >= b.len() {
+                continue;
+            }
+
+            let overlap = (a.len() - a_start).min(b.len() - b_start);
+            if overlap < MIN_OVERLAP {
+                continue;
+            }
+
+            let differing_bits: u32 = a[a_start..a_start + overlap]
+                .iter()
+                .zip(&b[b_start..b_start + overlap])
+                .map(|(x, y)| (x ^ y).count_ones())
+                .sum();
+            let similarity = 1.0 - differing_bits as f32 / (overlap * 32) as f32;
+            best = best.max(similarity);
+        }
+
+        best
+    }
+
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        self.similarity(other) >= FINGERPRINT_MATCH_THRESHOLD
+    }
+}
+
+impl Serialize for Fingerprint {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_hex().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Fingerprint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Fingerprint::from_hex(&hex).ok_or_else(|| serde::de::Error::custom("invalid fingerprint"))
+    }
+}
+
+// ============================================================================
+// Computing Fingerprints
+// ============================================================================
+
+/// Decode the start of an audio file and fingerprint it
+pub fn compute_fingerprint(path: &Path) -> Option<Fingerprint> {
+    let file = File::open(path).ok()?;
+    let decoder = Decoder::new(BufReader::new(file)).ok()?;
+
+    let sample_rate = decoder.sample_rate();
+    let channels = decoder.channels() as usize;
+    if sample_rate == 0 || channels == 0 {
+        return None;
+    }
+
+    let max_samples = (MAX_LENGTH.as_secs() as usize) * sample_rate as usize * channels;
+    let interleaved: Vec<i16> = decoder.take(max_samples).collect();
+
+    let mono: Vec<f32> = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().map(|&s| s as f32).sum::<f32>() / channels as f32)
+        .collect();
+
+    let fingerprint = fingerprint_samples(&mono, sample_rate);
+    (!fingerprint.is_empty()).then_some(fingerprint)
+}
+
+/// Fingerprint mono samples at the given sample rate
+pub fn fingerprint_samples(samples: &[f32], sample_rate: u32) -> Fingerprint {
+    let samples = resample(samples, sample_rate, SAMPLE_RATE);
+    if samples.len() < FRAME_SIZE {
+        return Fingerprint(Vec::new());
+    }
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(FRAME_SIZE);
+    let window: Vec<f32> = (0..FRAME_SIZE)
+        .map(|i| {
+            0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (FRAME_SIZE - 1) as f32).cos()
+        })
+        .collect();
+    let pitch_classes = bin_pitch_classes();
+
+    let mut buffer = vec![Complex::new(0.0f32, 0.0); FRAME_SIZE];
+    let mut previous: Option<[f32; 12]> = None;
+    let mut items = Vec::new();
+
+    for start in (0..=samples.len() - FRAME_SIZE).step_by(HOP_SIZE) {
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+
+        let mut chroma = [0.0f32; 12];
+        for (bin, pitch_class) in &pitch_classes {
+            chroma[*pitch_class] += buffer[*bin].norm_sqr();
+        }
+        let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            chroma.iter_mut().for_each(|c| *c /= norm);
+        }
+
+        if let Some(previous) = previous {
+            items.push(sub_fingerprint(&chroma, &previous));
+        }
+        previous = Some(chroma);
+    }
+
+    Fingerprint(items)
+}
+
+/// The FFT bins in the fingerprinted frequency range and the pitch class each one belongs to
+fn bin_pitch_classes() -> Vec<(usize, usize)> {
+    (1..FRAME_SIZE / 2)
+        .filter_map(|bin| {
+            let frequency = bin as f32 * SAMPLE_RATE as f32 / FRAME_SIZE as f32;
+            if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                return None;
+            }
+            let note = 12.0 * (frequency / 440.0).log2() + 69.0;
+            Some((bin, (note.round() as i32).rem_euclid(12) as usize))
+        })
+        .collect()
+}
+
+fn sub_fingerprint(chroma: &[f32; 12], previous: &[f32; 12]) -> u32 {
+    let mut bits = 0u32;
+    for i in 0..12 {
+        // Shape of the frame: each pitch class against its neighbour
+        if chroma[i] > chroma[(i + 1) % 12] {
+            bits |= 1 << i;
+        }
+        // Movement over time: each pitch class against the previous frame
+        if chroma[i] > previous[i] {
+            bits |= 1 << (12 + i);
+        }
+    }
+    for i in 0..8 {
+        // Harmony: each pitch class against its minor third
+        if chroma[i] > chroma[(i + 3) % 12] {
+            bits |= 1 << (24 + i);
+        }
+    }
+    bits
+}
+
+/// Linear-interpolation resampling; good enough for the coarse spectrum fingerprints use
+fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = from_rate as f64 / to_rate as f64;
+    let output_len = (samples.len() as f64 / ratio) as usize;
+
+    (0..output_len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).copied().unwrap_or(current);
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+/// Compute fingerprints for library songs that don't have one yet. Songs that
+/// fail are recorded in `failures` and skipped until their file changes.
+pub fn backfill_fingerprints(library: &mut Library, failures: &mut BackfillFailures) {
+    let results: Vec<(SongId, Result<Fingerprint, Option<FileStamp>>)> = library
+        .songs
+        .values()
+        .filter(|song| song.fingerprint.is_none())
+        .filter(|song| !failed_before(&failures.fingerprint, song.id, &song.file.path))
+        .map(|song| (song.id, song.file.path.clone()))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .map(|(id, path)| {
+            let fingerprint = compute_fingerprint(&path).ok_or_else(|| FileStamp::read(&path).ok());
+            (id, fingerprint)
+        })
+        .collect();
+
+    for (id, result) in results {
+        match result {
+            Ok(fingerprint) => {
+                failures.fingerprint.remove(&id.0);
+                if let Some(song) = library.songs.get_mut(&id) {
+                    song.fingerprint = Some(fingerprint);
+                }
+            }
+            Err(stamp) => {
+                failures.fingerprint.insert(id.0, stamp);
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Metadata Lookup
+// ============================================================================
+
+/// Suggests metadata for a recording from its fingerprint.
+///
+/// Used at import time to fill in the tags of untagged files.
+pub trait MetadataLookup: Send + Sync {
+    fn lookup(&self, fingerprint: &Fingerprint, duration: Duration) -> Option<Metadata>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FingerprintRecord {
+    pub fingerprint: Fingerprint,
+    #[serde(with = "crate::storage::duration_serde")]
+    pub duration: Duration,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+}
+
+/// A local database of known fingerprints, stored as JSONL
+#[derive(Debug, Default)]
+pub struct FingerprintDatabase {
+    records: Vec<FingerprintRecord>,
+}
+
+impl FingerprintDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the database, skipping lines that fail to parse. A missing file is an empty database.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let reader = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Ok(record) = serde_json::from_str(line.trim()) {
+                records.push(record);
+            }
+        }
+
+        Ok(Self { records })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for record in &self.records {
+            writeln!(writer, "{}", serde_json::to_string(record)?)?;
+        }
+        writer.flush()
+    }
+
+    pub fn insert(&mut self, record: FingerprintRecord) {
+        self.records.push(record);
+    }
+
+    /// Add every fingerprinted, tagged song in the library
+    pub fn insert_library(&mut self, library: &Library) {
+        for song in library.songs.values() {
+            if let Some(fingerprint) = &song.fingerprint {
+                self.insert(FingerprintRecord {
+                    fingerprint: fingerprint.clone(),
+                    duration: song.duration,
+                    title: Some(song.title.clone()),
+                    artist: song.artist.clone(),
+                    album: song.album.clone(),
+                    track_number: song.track_number,
+                });
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+impl MetadataLookup for FingerprintDatabase {
+    fn lookup(&self, fingerprint: &Fingerprint, duration: Duration) -> Option<Metadata> {
+        self.records
+            .iter()
+            .filter(|record| record.duration.abs_diff(duration) <= DURATION_TOLERANCE)
+            .map(|record| (record, record.fingerprint.similarity(fingerprint)))
+            .filter(|(_, similarity)| *similarity >= FINGERPRINT_MATCH_THRESHOLD)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(record, _)| Metadata {
+                title: record.title.clone(),
+                artist: record.artist.clone(),
+                album: record.album.clone(),
+                track_number: record.track_number,
+                ..Default::default()
+            })
+    }
+}
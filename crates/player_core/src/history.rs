@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::library::{Library, Song, SongId};
+use crate::playback::PlaybackState;
+use crate::storage::{duration_serde, Paths, StorageError};
+
+// ============================================================================
+// Listening History
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayOutcome {
+    /// The song played through to its end
+    Completed,
+    /// Another song was started, or playback stopped, before the end
+    Skipped,
+}
+
+/// One play of a song, as recorded in the listening history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayRecord {
+    pub song_id: u64,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    /// Time spent playing, not counting pauses
+    #[serde(with = "duration_serde")]
+    pub listened: Duration,
+    pub outcome: PlayOutcome,
+}
+
+/// Add a play to the end of the listening history
+pub fn append_play(record: &PlayRecord, paths: &Paths) -> Result<(), StorageError> {
+    let path = paths.listening_history();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Every recorded play, oldest first. A line cut short by a crash is skipped.
+pub fn load_history(paths: &Paths) -> Result<Vec<PlayRecord>, StorageError> {
+    let path = paths.listening_history();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => eprintln!("Warning: Skipped history line: {}", e),
+        }
+    }
+    Ok(records)
+}
+
+/// Point plays of rekeyed songs at their new ids. The history is only
+/// rewritten if it refers to any of them.
+pub(crate) fn rekey_history(
+    remap: &HashMap<SongId, SongId>,
+    paths: &Paths,
+) -> Result<(), StorageError> {
+    let mut records = load_history(paths)?;
+    let mut changed = false;
+    for record in &mut records {
+        if let Some(new_id) = remap.get(&SongId(record.song_id)) {
+            record.song_id = new_id.0;
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(());
+    }
+
+    let path = paths.listening_history();
+    let temp_path = path.with_extension("jsonl.tmp");
+    let mut file = File::create(&temp_path)?;
+    for record in &records {
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+    }
+    file.sync_all()?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+// ============================================================================
+// Play Statistics
+// ============================================================================
+
+/// What the listening history says about one song
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayStats {
+    /// Plays that reached the end of the song
+    pub play_count: u32,
+    pub skip_count: u32,
+    /// When the song was last started, in seconds since the Unix epoch
+    pub last_played: Option<u64>,
+}
+
+impl PlayStats {
+    pub fn record(&mut self, record: &PlayRecord) {
+        match record.outcome {
+            PlayOutcome::Completed => self.play_count += 1,
+            PlayOutcome::Skipped => self.skip_count += 1,
+        }
+        self.last_played = self.last_played.max(Some(record.started_at));
+    }
+}
+
+impl Library {
+    /// Count a play towards its song's stats, if the song is in the library
+    pub fn record_play(&mut self, record: &PlayRecord) {
+        if let Some(song) = self.songs.get_mut(&SongId(record.song_id)) {
+            song.stats.record(record);
+        }
+    }
+
+    /// Replace every song's stats with those derived from `history`
+    pub fn apply_history(&mut self, history: &[PlayRecord]) {
+        for song in self.songs.values_mut() {
+            song.stats = PlayStats::default();
+        }
+        for record in history {
+            self.record_play(record);
+        }
+    }
+}
+
+// ============================================================================
+// Play Tracker
+// ============================================================================
+
+struct CurrentPlay {
+    song_id: SongId,
+    started_at: u64,
+    listened: Duration,
+    /// When playback last started or resumed, while playing
+    playing_since: Option<Instant>,
+}
+
+impl CurrentPlay {
+    fn finish(mut self, now: Instant, outcome: PlayOutcome) -> PlayRecord {
+        if let Some(since) = self.playing_since.take() {
+            self.listened += now.saturating_duration_since(since);
+        }
+        PlayRecord {
+            song_id: self.song_id.0,
+            started_at: self.started_at,
+            listened: self.listened,
+            outcome,
+        }
+    }
+}
+
+/// Turns the audio player's events into play records. Feed it each
+/// `AudioPlayerEvent` as it happens; it returns a record whenever a play ends.
+#[derive(Default)]
+pub struct PlayTracker {
+    current: Option<CurrentPlay>,
+}
+
+impl PlayTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new song was loaded, or playback was stopped (`None`). The song that
+    /// was playing, if any, ends as skipped.
+    pub fn song_changed(&mut self, song: Option<&Song>, now: Instant) -> Option<PlayRecord> {
+        let ended = self
+            .current
+            .take()
+            .map(|play| play.finish(now, PlayOutcome::Skipped));
+        self.current = song.map(|song| CurrentPlay {
+            song_id: song.id,
+            started_at: unix_seconds(),
+            listened: Duration::ZERO,
+            playing_since: None,
+        });
+        ended
+    }
+
+    pub fn state_changed(&mut self, state: PlaybackState, now: Instant) {
+        let Some(play) = &mut self.current else {
+            return;
+        };
+        match state {
+            PlaybackState::Playing => {
+                play.playing_since.get_or_insert(now);
+            }
+            PlaybackState::Paused | PlaybackState::Stopped => {
+                if let Some(since) = play.playing_since.take() {
+                    play.listened += now.saturating_duration_since(since);
+                }
+            }
+        }
+    }
+
+    /// The song played to its end
+    pub fn finished(&mut self, now: Instant) -> Option<PlayRecord> {
+        self.current
+            .take()
+            .map(|play| play.finish(now, PlayOutcome::Completed))
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
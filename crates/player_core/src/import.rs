@@ -1,17 +1,29 @@
 use std::fs::{self, File};
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use id3::{Tag, TagLike};
 use rayon::prelude::*;
 use rodio::{Decoder, Source};
 
 use crate::audio::{AudioFile, AudioFormat};
+use crate::backup::back_up;
+use crate::duplicates::{
+    backfill_content_hashes, content_hash, estimated_bitrate, find_duplicate, BackfillFailures,
+    Duplicate, DuplicatePolicy,
+};
+use crate::fingerprint::{
+    backfill_fingerprints, compute_fingerprint, Fingerprint, FingerprintDatabase, MetadataLookup,
+};
+use crate::history::PlayStats;
+use crate::journal::ImportJournal;
 use crate::library::{Library, Song, SongId};
-use crate::storage::{import_path, imported_path, music_path, problem_path};
+use crate::mp4::read_mp4_metadata;
+use crate::problems::{forget_problems, quarantine_failed_import};
+use crate::storage::Paths;
 
 // ============================================================================
 // Error Types
@@ -88,6 +100,62 @@ pub struct ImportResult {
     pub original_path: PathBuf,
     pub library_path: PathBuf,
     pub archived_path: PathBuf,
+    pub outcome: ImportOutcome,
+}
+
+/// What happened to an imported file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The file was added as a new song
+    Imported,
+    /// The file duplicated an existing song and was only archived; `song` is the existing song
+    Skipped(Duplicate),
+    /// The file replaced the library copy of an existing song, keeping its id
+    Replaced(Duplicate),
+    /// The file duplicated an existing song but was added as a new song anyway
+    KeptBoth(Duplicate),
+}
+
+impl ImportOutcome {
+    /// Whether a song was added to or changed in the library
+    pub fn changed_library(&self) -> bool {
+        !matches!(self, ImportOutcome::Skipped(_))
+    }
+}
+
+#[derive(Clone)]
+pub struct ImportOptions {
+    pub duplicate_policy: DuplicatePolicy,
+    /// Compute acoustic fingerprints, used to match duplicates across encodings
+    pub fingerprint: bool,
+    /// Suggests tags for untagged files from their fingerprint
+    pub metadata_lookup: Option<Arc<dyn MetadataLookup>>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            duplicate_policy: DuplicatePolicy::default(),
+            fingerprint: true,
+            metadata_lookup: None,
+        }
+    }
+}
+
+impl ImportOptions {
+    /// The default options, suggesting tags for untagged files from the local
+    /// fingerprint database if there is one
+    pub fn for_library(paths: &Paths) -> Self {
+        let mut options = Self::default();
+        match FingerprintDatabase::load(&paths.fingerprint_database()) {
+            Ok(database) if !database.is_empty() => {
+                options.metadata_lookup = Some(Arc::new(database));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Failed to load fingerprint database: {}", e),
+        }
+        options
+    }
 }
 
 // ============================================================================
@@ -138,31 +206,51 @@ impl MetadataReader for Mp3MetadataReader {
     type Error = ImportError;
 
     fn read(file: &AudioFile) -> Result<Metadata, Self::Error> {
-        let tag = id3::Tag::read_from_path(&file.path)?;
-
-        Ok(Metadata {
-            title: tag.title().map(String::from),
-            artist: tag.artist().map(String::from),
-            album_artist: tag.album_artist().map(String::from),
-            album: tag.album().map(String::from),
-            track_number: tag.track(),
-            duration: get_audio_duration(&file.path)
-                .or_else(|| {
-                    tag.duration()
-                        .map(|millis| Duration::from_millis(millis as u64))
-                })
-                .or_else(|| {
-                    let duration = calculate_duration_by_decoding(&file.path)?;
-                    if write_duration_to_file(&file.path, duration).is_ok() {
-                        eprintln!(
-                            "Wrote calculated duration {:?} to {:?}",
-                            duration, file.path
-                        );
-                    }
-                    Some(duration)
-                }),
-            chapters: Vec::new(),
-        })
+        read_mp3_metadata(file, true)
+    }
+}
+
+/// Read an MP3's tags. A duration found by decoding the whole file is written
+/// back as a tag if `write_duration` is set, so the next read is fast.
+fn read_mp3_metadata(file: &AudioFile, write_duration: bool) -> Result<Metadata, ImportError> {
+    let tag = id3::Tag::read_from_path(&file.path)?;
+
+    Ok(Metadata {
+        title: tag.title().map(String::from),
+        artist: tag.artist().map(String::from),
+        album_artist: tag.album_artist().map(String::from),
+        album: tag.album().map(String::from),
+        track_number: tag.track(),
+        duration: get_audio_duration(&file.path)
+            .or_else(|| {
+                tag.duration()
+                    .map(|millis| Duration::from_millis(millis as u64))
+            })
+            .or_else(|| {
+                let duration = calculate_duration_by_decoding(&file.path)?;
+                if write_duration && write_duration_to_file(&file.path, duration).is_ok() {
+                    eprintln!(
+                        "Wrote calculated duration {:?} to {:?}",
+                        duration, file.path
+                    );
+                }
+                Some(duration)
+            }),
+        chapters: Vec::new(),
+    })
+}
+
+// ============================================================================
+// M4B Metadata Reader
+// ============================================================================
+
+pub struct M4bMetadataReader;
+
+impl MetadataReader for M4bMetadataReader {
+    type Error = ImportError;
+
+    fn read(file: &AudioFile) -> Result<Metadata, Self::Error> {
+        Ok(read_mp4_metadata(&file.path)?)
     }
 }
 
@@ -173,7 +261,7 @@ fn get_audio_duration(path: &Path) -> Option<Duration> {
     decoder.total_duration()
 }
 
-fn calculate_duration_by_decoding(path: &Path) -> Option<Duration> {
+pub(crate) fn calculate_duration_by_decoding(path: &Path) -> Option<Duration> {
     let file = File::open(path).ok()?;
     let reader = BufReader::new(file);
     let decoder = Decoder::new(reader).ok()?;
@@ -192,7 +280,7 @@ fn calculate_duration_by_decoding(path: &Path) -> Option<Duration> {
     Some(Duration::from_secs(rounded_secs))
 }
 
-fn write_duration_to_file(path: &Path, duration: Duration) -> Result<(), ImportError> {
+pub(crate) fn write_duration_to_file(path: &Path, duration: Duration) -> Result<(), ImportError> {
     let mut tag = Tag::read_from_path(path).unwrap_or_else(|_| Tag::new());
     let millis = duration.as_millis() as u32;
     tag.set_duration(millis);
@@ -204,9 +292,19 @@ fn write_duration_to_file(path: &Path, duration: Duration) -> Result<(), ImportE
 // Basic Import Functions
 // ============================================================================
 
-/// Read metadata from an audio file without importing it
+/// Read metadata from an audio file without importing it. An MP3 with no
+/// duration tag gets one once its duration has been found by decoding.
 pub fn read_metadata(path: impl AsRef<Path>) -> Result<ImportedFile, ImportError> {
-    let path = path.as_ref();
+    read_metadata_with(path.as_ref(), true)
+}
+
+/// Read metadata from an audio file without ever writing to it, for files the
+/// player doesn't own (see `roots::LibraryRoot`)
+pub fn read_metadata_read_only(path: impl AsRef<Path>) -> Result<ImportedFile, ImportError> {
+    read_metadata_with(path.as_ref(), false)
+}
+
+fn read_metadata_with(path: &Path, write_duration: bool) -> Result<ImportedFile, ImportError> {
     let format = AudioFormat::from_path(path).ok_or(ImportError::UnknownFormat)?;
 
     let file = AudioFile {
@@ -215,8 +313,8 @@ pub fn read_metadata(path: impl AsRef<Path>) -> Result<ImportedFile, ImportError
     };
 
     let metadata = match format {
-        AudioFormat::Mp3 => Mp3MetadataReader::read(&file)?,
-        AudioFormat::M4b => Metadata::default(),
+        AudioFormat::Mp3 => read_mp3_metadata(&file, write_duration)?,
+        AudioFormat::M4b => M4bMetadataReader::read(&file)?,
     };
 
     Ok(ImportedFile { file, metadata })
@@ -269,8 +367,12 @@ fn sanitize_filename(name: &str) -> String {
 }
 
 /// Generate the library path for a song based on its metadata
-/// Format: ~/Player/Music/Artist/Album/TrackNum - Title.ext
-fn generate_library_path(metadata: &Metadata, format: AudioFormat) -> PathBuf {
+/// Format: Music/Artist/Album/TrackNum - Title.ext
+pub(crate) fn generate_library_path(
+    metadata: &Metadata,
+    format: AudioFormat,
+    paths: &Paths,
+) -> PathBuf {
     let artist = metadata
         .artist
         .as_ref()
@@ -295,152 +397,637 @@ fn generate_library_path(metadata: &Metadata, format: AudioFormat) -> PathBuf {
         None => format!("{}.{}", title, format.extension()),
     };
 
-    music_path().join(&artist).join(&album).join(&filename)
+    paths.music.join(&artist).join(&album).join(&filename)
 }
 
 /// Generate the archived path for a file, preserving its relative structure from Import/
-fn generate_archived_path(original_path: &Path) -> PathBuf {
-    let import_dir = import_path();
-
+fn generate_archived_path(original_path: &Path, paths: &Paths) -> PathBuf {
     // Try to preserve relative path structure
     let relative = original_path
-        .strip_prefix(&import_dir)
+        .strip_prefix(&paths.import)
+        .or_else(|_| original_path.strip_prefix(&paths.problem))
         .unwrap_or(original_path);
 
-    imported_path().join(relative)
+    paths.imported.join(relative)
 }
 
-fn generate_problem_path(original_path: &Path) -> PathBuf {
-    let import_dir = import_path();
-
+pub(crate) fn generate_problem_path(original_path: &Path, paths: &Paths) -> PathBuf {
     // Try to preserve relative path structure
     let relative = original_path
-        .strip_prefix(&import_dir)
+        .strip_prefix(&paths.import)
         .unwrap_or(original_path);
 
-    problem_path().join(relative)
+    paths.problem.join(relative)
+}
+
+/// A pending file whose tags, hash and fingerprint have been read,
+/// ready to be checked for duplicates and copied into the library
+#[derive(Debug)]
+pub struct AnalyzedFile {
+    pub source_path: PathBuf,
+    pub imported: ImportedFile,
+    pub duration: Duration,
+    pub content_hash: Option<String>,
+    pub fingerprint: Option<Fingerprint>,
 }
 
 /// Import a single file into the library:
 /// 1. Read metadata
-/// 2. Copy to ~/Player/Music/Artist/Album/
-/// 3. Move original to ~/Player/Imported/
-/// 4. Return the new Song
+/// 2. Check for a duplicate already in the library and apply the duplicate policy
+/// 3. Copy to Music/Artist/Album/
+/// 4. Move original to Imported/
+/// 5. Return the new Song
 ///
-/// If duration cannot be determined, moves file to ~/Player/Problem/ and returns NoDuration error.
+/// If the file can't be read or has no duration, it is moved to Problem/ and logged.
 pub fn import_file_to_library(
     source_path: impl AsRef<Path>,
-    next_id: u64,
+    library: &Library,
+    options: &ImportOptions,
+    paths: &Paths,
 ) -> Result<ImportResult, ImportError> {
     let source_path = source_path.as_ref();
+    let analyzed = analyze_file(source_path, options, true)
+        .map_err(|e| quarantine_failed_import(source_path, e, paths))?;
+    let plan = plan_import(analyzed, library, options, paths);
+    execute_import(plan, |_| {})
+}
 
+/// Read everything needed to import a file. This is the slow part of an import and
+/// doesn't look at the library or move any files, so many files can be analyzed in parallel.
+///
+/// With `write_duration`, an MP3 whose duration had to be decoded gets a duration
+/// tag; previews pass `false` so they leave the file untouched.
+pub fn analyze_file(
+    source_path: &Path,
+    options: &ImportOptions,
+    write_duration: bool,
+) -> Result<AnalyzedFile, ImportError> {
     // Read metadata from source
-    let imported = read_metadata(source_path)?;
+    let mut imported = read_metadata_with(source_path, write_duration)?;
 
     // Check duration before we copy anything
-    let duration = match imported.metadata.duration {
-        Some(d) => d,
-        None => {
-            // Move to Problem folder
-            let problem_dest = generate_problem_path(source_path);
-            if let Some(parent) = problem_dest.parent() {
-                fs::create_dir_all(parent)?;
+    let duration = imported
+        .metadata
+        .duration
+        .ok_or_else(|| ImportError::NoDuration(source_path.to_path_buf()))?;
+
+    // Hash after reading metadata, since reading may write a duration tag
+    let hash = content_hash(source_path, imported.file.format).ok();
+    let fingerprint = if options.fingerprint {
+        compute_fingerprint(source_path)
+    } else {
+        None
+    };
+
+    // Fill in missing tags for untagged files
+    if let (Some(lookup), Some(fingerprint)) = (&options.metadata_lookup, &fingerprint) {
+        let untagged = imported.metadata.title.is_none() && imported.metadata.artist.is_none();
+        if untagged {
+            if let Some(suggested) = lookup.lookup(fingerprint, duration) {
+                apply_suggested_metadata(&mut imported.metadata, suggested);
             }
-            fs::rename(source_path, &problem_dest)?;
-            return Err(ImportError::NoDuration(problem_dest));
         }
-    };
+    }
+
+    Ok(AnalyzedFile {
+        source_path: source_path.to_path_buf(),
+        imported,
+        duration,
+        content_hash: hash,
+        fingerprint,
+    })
+}
 
-    // Generate destination paths
-    let library_path = generate_library_path(&imported.metadata, imported.file.format);
-    let archived_path = generate_archived_path(source_path);
+/// Everything importing a file will do, decided before any file is touched
+/// so it can be written to the import journal first
+#[derive(Debug, Clone)]
+pub(crate) struct ImportPlan {
+    pub song: Song,
+    pub source_path: PathBuf,
+    pub library_path: PathBuf,
+    pub archived_path: PathBuf,
+    /// Whether the file still needs copying into the library
+    pub copy: bool,
+    pub outcome: ImportOutcome,
+}
 
-    // Create destination directories
-    if let Some(parent) = library_path.parent() {
-        fs::create_dir_all(parent)?;
+/// Check an analyzed file against the library and decide where it goes
+pub(crate) fn plan_import(
+    analyzed: AnalyzedFile,
+    library: &Library,
+    options: &ImportOptions,
+    paths: &Paths,
+) -> ImportPlan {
+    let AnalyzedFile {
+        source_path,
+        imported,
+        duration,
+        content_hash: hash,
+        fingerprint,
+    } = analyzed;
+    let archived_path = unique_path(generate_archived_path(&source_path, paths));
+
+    let duplicate = find_duplicate(
+        library,
+        hash.as_deref(),
+        fingerprint.as_ref(),
+        &imported.metadata,
+    );
+
+    if let Some(duplicate) = duplicate {
+        let existing = &library.songs[&duplicate.song_id];
+
+        let replace = match options.duplicate_policy {
+            DuplicatePolicy::Skip => false,
+            DuplicatePolicy::KeepBoth => true,
+            // Replacing keeps the library path, so it must keep the format too
+            DuplicatePolicy::ReplaceIfBetterBitrate
+                if imported.file.format != existing.file.format =>
+            {
+                false
+            }
+            DuplicatePolicy::ReplaceIfBetterBitrate => {
+                let incoming = estimated_bitrate(&source_path, duration);
+                let current = estimated_bitrate(&existing.file.path, existing.duration);
+                incoming > current
+            }
+        };
+
+        if !replace {
+            return ImportPlan {
+                song: existing.clone(),
+                source_path,
+                library_path: existing.file.path.clone(),
+                archived_path,
+                copy: false,
+                outcome: ImportOutcome::Skipped(duplicate),
+            };
+        }
+
+        if options.duplicate_policy == DuplicatePolicy::ReplaceIfBetterBitrate {
+            // Copy the better file over the existing library copy
+            let library_path = existing.file.path.clone();
+            let mut song = song_from_metadata(
+                existing.id,
+                &imported,
+                library_path.clone(),
+                duration,
+                hash,
+                fingerprint,
+            );
+            song.keep_user_data(existing);
+
+            return ImportPlan {
+                song,
+                source_path,
+                library_path,
+                archived_path,
+                copy: true,
+                outcome: ImportOutcome::Replaced(duplicate),
+            };
+        }
     }
-    if let Some(parent) = archived_path.parent() {
-        fs::create_dir_all(parent)?;
+
+    // Generate destination paths, never overwriting a different song's file
+    let (library_path, already_copied) = library_destination(
+        generate_library_path(&imported.metadata, imported.file.format, paths),
+        hash.as_deref(),
+        imported.file.format,
+        library,
+    );
+
+    // Create the song with the new library path
+    let song = song_from_metadata(
+        library.assign_song_id(hash.as_deref(), &library_path),
+        &imported,
+        library_path.clone(),
+        duration,
+        hash,
+        fingerprint,
+    );
+
+    ImportPlan {
+        song,
+        source_path,
+        library_path,
+        archived_path,
+        copy: !already_copied,
+        outcome: match duplicate {
+            Some(duplicate) => ImportOutcome::KeptBoth(duplicate),
+            None => ImportOutcome::Imported,
+        },
     }
+}
 
+/// Copy a planned file into the library and archive the original.
+/// `on_stage` is called as the file enters the copying and archiving stages.
+pub(crate) fn execute_import(
+    plan: ImportPlan,
+    on_stage: impl Fn(ImportStage),
+) -> Result<ImportResult, ImportError> {
     // Copy file to library
-    fs::copy(source_path, &library_path)?;
+    if plan.copy {
+        on_stage(ImportStage::Copying);
+        copy_atomically(&plan.source_path, &plan.library_path)?;
+    }
 
     // Move original to archived
-    fs::rename(source_path, &archived_path)?;
+    on_stage(ImportStage::Archiving);
+    archive_original(&plan.source_path, &plan.archived_path)?;
 
-    // Create the song with the new library path
-    let song = Song {
-        id: SongId(next_id),
+    Ok(ImportResult {
+        song: plan.song,
+        original_path: plan.source_path,
+        library_path: plan.library_path,
+        archived_path: plan.archived_path,
+        outcome: plan.outcome,
+    })
+}
+
+pub(crate) fn song_from_metadata(
+    id: SongId,
+    imported: &ImportedFile,
+    library_path: PathBuf,
+    duration: Duration,
+    content_hash: Option<String>,
+    fingerprint: Option<Fingerprint>,
+) -> Song {
+    let metadata = imported.metadata.clone();
+    Song {
+        id,
         file: AudioFile {
-            path: library_path.clone(),
+            path: library_path,
             format: imported.file.format,
         },
-        title: imported
-            .metadata
+        title: metadata
             .title
             .unwrap_or_else(|| "Unknown Title".to_string()),
-        artist: imported.metadata.artist.or(imported.metadata.album_artist),
-        album: imported.metadata.album,
-        track_number: imported.metadata.track_number,
+        artist: metadata.artist.or(metadata.album_artist),
+        album: metadata.album,
+        track_number: metadata.track_number,
         duration,
-    };
+        content_hash,
+        fingerprint,
+        file_stamp: None,
+        added_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .ok(),
+        rating: 0,
+        loved: false,
+        stats: PlayStats::default(),
+    }
+}
 
-    Ok(ImportResult {
-        song,
-        original_path: source_path.to_path_buf(),
-        library_path,
-        archived_path,
-    })
+/// Fill in fields the file's own tags are missing from a lookup suggestion
+fn apply_suggested_metadata(metadata: &mut Metadata, suggested: Metadata) {
+    metadata.title = metadata.title.take().or(suggested.title);
+    metadata.artist = metadata.artist.take().or(suggested.artist);
+    metadata.album = metadata.album.take().or(suggested.album);
+    metadata.track_number = metadata.track_number.or(suggested.track_number);
+}
+
+/// Move the original file from Import/ to its place in Imported/
+fn archive_original(source_path: &Path, archived_path: &Path) -> Result<(), ImportError> {
+    if let Some(parent) = archived_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(source_path, archived_path)?;
+    Ok(())
+}
+
+/// Pick where to copy a file in the library, never overwriting a different song's file.
+///
+/// A file with the same audio at a path no song uses was left by an import that was
+/// interrupted before it finished, so it's reused instead of copied again.
+/// Returns the path and whether the file is already there.
+fn library_destination(
+    path: PathBuf,
+    hash: Option<&str>,
+    format: AudioFormat,
+    library: &Library,
+) -> (PathBuf, bool) {
+    candidate_paths(path)
+        .find_map(|candidate| {
+            if !candidate.exists() {
+                return Some((candidate, false));
+            }
+            let orphaned = hash.is_some()
+                && content_hash(&candidate, format).ok().as_deref() == hash
+                && !library
+                    .songs
+                    .values()
+                    .any(|song| song.file.path == candidate);
+            orphaned.then_some((candidate, true))
+        })
+        .expect("unbounded candidates always yield a free path")
+}
+
+/// Copy through a temporary file next to the destination, so an interrupted
+/// copy never leaves a truncated file at `to`
+pub(crate) fn copy_atomically(from: &Path, to: &Path) -> Result<(), ImportError> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file_name = to
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let partial_path = to.with_file_name(format!(".{}.partial", file_name));
+
+    fs::copy(from, &partial_path)?;
+    fs::rename(&partial_path, to)?;
+    Ok(())
+}
+
+/// Append " (2)", " (3)", ... to the file name until the path doesn't exist
+pub(crate) fn unique_path(path: PathBuf) -> PathBuf {
+    candidate_paths(path)
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded candidates always yield a free path")
+}
+
+/// The path itself, then the path with " (2)", " (3)", ... appended to the file name
+fn candidate_paths(path: PathBuf) -> impl Iterator<Item = PathBuf> {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|s| format!(".{}", s.to_string_lossy()))
+        .unwrap_or_default();
+
+    let numbered = path.clone();
+    std::iter::once(path).chain(
+        (2..).map(move |n| numbered.with_file_name(format!("{} ({}){}", stem, n, extension))),
+    )
+}
+
+// ============================================================================
+// Import Pipeline
+// ============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportStage {
+    /// Reading tags, hashing and fingerprinting (runs in parallel)
+    ReadingMetadata,
+    /// Copying into the Music folder
+    Copying,
+    /// Moving the original to the Imported folder
+    Archiving,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportProgress {
+    pub stage: ImportStage,
+    pub current: usize,
+    pub total: usize,
+    pub current_file: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub enum ImportEvent {
+    /// The Import folder was scanned and `total` files are pending
+    Scanned { total: usize },
+    /// A file entered a stage
+    Progress(ImportProgress),
+    /// A file added or replaced a song in the library
+    SongImported(Box<Song>),
+    /// A file could not be imported
+    Failed { path: PathBuf, error: String },
+}
+
+/// Stops a running import from another thread.
+///
+/// Files are only checked between steps, so a file that has started copying is
+/// finished and archived first; nothing is left half-imported.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Recursively list audio files under a directory without reading them
+pub fn scan_audio_files(path: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut paths_to_scan = vec![path.as_ref().to_path_buf()];
+
+    while let Some(current_path) = paths_to_scan.pop() {
+        let Ok(entries) = fs::read_dir(&current_path) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                paths_to_scan.push(entry_path);
+            } else if AudioFormat::from_path(&entry_path).is_some() {
+                files.push(entry_path);
+            }
+        }
+    }
+
+    files.sort();
+    files
 }
 
 /// Scan the Import directory and import all new files
-pub fn import_all_pending(library: &mut Library) -> Vec<Result<ImportResult, ImportError>> {
-    let import_dir = import_path();
+pub fn import_all_pending(
+    library: &mut Library,
+    options: &ImportOptions,
+    paths: &Paths,
+) -> Vec<Result<ImportResult, ImportError>> {
+    import_all_pending_with_events(library, options, paths, &CancelToken::new(), |_| {})
+}
+
+/// What `import_all_pending` would do with each file in Import, without copying,
+/// archiving, quarantining or writing to anything
+pub fn preview_import(
+    library: &Library,
+    options: &ImportOptions,
+    paths: &Paths,
+) -> Vec<(PathBuf, Result<ImportResult, ImportError>)> {
+    let analyzed: Vec<(PathBuf, Result<AnalyzedFile, ImportError>)> =
+        scan_audio_files(&paths.import)
+            .into_par_iter()
+            .map(|path| {
+                let analyzed = analyze_file(&path, options, false);
+                (path, analyzed)
+            })
+            .collect();
+
+    // Plan against a copy that gains each planned song, so duplicates within
+    // the Import folder are found as they would be on import
+    let mut library = library.clone();
+    analyzed
+        .into_iter()
+        .map(|(path, analyzed)| {
+            let result = analyzed.map(|analyzed| {
+                let plan = plan_import(analyzed, &library, options, paths);
+                library.songs.insert(plan.song.id, plan.song.clone());
+                ImportResult {
+                    song: plan.song,
+                    original_path: plan.source_path,
+                    library_path: plan.library_path,
+                    archived_path: plan.archived_path,
+                    outcome: plan.outcome,
+                }
+            });
+            (path, result)
+        })
+        .collect()
+}
+
+/// Scan the Import directory and import all new files in stages:
+/// 1. Scan: list pending files without reading them
+/// 2. Read metadata, hashes and fingerprints in parallel
+/// 3. Check for duplicates, copy and archive one file at a time, since each
+///    file is checked against the songs imported before it
+///
+/// `on_event` is called from worker threads as files move through the stages.
+/// Each file is written to the import journal before it is touched, so an import
+/// interrupted at any point can be finished or undone by `recover_imports`.
+/// Call `ImportJournal::clear` once the library has been saved.
+pub fn import_all_pending_with_events<F>(
+    library: &mut Library,
+    options: &ImportOptions,
+    paths: &Paths,
+    cancel: &CancelToken,
+    on_event: F,
+) -> Vec<Result<ImportResult, ImportError>>
+where
+    F: Fn(ImportEvent) + Send + Sync,
+{
+    let import_dir = &paths.import;
     let mut results = Vec::new();
 
     // Ensure import directory exists
     if !import_dir.exists() {
-        if let Err(e) = fs::create_dir_all(&import_dir) {
+        if let Err(e) = fs::create_dir_all(import_dir) {
             results.push(Err(ImportError::IoError(e)));
             return results;
         }
     }
 
-    // Get next available song ID
-    let mut next_id = library.songs.keys().map(|id| id.0).max().unwrap_or(0) + 1;
+    let files = scan_audio_files(import_dir);
+    let total = files.len();
+    on_event(ImportEvent::Scanned { total });
 
-    // Scan for files
-    let files = match scan_directory(&import_dir) {
-        Ok(files) => files,
+    if !files.is_empty() {
+        // A sync can change much of the library, so keep the state from before it
+        if let Err(e) = back_up(paths) {
+            eprintln!("Failed to back up library before importing: {}", e);
+        }
+
+        // Make sure songs imported before hashing and fingerprinting existed can be matched
+        let mut failures = BackfillFailures::load(paths).unwrap_or_else(|e| {
+            eprintln!("Failed to load backfill failures: {}", e);
+            BackfillFailures::default()
+        });
+        backfill_content_hashes(library, &mut failures);
+        if options.fingerprint {
+            backfill_fingerprints(library, &mut failures);
+        }
+        if let Err(e) = failures.save(paths) {
+            eprintln!("Failed to save backfill failures: {}", e);
+        }
+    }
+
+    let read = AtomicUsize::new(0);
+    let analyzed: Vec<(PathBuf, Result<AnalyzedFile, ImportError>)> = files
+        .into_par_iter()
+        .filter_map(|path| {
+            if cancel.is_cancelled() {
+                return None;
+            }
+            let current = read.fetch_add(1, Ordering::SeqCst) + 1;
+            on_event(ImportEvent::Progress(ImportProgress {
+                stage: ImportStage::ReadingMetadata,
+                current,
+                total,
+                current_file: path.clone(),
+            }));
+            let analyzed = analyze_file(&path, options, true);
+            Some((path, analyzed))
+        })
+        .collect();
+
+    let mut journal = match ImportJournal::open(paths) {
+        Ok(journal) => journal,
         Err(e) => {
-            results.push(Err(e));
+            results.push(Err(ImportError::IoError(e)));
             return results;
         }
     };
 
-    // Import each file
-    for file in files {
-        let source_path = file.file.path.clone();
+    for (index, (path, analyzed)) in analyzed.into_iter().enumerate() {
+        if cancel.is_cancelled() {
+            break;
+        }
 
-        match import_file_to_library(&source_path, next_id) {
+        let analyzed = analyzed.map_err(|e| quarantine_failed_import(&path, e, paths));
+        let result = analyzed.and_then(|analyzed| {
+            let plan = plan_import(analyzed, library, options, paths);
+            let transaction = journal.begin(&plan)?;
+            let result = execute_import(plan, |stage| {
+                on_event(ImportEvent::Progress(ImportProgress {
+                    stage,
+                    current: index + 1,
+                    total,
+                    current_file: path.clone(),
+                }));
+            })?;
+            journal.done(transaction)?;
+            Ok(result)
+        });
+
+        match &result {
             Ok(result) => {
                 library.songs.insert(result.song.id, result.song.clone());
-                next_id += 1;
-                results.push(Ok(result));
-            }
-            Err(e) => {
-                results.push(Err(e));
+                if result.outcome.changed_library() {
+                    on_event(ImportEvent::SongImported(Box::new(result.song.clone())));
+                }
             }
+            Err(e) => on_event(ImportEvent::Failed {
+                path: path.clone(),
+                error: e.to_string(),
+            }),
         }
+        results.push(result);
     }
 
     // Clean up empty directories in Import folder
-    cleanup_empty_directories(&import_dir);
+    cleanup_empty_subdirectories(import_dir);
 
     results
 }
 
+/// Remove empty directories inside the given path, keeping the path itself,
+/// which may be watched for new files
+fn cleanup_empty_subdirectories(path: &Path) {
+    if let Ok(entries) = fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                cleanup_empty_directories(&entry_path);
+            }
+        }
+    }
+}
+
 /// Recursively remove empty directories from the given path
 fn cleanup_empty_directories(path: &Path) {
     if !path.is_dir() {
@@ -484,20 +1071,21 @@ pub struct RepairProgress {
 
 /// Attempt to repair files in the Problem folder by calculating duration and writing it to ID3.
 /// Successfully repaired files are moved back to the Import folder.
-pub fn repair_problem_files() -> (Vec<RepairResult>, Vec<RepairFailure>) {
-    repair_problem_files_with_progress(|_| {})
+pub fn repair_problem_files(paths: &Paths) -> (Vec<RepairResult>, Vec<RepairFailure>) {
+    repair_problem_files_with_progress(paths, |_| {})
 }
 
 /// Attempt to repair files in the Problem folder with progress callback.
 /// The callback receives progress info for each file being processed.
 /// Uses parallel processing for CPU-bound decoding work.
 pub fn repair_problem_files_with_progress<F>(
+    paths: &Paths,
     on_progress: F,
 ) -> (Vec<RepairResult>, Vec<RepairFailure>)
 where
     F: Fn(RepairProgress) + Send + Sync,
 {
-    let problem_dir = problem_path();
+    let problem_dir = paths.problem.clone();
 
     if !problem_dir.exists() {
         return (Vec::new(), Vec::new());
@@ -542,7 +1130,7 @@ where
             })?;
 
             let relative = path.strip_prefix(&problem_dir).unwrap_or(&path);
-            let import_dest = import_path().join(relative);
+            let import_dest = paths.import.join(relative);
 
             if let Some(parent) = import_dest.parent() {
                 fs::create_dir_all(parent).map_err(|e| RepairFailure {
@@ -575,6 +1163,14 @@ where
         }
     }
 
+    let repaired: Vec<&Path> = successes
+        .iter()
+        .map(|success: &RepairResult| success.path.as_path())
+        .collect();
+    if let Err(e) = forget_problems(&repaired, paths) {
+        eprintln!("Failed to update problem log: {}", e);
+    }
+
     cleanup_empty_directories(&problem_dir);
 
     (successes, failures)
@@ -0,0 +1,324 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::duplicates::content_hash;
+use crate::import::{
+    read_metadata, scan_audio_files, song_from_metadata, unique_path, ImportError, ImportPlan,
+};
+use crate::library::{Library, Song, SongId};
+use crate::storage::{load_library, save_library, Paths, SongEntry, StorageError};
+
+// ============================================================================
+// Journal Entries
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum JournalEntry {
+    /// Written before a file is copied or archived
+    Begin {
+        transaction: u64,
+        source: PathBuf,
+        archived: PathBuf,
+        /// Whether the import copies the file, rather than reusing one already in Music
+        copy: bool,
+        song: Box<SongEntry>,
+    },
+    /// Written once the file is copied and the original archived
+    Done { transaction: u64 },
+}
+
+impl JournalEntry {
+    fn transaction(&self) -> u64 {
+        match self {
+            JournalEntry::Begin { transaction, .. } | JournalEntry::Done { transaction } => {
+                *transaction
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Import Journal
+// ============================================================================
+
+/// Write-ahead log that makes each file's import atomic.
+///
+/// Every import is recorded before any file is touched and marked done once the
+/// original is archived. Entries stay until the manifest containing the imported
+/// songs is saved, so after a crash `recover_imports` can finish or undo each one.
+pub struct ImportJournal {
+    file: File,
+    next_transaction: u64,
+}
+
+impl ImportJournal {
+    /// Open the journal for appending, continuing after any transactions already in it
+    pub fn open(paths: &Paths) -> io::Result<Self> {
+        let path = paths.import_journal();
+        let next_transaction = read_entries(&path)?
+            .iter()
+            .map(|entry| entry.transaction() + 1)
+            .max()
+            .unwrap_or(0);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self {
+            file,
+            next_transaction,
+        })
+    }
+
+    /// Record a planned import before it starts
+    pub(crate) fn begin(&mut self, plan: &ImportPlan) -> io::Result<u64> {
+        let transaction = self.next_transaction;
+        self.next_transaction += 1;
+
+        self.append(&JournalEntry::Begin {
+            transaction,
+            source: plan.source_path.clone(),
+            archived: plan.archived_path.clone(),
+            copy: plan.copy,
+            song: Box::new(SongEntry::from_song(&plan.song)),
+        })?;
+        Ok(transaction)
+    }
+
+    /// Record that a file has been copied and its original archived
+    pub(crate) fn done(&mut self, transaction: u64) -> io::Result<()> {
+        self.append(&JournalEntry::Done { transaction })
+    }
+
+    fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        writeln!(self.file, "{}", serde_json::to_string(entry)?)?;
+        self.file.sync_data()
+    }
+
+    /// Forget all journaled imports. Call once the manifest containing them is saved.
+    pub fn clear(paths: &Paths) -> io::Result<()> {
+        match fs::remove_file(paths.import_journal()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read journal entries, skipping a line torn by a crash mid-write
+fn read_entries(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+// ============================================================================
+// Crash Recovery
+// ============================================================================
+
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// Interrupted imports whose copy was complete, now added to the library
+    pub completed: Vec<Song>,
+    /// Interrupted imports that were undone, leaving the original in Import
+    pub rolled_back: Vec<PathBuf>,
+    /// Files in Music that no song pointed at, added to the library where they are
+    pub adopted: Vec<Song>,
+    /// Partial copies left by an interrupted copy, deleted
+    pub removed_partials: Vec<PathBuf>,
+    /// Orphaned files in Music that couldn't be read
+    pub unresolved: Vec<(PathBuf, ImportError)>,
+}
+
+impl RecoveryReport {
+    pub fn changed_library(&self) -> bool {
+        !self.completed.is_empty() || !self.adopted.is_empty()
+    }
+}
+
+/// Whether the journal has imports that were never saved to the manifest
+pub fn needs_recovery(paths: &Paths) -> bool {
+    fs::metadata(paths.import_journal()).is_ok_and(|metadata| metadata.len() > 0)
+}
+
+/// Load the library, recover imports left in the journal, save the library if
+/// recovery changed it, and clear the journal. Check `needs_recovery` first.
+pub fn recover_interrupted_imports(paths: &Paths) -> Result<RecoveryReport, StorageError> {
+    let mut library = load_library(paths)?;
+    let report = recover_imports(&mut library, paths)?;
+    if report.changed_library() {
+        save_library(&library, paths)?;
+    }
+    ImportJournal::clear(paths)?;
+    Ok(report)
+}
+
+/// Finish or undo imports left in the journal, then reconcile the managed folders
+/// against the library:
+///
+/// - An import whose library copy is complete is rolled forward: the original is
+///   archived if it's still in Import, and the song is added.
+/// - Any other import is rolled back: the original goes back to Import to be
+///   imported again. Copies are atomic, so there's never a half-written file in Music.
+/// - Audio files in Music that no song points at are added where they are.
+/// - Partial copies in Music are deleted.
+///
+/// Originals in Imported and Problem are never in the manifest; the journal is what
+/// says whether they belong to a finished import. Save the library and then call
+/// `ImportJournal::clear` once recovery is done.
+pub fn recover_imports(library: &mut Library, paths: &Paths) -> io::Result<RecoveryReport> {
+    let mut report = RecoveryReport::default();
+
+    let entries = read_entries(&paths.import_journal())?;
+    let done: HashSet<u64> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            JournalEntry::Done { transaction } => Some(*transaction),
+            JournalEntry::Begin { .. } => None,
+        })
+        .collect();
+
+    for entry in entries {
+        let JournalEntry::Begin {
+            transaction,
+            source,
+            archived,
+            copy,
+            song,
+        } = entry
+        else {
+            continue;
+        };
+        // A song the manifest couldn't load can't be added, so it's rolled back
+        let song = (*song).into_song();
+        // Copies are renamed into place only once complete, so a file at the library
+        // path is a finished copy, unless its hash says it's some other file
+        let copied = song.as_ref().is_ok_and(|song| {
+            done.contains(&transaction)
+                || !copy
+                || (song.file.path.exists()
+                    && song.content_hash.as_ref().is_none_or(|hash| {
+                        content_hash(&song.file.path, song.file.format)
+                            .ok()
+                            .as_ref()
+                            == Some(hash)
+                    }))
+        });
+
+        if let (true, Ok(song)) = (copied, song) {
+            if source.exists() {
+                let archived = unique_path(archived);
+                if let Some(parent) = archived.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&source, &archived)?;
+            }
+            if let Some(song) = add_recovered_song(library, song) {
+                report.completed.push(song);
+            }
+        } else {
+            if !source.exists() && archived.exists() {
+                if let Some(parent) = source.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&archived, &source)?;
+            }
+            report.rolled_back.push(source);
+        }
+    }
+
+    reconcile_music_folder(library, paths, &mut report)?;
+
+    Ok(report)
+}
+
+/// Add a song from the journal unless the manifest already has it, giving it a
+/// new id if its id was taken by another song in the meantime
+fn add_recovered_song(library: &mut Library, mut song: Song) -> Option<Song> {
+    if library
+        .songs
+        .values()
+        .any(|existing| existing.file.path == song.file.path)
+    {
+        return None;
+    }
+
+    if library.songs.contains_key(&song.id) {
+        song.id = library.assign_song_id(song.content_hash.as_deref(), &song.file.path);
+    }
+    library.add_song(song.clone());
+    Some(song)
+}
+
+/// Delete partial copies and add files in Music that no song points at
+fn reconcile_music_folder(
+    library: &mut Library,
+    paths: &Paths,
+    report: &mut RecoveryReport,
+) -> io::Result<()> {
+    let mut paths_to_scan = vec![paths.music.clone()];
+    while let Some(current_path) = paths_to_scan.pop() {
+        let Ok(entries) = fs::read_dir(&current_path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                paths_to_scan.push(entry_path);
+            } else if entry_path.extension().is_some_and(|ext| ext == "partial") {
+                fs::remove_file(&entry_path)?;
+                report.removed_partials.push(entry_path);
+            }
+        }
+    }
+
+    let known: HashMap<&Path, SongId> = library
+        .songs
+        .values()
+        .map(|song| (song.file.path.as_path(), song.id))
+        .collect();
+    let orphans: Vec<PathBuf> = scan_audio_files(&paths.music)
+        .into_iter()
+        .filter(|path| !known.contains_key(path.as_path()))
+        .collect();
+
+    for path in orphans {
+        match adopt_file(&path, library) {
+            Ok(song) => report.adopted.push(song),
+            Err(e) => report.unresolved.push((path, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a file to the library at its current path
+pub(crate) fn adopt_file(path: &Path, library: &mut Library) -> Result<Song, ImportError> {
+    let imported = read_metadata(path)?;
+    let duration = imported
+        .metadata
+        .duration
+        .ok_or_else(|| ImportError::NoDuration(path.to_path_buf()))?;
+    let hash = content_hash(path, imported.file.format).ok();
+
+    let id = library.assign_song_id(hash.as_deref(), path);
+    let song = song_from_metadata(id, &imported, path.to_path_buf(), duration, hash, None);
+    library.add_song(song.clone());
+    Ok(song)
+}
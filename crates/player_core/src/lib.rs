@@ -1,15 +1,57 @@
 pub mod audio;
+pub mod audio_engine;
+#[cfg(feature = "gpui")]
 pub mod audio_player;
+pub mod backup;
+pub mod duplicates;
+pub mod edit;
+pub mod fingerprint;
+pub mod history;
 pub mod import;
+pub mod journal;
 pub mod library;
 pub mod media_controls;
+pub mod mp4;
+pub mod mpd;
+#[cfg(target_os = "linux")]
+pub mod mpris;
 pub mod playback;
+pub mod problems;
+pub mod remote;
+pub mod roots;
+pub mod scrobble;
+pub mod session;
+pub mod settings;
+pub mod sqlite;
 pub mod storage;
+pub mod verify;
+pub mod watcher;
 
 pub use audio::*;
+pub use audio_engine::*;
+#[cfg(feature = "gpui")]
 pub use audio_player::*;
+pub use backup::*;
+pub use duplicates::*;
+pub use edit::*;
+pub use fingerprint::*;
+pub use history::*;
 pub use import::*;
+pub use journal::*;
 pub use library::*;
 pub use media_controls::*;
+pub use mp4::*;
+pub use mpd::*;
+#[cfg(target_os = "linux")]
+pub use mpris::*;
 pub use playback::*;
+pub use problems::*;
+pub use remote::*;
+pub use roots::*;
+pub use scrobble::*;
+pub use session::*;
+pub use settings::*;
+pub use sqlite::*;
 pub use storage::*;
+pub use verify::*;
+pub use watcher::*;
@@ -1,19 +1,124 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
 use crate::audio::AudioFile;
+use crate::fingerprint::Fingerprint;
+use crate::history::PlayStats;
+use crate::roots::FileStamp;
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SortOrder {
     #[default]
     Artist,
     Album,
     Title,
+    /// Most completed plays first
+    MostPlayed,
+    /// Most recently started first
+    RecentlyPlayed,
+    /// Newest additions to the library first
+    RecentlyAdded,
+    /// Highest rated first, loved songs first among equal ratings
+    Rating,
+}
+
+impl SortOrder {
+    pub const ALL: [SortOrder; 7] = [
+        SortOrder::Artist,
+        SortOrder::Album,
+        SortOrder::Title,
+        SortOrder::MostPlayed,
+        SortOrder::RecentlyPlayed,
+        SortOrder::RecentlyAdded,
+        SortOrder::Rating,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SortOrder::Artist => "Artist",
+            SortOrder::Album => "Album",
+            SortOrder::Title => "Title",
+            SortOrder::MostPlayed => "Most Played",
+            SortOrder::RecentlyPlayed => "Recently Played",
+            SortOrder::RecentlyAdded => "Recently Added",
+            SortOrder::Rating => "Rating",
+        }
+    }
+}
+
+/// Which songs a list shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SongFilter {
+    #[default]
+    All,
+    Loved,
+    /// Songs rated at least this many stars
+    MinRating(u8),
+    Unrated,
+}
+
+impl SongFilter {
+    /// The filters offered in the UI, in the order they're cycled through
+    pub const PRESETS: [SongFilter; 5] = [
+        SongFilter::All,
+        SongFilter::Loved,
+        SongFilter::MinRating(4),
+        SongFilter::MinRating(3),
+        SongFilter::Unrated,
+    ];
+
+    pub fn matches(&self, song: &Song) -> bool {
+        match self {
+            SongFilter::All => true,
+            SongFilter::Loved => song.loved,
+            SongFilter::MinRating(stars) => song.rating >= *stars,
+            SongFilter::Unrated => song.rating == 0,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            SongFilter::All => "All".to_string(),
+            SongFilter::Loved => "Loved".to_string(),
+            SongFilter::MinRating(stars) if *stars >= MAX_RATING => "5 Stars".to_string(),
+            SongFilter::MinRating(stars) => format!("{}+ Stars", stars),
+            SongFilter::Unrated => "Unrated".to_string(),
+        }
+    }
 }
 
+/// Highest star rating a song can have
+pub const MAX_RATING: u8 = 5;
+
+/// Identifies a song. Ids are derived from the song's audio content (see
+/// `Library::assign_song_id`), so they stay the same across re-imports and machines.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SongId(pub u64);
 
+/// Set on every derived id, so they never overlap the sequential ids songs had before
+const DERIVED_ID_BIT: u64 = 1 << 62;
+
+impl SongId {
+    /// The id for the `copy`th song with the given key
+    fn derived(key: &str, copy: u32) -> Self {
+        let hash = blake3::hash(format!("{}#{}", key, copy).as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash.as_bytes()[..8]);
+        // Keep ids positive as signed integers, as databases store them
+        SongId((u64::from_le_bytes(bytes) >> 2) | DERIVED_ID_BIT)
+    }
+
+    /// Whether this id was derived from a song's content rather than being a
+    /// sequential number from an older library
+    pub fn is_derived(&self) -> bool {
+        self.0 & DERIVED_ID_BIT != 0
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AudiobookId(pub u64);
 
@@ -26,6 +131,30 @@ pub struct Song {
     pub album: Option<String>,
     pub track_number: Option<u32>,
     pub duration: Duration,
+    /// Hash of the audio data, ignoring tags (see `duplicates::content_hash`)
+    pub content_hash: Option<String>,
+    /// Acoustic fingerprint of the start of the recording
+    pub fingerprint: Option<Fingerprint>,
+    /// Size and modification time when indexed, for songs in a library root
+    pub file_stamp: Option<FileStamp>,
+    /// When the song was added to the library, in seconds since the Unix epoch
+    pub added_at: Option<u64>,
+    /// Stars out of `MAX_RATING`; 0 means unrated
+    pub rating: u8,
+    pub loved: bool,
+    /// Derived from the listening history (see `history`), not stored with the song
+    pub stats: PlayStats,
+}
+
+impl Song {
+    /// Keep what the user and the library know about `existing` when this song
+    /// is rebuilt from its file's tags: the rating, loved flag, added date and stats
+    pub fn keep_user_data(&mut self, existing: &Song) {
+        self.added_at = existing.added_at;
+        self.rating = existing.rating;
+        self.loved = existing.loved;
+        self.stats = existing.stats;
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -51,11 +180,10 @@ pub enum MediaItem {
     Audiobook(Audiobook),
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct Library {
     pub songs: HashMap<SongId, Song>,
     pub audiobooks: HashMap<AudiobookId, Audiobook>,
-    next_song_id: u64,
     next_audiobook_id: u64,
 }
 
@@ -65,11 +193,44 @@ impl Library {
         Self::default()
     }
 
-    /// Get the next available song ID and increment the counter
-    pub fn next_song_id(&mut self) -> SongId {
-        let id = SongId(self.next_song_id);
-        self.next_song_id += 1;
-        id
+    /// The id for a new song, derived from its audio content hash, or from its path
+    /// if it has none. Re-importing the same recording gives it the same id; copies
+    /// of a recording kept side by side each get the next free id for it.
+    pub fn assign_song_id(&self, content_hash: Option<&str>, path: &Path) -> SongId {
+        let key = match content_hash {
+            Some(hash) => hash.to_string(),
+            None => format!("path:{}", path.to_string_lossy()),
+        };
+        (0..)
+            .map(|copy| SongId::derived(&key, copy))
+            .find(|id| !self.songs.contains_key(id))
+            .expect("a free id")
+    }
+
+    /// Give songs that have ids from before ids were derived from content (which
+    /// were sequential numbers) a derived id. Songs that already have one keep it.
+    ///
+    /// Returns the old and new id of every song that changed, so anything that
+    /// refers to songs by id can be updated.
+    pub fn rekey_songs(&mut self) -> HashMap<SongId, SongId> {
+        let mut legacy: Vec<SongId> = self
+            .songs
+            .values()
+            .filter(|song| !song.id.is_derived())
+            .map(|song| song.id)
+            .collect();
+        legacy.sort_by_key(|id| id.0);
+
+        let mut remap = HashMap::new();
+        for old_id in legacy {
+            let Some(mut song) = self.songs.remove(&old_id) else {
+                continue;
+            };
+            song.id = self.assign_song_id(song.content_hash.as_deref(), &song.file.path);
+            remap.insert(old_id, song.id);
+            self.songs.insert(song.id, song);
+        }
+        remap
     }
 
     /// Get the next available audiobook ID and increment the counter
@@ -79,15 +240,18 @@ impl Library {
         id
     }
 
-    /// Add a song to the library
+    /// Add a song to the library, replacing any song with the same id. Nothing
+    /// carries over from a replaced song; callers rebuilding a song from its file
+    /// use `Song::keep_user_data` first.
     pub fn add_song(&mut self, song: Song) {
-        // Update next_song_id if necessary
-        if song.id.0 >= self.next_song_id {
-            self.next_song_id = song.id.0 + 1;
-        }
         self.songs.insert(song.id, song);
     }
 
+    /// Remove a song from the library
+    pub fn remove_song(&mut self, id: SongId) -> Option<Song> {
+        self.songs.remove(&id)
+    }
+
     /// Add an audiobook to the library
     pub fn add_audiobook(&mut self, audiobook: Audiobook) {
         // Update next_audiobook_id if necessary
@@ -140,8 +304,62 @@ impl Library {
             SortOrder::Title => {
                 songs.sort_by(|a, b| a.title.cmp(&b.title));
             }
+            SortOrder::MostPlayed => {
+                songs.sort_by(|a, b| {
+                    b.stats
+                        .play_count
+                        .cmp(&a.stats.play_count)
+                        .then_with(|| b.stats.last_played.cmp(&a.stats.last_played))
+                        .then_with(|| a.title.cmp(&b.title))
+                });
+            }
+            SortOrder::RecentlyPlayed => {
+                songs.sort_by(|a, b| {
+                    b.stats
+                        .last_played
+                        .cmp(&a.stats.last_played)
+                        .then_with(|| a.title.cmp(&b.title))
+                });
+            }
+            SortOrder::RecentlyAdded => {
+                songs.sort_by(|a, b| {
+                    b.added_at
+                        .cmp(&a.added_at)
+                        .then_with(|| a.title.cmp(&b.title))
+                });
+            }
+            SortOrder::Rating => {
+                songs.sort_by(|a, b| {
+                    b.rating
+                        .cmp(&a.rating)
+                        .then_with(|| b.loved.cmp(&a.loved))
+                        .then_with(|| a.title.cmp(&b.title))
+                });
+            }
         }
 
         songs
     }
+
+    /// The songs `filter` lets through, sorted by `sort_order`
+    pub fn list_filtered(&self, sort_order: SortOrder, filter: SongFilter) -> Vec<Song> {
+        let mut songs = self.list(sort_order);
+        songs.retain(|song| filter.matches(song));
+        songs
+    }
+
+    /// The songs whose title, artist or album contains `query`, ignoring case,
+    /// sorted by `sort_order`
+    pub fn search(&self, query: &str, sort_order: SortOrder) -> Vec<Song> {
+        let query = query.to_lowercase();
+        let matches =
+            |field: Option<&str>| field.is_some_and(|field| field.to_lowercase().contains(&query));
+        let mut songs = self.list(sort_order);
+        songs.retain(|song| {
+            matches(Some(&song.title))
+                || matches(song.artist.as_deref())
+                || matches(song.album.as_deref())
+        });
+        songs
+    }
 }
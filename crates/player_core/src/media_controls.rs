@@ -1,11 +1,11 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use souvlaki::{
-    MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
-};
+use crate::library::{Song, SongId};
+use crate::playback::RepeatMode;
 
-#[derive(Debug, Clone)]
+/// A request from the desktop's media controls
+#[derive(Debug, Clone, PartialEq)]
 pub enum MediaKeyEvent {
     Play,
     Pause,
@@ -15,12 +15,19 @@ pub enum MediaKeyEvent {
     Stop,
     SeekForward,
     SeekBackward,
+    SeekBy {
+        offset: Duration,
+        forward: bool,
+    },
     SetPosition(Duration),
-}
-
-pub struct MediaControlsHandler {
-    controls: MediaControls,
-    receiver: Receiver<MediaKeyEvent>,
+    SetVolume(f64),
+    SetShuffle(bool),
+    SetRepeat(RepeatMode),
+    /// Play a song from the track list
+    GoTo(SongId),
+    OpenUri(String),
+    Raise,
+    Quit,
 }
 
 #[derive(Debug)]
@@ -48,133 +55,205 @@ impl std::fmt::Display for MediaControlsError {
 
 impl std::error::Error for MediaControlsError {}
 
-impl MediaControlsHandler {
-    pub fn new() -> Result<Self, MediaControlsError> {
-        let config = PlatformConfig {
-            dbus_name: "player",
-            display_name: "Player",
-            hwnd: None,
-        };
+/// On Linux the player speaks MPRIS over D-Bus itself (see `mpris`)
+#[cfg(target_os = "linux")]
+pub type MediaControlsHandler = crate::mpris::MprisServer;
 
-        let mut controls = MediaControls::new(config)
-            .map_err(|e| MediaControlsError::InitFailed(e.to_string()))?;
+// ============================================================================
+// Helpers
+// ============================================================================
 
-        let (sender, receiver) = mpsc::channel::<MediaKeyEvent>();
+const COVER_ART_NAMES: &[&str] = &["cover", "folder", "front"];
+const COVER_ART_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
 
-        Self::attach_handler(&mut controls, sender)?;
+/// A cover image next to the song, like `cover.jpg` or `folder.png`
+pub fn cover_art(song: &Song) -> Option<PathBuf> {
+    let dir = song.file.path.parent()?;
+    COVER_ART_NAMES.iter().find_map(|name| {
+        COVER_ART_EXTENSIONS
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+    })
+}
 
-        Ok(Self { controls, receiver })
+/// A `file://` URI for an absolute path, percent-encoding what needs it
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
     }
+    uri
+}
 
-    fn attach_handler(
-        controls: &mut MediaControls,
-        sender: Sender<MediaKeyEvent>,
-    ) -> Result<(), MediaControlsError> {
-        controls
-            .attach(move |event: MediaControlEvent| {
-                let media_event = match event {
-                    MediaControlEvent::Play => Some(MediaKeyEvent::Play),
-                    MediaControlEvent::Pause => Some(MediaKeyEvent::Pause),
-                    MediaControlEvent::Toggle => Some(MediaKeyEvent::Toggle),
-                    MediaControlEvent::Next => Some(MediaKeyEvent::Next),
-                    MediaControlEvent::Previous => Some(MediaKeyEvent::Previous),
-                    MediaControlEvent::Stop => Some(MediaKeyEvent::Stop),
-                    MediaControlEvent::Seek(souvlaki::SeekDirection::Forward) => {
-                        Some(MediaKeyEvent::SeekForward)
-                    }
-                    MediaControlEvent::Seek(souvlaki::SeekDirection::Backward) => {
-                        Some(MediaKeyEvent::SeekBackward)
-                    }
-                    MediaControlEvent::SetPosition(pos) => Some(MediaKeyEvent::SetPosition(pos.0)),
-                    _ => None,
-                };
-
-                if let Some(event) = media_event {
-                    let _ = sender.send(event);
-                }
-            })
-            .map_err(|e| MediaControlsError::AttachFailed(e.to_string()))
+/// The path in a `file://` URI, or `None` for any other scheme
+pub fn path_from_file_uri(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = (encoded[i] == b'%')
+            .then(|| encoded.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
     }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
 
-    pub fn try_recv(&self) -> Option<MediaKeyEvent> {
-        self.receiver.try_recv().ok()
+// ============================================================================
+// Other Platforms
+// ============================================================================
+
+#[cfg(not(target_os = "linux"))]
+pub use platform::MediaControlsHandler;
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use std::time::Duration;
+
+    use souvlaki::{
+        MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, MediaPosition,
+        PlatformConfig, SeekDirection,
+    };
+
+    use super::{cover_art, file_uri, MediaControlsError, MediaKeyEvent};
+    use crate::library::Song;
+    use crate::playback::RepeatMode;
+
+    /// The system media controls, through souvlaki. Only the parts souvlaki
+    /// supports are reported; the others are accepted and ignored.
+    pub struct MediaControlsHandler {
+        controls: MediaControls,
     }
 
-    pub fn poll_events(&self) -> Vec<MediaKeyEvent> {
-        let mut events = Vec::new();
-        while let Ok(event) = self.receiver.try_recv() {
-            events.push(event);
+    impl MediaControlsHandler {
+        pub fn new(
+            on_event: impl Fn(MediaKeyEvent) + Send + Sync + 'static,
+        ) -> Result<Self, MediaControlsError> {
+            let config = PlatformConfig {
+                dbus_name: "player",
+                display_name: "Player",
+                hwnd: None,
+            };
+
+            let mut controls = MediaControls::new(config)
+                .map_err(|e| MediaControlsError::InitFailed(e.to_string()))?;
+
+            controls
+                .attach(move |event: MediaControlEvent| {
+                    let media_event = match event {
+                        MediaControlEvent::Play => MediaKeyEvent::Play,
+                        MediaControlEvent::Pause => MediaKeyEvent::Pause,
+                        MediaControlEvent::Toggle => MediaKeyEvent::Toggle,
+                        MediaControlEvent::Next => MediaKeyEvent::Next,
+                        MediaControlEvent::Previous => MediaKeyEvent::Previous,
+                        MediaControlEvent::Stop => MediaKeyEvent::Stop,
+                        MediaControlEvent::Seek(SeekDirection::Forward) => {
+                            MediaKeyEvent::SeekForward
+                        }
+                        MediaControlEvent::Seek(SeekDirection::Backward) => {
+                            MediaKeyEvent::SeekBackward
+                        }
+                        MediaControlEvent::SeekBy(direction, offset) => MediaKeyEvent::SeekBy {
+                            offset,
+                            forward: matches!(direction, SeekDirection::Forward),
+                        },
+                        MediaControlEvent::SetPosition(position) => {
+                            MediaKeyEvent::SetPosition(position.0)
+                        }
+                        MediaControlEvent::SetVolume(volume) => MediaKeyEvent::SetVolume(volume),
+                        MediaControlEvent::OpenUri(uri) => MediaKeyEvent::OpenUri(uri),
+                        MediaControlEvent::Raise => MediaKeyEvent::Raise,
+                        MediaControlEvent::Quit => MediaKeyEvent::Quit,
+                    };
+                    on_event(media_event);
+                })
+                .map_err(|e| MediaControlsError::AttachFailed(e.to_string()))?;
+
+            Ok(Self { controls })
         }
-        events
-    }
 
-    pub fn set_metadata(
-        &mut self,
-        title: Option<&str>,
-        artist: Option<&str>,
-        album: Option<&str>,
-        duration: Option<Duration>,
-    ) -> Result<(), MediaControlsError> {
-        self.controls
-            .set_metadata(MediaMetadata {
-                title,
-                artist,
-                album,
-                duration,
-                ..Default::default()
-            })
-            .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
-    }
+        pub fn set_metadata(&mut self, song: Option<&Song>) -> Result<(), MediaControlsError> {
+            let cover_url = song.and_then(cover_art).map(|path| file_uri(&path));
+            let metadata = match song {
+                Some(song) => MediaMetadata {
+                    title: Some(&song.title),
+                    artist: song.artist.as_deref(),
+                    album: song.album.as_deref(),
+                    duration: Some(song.duration),
+                    cover_url: cover_url.as_deref(),
+                },
+                None => MediaMetadata::default(),
+            };
+            self.controls
+                .set_metadata(metadata)
+                .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
+        }
 
-    pub fn set_playback_playing(
-        &mut self,
-        position: Option<Duration>,
-    ) -> Result<(), MediaControlsError> {
-        let progress = position.map(MediaPosition);
-        self.controls
-            .set_playback(MediaPlayback::Playing { progress })
-            .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
-    }
+        pub fn set_playback_playing(
+            &mut self,
+            position: Option<Duration>,
+        ) -> Result<(), MediaControlsError> {
+            let progress = position.map(MediaPosition);
+            self.set_playback(MediaPlayback::Playing { progress })
+        }
 
-    pub fn set_playback_paused(
-        &mut self,
-        position: Option<Duration>,
-    ) -> Result<(), MediaControlsError> {
-        let progress = position.map(MediaPosition);
-        self.controls
-            .set_playback(MediaPlayback::Paused { progress })
-            .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
-    }
+        pub fn set_playback_paused(
+            &mut self,
+            position: Option<Duration>,
+        ) -> Result<(), MediaControlsError> {
+            let progress = position.map(MediaPosition);
+            self.set_playback(MediaPlayback::Paused { progress })
+        }
 
-    pub fn set_playback_stopped(&mut self) -> Result<(), MediaControlsError> {
-        self.controls
-            .set_playback(MediaPlayback::Stopped)
-            .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
-    }
+        pub fn set_playback_stopped(&mut self) -> Result<(), MediaControlsError> {
+            self.set_playback(MediaPlayback::Stopped)
+        }
 
-    pub fn update_from_song(
-        &mut self,
-        title: &str,
-        artist: Option<&str>,
-        album: Option<&str>,
-        duration: Duration,
-        playing: bool,
-        position: Duration,
-    ) -> Result<(), MediaControlsError> {
-        self.set_metadata(Some(title), artist, album, Some(duration))?;
-
-        if playing {
-            self.set_playback_playing(Some(position))?;
-        } else {
-            self.set_playback_paused(Some(position))?;
+        fn set_playback(&mut self, playback: MediaPlayback) -> Result<(), MediaControlsError> {
+            self.controls
+                .set_playback(playback)
+                .map_err(|e| MediaControlsError::UpdateFailed(e.to_string()))
         }
 
-        Ok(())
-    }
+        pub fn set_volume(&mut self, _volume: f64) -> Result<(), MediaControlsError> {
+            Ok(())
+        }
 
-    pub fn clear(&mut self) -> Result<(), MediaControlsError> {
-        self.set_metadata(None, None, None, None)?;
-        self.set_playback_stopped()?;
-        Ok(())
+        pub fn set_shuffle(&mut self, _shuffle: bool) -> Result<(), MediaControlsError> {
+            Ok(())
+        }
+
+        pub fn set_repeat(&mut self, _repeat: RepeatMode) -> Result<(), MediaControlsError> {
+            Ok(())
+        }
+
+        pub fn seeked(&mut self, _position: Duration) -> Result<(), MediaControlsError> {
+            Ok(())
+        }
+
+        pub fn set_tracks(&mut self, _tracks: &[Song]) -> Result<(), MediaControlsError> {
+            Ok(())
+        }
+
+        pub fn clear(&mut self) -> Result<(), MediaControlsError> {
+            self.set_metadata(None)?;
+            self.set_playback_stopped()
+        }
     }
 }
@@ -0,0 +1,401 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::edit::MetadataEdit;
+use crate::import::Metadata;
+
+// iTunes-style metadata items, as written by most M4B/M4A taggers
+const TITLE: &[u8; 4] = b"\xa9nam";
+const ARTIST: &[u8; 4] = b"\xa9ART";
+const ALBUM_ARTIST: &[u8; 4] = b"aART";
+const ALBUM: &[u8; 4] = b"\xa9alb";
+const TRACK: &[u8; 4] = b"trkn";
+
+/// `data` type indicators
+const DATA_IMPLICIT: u32 = 0;
+const DATA_UTF8: u32 = 1;
+
+// ============================================================================
+// Boxes
+// ============================================================================
+
+/// A box read into memory, without its header
+struct Atom {
+    kind: [u8; 4],
+    payload: Vec<u8>,
+}
+
+/// Where a top-level box sits in the file
+struct Span {
+    kind: [u8; 4],
+    offset: u64,
+    header_len: u64,
+    size: u64,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The top-level boxes of a file, without reading their payloads
+fn top_level(file: &mut File) -> io::Result<Vec<Span>> {
+    let len = file.metadata()?.len();
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= len {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let (size, header_len) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                0 => (len - offset, 8),
+                1 => {
+                    let mut large = [0u8; 8];
+                    file.read_exact(&mut large)?;
+                    (u64::from_be_bytes(large), 16)
+                }
+                size => (u64::from(size), 8),
+            };
+        if size < header_len || offset + size > len {
+            return Err(invalid("Truncated MP4 box"));
+        }
+        spans.push(Span {
+            kind,
+            offset,
+            header_len,
+            size,
+        });
+        offset += size;
+    }
+    Ok(spans)
+}
+
+/// The boxes inside a container's payload
+fn children(payload: &[u8]) -> io::Result<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut rest = payload;
+    while rest.len() >= 8 {
+        let kind = [rest[4], rest[5], rest[6], rest[7]];
+        let (size, header_len) = match u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) {
+            0 => (rest.len(), 8),
+            1 if rest.len() >= 16 => {
+                let mut large = [0u8; 8];
+                large.copy_from_slice(&rest[8..16]);
+                (
+                    usize::try_from(u64::from_be_bytes(large)).unwrap_or(usize::MAX),
+                    16,
+                )
+            }
+            1 => return Err(invalid("Truncated MP4 box")),
+            size => (size as usize, 8),
+        };
+        if size < header_len || size > rest.len() {
+            return Err(invalid("Truncated MP4 box"));
+        }
+        atoms.push(Atom {
+            kind,
+            payload: rest[header_len..size].to_vec(),
+        });
+        rest = &rest[size..];
+    }
+    Ok(atoms)
+}
+
+fn encode(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 16);
+    match u32::try_from(payload.len() + 8) {
+        Ok(size) => {
+            out.extend(size.to_be_bytes());
+            out.extend(kind);
+        }
+        Err(_) => {
+            out.extend(1u32.to_be_bytes());
+            out.extend(kind);
+            out.extend((payload.len() as u64 + 16).to_be_bytes());
+        }
+    }
+    out.extend(payload);
+    out
+}
+
+fn encode_all(atoms: &[Atom]) -> Vec<u8> {
+    atoms
+        .iter()
+        .flat_map(|atom| encode(&atom.kind, &atom.payload))
+        .collect()
+}
+
+fn find<'a>(atoms: &'a [Atom], kind: &[u8; 4]) -> Option<&'a Atom> {
+    atoms.iter().find(|atom| &atom.kind == kind)
+}
+
+/// The index of the first `kind` box, adding an empty one if there is none
+fn find_or_add(atoms: &mut Vec<Atom>, kind: &[u8; 4], payload: impl FnOnce() -> Vec<u8>) -> usize {
+    if let Some(index) = atoms.iter().position(|atom| &atom.kind == kind) {
+        return index;
+    }
+    atoms.push(Atom {
+        kind: *kind,
+        payload: payload(),
+    });
+    atoms.len() - 1
+}
+
+/// `meta` is a full box (version and flags before its children), except in
+/// some QuickTime files
+fn meta_header_len(payload: &[u8]) -> usize {
+    if payload.get(4..8) == Some(b"hdlr".as_slice()) {
+        0
+    } else {
+        4
+    }
+}
+
+/// The `moov` box of a file, where all the metadata lives
+fn read_moov(file: &mut File) -> io::Result<(Vec<Span>, Vec<u8>)> {
+    let spans = top_level(file)?;
+    let moov = spans
+        .iter()
+        .find(|span| &span.kind == b"moov")
+        .ok_or_else(|| invalid("No moov box"))?;
+    let mut payload = vec![0u8; (moov.size - moov.header_len) as usize];
+    file.seek(SeekFrom::Start(moov.offset + moov.header_len))?;
+    file.read_exact(&mut payload)?;
+    Ok((spans, payload))
+}
+
+// ============================================================================
+// Reading
+// ============================================================================
+
+/// Tags and duration of an MP4 (M4B/M4A) file
+pub fn read_mp4_metadata(path: &Path) -> io::Result<Metadata> {
+    let (_, moov) = read_moov(&mut File::open(path)?)?;
+    let atoms = children(&moov)?;
+
+    let mut metadata = Metadata {
+        duration: find(&atoms, b"mvhd").and_then(|mvhd| mvhd_duration(&mvhd.payload)),
+        ..Default::default()
+    };
+
+    let Some(udta) = find(&atoms, b"udta") else {
+        return Ok(metadata);
+    };
+    let udta = children(&udta.payload)?;
+    let Some(meta) = find(&udta, b"meta") else {
+        return Ok(metadata);
+    };
+    let meta = children(&meta.payload[meta_header_len(&meta.payload)..])?;
+    let Some(ilst) = find(&meta, b"ilst") else {
+        return Ok(metadata);
+    };
+
+    for item in children(&ilst.payload)? {
+        let data = children(&item.payload)?;
+        let Some(value) = find(&data, b"data").and_then(|data| data.payload.get(8..)) else {
+            continue;
+        };
+        let text = || Some(String::from_utf8_lossy(value).into_owned());
+        match &item.kind {
+            TITLE => metadata.title = text(),
+            ARTIST => metadata.artist = text(),
+            ALBUM_ARTIST => metadata.album_artist = text(),
+            ALBUM => metadata.album = text(),
+            TRACK if value.len() >= 4 => {
+                let track = u16::from_be_bytes([value[2], value[3]]);
+                metadata.track_number = (track > 0).then_some(u32::from(track));
+            }
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+fn mvhd_duration(payload: &[u8]) -> Option<Duration> {
+    let be_u32 = |at: usize| -> Option<u64> {
+        Some(u64::from(u32::from_be_bytes(
+            payload.get(at..at + 4)?.try_into().ok()?,
+        )))
+    };
+    let (timescale, duration) = match payload.first()? {
+        0 => (be_u32(12)?, be_u32(16)?),
+        1 => (
+            be_u32(20)?,
+            u64::from_be_bytes(payload.get(24..32)?.try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    (timescale > 0 && duration > 0)
+        .then(|| Duration::from_secs_f64(duration as f64 / timescale as f64))
+}
+
+// ============================================================================
+// Writing
+// ============================================================================
+
+/// Write the edited tags into the file's `ilst`, rewriting the file through a
+/// temporary copy. Chunk offsets are moved along when `moov` comes before the
+/// audio data and changes size.
+pub fn write_mp4_metadata(path: &Path, edit: &MetadataEdit) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let (spans, moov) = read_moov(&mut file)?;
+    let Some(moov_span) = spans.iter().find(|span| &span.kind == b"moov") else {
+        return Err(invalid("No moov box"));
+    };
+
+    let mut atoms = children(&moov)?;
+    edit_ilst(&mut atoms, edit)?;
+
+    let moov_end = moov_span.offset + moov_span.size;
+    let mut new_moov = encode(b"moov", &encode_all(&atoms));
+    let delta = new_moov.len() as i64 - moov_span.size as i64;
+    if delta != 0 && spans.iter().any(|span| span.offset >= moov_end) {
+        shift_chunk_offsets(&mut atoms, moov_end, delta)?;
+        new_moov = encode(b"moov", &encode_all(&atoms));
+    }
+
+    let temp_path = temp_path(path);
+    let written = (|| {
+        let mut out = BufWriter::new(File::create(&temp_path)?);
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(&mut (&mut file).take(moov_span.offset), &mut out)?;
+        out.write_all(&new_moov)?;
+        file.seek(SeekFrom::Start(moov_end))?;
+        io::copy(&mut file, &mut out)?;
+        out.flush()
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    fs::rename(&temp_path, path)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+fn edit_ilst(moov: &mut Vec<Atom>, edit: &MetadataEdit) -> io::Result<()> {
+    let udta_index = find_or_add(moov, b"udta", Vec::new);
+    let mut udta = children(&moov[udta_index].payload)?;
+
+    let meta_index = find_or_add(&mut udta, b"meta", || {
+        let mut payload = vec![0u8; 4];
+        payload.extend(encode(b"hdlr", &itunes_handler()));
+        payload
+    });
+    let meta_payload = &udta[meta_index].payload;
+    let header_len = meta_header_len(meta_payload);
+    let header = meta_payload[..header_len].to_vec();
+    let mut meta = children(&meta_payload[header_len..])?;
+
+    let ilst_index = find_or_add(&mut meta, b"ilst", Vec::new);
+    let mut items = children(&meta[ilst_index].payload)?;
+
+    if let Some(title) = &edit.title {
+        set_item(&mut items, TITLE, Some(text_data(title)));
+    }
+    if let Some(artist) = &edit.artist {
+        set_item(&mut items, ARTIST, artist.as_deref().map(text_data));
+    }
+    if let Some(album) = &edit.album {
+        set_item(&mut items, ALBUM, album.as_deref().map(text_data));
+    }
+    if let Some(track_number) = edit.track_number {
+        set_item(&mut items, TRACK, track_number.map(track_data));
+    }
+
+    meta[ilst_index].payload = encode_all(&items);
+    let mut meta_payload = header;
+    meta_payload.extend(encode_all(&meta));
+    udta[meta_index].payload = meta_payload;
+    moov[udta_index].payload = encode_all(&udta);
+    Ok(())
+}
+
+/// The `hdlr` iTunes expects in `meta`
+fn itunes_handler() -> Vec<u8> {
+    let mut payload = vec![0u8; 8];
+    payload.extend(b"mdirappl");
+    payload.extend([0u8; 9]);
+    payload
+}
+
+/// Replace an item's value, or remove the item for `None`
+fn set_item(items: &mut Vec<Atom>, kind: &[u8; 4], data: Option<Vec<u8>>) {
+    items.retain(|item| &item.kind != kind);
+    if let Some(data) = data {
+        items.push(Atom {
+            kind: *kind,
+            payload: encode(b"data", &data),
+        });
+    }
+}
+
+fn text_data(text: &str) -> Vec<u8> {
+    let mut data = DATA_UTF8.to_be_bytes().to_vec();
+    data.extend([0u8; 4]);
+    data.extend(text.as_bytes());
+    data
+}
+
+/// Track number, with no total
+fn track_data(track: u32) -> Vec<u8> {
+    let mut data = DATA_IMPLICIT.to_be_bytes().to_vec();
+    data.extend([0u8; 6]);
+    data.extend(u16::try_from(track).unwrap_or(u16::MAX).to_be_bytes());
+    data.extend([0u8; 4]);
+    data
+}
+
+/// Move every chunk offset at or after `after` by `delta`
+fn shift_chunk_offsets(atoms: &mut [Atom], after: u64, delta: i64) -> io::Result<()> {
+    for atom in atoms {
+        match &atom.kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                let mut inner = children(&atom.payload)?;
+                shift_chunk_offsets(&mut inner, after, delta)?;
+                atom.payload = encode_all(&inner);
+            }
+            b"stco" => shift_offset_table(&mut atom.payload, 4, after, delta)?,
+            b"co64" => shift_offset_table(&mut atom.payload, 8, after, delta)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn shift_offset_table(payload: &mut [u8], width: usize, after: u64, delta: i64) -> io::Result<()> {
+    let count = payload
+        .get(4..8)
+        .map(|count| u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize)
+        .ok_or_else(|| invalid("Truncated chunk offset table"))?;
+    let entries = payload
+        .get_mut(8..8 + count * width)
+        .ok_or_else(|| invalid("Truncated chunk offset table"))?;
+
+    for entry in entries.chunks_exact_mut(width) {
+        let offset = entry
+            .iter()
+            .fold(0u64, |offset, byte| offset << 8 | u64::from(*byte));
+        if offset < after {
+            continue;
+        }
+        let shifted = offset
+            .checked_add_signed(delta)
+            .ok_or_else(|| invalid("Chunk offset out of range"))?;
+        if width == 4 {
+            let shifted =
+                u32::try_from(shifted).map_err(|_| invalid("Chunk offset out of range"))?;
+            entry.copy_from_slice(&shifted.to_be_bytes());
+        } else {
+            entry.copy_from_slice(&shifted.to_be_bytes());
+        }
+    }
+    Ok(())
+}
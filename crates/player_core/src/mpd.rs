@@ -0,0 +1,951 @@
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::audio_engine::AudioPlayerEvent;
+use crate::playback::{PlaybackState, RepeatMode};
+use crate::remote::{RemoteCall, RemoteRequest, RemoteSong, RemoteStatus, CALL_TIMEOUT};
+
+const GREETING: &str = "OK MPD 0.23.0\n";
+
+/// Longer lines end the connection, so a client can't exhaust memory
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+/// The most a command list may hold, counting each command's bytes, as MPD's
+/// default `max_command_list_size`. Longer lists end the connection.
+const MAX_COMMAND_LIST_BYTES: usize = 2 * 1024 * 1024;
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "addid",
+    "clear",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "consume",
+    "currentsong",
+    "decoders",
+    "delete",
+    "deleteid",
+    "find",
+    "idle",
+    "list",
+    "next",
+    "noidle",
+    "notcommands",
+    "outputs",
+    "password",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistid",
+    "playlistinfo",
+    "plchanges",
+    "previous",
+    "random",
+    "repeat",
+    "replay_gain_status",
+    "search",
+    "seek",
+    "seekcur",
+    "seekid",
+    "setvol",
+    "single",
+    "stats",
+    "status",
+    "stop",
+    "tagtypes",
+    "urlhandlers",
+];
+
+/// Commands that work before the password is given
+const PUBLIC_COMMANDS: &[&str] = &["close", "commands", "notcommands", "password", "ping"];
+
+// ============================================================================
+// Subsystems
+// ============================================================================
+
+/// What changed, for clients waiting in `idle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MpdSubsystem {
+    /// Playback started, paused, stopped, seeked or moved to another song
+    Player,
+    /// The queue changed
+    Playlist,
+    /// The volume changed
+    Mixer,
+    /// Shuffle or repeat changed
+    Options,
+}
+
+impl MpdSubsystem {
+    const ALL: [MpdSubsystem; 4] = [
+        MpdSubsystem::Player,
+        MpdSubsystem::Playlist,
+        MpdSubsystem::Mixer,
+        MpdSubsystem::Options,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            MpdSubsystem::Player => "player",
+            MpdSubsystem::Playlist => "playlist",
+            MpdSubsystem::Mixer => "mixer",
+            MpdSubsystem::Options => "options",
+        }
+    }
+}
+
+// ============================================================================
+// Server
+// ============================================================================
+
+enum Input {
+    Line(String),
+    Changed(MpdSubsystem),
+    Closed,
+}
+
+struct Shared {
+    password: Option<String>,
+    on_call: Box<dyn Fn(RemoteCall) + Send + Sync>,
+    clients: Mutex<Vec<Sender<Input>>>,
+    /// Bumped whenever the playlist changes, for `status`
+    playlist_version: AtomicU64,
+    stopped: AtomicBool,
+}
+
+/// A subset of the MPD protocol, so MPD clients can drive the player.
+///
+/// Commands become the same `RemoteCall`s as the local control API (see
+/// `remote`), passed to `on_call` on the server's threads. MPD's playlist is
+/// the playing song followed by the queue, and a song's `Id` is its position.
+pub struct MpdServer {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl MpdServer {
+    pub fn start(
+        address: &str,
+        password: Option<&str>,
+        on_call: impl Fn(RemoteCall) + Send + Sync + 'static,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            password: password.map(str::to_string),
+            on_call: Box::new(on_call),
+            clients: Mutex::new(Vec::new()),
+            playlist_version: AtomicU64::new(1),
+            stopped: AtomicBool::new(false),
+        });
+
+        let accept_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let shared = accept_shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_client(stream, &shared) {
+                        if e.kind() != io::ErrorKind::BrokenPipe {
+                            eprintln!("MPD connection failed: {}", e);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(Self { address, shared })
+    }
+
+    /// Where the server is listening, with the port it was given if bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Wake clients waiting in `idle` for `subsystem`
+    pub fn changed(&self, subsystem: MpdSubsystem) {
+        if subsystem == MpdSubsystem::Playlist {
+            self.shared.playlist_version.fetch_add(1, Ordering::SeqCst);
+        }
+        lock_clients(&self.shared).retain(|client| client.send(Input::Changed(subsystem)).is_ok());
+    }
+
+    pub fn broadcast(&self, event: &AudioPlayerEvent) {
+        self.changed(MpdSubsystem::Player);
+        // The playlist starts with the playing song
+        if let AudioPlayerEvent::SongChanged(_) = event {
+            self.changed(MpdSubsystem::Playlist);
+        }
+    }
+}
+
+impl Drop for MpdServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        // Wake the accept loop so it sees the server stopped
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+fn lock_clients(shared: &Shared) -> MutexGuard<'_, Vec<Sender<Input>>> {
+    shared.clients.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Lines are read on their own thread, so a client waiting in `idle` can be
+/// woken by either a change or its `noidle`
+fn serve_client(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let (sender, input) = mpsc::channel();
+    lock_clients(shared).push(sender.clone());
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    thread::spawn(move || {
+        while let Some(line) = read_line(&mut reader) {
+            if sender.send(Input::Line(line)).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(Input::Closed);
+    });
+
+    let mut session = Session {
+        shared,
+        writer: stream,
+        authorized: shared.password.is_none(),
+        pending: HashSet::new(),
+        idle: None,
+        command_list: None,
+    };
+    let result = session.run(input);
+    // Ends the reader thread too
+    let _ = session.writer.shutdown(Shutdown::Both);
+    result
+}
+
+/// The next line without its line ending. `None` once the client is gone, or
+/// has sent a line longer than `MAX_LINE_BYTES`.
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    match reader.by_ref().take(MAX_LINE_BYTES).read_line(&mut line) {
+        Ok(0) | Err(_) => return None,
+        Ok(_) => {}
+    }
+    // Cut short by the limit, or by the client going away mid-line
+    if !line.ends_with('\n') {
+        return None;
+    }
+    line.pop();
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    Some(line)
+}
+
+// ============================================================================
+// Sessions
+// ============================================================================
+
+const ACK_ARG: u32 = 2;
+const ACK_PASSWORD: u32 = 3;
+const ACK_PERMISSION: u32 = 4;
+const ACK_UNKNOWN: u32 = 5;
+const ACK_NO_EXIST: u32 = 50;
+const ACK_SYSTEM: u32 = 52;
+
+/// A failed command, reported as `ACK [code@index] {command} message`
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+impl Ack {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+enum Reply {
+    Lines(String),
+    Close,
+}
+
+struct CommandList {
+    /// Whether each successful command is followed by `list_OK`
+    list_ok: bool,
+    commands: Vec<String>,
+    /// Bytes in `commands`, kept under `MAX_COMMAND_LIST_BYTES`
+    size: usize,
+}
+
+struct Session<'a> {
+    shared: &'a Shared,
+    writer: TcpStream,
+    authorized: bool,
+    /// Changes not yet reported by an `idle`
+    pending: HashSet<MpdSubsystem>,
+    /// The subsystems an `idle` is waiting for
+    idle: Option<HashSet<MpdSubsystem>>,
+    command_list: Option<CommandList>,
+}
+
+impl Session<'_> {
+    fn run(&mut self, input: Receiver<Input>) -> io::Result<()> {
+        self.write(GREETING)?;
+        while let Ok(input) = input.recv() {
+            let keep_open = match input {
+                Input::Line(line) => self.handle_line(&line)?,
+                Input::Changed(subsystem) => {
+                    self.pending.insert(subsystem);
+                    self.report_idle(false)?;
+                    true
+                }
+                Input::Closed => false,
+            };
+            if !keep_open {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, response: &str) -> io::Result<()> {
+        self.writer.write_all(response.as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// Returns false once the connection should close
+    fn handle_line(&mut self, line: &str) -> io::Result<bool> {
+        let line = line.trim();
+        if self.idle.is_some() {
+            // Anything but `noidle` while idle ends the connection, as in MPD
+            if line == "noidle" {
+                self.report_idle(true)?;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+
+        if let Some(command_list) = &mut self.command_list {
+            if line != "command_list_end" {
+                command_list.size += line.len();
+                if command_list.size > MAX_COMMAND_LIST_BYTES {
+                    return Ok(false);
+                }
+                command_list.commands.push(line.to_string());
+                return Ok(true);
+            }
+            let command_list = self.command_list.take().unwrap_or(CommandList {
+                list_ok: false,
+                commands: Vec::new(),
+                size: 0,
+            });
+            return self.run_commands(command_list);
+        }
+
+        match line {
+            "command_list_begin" | "command_list_ok_begin" => {
+                self.command_list = Some(CommandList {
+                    list_ok: line == "command_list_ok_begin",
+                    commands: Vec::new(),
+                    size: 0,
+                });
+                Ok(true)
+            }
+            "noidle" => Ok(true),
+            _ if line == "idle" || line.starts_with("idle ") => {
+                match self.start_idle(line) {
+                    Ok(()) => self.report_idle(false)?,
+                    Err(ack) => self.write(&format_ack(&ack, 0, "idle"))?,
+                }
+                Ok(true)
+            }
+            _ => self.run_commands(CommandList {
+                list_ok: false,
+                commands: vec![line.to_string()],
+                size: line.len(),
+            }),
+        }
+    }
+
+    fn start_idle(&mut self, line: &str) -> Result<(), Ack> {
+        let args = split_args(line)?;
+        // Subsystems the player never reports are waited on forever, as in MPD
+        let filter = MpdSubsystem::ALL
+            .into_iter()
+            .filter(|subsystem| {
+                args.len() == 1 || args[1..].iter().any(|name| name == subsystem.name())
+            })
+            .collect();
+        if !self.authorized {
+            return Err(Ack::new(
+                ACK_PERMISSION,
+                "you don't have permission for \"idle\"",
+            ));
+        }
+        self.idle = Some(filter);
+        Ok(())
+    }
+
+    /// Answer a waiting `idle` once something it waits for changed, or at
+    /// once for `noidle`
+    fn report_idle(&mut self, now: bool) -> io::Result<()> {
+        let Some(filter) = &self.idle else {
+            return Ok(());
+        };
+        let changed: Vec<MpdSubsystem> = MpdSubsystem::ALL
+            .into_iter()
+            .filter(|subsystem| self.pending.contains(subsystem) && filter.contains(subsystem))
+            .collect();
+        if changed.is_empty() && !now {
+            return Ok(());
+        }
+
+        let mut response = String::new();
+        for subsystem in changed {
+            self.pending.remove(&subsystem);
+            response.push_str(&format!("changed: {}\n", subsystem.name()));
+        }
+        response.push_str("OK\n");
+        self.idle = None;
+        self.write(&response)
+    }
+
+    fn run_commands(&mut self, command_list: CommandList) -> io::Result<bool> {
+        let mut response = String::new();
+        for (index, command) in command_list.commands.iter().enumerate() {
+            match self.execute(command) {
+                Ok(Reply::Lines(lines)) => {
+                    response.push_str(&lines);
+                    if command_list.list_ok {
+                        response.push_str("list_OK\n");
+                    }
+                }
+                Ok(Reply::Close) => return Ok(false),
+                Err(ack) => {
+                    let name = command.split_whitespace().next().unwrap_or("");
+                    response.push_str(&format_ack(&ack, index, name));
+                    self.write(&response)?;
+                    return Ok(true);
+                }
+            }
+        }
+        response.push_str("OK\n");
+        self.write(&response)?;
+        Ok(true)
+    }
+
+    // ========================================================================
+    // Commands
+    // ========================================================================
+
+    fn execute(&mut self, command: &str) -> Result<Reply, Ack> {
+        let args = split_args(command)?;
+        let Some((name, args)) = args.split_first() else {
+            return Err(Ack::new(ACK_UNKNOWN, "No command given"));
+        };
+        let name = name.as_str();
+        if !self.authorized && !PUBLIC_COMMANDS.contains(&name) {
+            return Err(Ack::new(
+                ACK_PERMISSION,
+                format!("you don't have permission for \"{}\"", name),
+            ));
+        }
+
+        let lines = match name {
+            "ping" => String::new(),
+            "close" => return Ok(Reply::Close),
+            "password" => {
+                let [password] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                if self.shared.password.as_deref() != Some(password.as_str()) {
+                    return Err(Ack::new(ACK_PASSWORD, "incorrect password"));
+                }
+                self.authorized = true;
+                String::new()
+            }
+            "commands" => COMMANDS
+                .iter()
+                .map(|command| format!("command: {}\n", command))
+                .collect(),
+            "notcommands" | "urlhandlers" | "decoders" => String::new(),
+            "tagtypes" => ["Artist", "Album", "Title", "Track"]
+                .iter()
+                .map(|tag| format!("tagtype: {}\n", tag))
+                .collect(),
+            "outputs" => {
+                "outputid: 0\noutputname: Player\nplugin: player\noutputenabled: 1\n".to_string()
+            }
+            "replay_gain_status" => "replay_gain_mode: off\n".to_string(),
+            "status" => self.status()?,
+            "stats" => self.stats()?,
+            "currentsong" => {
+                let status = self.status_call()?;
+                match &status.song {
+                    Some(song) => song_lines(song, Some(0)),
+                    None => String::new(),
+                }
+            }
+            "playlistinfo" | "playlistid" | "plchanges" => self.playlist_info(name, args)?,
+            "play" | "playid" => self.play(args)?,
+            "pause" => {
+                let request = match args {
+                    [] => RemoteRequest::Toggle,
+                    [pause] if pause == "1" => RemoteRequest::Pause,
+                    [pause] if pause == "0" => RemoteRequest::Play,
+                    _ => return Err(wrong_arguments(name)),
+                };
+                self.send(request)?
+            }
+            "stop" => self.send(RemoteRequest::Stop)?,
+            "next" => self.send(RemoteRequest::Next)?,
+            "previous" => self.send(RemoteRequest::Previous)?,
+            "seek" | "seekid" => {
+                let [position, time] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let status = self.status_call()?;
+                if status.song.is_none() || parse_number::<usize>(position)? != 0 {
+                    return Err(Ack::new(ACK_ARG, "Only the playing song can be seeked"));
+                }
+                self.send(RemoteRequest::Seek {
+                    position: parse_number(time)?,
+                })?
+            }
+            "seekcur" => {
+                let [time] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let request = if time.starts_with('+') || time.starts_with('-') {
+                    RemoteRequest::SeekBy {
+                        offset: parse_number(time)?,
+                    }
+                } else {
+                    RemoteRequest::Seek {
+                        position: parse_number(time)?,
+                    }
+                };
+                self.send(request)?
+            }
+            "setvol" => {
+                let [volume] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let volume: u8 = parse_number(volume)?;
+                self.send(RemoteRequest::SetVolume {
+                    volume: f32::from(volume.min(100)) / 100.0,
+                })?
+            }
+            "random" => {
+                let [shuffle] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                self.send(RemoteRequest::SetShuffle {
+                    shuffle: parse_flag(shuffle)?,
+                })?
+            }
+            "repeat" | "single" => {
+                let [flag] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let on = parse_flag(flag)?;
+                let current = self.status_call()?.repeat;
+                let repeat = match (name, on) {
+                    ("repeat", false) => RepeatMode::Off,
+                    ("repeat", true) if current == RepeatMode::Off => RepeatMode::All,
+                    ("single", true) => RepeatMode::One,
+                    ("single", false) if current == RepeatMode::One => RepeatMode::All,
+                    _ => current,
+                };
+                self.send(RemoteRequest::SetRepeat { repeat })?
+            }
+            "consume" => match args {
+                [flag] if flag == "0" => String::new(),
+                _ => return Err(Ack::new(ACK_ARG, "Consume mode isn't supported")),
+            },
+            "add" | "addid" => {
+                let [uri] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let song = self
+                    .all_songs()?
+                    .into_iter()
+                    .find(|song| song.file == Path::new(uri))
+                    .ok_or_else(|| Ack::new(ACK_NO_EXIST, "No such song"))?;
+                let queue: Vec<RemoteSong> =
+                    self.call(RemoteRequest::QueueAdd { ids: vec![song.id] })?;
+                if name == "addid" {
+                    let offset = usize::from(self.status_call()?.song.is_some());
+                    format!("Id: {}\n", offset + queue.len() - 1)
+                } else {
+                    String::new()
+                }
+            }
+            "delete" | "deleteid" => {
+                let [position] = args else {
+                    return Err(wrong_arguments(name));
+                };
+                let status = self.status_call()?;
+                let index = queue_index(&status, parse_number(position)?)
+                    .ok_or_else(|| Ack::new(ACK_ARG, "The playing song can't be deleted"))?;
+                self.send(RemoteRequest::QueueRemove { index })?
+            }
+            "clear" => {
+                self.send(RemoteRequest::QueueClear)?;
+                self.send(RemoteRequest::Stop)?
+            }
+            "find" | "search" => {
+                let filters = parse_filters(args)?;
+                let exact = name == "find";
+                self.all_songs()?
+                    .iter()
+                    .filter(|song| matches_filters(song, &filters, exact))
+                    .map(|song| song_lines(song, None))
+                    .collect()
+            }
+            "list" => {
+                let Some((tag, args)) = args.split_first() else {
+                    return Err(wrong_arguments(name));
+                };
+                // Grouping is left out; values are listed once each
+                let end = args
+                    .iter()
+                    .position(|arg| arg == "group")
+                    .unwrap_or(args.len());
+                let filters = parse_filters(&args[..end])?;
+                let label = tag_label(tag)?;
+                let values: BTreeSet<String> = self
+                    .all_songs()?
+                    .iter()
+                    .filter(|song| matches_filters(song, &filters, true))
+                    .flat_map(|song| tag_values(song, tag).unwrap_or_default())
+                    .collect();
+                values
+                    .iter()
+                    .map(|value| format!("{}: {}\n", label, value))
+                    .collect()
+            }
+            _ => {
+                return Err(Ack::new(
+                    ACK_UNKNOWN,
+                    format!("unknown command \"{}\"", name),
+                ))
+            }
+        };
+        Ok(Reply::Lines(lines))
+    }
+
+    fn status(&self) -> Result<String, Ack> {
+        let status = self.status_call()?;
+        let state = match status.state {
+            PlaybackState::Playing => "play",
+            PlaybackState::Paused => "pause",
+            PlaybackState::Stopped => "stop",
+        };
+        let playlist_length = usize::from(status.song.is_some()) + status.queue.len();
+        let mut lines = format!(
+            "volume: {}\nrepeat: {}\nrandom: {}\nsingle: {}\nconsume: 0\nplaylist: {}\nplaylistlength: {}\nstate: {}\n",
+            (status.volume * 100.0).round() as u32,
+            u8::from(status.repeat != RepeatMode::Off),
+            u8::from(status.shuffle),
+            u8::from(status.repeat == RepeatMode::One),
+            self.shared.playlist_version.load(Ordering::SeqCst),
+            playlist_length,
+            state,
+        );
+        if let Some(song) = &status.song {
+            lines.push_str(&format!(
+                "song: 0\nsongid: 0\ntime: {}:{}\nelapsed: {:.3}\nduration: {:.3}\n",
+                status.position as u64, song.duration as u64, status.position, song.duration,
+            ));
+            if !status.queue.is_empty() {
+                lines.push_str("nextsong: 1\nnextsongid: 1\n");
+            }
+        }
+        Ok(lines)
+    }
+
+    fn stats(&self) -> Result<String, Ack> {
+        let songs = self.all_songs()?;
+        let artists: HashSet<&str> = songs.iter().filter_map(|s| s.artist.as_deref()).collect();
+        let albums: HashSet<&str> = songs.iter().filter_map(|s| s.album.as_deref()).collect();
+        let playtime: f64 = songs.iter().map(|song| song.duration).sum();
+        Ok(format!(
+            "artists: {}\nalbums: {}\nsongs: {}\nuptime: 0\nplaytime: 0\ndb_playtime: {}\n",
+            artists.len(),
+            albums.len(),
+            songs.len(),
+            playtime as u64
+        ))
+    }
+
+    fn playlist_info(&self, name: &str, args: &[String]) -> Result<String, Ack> {
+        let status = self.status_call()?;
+        let playlist: Vec<&RemoteSong> = status.song.iter().chain(&status.queue).collect();
+        let range = match (name, args) {
+            (_, []) | ("plchanges", [_]) => 0..playlist.len(),
+            ("playlistinfo", [range]) if range.contains(':') => {
+                let (start, end) = range.split_once(':').unwrap_or_default();
+                let start = parse_number(start)?;
+                let end = if end.is_empty() {
+                    playlist.len()
+                } else {
+                    parse_number(end)?
+                };
+                start..end.min(playlist.len())
+            }
+            ("playlistinfo" | "playlistid", [position]) => {
+                let position: usize = parse_number(position)?;
+                if position >= playlist.len() {
+                    return Err(Ack::new(ACK_ARG, "Bad song index"));
+                }
+                position..position + 1
+            }
+            _ => return Err(wrong_arguments(name)),
+        };
+        Ok(playlist
+            .iter()
+            .enumerate()
+            .skip(range.start)
+            .take(range.len())
+            .map(|(position, song)| song_lines(song, Some(position)))
+            .collect())
+    }
+
+    fn play(&self, args: &[String]) -> Result<String, Ack> {
+        let status = self.status_call()?;
+        let position = match args {
+            [] => None,
+            [position] => Some(parse_number::<usize>(position)?),
+            _ => return Err(wrong_arguments("play")),
+        };
+        let queued = match position {
+            Some(position) => queue_index(&status, position),
+            // With nothing playing, start on the queue
+            None if status.song.is_none() && !status.queue.is_empty() => Some(0),
+            None => None,
+        };
+
+        match queued {
+            Some(index) => {
+                let song = status
+                    .queue
+                    .get(index)
+                    .ok_or_else(|| Ack::new(ACK_ARG, "Bad song index"))?;
+                self.send(RemoteRequest::QueueRemove { index })?;
+                self.send(RemoteRequest::PlaySong { id: song.id })
+            }
+            None => self.send(RemoteRequest::Play),
+        }
+    }
+
+    // ========================================================================
+    // Calls
+    // ========================================================================
+
+    fn call<T: DeserializeOwned>(&self, request: RemoteRequest) -> Result<T, Ack> {
+        let (call, answer) = RemoteCall::new(request);
+        (self.shared.on_call)(call);
+        let result = answer
+            .recv_timeout(CALL_TIMEOUT)
+            .map_err(|_| Ack::new(ACK_SYSTEM, "The player didn't answer"))?
+            .map_err(|message| Ack::new(ACK_NO_EXIST, message))?;
+        serde_json::from_value(result).map_err(|e| Ack::new(ACK_SYSTEM, e.to_string()))
+    }
+
+    /// Make a call whose answer doesn't matter
+    fn send(&self, request: RemoteRequest) -> Result<String, Ack> {
+        self.call::<Value>(request).map(|_| String::new())
+    }
+
+    fn status_call(&self) -> Result<RemoteStatus, Ack> {
+        self.call(RemoteRequest::Status)
+    }
+
+    fn all_songs(&self) -> Result<Vec<RemoteSong>, Ack> {
+        self.call(RemoteRequest::Search {
+            query: String::new(),
+            limit: usize::MAX,
+        })
+    }
+}
+
+// ============================================================================
+// Formatting and Parsing
+// ============================================================================
+
+fn format_ack(ack: &Ack, index: usize, command: &str) -> String {
+    format!(
+        "ACK [{}@{}] {{{}}} {}\n",
+        ack.code, index, command, ack.message
+    )
+}
+
+fn wrong_arguments(command: &str) -> Ack {
+    Ack::new(
+        ACK_ARG,
+        format!("wrong number of arguments for \"{}\"", command),
+    )
+}
+
+/// Where a playlist position is in the queue, or `None` for the playing song
+fn queue_index(status: &RemoteStatus, position: usize) -> Option<usize> {
+    match status.song {
+        Some(_) => position.checked_sub(1),
+        None => Some(position),
+    }
+}
+
+/// A song's tags, and its place in the playlist if it has one
+fn song_lines(song: &RemoteSong, position: Option<usize>) -> String {
+    let mut lines = format!("file: {}\nTitle: {}\n", song.file.display(), song.title);
+    if let Some(artist) = &song.artist {
+        lines.push_str(&format!("Artist: {}\n", artist));
+    }
+    if let Some(album) = &song.album {
+        lines.push_str(&format!("Album: {}\n", album));
+    }
+    if let Some(track_number) = song.track_number {
+        lines.push_str(&format!("Track: {}\n", track_number));
+    }
+    lines.push_str(&format!(
+        "Time: {}\nduration: {:.3}\n",
+        song.duration.round() as u64,
+        song.duration
+    ));
+    if let Some(position) = position {
+        lines.push_str(&format!("Pos: {}\nId: {}\n", position, position));
+    }
+    lines
+}
+
+/// Split a command line into words; quoted words may hold spaces, with `\"`
+/// and `\\` escaped
+fn split_args(line: &str) -> Result<Vec<String>, Ack> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else {
+            return Ok(args);
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => arg.extend(chars.next()),
+                    Some(c) => arg.push(c),
+                    None => return Err(Ack::new(ACK_ARG, "Missing closing '\"'")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str) -> Result<T, Ack> {
+    arg.trim_start_matches('+')
+        .parse()
+        .map_err(|_| Ack::new(ACK_ARG, format!("Number expected: {}", arg)))
+}
+
+fn parse_flag(arg: &str) -> Result<bool, Ack> {
+    match arg {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Ack::new(
+            ACK_ARG,
+            format!("Boolean (0/1) expected: {}", arg),
+        )),
+    }
+}
+
+/// `TAG VALUE` pairs; filter expressions aren't supported
+fn parse_filters(args: &[String]) -> Result<Vec<(String, String)>, Ack> {
+    if !args.len().is_multiple_of(2) || args.first().is_some_and(|arg| arg.starts_with('(')) {
+        return Err(Ack::new(ACK_ARG, "Expected TAG VALUE pairs"));
+    }
+    args.chunks(2)
+        .map(|pair| {
+            let tag = pair[0].to_lowercase();
+            tag_label(&tag)?;
+            Ok((tag, pair[1].clone()))
+        })
+        .collect()
+}
+
+fn tag_label(tag: &str) -> Result<&'static str, Ack> {
+    match tag.to_lowercase().as_str() {
+        "artist" => Ok("Artist"),
+        "albumartist" => Ok("AlbumArtist"),
+        "album" => Ok("Album"),
+        "title" => Ok("Title"),
+        "file" => Ok("file"),
+        "any" => Ok("any"),
+        _ => Err(Ack::new(ACK_ARG, format!("Unknown tag type: {}", tag))),
+    }
+}
+
+fn tag_values(song: &RemoteSong, tag: &str) -> Option<Vec<String>> {
+    let file = song.file.display().to_string();
+    let values = match tag.to_lowercase().as_str() {
+        "artist" | "albumartist" => song.artist.clone().into_iter().collect(),
+        "album" => song.album.clone().into_iter().collect(),
+        "title" => vec![song.title.clone()],
+        "file" => vec![file],
+        "any" => [
+            Some(song.title.clone()),
+            song.artist.clone(),
+            song.album.clone(),
+            Some(file),
+        ]
+        .into_iter()
+        .flatten()
+        .collect(),
+        _ => return None,
+    };
+    Some(values)
+}
+
+/// `find` compares whole values; `search` looks for them anywhere, ignoring case
+fn matches_filters(song: &RemoteSong, filters: &[(String, String)], exact: bool) -> bool {
+    filters.iter().all(|(tag, wanted)| {
+        let wanted_lower = wanted.to_lowercase();
+        tag_values(song, tag)
+            .unwrap_or_default()
+            .iter()
+            .any(|value| {
+                if exact {
+                    value == wanted
+                } else {
+                    value.to_lowercase().contains(&wanted_lower)
+                }
+            })
+    })
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use zbus::blocking::connection::Builder;
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, Value};
+use zbus::{fdo, interface};
+
+use crate::library::{Song, SongId, MAX_RATING};
+use crate::media_controls::{cover_art, file_uri, MediaControlsError, MediaKeyEvent};
+use crate::playback::{PlaybackState, RepeatMode};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.player";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PATH_PREFIX: &str = "/org/mpris/MediaPlayer2/Track/";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+type EventHandler = Arc<dyn Fn(MediaKeyEvent) + Send + Sync>;
+
+// ============================================================================
+// Player State
+// ============================================================================
+
+/// What the player last reported, read by D-Bus clients
+struct PlayerState {
+    playback: PlaybackState,
+    song: Option<Song>,
+    /// The position when it was last reported, at `position_at`
+    position: Duration,
+    position_at: Instant,
+    volume: f64,
+    shuffle: bool,
+    repeat: RepeatMode,
+    tracks: Vec<Song>,
+}
+
+impl Default for PlayerState {
+    fn default() -> Self {
+        Self {
+            playback: PlaybackState::Stopped,
+            song: None,
+            position: Duration::ZERO,
+            position_at: Instant::now(),
+            volume: 1.0,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+impl PlayerState {
+    fn position(&self) -> Duration {
+        match self.playback {
+            PlaybackState::Playing => self.position + self.position_at.elapsed(),
+            PlaybackState::Paused | PlaybackState::Stopped => self.position,
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<PlayerState>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, PlayerState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn track_path(id: SongId) -> OwnedObjectPath {
+    ObjectPath::try_from(format!("{}{}", TRACK_PATH_PREFIX, id.0))
+        .map(OwnedObjectPath::from)
+        .unwrap_or_else(|_| no_track())
+}
+
+fn no_track() -> OwnedObjectPath {
+    ObjectPath::from_static_str_unchecked(NO_TRACK).into()
+}
+
+fn song_id_from_path(path: &ObjectPath<'_>) -> Option<SongId> {
+    path.as_str()
+        .strip_prefix(TRACK_PATH_PREFIX)?
+        .parse()
+        .ok()
+        .map(SongId)
+}
+
+fn micros(duration: Duration) -> i64 {
+    duration.as_micros().try_into().unwrap_or(i64::MAX)
+}
+
+/// The `xesam:` and `mpris:` metadata for a song
+fn song_metadata(song: &Song) -> HashMap<String, Value<'static>> {
+    let mut metadata = HashMap::new();
+    metadata.insert("mpris:trackid".to_string(), track_path(song.id).into());
+    metadata.insert("mpris:length".to_string(), micros(song.duration).into());
+    metadata.insert("xesam:title".to_string(), song.title.clone().into());
+    metadata.insert("xesam:url".to_string(), file_uri(&song.file.path).into());
+    if let Some(artist) = &song.artist {
+        metadata.insert("xesam:artist".to_string(), vec![artist.clone()].into());
+    }
+    if let Some(album) = &song.album {
+        metadata.insert("xesam:album".to_string(), album.clone().into());
+    }
+    if let Some(track_number) = song.track_number {
+        metadata.insert(
+            "xesam:trackNumber".to_string(),
+            (track_number as i32).into(),
+        );
+    }
+    if let Some(art) = cover_art(song) {
+        metadata.insert("mpris:artUrl".to_string(), file_uri(&art).into());
+    }
+    if song.rating > 0 {
+        let rating = f64::from(song.rating) / f64::from(MAX_RATING);
+        metadata.insert("xesam:userRating".to_string(), rating.into());
+    }
+    metadata.insert(
+        "xesam:useCount".to_string(),
+        (song.stats.play_count as i32).into(),
+    );
+    metadata
+}
+
+// ============================================================================
+// org.mpris.MediaPlayer2
+// ============================================================================
+
+struct Root {
+    on_event: EventHandler,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {
+        (self.on_event)(MediaKeyEvent::Raise);
+    }
+
+    fn quit(&self) {
+        (self.on_event)(MediaKeyEvent::Quit);
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn has_track_list(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn identity(&self) -> String {
+        "Player".to_string()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn desktop_entry(&self) -> String {
+        "player".to_string()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["file".to_string()]
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec!["audio/mpeg".to_string()]
+    }
+}
+
+// ============================================================================
+// org.mpris.MediaPlayer2.Player
+// ============================================================================
+
+struct Player {
+    state: SharedState,
+    on_event: EventHandler,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn next(&self) {
+        (self.on_event)(MediaKeyEvent::Next);
+    }
+
+    fn previous(&self) {
+        (self.on_event)(MediaKeyEvent::Previous);
+    }
+
+    fn pause(&self) {
+        (self.on_event)(MediaKeyEvent::Pause);
+    }
+
+    fn play_pause(&self) {
+        (self.on_event)(MediaKeyEvent::Toggle);
+    }
+
+    fn stop(&self) {
+        (self.on_event)(MediaKeyEvent::Stop);
+    }
+
+    fn play(&self) {
+        (self.on_event)(MediaKeyEvent::Play);
+    }
+
+    /// Seek by `offset` microseconds, backwards if negative
+    fn seek(&self, offset: i64) {
+        (self.on_event)(MediaKeyEvent::SeekBy {
+            offset: Duration::from_micros(offset.unsigned_abs()),
+            forward: offset >= 0,
+        });
+    }
+
+    /// Ignored unless `track_id` is the current song, as the spec requires
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        let current = lock(&self.state).song.as_ref().map(|song| song.id);
+        if position >= 0 && current.is_some() && song_id_from_path(&track_id) == current {
+            (self.on_event)(MediaKeyEvent::SetPosition(Duration::from_micros(
+                position as u64,
+            )));
+        }
+    }
+
+    fn open_uri(&self, uri: String) {
+        (self.on_event)(MediaKeyEvent::OpenUri(uri));
+    }
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        match lock(&self.state).playback {
+            PlaybackState::Playing => "Playing",
+            PlaybackState::Paused => "Paused",
+            PlaybackState::Stopped => "Stopped",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> String {
+        match lock(&self.state).repeat {
+            RepeatMode::Off => "None",
+            RepeatMode::One => "Track",
+            RepeatMode::All => "Playlist",
+        }
+        .to_string()
+    }
+
+    #[zbus(property)]
+    fn set_loop_status(&mut self, value: String) -> fdo::Result<()> {
+        let repeat = match value.as_str() {
+            "None" => RepeatMode::Off,
+            "Track" => RepeatMode::One,
+            "Playlist" => RepeatMode::All,
+            _ => {
+                return Err(fdo::Error::InvalidArgs(format!(
+                    "Unknown loop status {}",
+                    value
+                )))
+            }
+        };
+        lock(&self.state).repeat = repeat;
+        (self.on_event)(MediaKeyEvent::SetRepeat(repeat));
+        Ok(())
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        lock(&self.state).shuffle
+    }
+
+    #[zbus(property)]
+    fn set_shuffle(&mut self, value: bool) {
+        lock(&self.state).shuffle = value;
+        (self.on_event)(MediaKeyEvent::SetShuffle(value));
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, Value<'static>> {
+        match &lock(&self.state).song {
+            Some(song) => song_metadata(song),
+            None => HashMap::from([("mpris:trackid".to_string(), no_track().into())]),
+        }
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        lock(&self.state).volume
+    }
+
+    #[zbus(property)]
+    fn set_volume(&mut self, value: f64) {
+        let volume = value.clamp(0.0, 1.0);
+        lock(&self.state).volume = volume;
+        (self.on_event)(MediaKeyEvent::SetVolume(volume));
+    }
+
+    /// Clients extrapolate the position between `Seeked` signals
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        micros(lock(&self.state).position())
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        !lock(&self.state).tracks.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        !lock(&self.state).tracks.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        let state = lock(&self.state);
+        state.song.is_some() || !state.tracks.is_empty()
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        lock(&self.state).song.is_some()
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        lock(&self.state).song.is_some()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+// ============================================================================
+// org.mpris.MediaPlayer2.TrackList
+// ============================================================================
+
+struct TrackList {
+    state: SharedState,
+    on_event: EventHandler,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.TrackList")]
+impl TrackList {
+    fn get_tracks_metadata(
+        &self,
+        track_ids: Vec<OwnedObjectPath>,
+    ) -> Vec<HashMap<String, Value<'static>>> {
+        let state = lock(&self.state);
+        track_ids
+            .iter()
+            .filter_map(|path| {
+                let id = song_id_from_path(path)?;
+                state.tracks.iter().find(|song| song.id == id)
+            })
+            .map(song_metadata)
+            .collect()
+    }
+
+    /// The track list follows the library list, so it can't be edited
+    fn add_track(
+        &self,
+        _uri: String,
+        _after_track: ObjectPath<'_>,
+        _set_as_current: bool,
+    ) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "The track list can't be edited".to_string(),
+        ))
+    }
+
+    fn remove_track(&self, _track_id: ObjectPath<'_>) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "The track list can't be edited".to_string(),
+        ))
+    }
+
+    fn go_to(&self, track_id: ObjectPath<'_>) {
+        let Some(id) = song_id_from_path(&track_id) else {
+            return;
+        };
+        if lock(&self.state).tracks.iter().any(|song| song.id == id) {
+            (self.on_event)(MediaKeyEvent::GoTo(id));
+        }
+    }
+
+    #[zbus(signal)]
+    async fn track_list_replaced(
+        emitter: &SignalEmitter<'_>,
+        tracks: Vec<OwnedObjectPath>,
+        current_track: OwnedObjectPath,
+    ) -> zbus::Result<()>;
+
+    #[zbus(property(emits_changed_signal = "invalidates"))]
+    fn tracks(&self) -> Vec<OwnedObjectPath> {
+        lock(&self.state)
+            .tracks
+            .iter()
+            .map(|song| track_path(song.id))
+            .collect()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_edit_tracks(&self) -> bool {
+        false
+    }
+}
+
+// ============================================================================
+// Server
+// ============================================================================
+
+/// Publishes the player on D-Bus as `org.mpris.MediaPlayer2.player`, with the
+/// MPRIS2 Player and TrackList interfaces. Requests from clients are passed
+/// to the event handler; the player reports its state back with the setters.
+pub struct MprisServer {
+    connection: Connection,
+    state: SharedState,
+}
+
+impl MprisServer {
+    /// Serve on the session bus
+    pub fn new(
+        on_event: impl Fn(MediaKeyEvent) + Send + Sync + 'static,
+    ) -> Result<Self, MediaControlsError> {
+        let builder = Builder::session().map_err(init_failed)?;
+        Self::serve(builder, Arc::new(on_event))
+    }
+
+    /// Serve on the bus at `address`, e.g. a private bus for tests
+    pub fn with_address(
+        address: &str,
+        on_event: impl Fn(MediaKeyEvent) + Send + Sync + 'static,
+    ) -> Result<Self, MediaControlsError> {
+        let builder = Builder::address(address).map_err(init_failed)?;
+        Self::serve(builder, Arc::new(on_event))
+    }
+
+    fn serve(builder: Builder<'_>, on_event: EventHandler) -> Result<Self, MediaControlsError> {
+        let state = SharedState::default();
+        let connection = builder
+            .name(BUS_NAME)
+            .and_then(|builder| {
+                builder.serve_at(
+                    OBJECT_PATH,
+                    Root {
+                        on_event: on_event.clone(),
+                    },
+                )
+            })
+            .and_then(|builder| {
+                builder.serve_at(
+                    OBJECT_PATH,
+                    Player {
+                        state: state.clone(),
+                        on_event: on_event.clone(),
+                    },
+                )
+            })
+            .and_then(|builder| {
+                builder.serve_at(
+                    OBJECT_PATH,
+                    TrackList {
+                        state: state.clone(),
+                        on_event,
+                    },
+                )
+            })
+            .and_then(|builder| builder.build())
+            .map_err(init_failed)?;
+        Ok(Self { connection, state })
+    }
+
+    pub fn set_metadata(&mut self, song: Option<&Song>) -> Result<(), MediaControlsError> {
+        lock(&self.state).song = song.cloned();
+        self.notify_player(|player, emitter| {
+            zbus::block_on(async {
+                player.metadata_changed(emitter).await?;
+                player.can_play_changed(emitter).await?;
+                player.can_pause_changed(emitter).await?;
+                player.can_seek_changed(emitter).await
+            })
+        })
+    }
+
+    pub fn set_playback_playing(
+        &mut self,
+        position: Option<Duration>,
+    ) -> Result<(), MediaControlsError> {
+        self.set_playback(PlaybackState::Playing, position)
+    }
+
+    pub fn set_playback_paused(
+        &mut self,
+        position: Option<Duration>,
+    ) -> Result<(), MediaControlsError> {
+        self.set_playback(PlaybackState::Paused, position)
+    }
+
+    pub fn set_playback_stopped(&mut self) -> Result<(), MediaControlsError> {
+        self.set_playback(PlaybackState::Stopped, Some(Duration::ZERO))
+    }
+
+    fn set_playback(
+        &mut self,
+        playback: PlaybackState,
+        position: Option<Duration>,
+    ) -> Result<(), MediaControlsError> {
+        {
+            let mut state = lock(&self.state);
+            state.position = position.unwrap_or_else(|| state.position());
+            state.position_at = Instant::now();
+            state.playback = playback;
+        }
+        self.notify_player(|player, emitter| {
+            zbus::block_on(player.playback_status_changed(emitter))
+        })
+    }
+
+    pub fn set_volume(&mut self, volume: f64) -> Result<(), MediaControlsError> {
+        let volume = volume.clamp(0.0, 1.0);
+        if std::mem::replace(&mut lock(&self.state).volume, volume) == volume {
+            return Ok(());
+        }
+        self.notify_player(|player, emitter| zbus::block_on(player.volume_changed(emitter)))
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) -> Result<(), MediaControlsError> {
+        if std::mem::replace(&mut lock(&self.state).shuffle, shuffle) == shuffle {
+            return Ok(());
+        }
+        self.notify_player(|player, emitter| zbus::block_on(player.shuffle_changed(emitter)))
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) -> Result<(), MediaControlsError> {
+        if std::mem::replace(&mut lock(&self.state).repeat, repeat) == repeat {
+            return Ok(());
+        }
+        self.notify_player(|player, emitter| zbus::block_on(player.loop_status_changed(emitter)))
+    }
+
+    /// Tell clients the position jumped, after a seek or restarting a song
+    pub fn seeked(&mut self, position: Duration) -> Result<(), MediaControlsError> {
+        {
+            let mut state = lock(&self.state);
+            state.position = position;
+            state.position_at = Instant::now();
+        }
+        self.notify_player(|_, emitter| zbus::block_on(Player::seeked(emitter, micros(position))))
+    }
+
+    /// The songs that play next, current song included, for the TrackList interface
+    pub fn set_tracks(&mut self, tracks: &[Song]) -> Result<(), MediaControlsError> {
+        let (paths, current) = {
+            let mut state = lock(&self.state);
+            state.tracks = tracks.to_vec();
+            let current = state
+                .song
+                .as_ref()
+                .map(|song| track_path(song.id))
+                .unwrap_or_else(no_track);
+            let paths: Vec<OwnedObjectPath> =
+                tracks.iter().map(|song| track_path(song.id)).collect();
+            (paths, current)
+        };
+
+        let tracks = self
+            .connection
+            .object_server()
+            .interface::<_, TrackList>(OBJECT_PATH)
+            .map_err(update_failed)?;
+        zbus::block_on(async {
+            tracks
+                .get()
+                .tracks_invalidate(tracks.signal_emitter())
+                .await?;
+            TrackList::track_list_replaced(tracks.signal_emitter(), paths, current).await
+        })
+        .map_err(update_failed)?;
+
+        self.notify_player(|player, emitter| {
+            zbus::block_on(async {
+                player.can_go_next_changed(emitter).await?;
+                player.can_go_previous_changed(emitter).await?;
+                player.can_play_changed(emitter).await
+            })
+        })
+    }
+
+    pub fn clear(&mut self) -> Result<(), MediaControlsError> {
+        self.set_metadata(None)?;
+        self.set_playback_stopped()
+    }
+
+    fn notify_player(
+        &self,
+        notify: impl FnOnce(&Player, &SignalEmitter<'static>) -> zbus::Result<()>,
+    ) -> Result<(), MediaControlsError> {
+        let player = self
+            .connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)
+            .map_err(update_failed)?;
+        let result = notify(&player.get(), player.signal_emitter());
+        result.map_err(update_failed)
+    }
+}
+
+fn init_failed(e: zbus::Error) -> MediaControlsError {
+    MediaControlsError::InitFailed(e.to_string())
+}
+
+fn update_failed(e: zbus::Error) -> MediaControlsError {
+    MediaControlsError::UpdateFailed(e.to_string())
+}
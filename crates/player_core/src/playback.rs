@@ -1,8 +1,11 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
 
 use crate::library::MediaItem;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PlaybackState {
     #[default]
     Stopped,
@@ -10,6 +13,15 @@ pub enum PlaybackState {
     Paused,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    All,
+    One,
+}
+
 #[derive(Debug)]
 pub struct NowPlaying {
     pub item: Option<MediaItem>,
@@ -34,3 +46,75 @@ pub struct Queue {
     pub items: Vec<MediaItem>,
     pub current: Option<usize>,
 }
+
+impl Queue {
+    pub fn new(items: Vec<MediaItem>) -> Self {
+        Self {
+            items,
+            current: None,
+        }
+    }
+
+    pub fn current_item(&self) -> Option<&MediaItem> {
+        self.items.get(self.current?)
+    }
+
+    /// Make the item at `index` current
+    pub fn select(&mut self, index: usize) -> Option<&MediaItem> {
+        self.current = (index < self.items.len()).then_some(index);
+        self.current_item()
+    }
+
+    /// Move to the item that plays after the current one. `finished` is true when
+    /// the current item played to its end, so `RepeatMode::One` plays it again;
+    /// a skip always moves on. Returns `None` at the end of the queue.
+    pub fn advance(
+        &mut self,
+        shuffle: bool,
+        repeat: RepeatMode,
+        finished: bool,
+    ) -> Option<&MediaItem> {
+        let current = self.current?;
+        let next = if finished && repeat == RepeatMode::One {
+            Some(current)
+        } else if shuffle {
+            self.random_index()
+        } else if current + 1 < self.items.len() {
+            Some(current + 1)
+        } else if repeat == RepeatMode::All {
+            Some(0)
+        } else {
+            None
+        };
+        self.current = next.or(self.current);
+        next.and_then(|index| self.items.get(index))
+    }
+
+    /// Move to the item before the current one, or a random one when shuffling
+    pub fn go_back(&mut self, shuffle: bool) -> Option<&MediaItem> {
+        let current = self.current?;
+        let previous = if shuffle {
+            self.random_index()
+        } else {
+            current.checked_sub(1)
+        };
+        self.current = previous.or(self.current);
+        previous.and_then(|index| self.items.get(index))
+    }
+
+    /// Any item but the current one, unless it's the only one
+    fn random_index(&self) -> Option<usize> {
+        let candidates: Vec<usize> = (0..self.items.len())
+            .filter(|index| Some(*index) != self.current)
+            .collect();
+        if candidates.is_empty() {
+            return self.current;
+        }
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as usize)
+            .unwrap_or(0);
+        Some(candidates[seed % candidates.len()])
+    }
+}
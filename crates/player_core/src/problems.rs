@@ -0,0 +1,382 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{AudioFile, AudioFormat};
+use crate::duplicates::content_hash;
+use crate::edit::{write_tags, EditError, MetadataEdit};
+use crate::import::{
+    calculate_duration_by_decoding, execute_import, generate_problem_path, plan_import,
+    read_metadata, scan_audio_files, unique_path, write_duration_to_file, AnalyzedFile,
+    ImportError, ImportOptions, ImportResult, ImportedFile, Metadata,
+};
+use crate::journal::ImportJournal;
+use crate::library::Library;
+use crate::storage::{Paths, StorageError};
+
+// ============================================================================
+// Error Types
+// ============================================================================
+
+#[derive(Debug)]
+pub enum ProblemError {
+    Import(ImportError),
+    Edit(EditError),
+    Storage(StorageError),
+}
+
+impl From<ImportError> for ProblemError {
+    fn from(e: ImportError) -> Self {
+        ProblemError::Import(e)
+    }
+}
+
+impl From<EditError> for ProblemError {
+    fn from(e: EditError) -> Self {
+        ProblemError::Edit(e)
+    }
+}
+
+impl From<StorageError> for ProblemError {
+    fn from(e: StorageError) -> Self {
+        ProblemError::Storage(e)
+    }
+}
+
+impl From<io::Error> for ProblemError {
+    fn from(e: io::Error) -> Self {
+        ProblemError::Import(ImportError::IoError(e))
+    }
+}
+
+impl std::fmt::Display for ProblemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProblemError::Import(e) => write!(f, "{}", e),
+            ProblemError::Edit(e) => write!(f, "{}", e),
+            ProblemError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProblemError {}
+
+// ============================================================================
+// Problem Files
+// ============================================================================
+
+/// Why a file couldn't be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// The file's length couldn't be read or decoded
+    NoDuration,
+    /// The file's tags are missing or corrupt
+    UnreadableTags,
+    UnknownFormat,
+    /// The file was moved to Problem before reasons were logged
+    Unknown,
+}
+
+impl ProblemKind {
+    /// The kind of problem an import error means, or `None` for errors that may go
+    /// away on their own, like IO errors, where the file should stay in Import
+    pub fn from_error(error: &ImportError) -> Option<Self> {
+        match error {
+            ImportError::NoDuration(_) => Some(ProblemKind::NoDuration),
+            ImportError::Id3Error(_) => Some(ProblemKind::UnreadableTags),
+            ImportError::UnknownFormat => Some(ProblemKind::UnknownFormat),
+            ImportError::IoError(_) => None,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProblemKind::NoDuration => "No duration",
+            ProblemKind::UnreadableTags => "Unreadable tags",
+            ProblemKind::UnknownFormat => "Unknown format",
+            ProblemKind::Unknown => "Unknown problem",
+        }
+    }
+}
+
+/// A file in the Problem folder and why it's there
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProblemFile {
+    /// Where the file is now, inside the Problem folder
+    pub path: PathBuf,
+    /// Where the file was in the Import folder
+    pub original_path: PathBuf,
+    pub kind: ProblemKind,
+    /// The error message from the failed import
+    pub message: String,
+    /// Seconds since the Unix epoch
+    pub recorded_at: u64,
+}
+
+// ============================================================================
+// Problem Log
+// ============================================================================
+
+/// Load the problem log as written, without checking it against the Problem folder
+pub fn load_problem_log(paths: &Paths) -> Result<Vec<ProblemFile>, StorageError> {
+    let path = paths.problem_log();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut problems = Vec::new();
+    for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(problem) => problems.push(problem),
+            Err(e) => eprintln!("Warning: Skipped problem log line: {}", e),
+        }
+    }
+    Ok(problems)
+}
+
+pub fn save_problem_log(problems: &[ProblemFile], paths: &Paths) -> Result<(), StorageError> {
+    let path = paths.problem_log();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("jsonl.tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    for problem in problems {
+        writeln!(writer, "{}", serde_json::to_string(problem)?)?;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+fn append_problem(problem: &ProblemFile, paths: &Paths) -> Result<(), StorageError> {
+    let path = paths.problem_log();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(problem)?)?;
+    Ok(())
+}
+
+/// Drop the log entries for files that have left the Problem folder
+pub(crate) fn forget_problems(problem_paths: &[&Path], paths: &Paths) -> Result<(), StorageError> {
+    if problem_paths.is_empty() {
+        return Ok(());
+    }
+    let forgotten: HashSet<&Path> = problem_paths.iter().copied().collect();
+    let mut problems = load_problem_log(paths)?;
+    problems.retain(|problem| !forgotten.contains(problem.path.as_path()));
+    save_problem_log(&problems, paths)
+}
+
+/// Every file in the Problem folder with its logged reason. Files moved there
+/// before the log existed are listed as `ProblemKind::Unknown`.
+pub fn problem_files(paths: &Paths) -> Result<Vec<ProblemFile>, StorageError> {
+    let mut problems: Vec<ProblemFile> = load_problem_log(paths)?
+        .into_iter()
+        .filter(|problem| problem.path.exists())
+        .collect();
+
+    let logged: HashSet<PathBuf> = problems.iter().map(|p| p.path.clone()).collect();
+    for path in scan_audio_files(&paths.problem) {
+        if logged.contains(&path) {
+            continue;
+        }
+        let recorded_at = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(unix_seconds)
+            .unwrap_or(0);
+        problems.push(ProblemFile {
+            original_path: path.clone(),
+            path,
+            kind: ProblemKind::Unknown,
+            message: String::new(),
+            recorded_at,
+        });
+    }
+
+    problems.sort_by_key(|problem| Reverse(problem.recorded_at));
+    Ok(problems)
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Move a file that failed to import into Problem and log why. Errors that may go
+/// away on their own leave the file in Import to be retried on the next sync.
+///
+/// Returns the error to report, pointing at the file's new path for `NoDuration`.
+pub(crate) fn quarantine_failed_import(
+    source_path: &Path,
+    error: ImportError,
+    paths: &Paths,
+) -> ImportError {
+    let Some(kind) = ProblemKind::from_error(&error) else {
+        return error;
+    };
+
+    let problem_path = unique_path(generate_problem_path(source_path, paths));
+    if let Some(parent) = problem_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return ImportError::IoError(e);
+        }
+    }
+    if let Err(e) = fs::rename(source_path, &problem_path) {
+        return ImportError::IoError(e);
+    }
+
+    let problem = ProblemFile {
+        path: problem_path.clone(),
+        original_path: source_path.to_path_buf(),
+        kind,
+        message: error.to_string(),
+        recorded_at: unix_seconds(SystemTime::now()),
+    };
+    if let Err(e) = append_problem(&problem, paths) {
+        eprintln!("Failed to log problem file {:?}: {}", problem_path, e);
+    }
+
+    match error {
+        ImportError::NoDuration(_) => ImportError::NoDuration(problem_path),
+        error => error,
+    }
+}
+
+// ============================================================================
+// Problem Actions
+// ============================================================================
+
+/// Move a problem file back to Import so the next sync tries it again.
+/// Returns where the file was moved to.
+pub fn retry_problem(problem: &ProblemFile, paths: &Paths) -> Result<PathBuf, ProblemError> {
+    let destination = if problem.original_path.starts_with(&paths.import) {
+        problem.original_path.clone()
+    } else {
+        paths
+            .import
+            .join(problem.path.file_name().unwrap_or_default())
+    };
+    let destination = unique_path(destination);
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&problem.path, &destination)?;
+    forget_problems(&[&problem.path], paths)?;
+    Ok(destination)
+}
+
+/// Decode the file to find its duration, write it to the tags and retry the import
+pub fn repair_problem_duration(
+    problem: &ProblemFile,
+    paths: &Paths,
+) -> Result<PathBuf, ProblemError> {
+    let duration = calculate_duration_by_decoding(&problem.path)
+        .ok_or_else(|| ImportError::NoDuration(problem.path.clone()))?;
+    write_duration_to_file(&problem.path, duration)?;
+    retry_problem(problem, paths)
+}
+
+/// Write new tags to a problem file and retry the import
+pub fn edit_problem_tags(
+    problem: &ProblemFile,
+    edit: &MetadataEdit,
+    paths: &Paths,
+) -> Result<PathBuf, ProblemError> {
+    write_tags(&problem_audio_file(problem)?, edit)?;
+    retry_problem(problem, paths)
+}
+
+/// Delete a problem file for good
+pub fn delete_problem(problem: &ProblemFile, paths: &Paths) -> Result<(), ProblemError> {
+    match fs::remove_file(&problem.path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    forget_problems(&[&problem.path], paths)?;
+    Ok(())
+}
+
+/// Import a problem file with the given metadata, without relying on its tags.
+/// The duration is decoded if the file doesn't have one; if it can't be, the
+/// import fails with `ImportError::NoDuration`, since a song has to have a length.
+///
+/// The file's tags are left as they are. Save the library and clear the import
+/// journal afterwards, as after a sync.
+pub fn force_import_problem(
+    problem: &ProblemFile,
+    library: &mut Library,
+    edit: &MetadataEdit,
+    options: &ImportOptions,
+    paths: &Paths,
+) -> Result<ImportResult, ProblemError> {
+    let file = problem_audio_file(problem)?;
+    let mut metadata = read_metadata(&problem.path)
+        .map(|imported| imported.metadata)
+        .unwrap_or_default();
+    apply_edit(&mut metadata, edit);
+
+    let duration = metadata
+        .duration
+        .or_else(|| calculate_duration_by_decoding(&problem.path))
+        .ok_or_else(|| ImportError::NoDuration(problem.path.clone()))?;
+    let analyzed = AnalyzedFile {
+        source_path: problem.path.clone(),
+        content_hash: content_hash(&problem.path, file.format).ok(),
+        imported: ImportedFile { file, metadata },
+        duration,
+        fingerprint: None,
+    };
+
+    let plan = plan_import(analyzed, library, options, paths);
+
+    let mut journal = ImportJournal::open(paths)?;
+    let transaction = journal.begin(&plan)?;
+    let result = execute_import(plan, |_| {})?;
+    journal.done(transaction)?;
+
+    library.add_song(result.song.clone());
+    forget_problems(&[&problem.path], paths)?;
+    Ok(result)
+}
+
+fn problem_audio_file(problem: &ProblemFile) -> Result<AudioFile, ImportError> {
+    let format = AudioFormat::from_path(&problem.path).ok_or(ImportError::UnknownFormat)?;
+    Ok(AudioFile {
+        path: problem.path.clone(),
+        format,
+    })
+}
+
+fn apply_edit(metadata: &mut Metadata, edit: &MetadataEdit) {
+    if let Some(title) = &edit.title {
+        metadata.title = Some(title.clone());
+    }
+    if let Some(artist) = &edit.artist {
+        metadata.artist = artist.clone();
+    }
+    if let Some(album) = &edit.album {
+        metadata.album = album.clone();
+    }
+    if let Some(track_number) = edit.track_number {
+        metadata.track_number = track_number;
+    }
+}
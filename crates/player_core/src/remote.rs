@@ -0,0 +1,549 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::audio_engine::AudioPlayerEvent;
+use crate::library::Song;
+use crate::playback::{PlaybackState, RepeatMode};
+
+/// How long a client waits for the player to answer a call
+pub(crate) const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often an idle event stream sends a comment, to notice closed clients
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const MAX_HEADER_BYTES: u64 = 16 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// ============================================================================
+// Remote Error
+// ============================================================================
+
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The server won't run without a token to check clients against
+    NoToken,
+    Io(io::Error),
+}
+
+impl From<io::Error> for RemoteError {
+    fn from(e: io::Error) -> Self {
+        RemoteError::Io(e)
+    }
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteError::NoToken => write!(f, "No token for the remote control server"),
+            RemoteError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+// ============================================================================
+// Requests
+// ============================================================================
+
+/// A JSON-RPC call from a client: `method` names the variant and `params`
+/// holds its fields. Times are in seconds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum RemoteRequest {
+    Status,
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    Seek {
+        position: f64,
+    },
+    /// Backwards if `offset` is negative
+    SeekBy {
+        offset: f64,
+    },
+    SetVolume {
+        volume: f32,
+    },
+    SetShuffle {
+        shuffle: bool,
+    },
+    SetRepeat {
+        repeat: RepeatMode,
+    },
+    PlaySong {
+        id: u64,
+    },
+    #[serde(rename = "queue.list")]
+    QueueList,
+    /// Songs to play after the current one, before the rest of the list
+    #[serde(rename = "queue.add")]
+    QueueAdd {
+        ids: Vec<u64>,
+    },
+    #[serde(rename = "queue.remove")]
+    QueueRemove {
+        index: usize,
+    },
+    #[serde(rename = "queue.clear")]
+    QueueClear,
+    Search {
+        query: String,
+        #[serde(default = "default_search_limit")]
+        limit: usize,
+    },
+}
+
+fn default_search_limit() -> usize {
+    50
+}
+
+impl RemoteRequest {
+    pub const METHODS: &'static [&'static str] = &[
+        "status",
+        "play",
+        "pause",
+        "toggle",
+        "stop",
+        "next",
+        "previous",
+        "seek",
+        "seek_by",
+        "set_volume",
+        "set_shuffle",
+        "set_repeat",
+        "play_song",
+        "queue.list",
+        "queue.add",
+        "queue.remove",
+        "queue.clear",
+        "search",
+    ];
+}
+
+/// A request waiting for the player's answer
+#[derive(Debug)]
+pub struct RemoteCall {
+    pub request: RemoteRequest,
+    reply: Sender<Result<Value, String>>,
+}
+
+impl RemoteCall {
+    /// A call, and where its answer arrives
+    pub(crate) fn new(request: RemoteRequest) -> (Self, Receiver<Result<Value, String>>) {
+        let (reply, answer) = mpsc::channel();
+        (Self { request, reply }, answer)
+    }
+
+    pub fn respond(self, result: impl Serialize) {
+        let result = serde_json::to_value(result).map_err(|e| e.to_string());
+        let _ = self.reply.send(result);
+    }
+
+    pub fn fail(self, message: impl Into<String>) {
+        let _ = self.reply.send(Err(message.into()));
+    }
+}
+
+/// A song as clients see it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSong {
+    pub id: u64,
+    pub file: PathBuf,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: f64,
+    pub rating: u8,
+    pub loved: bool,
+    pub play_count: u32,
+}
+
+impl From<&Song> for RemoteSong {
+    fn from(song: &Song) -> Self {
+        Self {
+            id: song.id.0,
+            file: song.file.path.clone(),
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            track_number: song.track_number,
+            duration: song.duration.as_secs_f64(),
+            rating: song.rating,
+            loved: song.loved,
+            play_count: song.stats.play_count,
+        }
+    }
+}
+
+/// The answer to `status`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteStatus {
+    pub state: PlaybackState,
+    pub song: Option<RemoteSong>,
+    pub position: f64,
+    pub volume: f32,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    pub queue: Vec<RemoteSong>,
+}
+
+/// A random token for `RemoteSettings::token`
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    let random = File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut bytes));
+    if random.is_err() {
+        // No system randomness to read, so mix what varies between runs
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let seed = format!("{}#{}#{:p}", now, std::process::id(), &bytes);
+        bytes = *blake3::hash(seed.as_bytes()).as_bytes();
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// ============================================================================
+// Server
+// ============================================================================
+
+struct Shared {
+    token: String,
+    on_call: Box<dyn Fn(RemoteCall) + Send + Sync>,
+    streams: Mutex<Vec<Sender<String>>>,
+    stopped: AtomicBool,
+}
+
+/// The local control API: JSON-RPC 2.0 calls are POSTed to `/rpc`, and
+/// `/events` streams player events as server-sent events. Every request needs
+/// `Authorization: Bearer <token>`, or `?token=<token>` for clients like
+/// `EventSource` that can't set headers.
+///
+/// Calls are passed to `on_call` on the server's threads; the player answers
+/// each with `RemoteCall::respond` or `RemoteCall::fail`.
+pub struct RemoteServer {
+    address: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl RemoteServer {
+    pub fn start(
+        address: &str,
+        token: &str,
+        on_call: impl Fn(RemoteCall) + Send + Sync + 'static,
+    ) -> Result<Self, RemoteError> {
+        if token.is_empty() {
+            return Err(RemoteError::NoToken);
+        }
+
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            token: token.to_string(),
+            on_call: Box::new(on_call),
+            streams: Mutex::new(Vec::new()),
+            stopped: AtomicBool::new(false),
+        });
+
+        let accept_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let shared = accept_shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, &shared) {
+                        if e.kind() != io::ErrorKind::BrokenPipe {
+                            eprintln!("Remote control connection failed: {}", e);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(Self { address, shared })
+    }
+
+    /// Where the server is listening, with the port it was given if bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Send an event to every open `/events` stream
+    pub fn broadcast(&self, event: &AudioPlayerEvent) {
+        let (name, data) = event_json(event);
+        let message = format!("event: {}\ndata: {}\n\n", name, data);
+        lock_streams(&self.shared).retain(|stream| stream.send(message.clone()).is_ok());
+    }
+}
+
+impl Drop for RemoteServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        lock_streams(&self.shared).clear();
+        // Wake the accept loop so it sees the server stopped
+        let _ = TcpStream::connect(self.address);
+    }
+}
+
+fn lock_streams(shared: &Shared) -> std::sync::MutexGuard<'_, Vec<Sender<String>>> {
+    shared.streams.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn event_json(event: &AudioPlayerEvent) -> (&'static str, Value) {
+    match event {
+        AudioPlayerEvent::StateChanged(state) => ("state", json!({ "state": state })),
+        AudioPlayerEvent::SongChanged(song) => (
+            "song",
+            json!({ "song": song.as_deref().map(RemoteSong::from) }),
+        ),
+        AudioPlayerEvent::PlaybackFinished => ("finished", json!({})),
+    }
+}
+
+// ============================================================================
+// HTTP
+// ============================================================================
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    /// Names are lowercase
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => request,
+        Err(_) => return respond(&stream, "400 Bad Request", "text/plain", b"Bad request"),
+    };
+
+    if !authorized(&request, &shared.token) {
+        return respond(&stream, "401 Unauthorized", "text/plain", b"Unauthorized");
+    }
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/rpc") => {
+            let response = handle_rpc(&request.body, shared);
+            respond(
+                &stream,
+                "200 OK",
+                "application/json",
+                response.to_string().as_bytes(),
+            )
+        }
+        ("GET", "/events") => stream_events(stream, shared),
+        _ => respond(&stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<HttpRequest> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut head = reader.take(MAX_HEADER_BYTES);
+
+    let mut line = String::new();
+    head.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if head.read_line(&mut line)? == 0 {
+            return Err(invalid("Headers ended early"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("Malformed header"))?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    let length = match headers.get("content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| invalid("Malformed content length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_BYTES {
+        return Err(invalid("Body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body,
+    })
+}
+
+fn authorized(request: &HttpRequest, token: &str) -> bool {
+    let bearer = request
+        .headers
+        .get("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer
+        .or(request.query.get("token").map(String::as_str))
+        .is_some_and(|given| same_token(given.trim(), token))
+}
+
+/// Compare without stopping at the first difference, so timing doesn't leak the token
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn respond(
+    mut stream: &TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn stream_events(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    lock_streams(shared).push(sender);
+
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n",
+    )?;
+    // Once a client reads this, it won't miss any event
+    stream.write_all(b": connected\n\n")?;
+    stream.flush()?;
+
+    loop {
+        match receiver.recv_timeout(KEEP_ALIVE) {
+            Ok(message) => stream.write_all(message.as_bytes())?,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+// ============================================================================
+// JSON-RPC
+// ============================================================================
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// The player couldn't do what was asked
+const PLAYER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct RpcEnvelope {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Answer one call. Every call gets a response, even without an `id`.
+fn handle_rpc(body: &[u8], shared: &Shared) -> Value {
+    let Ok(value) = serde_json::from_slice::<Value>(body) else {
+        return rpc_error(Value::Null, PARSE_ERROR, "Parse error");
+    };
+    let envelope = match serde_json::from_value::<RpcEnvelope>(value) {
+        Ok(envelope) if envelope.jsonrpc == "2.0" => envelope,
+        _ => return rpc_error(Value::Null, INVALID_REQUEST, "Invalid request"),
+    };
+
+    let id = envelope.id;
+    let request = match parse_request(&envelope.method, envelope.params) {
+        Ok(request) => request,
+        Err((code, message)) => return rpc_error(id, code, &message),
+    };
+
+    let (call, answer) = RemoteCall::new(request);
+    (shared.on_call)(call);
+    match answer.recv_timeout(CALL_TIMEOUT) {
+        Ok(Ok(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Ok(Err(message)) => rpc_error(id, PLAYER_ERROR, &message),
+        Err(_) => rpc_error(id, INTERNAL_ERROR, "The player didn't answer"),
+    }
+}
+
+fn parse_request(method: &str, params: Value) -> Result<RemoteRequest, (i64, String)> {
+    if !RemoteRequest::METHODS.contains(&method) {
+        return Err((METHOD_NOT_FOUND, format!("Unknown method {}", method)));
+    }
+
+    // Methods without params accept them empty or left out
+    let empty = match &params {
+        Value::Null => true,
+        Value::Object(map) => map.is_empty(),
+        Value::Array(items) => items.is_empty(),
+        _ => false,
+    };
+    let call = if empty {
+        json!({ "method": method })
+    } else {
+        json!({ "method": method, "params": params })
+    };
+    serde_json::from_value(call).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
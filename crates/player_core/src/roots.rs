@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::audio::AudioFormat;
+use crate::duplicates::content_hash;
+use crate::fingerprint::compute_fingerprint;
+use crate::import::{read_metadata_read_only, song_from_metadata, ImportError, ImportOptions};
+use crate::library::{Library, Song, SongId};
+use crate::storage::{Paths, StorageError};
+
+// ============================================================================
+// Library Roots
+// ============================================================================
+
+/// A folder whose audio files are indexed where they are, without being copied
+/// into the Music folder. Songs belong to a root when their path is inside it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryRoot {
+    pub path: PathBuf,
+}
+
+impl LibraryRoot {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        path.starts_with(&self.path)
+    }
+}
+
+/// Load the watched library roots (JSONL format, one root per line)
+pub fn load_library_roots(paths: &Paths) -> Result<Vec<LibraryRoot>, StorageError> {
+    let path = paths.library_roots();
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut roots = Vec::new();
+    for line in BufReader::new(File::open(&path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        roots.push(serde_json::from_str(&line)?);
+    }
+    Ok(roots)
+}
+
+pub fn save_library_roots(roots: &[LibraryRoot], paths: &Paths) -> Result<(), StorageError> {
+    let path = paths.library_roots();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("jsonl.tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    for root in roots {
+        writeln!(writer, "{}", serde_json::to_string(root)?)?;
+    }
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+// ============================================================================
+// File Stamps
+// ============================================================================
+
+/// Size and modification time of a file when it was indexed.
+/// A rescan only re-reads files whose stamp has changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl FileStamp {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::from_metadata(&fs::metadata(path)?)
+    }
+
+    fn from_metadata(metadata: &fs::Metadata) -> io::Result<Self> {
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Ok(Self {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+// ============================================================================
+// Rescanning
+// ============================================================================
+
+#[derive(Debug, Default)]
+pub struct RescanResult {
+    /// Files seen for the first time
+    pub added: Vec<Song>,
+    /// Files whose size or modification time changed, re-read in place
+    pub updated: Vec<Song>,
+    /// Songs whose file was found at a new path, matched by content hash
+    pub moved: Vec<Song>,
+    /// Songs whose file no longer exists
+    pub removed: Vec<SongId>,
+    pub errors: Vec<(PathBuf, ImportError)>,
+}
+
+impl RescanResult {
+    pub fn changed_library(&self) -> bool {
+        !self.added.is_empty()
+            || !self.updated.is_empty()
+            || !self.moved.is_empty()
+            || !self.removed.is_empty()
+    }
+}
+
+/// Rescan every library root. Roots that can't be read are reported and skipped.
+pub fn rescan_roots(
+    library: &mut Library,
+    options: &ImportOptions,
+    paths: &Paths,
+) -> Result<Vec<RescanResult>, StorageError> {
+    let rescans = load_library_roots(paths)?
+        .iter()
+        .filter_map(|root| match rescan_root(library, root, options) {
+            Ok(rescan) => Some(rescan),
+            Err(e) => {
+                eprintln!("Failed to scan {:?}: {}", root.path, e);
+                None
+            }
+        })
+        .collect();
+    Ok(rescans)
+}
+
+/// Bring the library in line with the files under a root, reading only new and
+/// changed files. Files are never copied, moved or renamed.
+///
+/// Returns an error without touching the library if the root can't be read,
+/// so an unmounted drive doesn't look like every song was deleted.
+pub fn rescan_root(
+    library: &mut Library,
+    root: &LibraryRoot,
+    options: &ImportOptions,
+) -> Result<RescanResult, ImportError> {
+    let on_disk = audio_files(&root.path)?;
+    let mut result = RescanResult::default();
+
+    let indexed: HashMap<PathBuf, SongId> = library
+        .songs
+        .values()
+        .filter(|song| root.contains(&song.file.path))
+        .map(|song| (song.file.path.clone(), song.id))
+        .collect();
+
+    // Songs whose files are gone, which may turn up again at a new path
+    let mut missing: HashSet<SongId> = indexed
+        .iter()
+        .filter(|(path, _)| !on_disk.contains_key(*path))
+        .map(|(_, id)| *id)
+        .collect();
+
+    let to_read: Vec<(PathBuf, Option<SongId>)> = on_disk
+        .iter()
+        .filter_map(|(path, stamp)| match indexed.get(path) {
+            Some(id) if library.songs[id].file_stamp.as_ref() == Some(stamp) => None,
+            Some(id) => Some((path.clone(), Some(*id))),
+            None => Some((path.clone(), None)),
+        })
+        .collect();
+
+    let read: Vec<_> = to_read
+        .into_par_iter()
+        .map(|(path, existing)| {
+            let song = index_file(&path, existing.unwrap_or(SongId(0)), options);
+            (path, existing, song)
+        })
+        .collect();
+
+    for (path, existing, song) in read {
+        let mut song = match song {
+            Ok(song) => song,
+            Err(e) => {
+                result.errors.push((path, e));
+                continue;
+            }
+        };
+
+        if let Some(id) = existing {
+            song.keep_user_data(&library.songs[&id]);
+            result.updated.push(song);
+            continue;
+        }
+
+        let moved_from = song.content_hash.as_deref().and_then(|hash| {
+            missing
+                .iter()
+                .copied()
+                .find(|id| library.songs[id].content_hash.as_deref() == Some(hash))
+        });
+
+        match moved_from {
+            Some(id) => {
+                missing.remove(&id);
+                song.id = id;
+                song.keep_user_data(&library.songs[&id]);
+                result.moved.push(song);
+            }
+            None => {
+                song.id = library.assign_song_id(song.content_hash.as_deref(), &song.file.path);
+                // Claim the id now, so a copy found later in this scan gets another
+                library.add_song(song.clone());
+                result.added.push(song);
+            }
+        }
+    }
+
+    for song in result
+        .added
+        .iter()
+        .chain(&result.updated)
+        .chain(&result.moved)
+    {
+        library.add_song(song.clone());
+    }
+    for id in &missing {
+        library.remove_song(*id);
+    }
+    result.removed = missing.into_iter().collect();
+
+    Ok(result)
+}
+
+/// Read a file's tags, hash and fingerprint into a song that points at the file
+/// itself. The file is never written to, even to save a decoded duration.
+fn index_file(path: &Path, id: SongId, options: &ImportOptions) -> Result<Song, ImportError> {
+    let imported = read_metadata_read_only(path)?;
+    let duration = imported
+        .metadata
+        .duration
+        .ok_or_else(|| ImportError::NoDuration(path.to_path_buf()))?;
+
+    let stamp = FileStamp::read(path)?;
+    let hash = content_hash(path, imported.file.format).ok();
+    let fingerprint = if options.fingerprint {
+        compute_fingerprint(path)
+    } else {
+        None
+    };
+
+    let mut song = song_from_metadata(
+        id,
+        &imported,
+        path.to_path_buf(),
+        duration,
+        hash,
+        fingerprint,
+    );
+    song.file_stamp = Some(stamp);
+    Ok(song)
+}
+
+/// Stamp of every audio file under `path`. Fails only if `path` itself can't be read.
+fn audio_files(path: &Path) -> io::Result<HashMap<PathBuf, FileStamp>> {
+    let mut files = HashMap::new();
+    let mut paths_to_scan = vec![path.to_path_buf()];
+    fs::read_dir(path)?;
+
+    while let Some(current_path) = paths_to_scan.pop() {
+        let Ok(entries) = fs::read_dir(&current_path) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                paths_to_scan.push(entry_path);
+            } else if AudioFormat::from_path(&entry_path).is_some() {
+                if let Ok(stamp) = entry
+                    .metadata()
+                    .and_then(|metadata| FileStamp::from_metadata(&metadata))
+                {
+                    files.insert(entry_path, stamp);
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
@@ -0,0 +1,276 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::PlayRecord;
+use crate::library::Song;
+use crate::storage::Paths;
+
+/// Tracks this short are never scrobbled
+const MIN_SCROBBLE_DURATION: Duration = Duration::from_secs(30);
+
+/// Listening this long always counts as a scrobble, however long the track
+const ALWAYS_SCROBBLE_AFTER: Duration = Duration::from_secs(4 * 60);
+
+/// Most listens ListenBrainz accepts in one submission
+const MAX_LISTENS_PER_SUBMISSION: usize = 1000;
+
+const CLIENT_NAME: &str = "player";
+
+// ============================================================================
+// Error Types
+// ============================================================================
+
+#[derive(Debug)]
+pub enum ScrobbleError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The service refused or failed the submission
+    Submit(String),
+}
+
+impl From<io::Error> for ScrobbleError {
+    fn from(e: io::Error) -> Self {
+        ScrobbleError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ScrobbleError {
+    fn from(e: serde_json::Error) -> Self {
+        ScrobbleError::Json(e)
+    }
+}
+
+impl std::fmt::Display for ScrobbleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScrobbleError::Io(e) => write!(f, "IO error: {}", e),
+            ScrobbleError::Json(e) => write!(f, "JSON error: {}", e),
+            ScrobbleError::Submit(message) => write!(f, "Submission failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScrobbleError {}
+
+// ============================================================================
+// Scrobble Rules
+// ============================================================================
+
+/// Whether a play counts as a scrobble: the track is over 30 seconds long and
+/// was listened to for half its length or 4 minutes, whichever comes first
+pub fn is_scrobble(duration: Duration, listened: Duration) -> bool {
+    duration > MIN_SCROBBLE_DURATION && listened >= (duration / 2).min(ALWAYS_SCROBBLE_AFTER)
+}
+
+// ============================================================================
+// ListenBrainz Listens
+// ============================================================================
+
+/// One listen, in the shape ListenBrainz's submit-listens API takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listen {
+    /// When the track started, in seconds since the Unix epoch
+    pub listened_at: u64,
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_name: Option<String>,
+    pub additional_info: AdditionalInfo,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdditionalInfo {
+    pub duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracknumber: Option<u32>,
+    pub submission_client: String,
+}
+
+impl Listen {
+    /// The listen for a play of `song`, or `None` if the song has no artist,
+    /// which both Last.fm and ListenBrainz require
+    pub fn from_play(song: &Song, record: &PlayRecord) -> Option<Self> {
+        Some(Listen {
+            listened_at: record.started_at,
+            track_metadata: TrackMetadata {
+                artist_name: song.artist.clone()?,
+                track_name: song.title.clone(),
+                release_name: song.album.clone(),
+                additional_info: AdditionalInfo {
+                    duration_ms: song.duration.as_millis() as u64,
+                    tracknumber: song.track_number,
+                    submission_client: CLIENT_NAME.to_string(),
+                },
+            },
+        })
+    }
+}
+
+/// A submit-listens request body. The queue file is kept in this shape, so it
+/// can be submitted as-is.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ListenPayload {
+    /// "import" for a batch of past listens
+    pub listen_type: String,
+    pub payload: Vec<Listen>,
+}
+
+impl ListenPayload {
+    pub fn import(listens: Vec<Listen>) -> Self {
+        ListenPayload {
+            listen_type: "import".to_string(),
+            payload: listens,
+        }
+    }
+}
+
+// ============================================================================
+// Scrobble Log
+// ============================================================================
+
+/// Record a finished play of `song` for scrobbling:
+/// 1. Append it to `.scrobbler.log`, as listened ("L") or skipped ("S")
+/// 2. If it counts as a scrobble, queue it for ListenBrainz
+///
+/// Returns the queued listen, if any. Songs without an artist are not logged.
+pub fn log_scrobble(
+    song: &Song,
+    record: &PlayRecord,
+    paths: &Paths,
+) -> Result<Option<Listen>, ScrobbleError> {
+    let Some(listen) = Listen::from_play(song, record) else {
+        return Ok(None);
+    };
+    let scrobbled = is_scrobble(song.duration, record.listened);
+    let _lock = lock_scrobble_files();
+
+    append_log_line(&scrobbler_log_line(&listen, scrobbled), paths)?;
+    if !scrobbled {
+        return Ok(None);
+    }
+
+    let mut queue = load_queue(paths)?;
+    queue.push(listen.clone());
+    save_queue(&queue, paths)?;
+    Ok(Some(listen))
+}
+
+/// A line in the Audioscrobbler portable player log format: artist, album,
+/// title, track number, length, rating, timestamp and MusicBrainz id, tab separated
+fn scrobbler_log_line(listen: &Listen, scrobbled: bool) -> String {
+    let metadata = &listen.track_metadata;
+    let field = |text: &str| text.replace(['\t', '\n', '\r'], " ");
+    [
+        field(&metadata.artist_name),
+        field(metadata.release_name.as_deref().unwrap_or("")),
+        field(&metadata.track_name),
+        metadata
+            .additional_info
+            .tracknumber
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
+        (metadata.additional_info.duration_ms / 1000).to_string(),
+        if scrobbled { "L" } else { "S" }.to_string(),
+        listen.listened_at.to_string(),
+        String::new(),
+    ]
+    .join("\t")
+}
+
+fn append_log_line(line: &str, paths: &Paths) -> Result<(), ScrobbleError> {
+    let path = paths.scrobbler_log();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let is_new = !path.exists();
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if is_new {
+        writeln!(file, "#AUDIOSCROBBLER/1.1")?;
+        writeln!(file, "#TZ/UTC")?;
+        writeln!(file, "#CLIENT/{}", CLIENT_NAME)?;
+    }
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+// ============================================================================
+// Listen Queue
+// ============================================================================
+
+/// Held while reading and rewriting the scrobble log or queue, so plays that
+/// end close together, and flushes, don't lose each other's listens
+fn lock_scrobble_files() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Listens waiting to be submitted, oldest first
+pub fn load_queue(paths: &Paths) -> Result<Vec<Listen>, ScrobbleError> {
+    match fs::read_to_string(paths.listen_queue()) {
+        Ok(contents) => Ok(serde_json::from_str::<ListenPayload>(&contents)?.payload),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_queue(listens: &[Listen], paths: &Paths) -> Result<(), ScrobbleError> {
+    let path = paths.listen_queue();
+    if listens.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    let payload = ListenPayload::import(listens.to_vec());
+    fs::write(&temp_path, serde_json::to_string_pretty(&payload)?)?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+// ============================================================================
+// Scrobbler Trait
+// ============================================================================
+
+/// Submits queued listens to a scrobbling service
+pub trait Scrobbler {
+    /// Submit a batch of listens. On error, the batch stays queued for the next flush.
+    fn submit(&mut self, payload: &ListenPayload) -> Result<(), ScrobbleError>;
+}
+
+/// Submit every queued listen, in batches, removing each batch from the queue
+/// once it's accepted. Stops at the first failed batch. Returns how many listens
+/// were submitted. Plays logged meanwhile wait for the flush to finish.
+pub fn flush_queue(scrobbler: &mut impl Scrobbler, paths: &Paths) -> Result<usize, ScrobbleError> {
+    let _lock = lock_scrobble_files();
+    let mut queue = load_queue(paths)?;
+    let mut submitted = 0;
+
+    while !queue.is_empty() {
+        let batch_len = queue.len().min(MAX_LISTENS_PER_SUBMISSION);
+        let payload = ListenPayload::import(queue[..batch_len].to_vec());
+        if let Err(e) = scrobbler.submit(&payload) {
+            save_queue(&queue, paths)?;
+            return Err(e);
+        }
+        queue.drain(..batch_len);
+        submitted += batch_len;
+    }
+
+    save_queue(&queue, paths)?;
+    Ok(submitted)
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::library::{SongId, SortOrder};
+use crate::storage::{duration_serde, Paths, StorageError};
+
+// ============================================================================
+// Session
+// ============================================================================
+
+/// What was loaded in the player. Audiobooks are imported as M4B songs,
+/// so they resume at the exact position like any other song.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "id")]
+pub enum SessionItem {
+    Song(u64),
+}
+
+/// Where the player was when it last ran, restored (paused) at launch.
+/// Shuffle, repeat and volume are settings (see `PlaybackSettings`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub current: Option<SessionItem>,
+    /// How far into `current` playback was, to the nanosecond so long
+    /// audiobooks resume where they were left
+    #[serde(with = "duration_serde")]
+    pub position: Duration,
+    /// Song ids queued to play next, in order
+    pub queue: Vec<u64>,
+    pub sort_order: SortOrder,
+    /// The song selected in the list
+    pub selected: Option<u64>,
+}
+
+impl Session {
+    /// Point the session's songs at their new ids after a rekey.
+    /// Returns whether anything changed.
+    pub fn rekey(&mut self, remap: &HashMap<SongId, SongId>) -> bool {
+        let mut changed = false;
+        let mut rekey = |id: &mut u64| {
+            if let Some(new_id) = remap.get(&SongId(*id)) {
+                *id = new_id.0;
+                changed = true;
+            }
+        };
+        if let Some(SessionItem::Song(id)) = &mut self.current {
+            rekey(id);
+        }
+        self.queue.iter_mut().for_each(&mut rekey);
+        if let Some(id) = &mut self.selected {
+            rekey(id);
+        }
+        changed
+    }
+}
+
+/// The last saved session, or an empty one if none was saved yet
+pub fn load_session(paths: &Paths) -> Result<Session, StorageError> {
+    match fs::read_to_string(paths.session()) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Session::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn save_session(session: &Session, paths: &Paths) -> Result<(), StorageError> {
+    let path = paths.session();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_string_pretty(session)?)?;
+    fs::rename(&temp_path, &path)?;
+    Ok(())
+}
+
+/// Rewrite the saved session's song ids after a rekey
+pub(crate) fn rekey_saved_session(
+    remap: &HashMap<SongId, SongId>,
+    paths: &Paths,
+) -> Result<(), StorageError> {
+    let mut session = load_session(paths)?;
+    if session.rekey(remap) {
+        save_session(&session, paths)?;
+    }
+    Ok(())
+}
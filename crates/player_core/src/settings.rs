@@ -0,0 +1,226 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::playback::RepeatMode;
+use crate::storage::{Paths, StorageBackend};
+
+/// Overrides the player root (and where settings are read from), e.g. to isolate tests
+pub const PLAYER_ROOT_ENV: &str = "PLAYER_ROOT";
+
+// ============================================================================
+// Settings Error
+// ============================================================================
+
+#[derive(Debug)]
+pub enum SettingsError {
+    /// No player root was configured and there is no home directory to default to
+    NoHomeDirectory,
+    NoConfigDirectory,
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for SettingsError {
+    fn from(e: io::Error) -> Self {
+        SettingsError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for SettingsError {
+    fn from(e: serde_json::Error) -> Self {
+        SettingsError::Json(e)
+    }
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::NoHomeDirectory => {
+                write!(
+                    f,
+                    "No home directory; set {} or player_root",
+                    PLAYER_ROOT_ENV
+                )
+            }
+            SettingsError::NoConfigDirectory => write!(f, "No config directory"),
+            SettingsError::Io(e) => write!(f, "IO error: {}", e),
+            SettingsError::Json(e) => write!(f, "JSON error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+// ============================================================================
+// Settings
+// ============================================================================
+
+/// User settings, stored as JSON in the platform config directory.
+/// Missing fields fall back to their defaults, so older files keep loading.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Where the manifest and managed folders live; defaults to ~/Player
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player_root: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub music_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub import_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imported_path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub problem_path: Option<PathBuf>,
+    /// Whether the library is kept in `library.jsonl` or `library.sqlite3`
+    pub storage: StorageBackend,
+    /// Also write star ratings to each MP3's POPM frame, for other players to read
+    pub write_ratings_to_tags: bool,
+    /// Log plays to `.scrobbler.log` and queue scrobbles for ListenBrainz
+    pub scrobbling: bool,
+    pub playback: PlaybackSettings,
+    pub remote: RemoteSettings,
+    pub mpd: MpdSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackSettings {
+    pub volume: f32,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+        }
+    }
+}
+
+/// The local control API (see `remote`), off unless enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteSettings {
+    pub enabled: bool,
+    /// Where the server listens; bind to 0.0.0.0 to allow other devices on the network
+    pub address: String,
+    /// Clients send this as a bearer token. Generated when the server first starts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Default for RemoteSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:7642".to_string(),
+            token: None,
+        }
+    }
+}
+
+/// The MPD protocol server (see `mpd`), off unless enabled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MpdSettings {
+    pub enabled: bool,
+    pub address: String,
+    /// Clients must send this with MPD's `password` command before anything else
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl Default for MpdSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1:6600".to_string(),
+            password: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Where the settings file lives: `$PLAYER_ROOT/settings.json` when the override
+    /// is set, otherwise `Player/settings.json` in the platform config directory
+    pub fn path() -> Result<PathBuf, SettingsError> {
+        if let Some(root) = env::var_os(PLAYER_ROOT_ENV) {
+            return Ok(PathBuf::from(root).join("settings.json"));
+        }
+        dirs::config_dir()
+            .map(|dir| dir.join("Player").join("settings.json"))
+            .ok_or(SettingsError::NoConfigDirectory)
+    }
+
+    /// Load settings, or the defaults if no settings file exists yet
+    pub fn load() -> Result<Self, SettingsError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), SettingsError> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// These settings without the remote control token and MPD password, for
+    /// files that may be moved or shared
+    pub fn without_secrets(mut self) -> Self {
+        self.remote.token = None;
+        self.mpd.password = None;
+        self
+    }
+
+    /// The player root, from `PLAYER_ROOT`, then `player_root`, then ~/Player
+    pub fn player_root(&self) -> Result<PathBuf, SettingsError> {
+        if let Some(root) = env::var_os(PLAYER_ROOT_ENV) {
+            return Ok(PathBuf::from(root));
+        }
+        if let Some(root) = &self.player_root {
+            return Ok(root.clone());
+        }
+        dirs::home_dir()
+            .map(|home| home.join("Player"))
+            .ok_or(SettingsError::NoHomeDirectory)
+    }
+
+    /// Resolve every storage path, applying per-folder overrides to the standard layout
+    pub fn paths(&self) -> Result<Paths, SettingsError> {
+        let mut paths = Paths::from_root(self.player_root()?);
+        paths.backend = self.storage;
+        if let Ok(settings_path) = Self::path() {
+            paths.settings = settings_path;
+        }
+
+        let overrides = [
+            (&self.music_path, &mut paths.music),
+            (&self.import_path, &mut paths.import),
+            (&self.imported_path, &mut paths.imported),
+            (&self.problem_path, &mut paths.problem),
+        ];
+        for (setting, path) in overrides {
+            if let Some(setting) = setting {
+                *path = setting.clone();
+            }
+        }
+
+        Ok(paths)
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, Row};
+use serde::de::DeserializeOwned;
+
+use crate::fingerprint::Fingerprint;
+use crate::library::Library;
+use crate::storage::{
+    AudiobookEntry, JsonlStorage, LoadedEntry, Paths, SongEntry, Storage, StorageError,
+};
+
+// ============================================================================
+// Schema
+// ============================================================================
+
+/// Schema changes, applied in order. The database's `user_version` is the number
+/// of migrations already applied, so only append to this list.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE songs (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        format TEXT NOT NULL,
+        title TEXT NOT NULL,
+        artist TEXT,
+        album TEXT,
+        track_number INTEGER,
+        duration REAL NOT NULL,
+        content_hash TEXT,
+        fingerprint TEXT,
+        file_stamp TEXT
+    );
+    CREATE INDEX songs_artist ON songs (artist);
+    CREATE INDEX songs_album ON songs (album);
+    CREATE INDEX songs_title ON songs (title);
+
+    CREATE TABLE audiobooks (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL,
+        format TEXT NOT NULL,
+        title TEXT NOT NULL,
+        author TEXT,
+        chapters TEXT NOT NULL,
+        total_duration REAL NOT NULL
+    );
+",
+    "ALTER TABLE songs ADD COLUMN added_at INTEGER;",
+    "
+    ALTER TABLE songs ADD COLUMN rating INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE songs ADD COLUMN loved INTEGER NOT NULL DEFAULT 0;
+    ",
+    // Fingerprints are several kilobytes each, so changing a song's tags or
+    // rating shouldn't rewrite one
+    "
+    CREATE TABLE song_fingerprints (
+        id INTEGER PRIMARY KEY,
+        fingerprint TEXT NOT NULL
+    );
+    INSERT INTO song_fingerprints (id, fingerprint)
+        SELECT id, fingerprint FROM songs WHERE fingerprint IS NOT NULL;
+    ALTER TABLE songs DROP COLUMN fingerprint;
+    ",
+];
+
+const SONG_COLUMNS: &[&str] = &[
+    "id",
+    "path",
+    "format",
+    "title",
+    "artist",
+    "album",
+    "track_number",
+    "duration",
+    "content_hash",
+    "file_stamp",
+    "added_at",
+    "rating",
+    "loved",
+];
+
+const FINGERPRINT_COLUMNS: &[&str] = &["id", "fingerprint"];
+
+const AUDIOBOOK_COLUMNS: &[&str] = &[
+    "id",
+    "path",
+    "format",
+    "title",
+    "author",
+    "chapters",
+    "total_duration",
+];
+
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(StorageError::NewerSchema {
+            found: version as u32,
+            supported: MIGRATIONS.len() as u32,
+        });
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    let tx = conn.transaction()?;
+    for (ix, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", ix + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// ============================================================================
+// SQLite Storage
+// ============================================================================
+
+/// The library in an embedded SQLite database, with songs indexed by artist,
+/// album and title. Saves only write the rows that changed.
+pub struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    /// Open (or create) the library database, bringing its schema up to date
+    pub fn open(paths: &Paths) -> Result<Self, StorageError> {
+        Self::open_at(&paths.database())
+    }
+
+    pub(crate) fn open_at(path: &Path) -> Result<Self, StorageError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn })
+    }
+
+    /// Write a consistent copy of the database to a new file
+    pub(crate) fn copy_to(&self, path: &Path) -> Result<(), StorageError> {
+        self.conn
+            .execute("VACUUM INTO ?1", [path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Number of migrations applied to the database
+    pub fn schema_version(&self) -> Result<usize, StorageError> {
+        Ok(self
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))?)
+    }
+
+    fn load_rows<T>(
+        &self,
+        table: &str,
+        columns: &[&str],
+        entry: impl Fn(&Row) -> Result<T, String>,
+        loaded: impl Fn(T) -> LoadedEntry,
+        entries: &mut Vec<LoadedEntry>,
+    ) -> Result<(), StorageError> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {} FROM {} ORDER BY id",
+            columns.join(", "),
+            table
+        ))?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            entries.push(match entry(row) {
+                Ok(entry) => loaded(entry),
+                Err(error) => LoadedEntry::Skipped {
+                    line_number: entries.len() + 1,
+                    error,
+                },
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    type Error = StorageError;
+
+    fn load_entries(&self) -> Result<Box<dyn Iterator<Item = LoadedEntry>>, Self::Error> {
+        let mut fingerprints = HashMap::new();
+        let mut statement = self
+            .conn
+            .prepare("SELECT id, fingerprint FROM song_fingerprints")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            if let Ok(Some(fingerprint)) = from_json::<Fingerprint>(row.get(1)?) {
+                fingerprints.insert(row.get::<_, i64>(0)? as u64, fingerprint);
+            }
+        }
+
+        let mut entries = Vec::new();
+        self.load_rows(
+            "songs",
+            SONG_COLUMNS,
+            |row| song_entry(row)?.into_song(),
+            |mut song| {
+                song.fingerprint = fingerprints.get(&song.id.0).cloned();
+                LoadedEntry::Song(song)
+            },
+            &mut entries,
+        )?;
+        self.load_rows(
+            "audiobooks",
+            AUDIOBOOK_COLUMNS,
+            audiobook_entry,
+            |entry| LoadedEntry::Audiobook(entry.into_audiobook()),
+            &mut entries,
+        )?;
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn save_library(&self, library: &Library) -> Result<(), Self::Error> {
+        let songs = library
+            .songs
+            .values()
+            .map(|song| song_row(&SongEntry::from_song(song)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fingerprints = library
+            .songs
+            .values()
+            .filter_map(|song| Some((song.id, song.fingerprint.as_ref()?)))
+            .map(|(id, fingerprint)| {
+                Ok(vec![
+                    (id.0 as i64).into(),
+                    serde_json::to_string(fingerprint)?.into(),
+                ])
+            })
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        let audiobooks = library
+            .audiobooks
+            .values()
+            .map(|audiobook| audiobook_row(&AudiobookEntry::from_audiobook(audiobook)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        sync_table(&tx, "songs", SONG_COLUMNS, songs)?;
+        sync_table(&tx, "song_fingerprints", FINGERPRINT_COLUMNS, fingerprints)?;
+        sync_table(&tx, "audiobooks", AUDIOBOOK_COLUMNS, audiobooks)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Make a table hold exactly `rows`, writing only the rows that differ.
+/// The first column of every row is its id.
+fn sync_table(
+    conn: &Connection,
+    table: &str,
+    columns: &[&str],
+    rows: Vec<Vec<Value>>,
+) -> Result<(), StorageError> {
+    let mut existing: HashMap<i64, Vec<Value>> = HashMap::new();
+    {
+        let mut statement =
+            conn.prepare(&format!("SELECT {} FROM {}", columns.join(", "), table))?;
+        let mut query = statement.query([])?;
+        while let Some(row) = query.next()? {
+            let values = (0..columns.len())
+                .map(|ix| row.get::<_, Value>(ix))
+                .collect::<Result<Vec<_>, _>>()?;
+            existing.insert(row.get(0)?, values);
+        }
+    }
+
+    let mut upsert = conn.prepare(&format!(
+        "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+        table,
+        columns.join(", "),
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+    for row in rows {
+        let Value::Integer(id) = row[0] else {
+            continue;
+        };
+        if existing.remove(&id).as_ref() != Some(&row) {
+            upsert.execute(params_from_iter(row))?;
+        }
+    }
+
+    let mut delete = conn.prepare(&format!("DELETE FROM {} WHERE id = ?", table))?;
+    for id in existing.into_keys() {
+        delete.execute([id])?;
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Rows
+// ============================================================================
+
+// Ids are stored bit-for-bit as SQLite's signed integers, so ids above
+// i64::MAX round-trip unchanged.
+
+fn song_row(entry: &SongEntry) -> Result<Vec<Value>, serde_json::Error> {
+    Ok(vec![
+        (entry.id as i64).into(),
+        path_value(&entry.path),
+        entry.format.clone().into(),
+        entry.title.clone().into(),
+        entry.artist.clone().into(),
+        entry.album.clone().into(),
+        entry.track_number.into(),
+        entry.duration.as_secs_f64().into(),
+        entry.content_hash.clone().into(),
+        entry
+            .file_stamp
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?
+            .into(),
+        entry.added_at.map(|added_at| added_at as i64).into(),
+        i64::from(entry.rating).into(),
+        entry.loved.into(),
+    ])
+}
+
+fn song_entry(row: &Row) -> Result<SongEntry, String> {
+    let get = |e: rusqlite::Error| e.to_string();
+    Ok(SongEntry {
+        id: row.get::<_, i64>(0).map_err(get)? as u64,
+        path: PathBuf::from(row.get::<_, String>(1).map_err(get)?),
+        format: row.get(2).map_err(get)?,
+        title: row.get(3).map_err(get)?,
+        artist: row.get(4).map_err(get)?,
+        album: row.get(5).map_err(get)?,
+        track_number: row.get(6).map_err(get)?,
+        duration: Duration::from_secs_f64(row.get(7).map_err(get)?),
+        content_hash: row.get(8).map_err(get)?,
+        fingerprint: None,
+        file_stamp: from_json(row.get(9).map_err(get)?)?,
+        added_at: row
+            .get::<_, Option<i64>>(10)
+            .map_err(get)?
+            .map(|added_at| added_at as u64),
+        rating: row.get(11).map_err(get)?,
+        loved: row.get(12).map_err(get)?,
+    })
+}
+
+fn audiobook_row(entry: &AudiobookEntry) -> Result<Vec<Value>, serde_json::Error> {
+    Ok(vec![
+        (entry.id as i64).into(),
+        path_value(&entry.path),
+        entry.format.clone().into(),
+        entry.title.clone().into(),
+        entry.author.clone().into(),
+        serde_json::to_string(&entry.chapters)?.into(),
+        entry.total_duration.as_secs_f64().into(),
+    ])
+}
+
+fn audiobook_entry(row: &Row) -> Result<AudiobookEntry, String> {
+    let get = |e: rusqlite::Error| e.to_string();
+    Ok(AudiobookEntry {
+        id: row.get::<_, i64>(0).map_err(get)? as u64,
+        path: PathBuf::from(row.get::<_, String>(1).map_err(get)?),
+        format: row.get(2).map_err(get)?,
+        title: row.get(3).map_err(get)?,
+        author: row.get(4).map_err(get)?,
+        chapters: serde_json::from_str(&row.get::<_, String>(5).map_err(get)?)
+            .map_err(|e| e.to_string())?,
+        total_duration: Duration::from_secs_f64(row.get(6).map_err(get)?),
+    })
+}
+
+fn path_value(path: &Path) -> Value {
+    path.to_string_lossy().into_owned().into()
+}
+
+fn from_json<T: DeserializeOwned>(text: Option<String>) -> Result<Option<T>, String> {
+    text.map(|text| serde_json::from_str(&text))
+        .transpose()
+        .map_err(|e| e.to_string())
+}
+
+// ============================================================================
+// Migration from JSONL
+// ============================================================================
+
+/// Copy `library.jsonl` into a new SQLite database. Does nothing if the database
+/// already exists or there is no manifest; otherwise returns how many songs and
+/// audiobooks were copied. The manifest is kept as it was, as a backup.
+pub fn migrate_jsonl_to_sqlite(paths: &Paths) -> Result<Option<usize>, StorageError> {
+    let database = paths.database();
+    if database.exists() || !paths.manifest().exists() {
+        return Ok(None);
+    }
+
+    let library = JsonlStorage::new(paths).load_library()?;
+
+    // Build the database under a temporary name so an interrupted migration
+    // is started over rather than leaving a partial library
+    let temp_path = database.with_extension("sqlite3.tmp");
+    if temp_path.exists() {
+        fs::remove_file(&temp_path)?;
+    }
+    SqliteStorage::open_at(&temp_path)?.save_library(&library)?;
+    fs::rename(&temp_path, &database)?;
+
+    Ok(Some(library.songs.len() + library.audiobooks.len()))
+}
@@ -1,54 +1,137 @@
-use std::fs::{self, File};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock, PoisonError};
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use crate::audio::{AudioFile, AudioFormat};
-use crate::library::{Audiobook, AudiobookId, Chapter, Library, Song, SongId};
+use crate::backup::back_up_if_due;
+use crate::duplicates::BackfillFailures;
+use crate::fingerprint::Fingerprint;
+use crate::history::{rekey_history, PlayStats};
+use crate::library::{Audiobook, AudiobookId, Chapter, Library, Song, SongId, MAX_RATING};
+use crate::roots::FileStamp;
+use crate::session::rekey_saved_session;
+use crate::sqlite::SqliteStorage;
 
 // ============================================================================
 // Directory paths
 // ============================================================================
 
-pub fn player_root() -> PathBuf {
-    dirs::home_dir().expect("no home directory").join("Player")
+/// Where the player keeps its files. Built from `Settings` and passed to every
+/// function that reads or writes the library, so nothing depends on a fixed location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub root: PathBuf,
+    /// How the library manifest is stored
+    pub backend: StorageBackend,
+    /// Where library audio files are stored (organized by artist/album)
+    pub music: PathBuf,
+    /// Where users drop files to be imported
+    pub import: PathBuf,
+    /// Where original files are moved after successful import
+    pub imported: PathBuf,
+    /// Where problematic files are moved when import fails
+    pub problem: PathBuf,
+    /// Where settings are read from, so they can be backed up with the library
+    pub settings: PathBuf,
 }
 
-/// Where the library manifest is stored (JSONL format)
-pub fn manifest_path() -> PathBuf {
-    player_root().join("library.jsonl")
-}
+impl Paths {
+    /// The standard layout, with every folder directly under `root`
+    pub fn from_root(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            music: root.join("Music"),
+            import: root.join("Import"),
+            imported: root.join("Imported"),
+            problem: root.join("Problem"),
+            settings: root.join("settings.json"),
+            backend: StorageBackend::default(),
+            root,
+        }
+    }
 
-/// Where library audio files are stored (organized by artist/album)
-pub fn music_path() -> PathBuf {
-    player_root().join("Music")
-}
+    /// Where the library manifest is stored (JSONL format)
+    pub fn manifest(&self) -> PathBuf {
+        self.root.join("library.jsonl")
+    }
 
-/// Where users drop files to be imported
-pub fn import_path() -> PathBuf {
-    player_root().join("Import")
-}
+    /// Where the library is stored with the SQLite backend
+    pub fn database(&self) -> PathBuf {
+        self.root.join("library.sqlite3")
+    }
 
-/// Where original files are moved after successful import
-pub fn imported_path() -> PathBuf {
-    player_root().join("Imported")
-}
+    /// Local database of known acoustic fingerprints, used to suggest tags for untagged files
+    pub fn fingerprint_database(&self) -> PathBuf {
+        self.root.join("fingerprints.jsonl")
+    }
 
-/// Where problematic files are moved when import fails
-pub fn problem_path() -> PathBuf {
-    player_root().join("Problem")
-}
+    /// Acoustic fingerprints of library songs, kept out of the manifest since
+    /// each is several kilobytes (JSONL backend only)
+    pub fn song_fingerprints(&self) -> PathBuf {
+        self.root.join("song-fingerprints.jsonl")
+    }
 
-/// Ensure all required directories exist
-pub fn ensure_directories() -> Result<(), StorageError> {
-    fs::create_dir_all(player_root())?;
-    fs::create_dir_all(music_path())?;
-    fs::create_dir_all(import_path())?;
-    fs::create_dir_all(imported_path())?;
-    fs::create_dir_all(problem_path())?;
-    Ok(())
+    /// Songs whose content hash or fingerprint couldn't be computed (see
+    /// `duplicates::BackfillFailures`)
+    pub fn backfill_failures(&self) -> PathBuf {
+        self.root.join("backfill-failures.json")
+    }
+
+    /// Write-ahead log of imports not yet saved to the manifest (see `journal`)
+    pub fn import_journal(&self) -> PathBuf {
+        self.root.join("import-journal.jsonl")
+    }
+
+    /// Folders indexed in place (see `roots::LibraryRoot`)
+    pub fn library_roots(&self) -> PathBuf {
+        self.root.join("roots.jsonl")
+    }
+
+    /// Why each file in Problem failed to import (see `problems`)
+    pub fn problem_log(&self) -> PathBuf {
+        self.root.join("problems.jsonl")
+    }
+
+    /// Copies of the library files from before recent saves (see `backup`)
+    pub fn backups(&self) -> PathBuf {
+        self.root.join("Backups")
+    }
+
+    /// Every play of every song, appended as it ends (see `history`)
+    pub fn listening_history(&self) -> PathBuf {
+        self.root.join("history.jsonl")
+    }
+
+    /// What was playing when the player last quit (see `session`)
+    pub fn session(&self) -> PathBuf {
+        self.root.join("session.json")
+    }
+
+    /// Plays in the Audioscrobbler portable player log format (see `scrobble`)
+    pub fn scrobbler_log(&self) -> PathBuf {
+        self.root.join(".scrobbler.log")
+    }
+
+    /// Scrobbles waiting to be submitted, as a ListenBrainz submit-listens payload
+    pub fn listen_queue(&self) -> PathBuf {
+        self.root.join("listenbrainz.json")
+    }
+
+    /// Ensure all required directories exist
+    pub fn ensure_directories(&self) -> Result<(), StorageError> {
+        fs::create_dir_all(&self.root)?;
+        fs::create_dir_all(&self.music)?;
+        fs::create_dir_all(&self.import)?;
+        fs::create_dir_all(&self.imported)?;
+        fs::create_dir_all(&self.problem)?;
+        Ok(())
+    }
 }
 
 // ============================================================================
@@ -59,6 +142,13 @@ pub fn ensure_directories() -> Result<(), StorageError> {
 pub enum StorageError {
     Io(io::Error),
     Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    /// The library was written by a newer version of the player, which this
+    /// version can't read correctly or write without losing data
+    NewerSchema {
+        found: u32,
+        supported: u32,
+    },
 }
 
 impl From<io::Error> for StorageError {
@@ -73,11 +163,23 @@ impl From<serde_json::Error> for StorageError {
     }
 }
 
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
 impl std::fmt::Display for StorageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             StorageError::Io(e) => write!(f, "IO error: {}", e),
             StorageError::Json(e) => write!(f, "JSON error: {}", e),
+            StorageError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            StorageError::NewerSchema { found, supported } => write!(
+                f,
+                "Library schema version {} is newer than this version supports ({})",
+                found, supported
+            ),
         }
     }
 }
@@ -88,6 +190,9 @@ impl std::error::Error for StorageError {}
 // JSONL Entry types (one per line in the manifest)
 // ============================================================================
 
+/// The manifest is a snapshot (a meta line followed by every song and audiobook)
+/// and then a log of operations appended by later saves, each with a sequence
+/// number. Readers replay the log in order, so the last write for each id wins.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum LibraryEntry {
@@ -97,15 +202,36 @@ pub enum LibraryEntry {
     Audiobook(AudiobookEntry),
     #[serde(rename = "meta")]
     Meta(LibraryMeta),
+    /// Add or replace a song
+    #[serde(rename = "put_song")]
+    PutSong { seq: u64, song: SongEntry },
+    #[serde(rename = "remove_song")]
+    RemoveSong { seq: u64, id: u64 },
+    /// Add or replace an audiobook
+    #[serde(rename = "put_audiobook")]
+    PutAudiobook { seq: u64, audiobook: AudiobookEntry },
+    #[serde(rename = "remove_audiobook")]
+    RemoveAudiobook { seq: u64, id: u64 },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LibraryMeta {
+    /// Version of the format the manifest was written in; manifests from before
+    /// versioning have none and are version 1
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u32,
     pub next_song_id: u64,
     pub next_audiobook_id: u64,
+    /// Sequence number of the last operation folded into the snapshot
+    #[serde(default)]
+    pub seq: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn first_schema_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SongEntry {
     pub id: u64,
     pub path: PathBuf,
@@ -116,9 +242,28 @@ pub struct SongEntry {
     pub track_number: Option<u32>,
     #[serde(with = "duration_serde")]
     pub duration: Duration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    /// Only read, from manifests written before fingerprints moved to their
+    /// own file (see `Paths::song_fingerprints`)
+    #[serde(default, skip_serializing)]
+    pub fingerprint: Option<Fingerprint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_stamp: Option<FileStamp>,
+    /// Seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_unrated")]
+    pub rating: u8,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub loved: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+fn is_unrated(rating: &u8) -> bool {
+    *rating == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AudiobookEntry {
     pub id: u64,
     pub path: PathBuf,
@@ -130,7 +275,7 @@ pub struct AudiobookEntry {
     pub total_duration: Duration,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ChapterEntry {
     pub title: String,
     #[serde(with = "duration_serde")]
@@ -139,7 +284,7 @@ pub struct ChapterEntry {
     pub end: Duration,
 }
 
-mod duration_serde {
+pub(crate) mod duration_serde {
     use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use std::time::Duration;
 
@@ -168,23 +313,30 @@ impl SongEntry {
             album: song.album.clone(),
             track_number: song.track_number,
             duration: song.duration,
+            content_hash: song.content_hash.clone(),
+            fingerprint: None,
+            file_stamp: song.file_stamp,
+            added_at: song.added_at,
+            rating: song.rating,
+            loved: song.loved,
         }
     }
 
-    pub fn into_song(self) -> Song {
+    /// The song, or why the entry can't be one
+    pub fn into_song(self) -> Result<Song, String> {
         if self.duration.is_zero() {
-            panic!(
+            return Err(format!(
                 "Song has zero duration: id={}, path={:?}, title={:?}",
                 self.id, self.path, self.title
-            );
+            ));
         }
         if self.duration.as_secs() > 24 * 60 * 60 {
-            panic!(
+            return Err(format!(
                 "Song has unreasonable duration (>24h, likely ms-as-seconds bug): id={}, path={:?}, title={:?}, duration={:?}",
                 self.id, self.path, self.title, self.duration
-            );
+            ));
         }
-        Song {
+        Ok(Song {
             id: SongId(self.id),
             file: AudioFile {
                 path: self.path,
@@ -195,7 +347,14 @@ impl SongEntry {
             album: self.album,
             track_number: self.track_number,
             duration: self.duration,
-        }
+            content_hash: self.content_hash,
+            fingerprint: self.fingerprint,
+            file_stamp: self.file_stamp,
+            added_at: self.added_at,
+            rating: self.rating.min(MAX_RATING),
+            loved: self.loved,
+            stats: PlayStats::default(),
+        })
     }
 }
 
@@ -259,15 +418,181 @@ fn format_from_string(s: &str) -> AudioFormat {
 }
 
 // ============================================================================
-// Streaming Save (JSONL format - one entry per line)
+// Saving (snapshot plus append-only log)
 // ============================================================================
 
+/// Compact once the log has more operations than this and than the snapshot has entries
+const MIN_COMPACTION_OPS: usize = 1000;
+
+/// What a manifest contains as of this process's last read or write of it
+struct ManifestState {
+    /// The manifest's size and modification time then. If the file has changed
+    /// since, the state can't be trusted and the next save writes a snapshot.
+    stamp: FileStamp,
+    songs: HashMap<u64, SongEntry>,
+    audiobooks: HashMap<u64, AudiobookEntry>,
+    /// Sequence number of the last operation written
+    seq: u64,
+    /// Operations appended since the snapshot
+    log_len: usize,
+}
+
+impl ManifestState {
+    fn from_library(library: &Library, stamp: FileStamp, seq: u64) -> Self {
+        Self {
+            stamp,
+            songs: library
+                .songs
+                .values()
+                .map(|song| (song.id.0, SongEntry::from_song(song)))
+                .collect(),
+            audiobooks: library
+                .audiobooks
+                .values()
+                .map(|audiobook| (audiobook.id.0, AudiobookEntry::from_audiobook(audiobook)))
+                .collect(),
+            seq,
+            log_len: 0,
+        }
+    }
+
+    fn needs_compaction(&self) -> bool {
+        self.log_len > MIN_COMPACTION_OPS.max(self.songs.len() + self.audiobooks.len())
+    }
+
+    /// The operations that bring the manifest in line with `library`
+    fn diff(&mut self, library: &Library) -> Vec<LibraryEntry> {
+        let mut ops = Vec::new();
+
+        for song in library.songs.values() {
+            let entry = SongEntry::from_song(song);
+            if self.songs.get(&entry.id) != Some(&entry) {
+                self.seq += 1;
+                self.songs.insert(entry.id, entry.clone());
+                ops.push(LibraryEntry::PutSong {
+                    seq: self.seq,
+                    song: entry,
+                });
+            }
+        }
+        let removed_songs: Vec<u64> = self
+            .songs
+            .keys()
+            .filter(|id| !library.songs.contains_key(&SongId(**id)))
+            .copied()
+            .collect();
+        for id in removed_songs {
+            self.seq += 1;
+            self.songs.remove(&id);
+            ops.push(LibraryEntry::RemoveSong { seq: self.seq, id });
+        }
+
+        for audiobook in library.audiobooks.values() {
+            let entry = AudiobookEntry::from_audiobook(audiobook);
+            if self.audiobooks.get(&entry.id) != Some(&entry) {
+                self.seq += 1;
+                self.audiobooks.insert(entry.id, entry.clone());
+                ops.push(LibraryEntry::PutAudiobook {
+                    seq: self.seq,
+                    audiobook: entry,
+                });
+            }
+        }
+        let removed_audiobooks: Vec<u64> = self
+            .audiobooks
+            .keys()
+            .filter(|id| !library.audiobooks.contains_key(&AudiobookId(**id)))
+            .copied()
+            .collect();
+        for id in removed_audiobooks {
+            self.seq += 1;
+            self.audiobooks.remove(&id);
+            ops.push(LibraryEntry::RemoveAudiobook { seq: self.seq, id });
+        }
+
+        self.log_len += ops.len();
+        ops
+    }
+}
+
+/// Manifest states by manifest path. Saves hold the lock while writing, so
+/// concurrent saves to the same manifest can't interleave.
+fn manifest_states() -> &'static Mutex<HashMap<PathBuf, ManifestState>> {
+    static STATES: OnceLock<Mutex<HashMap<PathBuf, ManifestState>>> = OnceLock::new();
+    STATES.get_or_init(Default::default)
+}
+
+/// Save a Library with the backend chosen in `paths`, backing up the library
+/// files first if the last backup is older than `BACKUP_INTERVAL`
+pub fn save_library(library: &Library, paths: &Paths) -> Result<(), StorageError> {
+    if let Err(e) = back_up_if_due(paths) {
+        eprintln!("Failed to back up library before saving: {}", e);
+    }
+    match paths.backend {
+        StorageBackend::Jsonl => save_jsonl(library, paths),
+        StorageBackend::Sqlite => SqliteStorage::open(paths)?.save_library(library),
+    }
+}
+
 /// Save a Library to the manifest file in JSONL format.
-/// Each line is a separate JSON object, making it resilient to partial corruption.
-pub fn save_library(library: &Library) -> Result<(), StorageError> {
-    ensure_directories()?;
+///
+/// Only songs and audiobooks that changed since the manifest was last read or
+/// written by this process are appended, as operations on the log. The manifest
+/// is rewritten as a snapshot the first time, when the log grows longer than the
+/// snapshot, or when the file was changed by something else.
+fn save_jsonl(library: &Library, paths: &Paths) -> Result<(), StorageError> {
+    paths.ensure_directories()?;
+
+    let path = paths.manifest();
+    let mut states = manifest_states()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
 
-    let path = manifest_path();
+    let state = states
+        .remove(&path)
+        .filter(|state| FileStamp::read(&path).ok() == Some(state.stamp));
+    let state = match state {
+        Some(mut state) => {
+            let ops = state.diff(library);
+            if state.needs_compaction() {
+                write_snapshot(library, &path, state.seq)?
+            } else {
+                if !ops.is_empty() {
+                    append_entries(&ops, &path)?;
+                    state.stamp = FileStamp::read(&path)?;
+                }
+                state
+            }
+        }
+        None => write_snapshot(library, &path, 0)?,
+    };
+    states.insert(path, state);
+
+    save_fingerprints(library, &paths.song_fingerprints())
+}
+
+/// Rewrite the manifest as a snapshot of the library, dropping the log
+pub fn compact_library(library: &Library, paths: &Paths) -> Result<(), StorageError> {
+    paths.ensure_directories()?;
+
+    let path = paths.manifest();
+    let mut states = manifest_states()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let seq = states.get(&path).map_or(0, |state| state.seq);
+    states.remove(&path);
+    let state = write_snapshot(library, &path, seq)?;
+    states.insert(path, state);
+
+    Ok(())
+}
+
+/// Each line is a separate JSON object, making it resilient to partial corruption.
+fn write_snapshot(library: &Library, path: &Path, seq: u64) -> Result<ManifestState, StorageError> {
+    if path.exists() {
+        check_schema_version(manifest_schema_version(path)?)?;
+    }
     let temp_path = path.with_extension("jsonl.tmp");
 
     let file = File::create(&temp_path)?;
@@ -275,8 +600,10 @@ pub fn save_library(library: &Library) -> Result<(), StorageError> {
 
     // Write metadata first
     let meta = LibraryEntry::Meta(LibraryMeta {
+        schema_version: MANIFEST_SCHEMA_VERSION,
         next_song_id: library.songs.keys().map(|id| id.0).max().unwrap_or(0) + 1,
         next_audiobook_id: library.audiobooks.keys().map(|id| id.0).max().unwrap_or(0) + 1,
+        seq,
     });
     writeln!(writer, "{}", serde_json::to_string(&meta)?)?;
 
@@ -296,8 +623,94 @@ pub fn save_library(library: &Library) -> Result<(), StorageError> {
     drop(writer);
 
     // Atomic rename
-    fs::rename(&temp_path, &path)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(ManifestState::from_library(
+        library,
+        FileStamp::read(path)?,
+        seq,
+    ))
+}
 
+/// Append log entries. A line torn by a crash is skipped when the manifest is read.
+fn append_entries(entries: &[LibraryEntry], path: &Path) -> Result<(), StorageError> {
+    let file = OpenOptions::new().append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    for entry in entries {
+        writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// ============================================================================
+// Schema Versions
+// ============================================================================
+
+/// Version of the manifest format written by this version of the player.
+///
+/// 1. Snapshot of songs and audiobooks after a meta line
+/// 2. Operation log appended after the snapshot; the meta line records the
+///    schema version and the last folded sequence number
+/// 3. Fingerprints moved to their own file
+pub const MANIFEST_SCHEMA_VERSION: u32 = 3;
+
+/// Upgrades a manifest line by one version: the migration at index `n` takes a
+/// version `n + 1` line to version `n + 2`. Add one for every new version.
+const MANIFEST_MIGRATIONS: &[fn(&mut serde_json::Value)] = &[migrate_v1_to_v2, migrate_v2_to_v3];
+
+fn migrate_v1_to_v2(entry: &mut serde_json::Value) {
+    if entry["type"] == "meta" {
+        if let Some(meta) = entry.as_object_mut() {
+            meta.entry("seq").or_insert(0.into());
+        }
+    }
+}
+
+/// Song lines keep their fingerprints, which are read until the next snapshot
+/// drops them and the next save writes them to the fingerprint file
+fn migrate_v2_to_v3(_entry: &mut serde_json::Value) {}
+
+/// Parse a manifest line written in `schema_version`, upgrading it to the
+/// current entry shape
+fn upgrade_entry(line: &str, schema_version: u32) -> Result<LibraryEntry, serde_json::Error> {
+    if schema_version >= MANIFEST_SCHEMA_VERSION {
+        return serde_json::from_str(line);
+    }
+
+    let mut entry: serde_json::Value = serde_json::from_str(line)?;
+    let first = schema_version.max(1) as usize - 1;
+    for migrate in &MANIFEST_MIGRATIONS[first..] {
+        migrate(&mut entry);
+    }
+    serde_json::from_value(entry)
+}
+
+/// The schema version in a manifest's meta line, which is always the first line
+fn manifest_schema_version(path: &Path) -> Result<u32, StorageError> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+
+    #[derive(Deserialize)]
+    struct VersionProbe {
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(default = "first_schema_version")]
+        schema_version: u32,
+    }
+    Ok(match serde_json::from_str::<VersionProbe>(&first_line) {
+        Ok(probe) if probe.kind == "meta" => probe.schema_version,
+        _ => first_schema_version(),
+    })
+}
+
+fn check_schema_version(found: u32) -> Result<(), StorageError> {
+    if found > MANIFEST_SCHEMA_VERSION {
+        return Err(StorageError::NewerSchema {
+            found,
+            supported: MANIFEST_SCHEMA_VERSION,
+        });
+    }
     Ok(())
 }
 
@@ -311,6 +724,10 @@ pub enum LoadedEntry {
     Song(Song),
     Audiobook(Audiobook),
     Meta(LibraryMeta),
+    /// A song removed by an operation in the log
+    RemovedSong(SongId),
+    /// An audiobook removed by an operation in the log
+    RemovedAudiobook(AudiobookId),
     /// Line was corrupted/invalid but we can continue
     Skipped {
         line_number: usize,
@@ -321,26 +738,69 @@ pub enum LoadedEntry {
 /// Iterator that streams entries from the library file
 pub struct LibraryReader {
     reader: BufReader<File>,
+    schema_version: u32,
     line_number: usize,
     line_buffer: String,
+    seq: u64,
+    log_len: usize,
+    fingerprints: HashMap<u64, Fingerprint>,
 }
 
 impl LibraryReader {
     /// Open the library file for streaming reads
-    pub fn open() -> Result<Option<Self>, StorageError> {
-        let path = manifest_path();
+    pub fn open(paths: &Paths) -> Result<Option<Self>, StorageError> {
+        let path = paths.manifest();
 
         if !path.exists() {
             return Ok(None);
         }
 
+        let schema_version = manifest_schema_version(&path)?;
+        check_schema_version(schema_version)?;
+
         let file = File::open(&path)?;
         Ok(Some(LibraryReader {
             reader: BufReader::new(file),
+            schema_version,
             line_number: 0,
             line_buffer: String::new(),
+            seq: 0,
+            log_len: 0,
+            fingerprints: load_fingerprints(&paths.song_fingerprints())?,
         }))
     }
+
+    /// Sequence number of the last operation read so far
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Version of the format the manifest was written in. Older entries are
+    /// upgraded as they are read.
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    fn read_op(&mut self, seq: u64) {
+        self.seq = self.seq.max(seq);
+        self.log_len += 1;
+    }
+
+    fn read_song(&self, entry: SongEntry) -> LoadedEntry {
+        let mut song = match entry.into_song() {
+            Ok(song) => song,
+            Err(error) => {
+                return LoadedEntry::Skipped {
+                    line_number: self.line_number,
+                    error,
+                }
+            }
+        };
+        if let Some(fingerprint) = self.fingerprints.get(&song.id.0) {
+            song.fingerprint = Some(fingerprint.clone());
+        }
+        LoadedEntry::Song(song)
+    }
 }
 
 impl Iterator for LibraryReader {
@@ -360,12 +820,31 @@ impl Iterator for LibraryReader {
                     return self.next();
                 }
 
-                match serde_json::from_str::<LibraryEntry>(line) {
-                    Ok(LibraryEntry::Song(entry)) => Some(LoadedEntry::Song(entry.into_song())),
+                match upgrade_entry(line, self.schema_version) {
+                    Ok(LibraryEntry::Song(entry)) => Some(self.read_song(entry)),
                     Ok(LibraryEntry::Audiobook(entry)) => {
                         Some(LoadedEntry::Audiobook(entry.into_audiobook()))
                     }
-                    Ok(LibraryEntry::Meta(meta)) => Some(LoadedEntry::Meta(meta)),
+                    Ok(LibraryEntry::Meta(meta)) => {
+                        self.seq = self.seq.max(meta.seq);
+                        Some(LoadedEntry::Meta(meta))
+                    }
+                    Ok(LibraryEntry::PutSong { seq, song }) => {
+                        self.read_op(seq);
+                        Some(self.read_song(song))
+                    }
+                    Ok(LibraryEntry::RemoveSong { seq, id }) => {
+                        self.read_op(seq);
+                        Some(LoadedEntry::RemovedSong(SongId(id)))
+                    }
+                    Ok(LibraryEntry::PutAudiobook { seq, audiobook }) => {
+                        self.read_op(seq);
+                        Some(LoadedEntry::Audiobook(audiobook.into_audiobook()))
+                    }
+                    Ok(LibraryEntry::RemoveAudiobook { seq, id }) => {
+                        self.read_op(seq);
+                        Some(LoadedEntry::RemovedAudiobook(AudiobookId(id)))
+                    }
                     Err(e) => Some(LoadedEntry::Skipped {
                         line_number: self.line_number,
                         error: e.to_string(),
@@ -380,47 +859,362 @@ impl Iterator for LibraryReader {
     }
 }
 
-/// Load the entire library at once (convenience function)
-pub fn load_library() -> Result<Library, StorageError> {
+/// Load the entire library at once with the backend chosen in `paths`. Songs
+/// with sequential ids from older libraries are given content-derived ids, which
+/// are saved along with the files that refer to them.
+pub fn load_library(paths: &Paths) -> Result<Library, StorageError> {
+    match paths.backend {
+        StorageBackend::Jsonl => load_jsonl(paths),
+        StorageBackend::Sqlite => {
+            let mut library = SqliteStorage::open(paths)?.load_library()?;
+            rekey_loaded_songs(&mut library, paths)?;
+            Ok(library)
+        }
+    }
+}
+
+/// Give a just loaded library's legacy songs derived ids. The manifest is saved
+/// with the new ids before the files that refer to songs by id are pointed at
+/// them, so those files never name ids the manifest doesn't have.
+/// Returns whether any song changed.
+fn rekey_loaded_songs(library: &mut Library, paths: &Paths) -> Result<bool, StorageError> {
+    let remap = library.rekey_songs();
+    if remap.is_empty() {
+        return Ok(false);
+    }
+    save_library(library, paths)?;
+    rekey_song_files(&remap, paths)?;
+    Ok(true)
+}
+
+/// Point the listening history, the saved session and the recorded backfill
+/// failures at songs' new ids after `Library::rekey_songs`.
+///
+/// Safe to repeat: derived ids never collide with legacy ones, so ids that were
+/// already updated are left alone. The import journal needs no update, as
+/// recovery matches songs by path.
+pub fn rekey_song_files(
+    remap: &HashMap<SongId, SongId>,
+    paths: &Paths,
+) -> Result<(), StorageError> {
+    rekey_history(remap, paths)?;
+    rekey_saved_session(remap, paths)?;
+    let mut failures = BackfillFailures::load(paths)?;
+    if failures.rekey(remap) {
+        failures.save(paths)?;
+    }
+    Ok(())
+}
+
+fn load_jsonl(paths: &Paths) -> Result<Library, StorageError> {
     let mut library = Library::default();
+    let path = paths.manifest();
+    let stamp = FileStamp::read(&path).ok();
 
-    if let Some(reader) = LibraryReader::open()? {
-        for entry in reader {
-            match entry {
-                LoadedEntry::Song(song) => {
-                    library.songs.insert(song.id, song);
-                }
-                LoadedEntry::Audiobook(audiobook) => {
-                    library.audiobooks.insert(audiobook.id, audiobook);
-                }
-                LoadedEntry::Meta(_) => {
-                    // Metadata is informational, we recalculate IDs as needed
-                }
-                LoadedEntry::Skipped { line_number, error } => {
-                    eprintln!("Warning: Skipped corrupted line {}: {}", line_number, error);
-                }
+    let Some(mut reader) = LibraryReader::open(paths)? else {
+        return Ok(library);
+    };
+    let mut skipped = false;
+    for entry in reader.by_ref() {
+        match entry {
+            LoadedEntry::Song(song) => {
+                library.songs.insert(song.id, song);
+            }
+            LoadedEntry::Audiobook(audiobook) => {
+                library.audiobooks.insert(audiobook.id, audiobook);
+            }
+            LoadedEntry::RemovedSong(id) => {
+                library.songs.remove(&id);
+            }
+            LoadedEntry::RemovedAudiobook(id) => {
+                library.audiobooks.remove(&id);
+            }
+            LoadedEntry::Meta(_) => {
+                // Metadata is informational, we recalculate IDs as needed
+            }
+            LoadedEntry::Skipped { line_number, error } => {
+                eprintln!("Warning: Skipped corrupted line {}: {}", line_number, error);
+                skipped = true;
             }
         }
     }
 
+    // Rekeying saves a snapshot, which the next save appends to
+    if rekey_loaded_songs(&mut library, paths)? {
+        return Ok(library);
+    }
+
+    // Let the next save append to what was read, unless the file changed while
+    // reading, has corrupt lines a snapshot would clean up, or is in an older
+    // format a snapshot would upgrade
+    let unchanged = stamp.filter(|stamp| FileStamp::read(&path).ok() == Some(*stamp));
+    let current = !skipped && reader.schema_version == MANIFEST_SCHEMA_VERSION;
+    if let Some(stamp) = unchanged.filter(|_| current) {
+        let mut state = ManifestState::from_library(&library, stamp, reader.seq);
+        state.log_len = reader.log_len;
+        manifest_states()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path, state);
+    }
+
     Ok(library)
 }
 
 // ============================================================================
-// Legacy compatibility (keep old paths working during migration)
+// Fingerprints (JSONL backend)
 // ============================================================================
 
-pub fn library_root() -> PathBuf {
-    player_root().join("Library")
+/// A line of the fingerprint file. Like the manifest it's a log where the last
+/// line for each id wins; saves append changes and rewrite it when it grows.
+#[derive(Debug, Serialize, Deserialize)]
+struct FingerprintEntry {
+    id: u64,
+    /// `None` once the song is gone or has no fingerprint
+    fingerprint: Option<Fingerprint>,
+}
+
+/// What the fingerprint file contains as of this process's last read or write of it
+struct FingerprintState {
+    stamp: FileStamp,
+    /// A hash of each song's fingerprint, to spot changes without keeping a copy
+    hashes: HashMap<u64, u64>,
+    /// Lines in the file
+    log_len: usize,
+}
+
+fn fingerprint_states() -> &'static Mutex<HashMap<PathBuf, FingerprintState>> {
+    static STATES: OnceLock<Mutex<HashMap<PathBuf, FingerprintState>>> = OnceLock::new();
+    STATES.get_or_init(Default::default)
+}
+
+fn fingerprint_hash(fingerprint: &Fingerprint) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    fingerprint.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Fingerprints by song id, skipping lines that fail to parse. A missing file has none.
+fn load_fingerprints(path: &Path) -> Result<HashMap<u64, Fingerprint>, StorageError> {
+    let mut fingerprints = HashMap::new();
+    let Ok(stamp) = FileStamp::read(path) else {
+        return Ok(fingerprints);
+    };
+
+    let mut log_len = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        log_len += 1;
+        match serde_json::from_str::<FingerprintEntry>(line?.trim()) {
+            Ok(FingerprintEntry {
+                id,
+                fingerprint: Some(fingerprint),
+            }) => {
+                fingerprints.insert(id, fingerprint);
+            }
+            Ok(FingerprintEntry {
+                id,
+                fingerprint: None,
+            }) => {
+                fingerprints.remove(&id);
+            }
+            Err(_) => {}
+        }
+    }
+
+    if FileStamp::read(path).ok() == Some(stamp) {
+        let state = FingerprintState {
+            stamp,
+            hashes: fingerprints
+                .iter()
+                .map(|(id, fingerprint)| (*id, fingerprint_hash(fingerprint)))
+                .collect(),
+            log_len,
+        };
+        fingerprint_states()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_path_buf(), state);
+    }
+    Ok(fingerprints)
+}
+
+/// Bring the fingerprint file in line with the library, appending only the
+/// fingerprints that changed since this process last read or wrote it
+fn save_fingerprints(library: &Library, path: &Path) -> Result<(), StorageError> {
+    let mut states = fingerprint_states()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let hashes: HashMap<u64, u64> = library
+        .songs
+        .values()
+        .filter_map(|song| Some((song.id.0, fingerprint_hash(song.fingerprint.as_ref()?))))
+        .collect();
+    let state = states
+        .remove(path)
+        .filter(|state| FileStamp::read(path).ok() == Some(state.stamp));
+
+    let entries: Vec<FingerprintEntry> = match &state {
+        Some(state) => {
+            let changed = hashes
+                .iter()
+                .filter(|(id, hash)| state.hashes.get(id) != Some(hash))
+                .map(|(id, _)| FingerprintEntry {
+                    id: *id,
+                    fingerprint: library.songs[&SongId(*id)].fingerprint.clone(),
+                });
+            let removed = state
+                .hashes
+                .keys()
+                .filter(|id| !hashes.contains_key(id))
+                .map(|id| FingerprintEntry {
+                    id: *id,
+                    fingerprint: None,
+                });
+            changed.chain(removed).collect()
+        }
+        None => Vec::new(),
+    };
+
+    let log_len = state.as_ref().map_or(0, |state| state.log_len) + entries.len();
+    let state = match state {
+        Some(state) if log_len <= MIN_COMPACTION_OPS.max(2 * hashes.len()) => {
+            if entries.is_empty() {
+                state
+            } else {
+                let file = OpenOptions::new().append(true).open(path)?;
+                write_fingerprint_entries(file, &entries)?;
+                FingerprintState {
+                    stamp: FileStamp::read(path)?,
+                    hashes,
+                    log_len,
+                }
+            }
+        }
+        // Rewrite the whole file: it's new, changed by something else, or mostly stale lines
+        _ => {
+            let entries: Vec<FingerprintEntry> = library
+                .songs
+                .values()
+                .filter(|song| song.fingerprint.is_some())
+                .map(|song| FingerprintEntry {
+                    id: song.id.0,
+                    fingerprint: song.fingerprint.clone(),
+                })
+                .collect();
+            if entries.is_empty() && !path.exists() {
+                return Ok(());
+            }
+            let temp_path = path.with_extension("jsonl.tmp");
+            write_fingerprint_entries(File::create(&temp_path)?, &entries)?;
+            fs::rename(&temp_path, path)?;
+            FingerprintState {
+                stamp: FileStamp::read(path)?,
+                hashes,
+                log_len: entries.len(),
+            }
+        }
+    };
+    states.insert(path.to_path_buf(), state);
+    Ok(())
+}
+
+fn write_fingerprint_entries(file: File, entries: &[FingerprintEntry]) -> Result<(), StorageError> {
+    let mut writer = BufWriter::new(file);
+    for entry in entries {
+        writeln!(writer, "{}", serde_json::to_string(entry)?)?;
+    }
+    writer.flush()?;
+    Ok(())
 }
 
 // ============================================================================
-// Traits for abstraction (future use)
+// Storage Backends
 // ============================================================================
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// `library.jsonl`: a snapshot plus an append-only log
+    #[default]
+    Jsonl,
+    /// `library.sqlite3`: an embedded database, indexed for lookups
+    Sqlite,
+}
+
 pub trait Storage {
     type Error;
 
     fn load_entries(&self) -> Result<Box<dyn Iterator<Item = LoadedEntry>>, Self::Error>;
     fn save_library(&self, library: &Library) -> Result<(), Self::Error>;
+
+    /// Load every entry into a library, applying removals in order. Songs keep
+    /// the ids they were stored with; the top-level `load_library` rekeys them.
+    fn load_library(&self) -> Result<Library, Self::Error> {
+        let mut library = Library::default();
+        for entry in self.load_entries()? {
+            match entry {
+                LoadedEntry::Song(song) => {
+                    library.songs.insert(song.id, song);
+                }
+                LoadedEntry::Audiobook(audiobook) => {
+                    library.audiobooks.insert(audiobook.id, audiobook);
+                }
+                LoadedEntry::RemovedSong(id) => {
+                    library.songs.remove(&id);
+                }
+                LoadedEntry::RemovedAudiobook(id) => {
+                    library.audiobooks.remove(&id);
+                }
+                LoadedEntry::Meta(_) => {}
+                LoadedEntry::Skipped { line_number, error } => {
+                    eprintln!(
+                        "Warning: Skipped corrupted entry {}: {}",
+                        line_number, error
+                    );
+                }
+            }
+        }
+        Ok(library)
+    }
+}
+
+/// The JSONL manifest behind the `Storage` trait
+pub struct JsonlStorage {
+    paths: Paths,
+}
+
+impl JsonlStorage {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            paths: paths.clone(),
+        }
+    }
+}
+
+impl Storage for JsonlStorage {
+    type Error = StorageError;
+
+    fn load_entries(&self) -> Result<Box<dyn Iterator<Item = LoadedEntry>>, Self::Error> {
+        Ok(match LibraryReader::open(&self.paths)? {
+            Some(reader) => Box::new(reader),
+            None => Box::new(std::iter::empty()),
+        })
+    }
+
+    fn save_library(&self, library: &Library) -> Result<(), Self::Error> {
+        save_jsonl(library, &self.paths)
+    }
+
+    fn load_library(&self) -> Result<Library, Self::Error> {
+        load_jsonl(&self.paths)
+    }
+}
+
+/// Open the backend chosen in `paths`
+pub fn open_storage(paths: &Paths) -> Result<Box<dyn Storage<Error = StorageError>>, StorageError> {
+    Ok(match paths.backend {
+        StorageBackend::Jsonl => Box::new(JsonlStorage::new(paths)),
+        StorageBackend::Sqlite => Box::new(SqliteStorage::open(paths)?),
+    })
 }
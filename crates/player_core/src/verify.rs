@@ -0,0 +1,309 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use id3::TagLike;
+use rayon::prelude::*;
+
+use crate::audio::AudioFormat;
+use crate::duplicates::{content_hash, DURATION_TOLERANCE};
+use crate::import::{
+    calculate_duration_by_decoding, read_metadata_read_only, scan_audio_files, song_from_metadata,
+    ImportError,
+};
+use crate::journal::adopt_file;
+use crate::library::{Library, Song, SongId};
+use crate::storage::Paths;
+
+// ============================================================================
+// Integrity Report
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityIssue {
+    /// A song's file no longer exists
+    MissingFile {
+        song_id: SongId,
+        path: PathBuf,
+        /// An untracked file with the same audio, if one was found
+        relink_to: Option<PathBuf>,
+    },
+    /// An audio file in Music that no song points at
+    Orphan { path: PathBuf },
+    /// The stored duration disagrees with the decoded length of the file
+    DurationMismatch {
+        song_id: SongId,
+        stored: Duration,
+        decoded: Duration,
+    },
+    /// The tags in the file were changed since the song was imported
+    StaleTags {
+        song_id: SongId,
+        /// The fields that differ, e.g. "title"
+        fields: Vec<&'static str>,
+    },
+}
+
+/// A fix that can be applied for an issue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityFix {
+    /// Point the song at a file found elsewhere with the same audio
+    Relink { song_id: SongId, path: PathBuf },
+    /// Add an untracked file to the library where it is
+    ImportOrphan { path: PathBuf },
+    /// Remove the song from the library
+    RemoveEntry { song_id: SongId },
+    /// Re-read the song's tags, duration and hash from its file
+    RefreshMetadata { song_id: SongId },
+}
+
+impl IntegrityIssue {
+    /// Fixes that can resolve this issue, the recommended one first
+    pub fn fixes(&self) -> Vec<IntegrityFix> {
+        match self {
+            IntegrityIssue::MissingFile {
+                song_id, relink_to, ..
+            } => {
+                let mut fixes = Vec::new();
+                if let Some(path) = relink_to {
+                    fixes.push(IntegrityFix::Relink {
+                        song_id: *song_id,
+                        path: path.clone(),
+                    });
+                }
+                fixes.push(IntegrityFix::RemoveEntry { song_id: *song_id });
+                fixes
+            }
+            IntegrityIssue::Orphan { path } => {
+                vec![IntegrityFix::ImportOrphan { path: path.clone() }]
+            }
+            IntegrityIssue::DurationMismatch { song_id, .. }
+            | IntegrityIssue::StaleTags { song_id, .. } => {
+                vec![IntegrityFix::RefreshMetadata { song_id: *song_id }]
+            }
+        }
+    }
+}
+
+impl IntegrityFix {
+    pub fn label(&self) -> &'static str {
+        match self {
+            IntegrityFix::Relink { .. } => "Relink",
+            IntegrityFix::ImportOrphan { .. } => "Import",
+            IntegrityFix::RemoveEntry { .. } => "Remove",
+            IntegrityFix::RefreshMetadata { .. } => "Refresh",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub songs_checked: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VerifyOptions {
+    /// Decode every file to check its duration. Much slower than the other checks.
+    pub check_durations: bool,
+}
+
+// ============================================================================
+// Verification
+// ============================================================================
+
+/// Check the library against the files on disk without changing anything
+pub fn verify_library(library: &Library, paths: &Paths, options: VerifyOptions) -> IntegrityReport {
+    let known: HashSet<&Path> = library
+        .songs
+        .values()
+        .map(|song| song.file.path.as_path())
+        .collect();
+    let orphans: Vec<PathBuf> = scan_audio_files(&paths.music)
+        .into_iter()
+        .filter(|path| !known.contains(path.as_path()))
+        .collect();
+
+    let mut issues: Vec<IntegrityIssue> = library
+        .songs
+        .values()
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .flat_map_iter(|song| check_song(song, options))
+        .collect();
+
+    // Missing songs may have moved to one of the untracked files
+    let missing_hashes: HashSet<&str> = issues
+        .iter()
+        .filter_map(|issue| match issue {
+            IntegrityIssue::MissingFile { song_id, .. } => {
+                library.songs[song_id].content_hash.as_deref()
+            }
+            _ => None,
+        })
+        .collect();
+    let orphans_by_hash: HashMap<String, PathBuf> = if missing_hashes.is_empty() {
+        HashMap::new()
+    } else {
+        orphans
+            .par_iter()
+            .filter_map(|path| {
+                let format = AudioFormat::from_path(path)?;
+                let hash = content_hash(path, format).ok()?;
+                missing_hashes
+                    .contains(hash.as_str())
+                    .then(|| (hash, path.clone()))
+            })
+            .collect()
+    };
+
+    let mut relinked = HashSet::new();
+    for issue in &mut issues {
+        if let IntegrityIssue::MissingFile {
+            song_id, relink_to, ..
+        } = issue
+        {
+            *relink_to = library.songs[song_id]
+                .content_hash
+                .as_ref()
+                .and_then(|hash| orphans_by_hash.get(hash))
+                .cloned();
+            relinked.extend(relink_to.clone());
+        }
+    }
+
+    issues.extend(
+        orphans
+            .into_iter()
+            .filter(|path| !relinked.contains(path))
+            .map(|path| IntegrityIssue::Orphan { path }),
+    );
+
+    IntegrityReport {
+        songs_checked: library.songs.len(),
+        issues,
+    }
+}
+
+fn check_song(song: &Song, options: VerifyOptions) -> Vec<IntegrityIssue> {
+    let path = &song.file.path;
+    if !path.exists() {
+        return vec![IntegrityIssue::MissingFile {
+            song_id: song.id,
+            path: path.clone(),
+            relink_to: None,
+        }];
+    }
+
+    let mut issues = Vec::new();
+
+    let fields = changed_tag_fields(song);
+    if !fields.is_empty() {
+        issues.push(IntegrityIssue::StaleTags {
+            song_id: song.id,
+            fields,
+        });
+    }
+
+    if options.check_durations {
+        if let Some(decoded) = calculate_duration_by_decoding(path) {
+            if decoded.abs_diff(song.duration) > DURATION_TOLERANCE {
+                issues.push(IntegrityIssue::DurationMismatch {
+                    song_id: song.id,
+                    stored: song.duration,
+                    decoded,
+                });
+            }
+        }
+    }
+
+    issues
+}
+
+/// Compare the tags in the file with the song. Only fields the file has a tag for
+/// are compared, since missing tags may have been filled in by a fingerprint lookup.
+fn changed_tag_fields(song: &Song) -> Vec<&'static str> {
+    if song.file.format != AudioFormat::Mp3 {
+        return Vec::new();
+    }
+    let Ok(tag) = id3::Tag::read_from_path(&song.file.path) else {
+        return Vec::new();
+    };
+
+    let checks = [
+        ("title", tag.title().map(|title| title != song.title)),
+        (
+            "artist",
+            tag.artist()
+                .or(tag.album_artist())
+                .map(|artist| Some(artist) != song.artist.as_deref()),
+        ),
+        (
+            "album",
+            tag.album()
+                .map(|album| Some(album) != song.album.as_deref()),
+        ),
+        (
+            "track",
+            tag.track().map(|track| Some(track) != song.track_number),
+        ),
+    ];
+    checks
+        .into_iter()
+        .filter(|(_, changed)| *changed == Some(true))
+        .map(|(field, _)| field)
+        .collect()
+}
+
+// ============================================================================
+// Fixes
+// ============================================================================
+
+/// Apply a fix to the library. Returns the song that was added or changed, if any.
+/// Files are never modified; save the library afterwards.
+pub fn apply_fix(library: &mut Library, fix: &IntegrityFix) -> Result<Option<Song>, ImportError> {
+    match fix {
+        IntegrityFix::Relink { song_id, path } => {
+            let Some(song) = library.songs.get_mut(song_id) else {
+                return Ok(None);
+            };
+            song.file.path = path.clone();
+            Ok(Some(song.clone()))
+        }
+        IntegrityFix::ImportOrphan { path } => adopt_file(path, library).map(Some),
+        IntegrityFix::RemoveEntry { song_id } => {
+            library.remove_song(*song_id);
+            Ok(None)
+        }
+        IntegrityFix::RefreshMetadata { song_id } => {
+            let Some(song) = library.songs.get(song_id) else {
+                return Ok(None);
+            };
+            let path = song.file.path.clone();
+            let imported = read_metadata_read_only(&path)?;
+            let duration = calculate_duration_by_decoding(&path)
+                .or(imported.metadata.duration)
+                .unwrap_or(song.duration);
+            let hash = content_hash(&path, imported.file.format).ok();
+
+            let mut refreshed = song_from_metadata(
+                song.id,
+                &imported,
+                path,
+                duration,
+                hash,
+                song.fingerprint.clone(),
+            );
+            refreshed.file_stamp = song.file_stamp;
+            refreshed.keep_user_data(song);
+            library.add_song(refreshed.clone());
+            Ok(Some(refreshed))
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long the Import folder must be quiet before checking whether files are complete
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often file sizes are re-checked while waiting for copies to finish
+const SETTLE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug)]
+pub enum WatchError {
+    Notify(notify::Error),
+    Io(std::io::Error),
+}
+
+impl From<notify::Error> for WatchError {
+    fn from(e: notify::Error) -> Self {
+        WatchError::Notify(e)
+    }
+}
+
+impl From<std::io::Error> for WatchError {
+    fn from(e: std::io::Error) -> Self {
+        WatchError::Io(e)
+    }
+}
+
+impl std::fmt::Display for WatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchError::Notify(e) => write!(f, "Watch error: {}", e),
+            WatchError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+/// Watches a folder and calls back once new files have landed and stopped growing.
+///
+/// Filesystem events are debounced, then file sizes are polled until two
+/// consecutive snapshots agree, so half-copied files are never imported.
+/// Watching stops when the watcher is dropped.
+pub struct ImportWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl ImportWatcher {
+    pub fn new<F>(path: &Path, debounce: Duration, on_ready: F) -> Result<Self, WatchError>
+    where
+        F: Fn() + Send + 'static,
+    {
+        fs::create_dir_all(path)?;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if event.is_ok_and(|event| !event.kind.is_access()) {
+                    let _ = sender.send(());
+                }
+            })?;
+        watcher.watch(path, RecursiveMode::Recursive)?;
+
+        let root = path.to_path_buf();
+        thread::spawn(move || {
+            let mut last_event: Option<Instant> = None;
+            let mut last_snapshot: Option<HashMap<PathBuf, u64>> = None;
+
+            loop {
+                match receiver.recv_timeout(SETTLE_INTERVAL) {
+                    Ok(()) => {
+                        last_event = Some(Instant::now());
+                        last_snapshot = None;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                let Some(event_time) = last_event else {
+                    continue;
+                };
+                if event_time.elapsed() < debounce {
+                    continue;
+                }
+
+                let snapshot = file_sizes(&root);
+                if last_snapshot.as_ref() == Some(&snapshot) {
+                    last_event = None;
+                    last_snapshot = None;
+                    if !snapshot.is_empty() {
+                        on_ready();
+                    }
+                } else {
+                    last_snapshot = Some(snapshot);
+                }
+            }
+        });
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// Size of every file under `path`, used to tell when copies have finished
+pub fn file_sizes(path: &Path) -> HashMap<PathBuf, u64> {
+    let mut sizes = HashMap::new();
+    let mut paths_to_scan = vec![path.to_path_buf()];
+
+    while let Some(current_path) = paths_to_scan.pop() {
+        let Ok(entries) = fs::read_dir(&current_path) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let entry_path = entry.path();
+            if entry_path.is_dir() {
+                paths_to_scan.push(entry_path);
+            } else if let Ok(metadata) = entry.metadata() {
+                sizes.insert(entry_path, metadata.len());
+            }
+        }
+    }
+
+    sizes
+}
@@ -0,0 +1,109 @@
+mod fixtures;
+
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use player_core::{AudioEngine, AudioFile, AudioFormat, AudioPlayerEvent, PlaybackState, Song};
+
+fn fixture_song() -> Song {
+    Song {
+        file: AudioFile {
+            path: fixtures::mp3_fixture(),
+            format: AudioFormat::Mp3,
+        },
+        duration: Duration::from_secs(42),
+        ..fixtures::song(1, "Fixture")
+    }
+}
+
+fn events(receiver: &Receiver<AudioPlayerEvent>) -> Vec<String> {
+    receiver
+        .try_iter()
+        .map(|event| match event {
+            AudioPlayerEvent::StateChanged(state) => format!("{:?}", state),
+            AudioPlayerEvent::SongChanged(Some(song)) => format!("Song {}", song.title),
+            AudioPlayerEvent::SongChanged(None) => "No song".to_string(),
+            AudioPlayerEvent::PlaybackFinished => "Finished".to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn controls_send_events_to_subscribers() {
+    let song = fixture_song();
+    let mut engine = AudioEngine::discarding();
+    let receiver = engine.subscribe();
+
+    engine.play_song(song.clone()).unwrap();
+    engine.toggle_playback();
+    assert_eq!(engine.state(), PlaybackState::Paused);
+    engine.toggle_playback();
+    engine.stop();
+
+    assert_eq!(
+        events(&receiver),
+        [
+            "Song Fixture",
+            "Playing",
+            "Paused",
+            "Playing",
+            "No song",
+            "Stopped"
+        ]
+    );
+
+    // A dropped receiver stops receiving without affecting the engine
+    drop(receiver);
+    engine.play_song(song).unwrap();
+    assert_eq!(engine.state(), PlaybackState::Playing);
+}
+
+#[test]
+fn reports_the_end_of_a_song() {
+    let song = fixture_song();
+    let mut engine = AudioEngine::discarding();
+    let receiver = engine.subscribe();
+    engine.play_song(song).unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !engine.check_and_handle_finished() {
+        assert!(Instant::now() < deadline, "song never finished");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    assert_eq!(engine.state(), PlaybackState::Stopped);
+    assert_eq!(
+        events(&receiver).last().map(String::as_str),
+        Some("Finished")
+    );
+}
+
+#[test]
+fn loads_a_song_paused_where_it_was_left() {
+    let mut engine = AudioEngine::discarding();
+    let receiver = engine.subscribe();
+
+    engine
+        .load_song(fixture_song(), Duration::from_millis(1500))
+        .unwrap();
+    assert_eq!(engine.state(), PlaybackState::Paused);
+    assert_eq!(engine.position(), Duration::from_millis(1500));
+    assert_eq!(events(&receiver), ["Song Fixture", "Paused"]);
+
+    engine.play();
+    assert_eq!(engine.state(), PlaybackState::Playing);
+    assert!(engine.position() >= Duration::from_millis(1500));
+}
+
+#[test]
+fn seeks_while_paused() {
+    let mut engine = AudioEngine::discarding();
+    engine.load_song(fixture_song(), Duration::ZERO).unwrap();
+
+    // A paused discarding output waits for samples to be wanted again; seeking
+    // has to wake it, or this would never return
+    engine.seek_to(Duration::from_secs(2));
+    assert_eq!(engine.state(), PlaybackState::Paused);
+    assert_eq!(engine.position(), Duration::from_secs(2));
+}
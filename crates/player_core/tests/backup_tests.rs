@@ -0,0 +1,129 @@
+mod fixtures;
+
+use std::fs;
+
+use fixtures::{mp3_fixture, song};
+use player_core::import::{import_all_pending, ImportOptions};
+use player_core::{
+    back_up, export_library, list_backups, load_library, restore_backup, save_library, Library,
+    Paths, Settings, KEPT_BACKUPS,
+};
+
+fn add_song(library: &mut Library, title: &str) {
+    let n = library.songs.len() as u64;
+    library.add_song(song(n, title));
+}
+
+#[test]
+fn saves_back_up_at_most_once_per_interval() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+
+    add_song(&mut library, "first");
+    save_library(&library, &paths).unwrap();
+    assert!(list_backups(&paths).unwrap().is_empty());
+
+    for n in 0..3 {
+        add_song(&mut library, &n.to_string());
+        save_library(&library, &paths).unwrap();
+    }
+
+    let backups = list_backups(&paths).unwrap();
+    assert_eq!(backups.len(), 1);
+    assert!(backups[0].files().contains(&"library.jsonl"));
+}
+
+#[test]
+fn backups_are_rotated() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    add_song(&mut library, "first");
+    save_library(&library, &paths).unwrap();
+
+    for _ in 0..KEPT_BACKUPS + 2 {
+        back_up(&paths).unwrap();
+    }
+
+    let backups = list_backups(&paths).unwrap();
+    assert_eq!(backups.len(), KEPT_BACKUPS);
+    assert!(backups[0].created_at > backups[1].created_at);
+}
+
+#[test]
+fn sync_backs_up_even_after_a_recent_backup() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    add_song(&mut library, "first");
+    save_library(&library, &paths).unwrap();
+    save_library(&library, &paths).unwrap();
+    assert_eq!(list_backups(&paths).unwrap().len(), 1);
+
+    fs::create_dir_all(&paths.import).unwrap();
+    fs::copy(mp3_fixture(), paths.import.join("song.mp3")).unwrap();
+    let options = ImportOptions {
+        fingerprint: false,
+        ..Default::default()
+    };
+    import_all_pending(&mut library, &options, &paths);
+
+    assert_eq!(list_backups(&paths).unwrap().len(), 2);
+}
+
+#[test]
+fn restore_brings_back_earlier_library() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    add_song(&mut library, "kept");
+    save_library(&library, &paths).unwrap();
+
+    // A bad change empties the library; the save backs up the good one first
+    save_library(&Library::new(), &paths).unwrap();
+    assert!(load_library(&paths).unwrap().is_empty());
+
+    let backup = list_backups(&paths).unwrap().remove(0);
+    restore_backup(&backup, &paths).unwrap();
+
+    assert_eq!(load_library(&paths).unwrap().songs.len(), 1);
+    // The state replaced by the restore was backed up too
+    assert_eq!(list_backups(&paths).unwrap().len(), 2);
+}
+
+#[test]
+fn export_writes_library_to_one_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    add_song(&mut library, "one");
+    add_song(&mut library, "two");
+    save_library(&library, &paths).unwrap();
+
+    let destination = dir.path().join("export").join("library.json");
+    let export = export_library(&paths, &destination).unwrap();
+
+    assert_eq!(export.songs.len(), 2);
+    let contents = fs::read_to_string(&destination).unwrap();
+    assert!(contents.contains("\"two\""));
+}
+
+#[test]
+fn export_leaves_out_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    save_library(&Library::new(), &paths).unwrap();
+    let mut settings = Settings::default();
+    settings.remote.token = Some("remote-secret".to_string());
+    settings.mpd.password = Some("mpd-secret".to_string());
+    fs::write(&paths.settings, serde_json::to_string(&settings).unwrap()).unwrap();
+
+    let destination = dir.path().join("library.json");
+    let export = export_library(&paths, &destination).unwrap();
+
+    assert_eq!(export.settings.unwrap().remote.token, None);
+    let contents = fs::read_to_string(&destination).unwrap();
+    assert!(!contents.contains("remote-secret"));
+    assert!(!contents.contains("mpd-secret"));
+}
@@ -0,0 +1,149 @@
+mod fixtures;
+
+use std::fs;
+use std::time::Duration;
+
+use fixtures::mp3_fixture;
+use player_core::duplicates::{
+    backfill_content_hashes, content_hash, find_duplicate, BackfillFailures, DuplicateMatch,
+};
+use player_core::edit::{write_tags, MetadataEdit};
+use player_core::import::Metadata;
+use player_core::{AudioFile, AudioFormat, Fingerprint, Library, Song, SongId};
+
+fn metadata(artist: &str, title: &str, secs: u64) -> Metadata {
+    Metadata {
+        title: Some(title.to_string()),
+        artist: Some(artist.to_string()),
+        duration: Some(Duration::from_secs(secs)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn content_hash_ignores_tag_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("song.mp3");
+    fs::copy(mp3_fixture(), &path).unwrap();
+
+    let before = content_hash(&path, AudioFormat::Mp3).unwrap();
+
+    let file = AudioFile {
+        path: path.clone(),
+        format: AudioFormat::Mp3,
+    };
+    let edit = MetadataEdit {
+        title: Some("A much longer title than before".to_string()),
+        ..Default::default()
+    };
+    write_tags(&file, &edit).unwrap();
+
+    assert_eq!(content_hash(&path, AudioFormat::Mp3).unwrap(), before);
+}
+
+#[test]
+fn duplicate_found_by_content_hash() {
+    let mut library = Library::new();
+    library.add_song(Song {
+        id: SongId(1),
+        content_hash: Some("abc".to_string()),
+        ..fixtures::song(1, "Title")
+    });
+
+    let duplicate = find_duplicate(&library, Some("abc"), None, &Metadata::default()).unwrap();
+
+    assert_eq!(duplicate.song_id, SongId(1));
+    assert_eq!(duplicate.matched_by, DuplicateMatch::ContentHash);
+}
+
+#[test]
+fn duplicate_found_by_metadata_within_tolerance() {
+    let mut library = Library::new();
+    library.add_song(Song {
+        artist: Some("Artist".to_string()),
+        duration: Duration::from_secs(200),
+        content_hash: Some("abc".to_string()),
+        ..fixtures::song(1, "Title")
+    });
+
+    let close = find_duplicate(
+        &library,
+        Some("def"),
+        None,
+        &metadata(" artist", "TITLE", 202),
+    );
+    let far = find_duplicate(
+        &library,
+        Some("def"),
+        None,
+        &metadata("Artist", "Title", 203),
+    );
+
+    assert_eq!(close.unwrap().matched_by, DuplicateMatch::Metadata);
+    assert!(far.is_none());
+}
+
+#[test]
+fn untagged_files_are_not_metadata_duplicates() {
+    let mut library = Library::new();
+    library.add_song(Song {
+        artist: Some("Artist".to_string()),
+        duration: Duration::from_secs(200),
+        ..fixtures::song(1, "Title")
+    });
+
+    let untagged = Metadata {
+        duration: Some(Duration::from_secs(200)),
+        ..Default::default()
+    };
+
+    assert!(find_duplicate(&library, None, None, &untagged).is_none());
+}
+
+#[test]
+fn most_similar_fingerprint_wins() {
+    let incoming = Fingerprint(vec![0; 32]);
+    // One differing bit in every other item, then none
+    let close = Fingerprint((0..32).map(|i| i % 2).collect());
+    let mut library = Library::new();
+    for (id, fingerprint) in [(1, close), (2, incoming.clone())] {
+        library.add_song(Song {
+            id: SongId(id),
+            artist: Some("Artist".to_string()),
+            duration: Duration::from_secs(200),
+            fingerprint: Some(fingerprint),
+            ..fixtures::song(id, "Title")
+        });
+    }
+
+    let duplicate = find_duplicate(
+        &library,
+        None,
+        Some(&incoming),
+        &metadata("Artist", "Title", 200),
+    )
+    .unwrap();
+
+    assert_eq!(duplicate.song_id, SongId(2));
+    assert_eq!(duplicate.matched_by, DuplicateMatch::Fingerprint);
+}
+
+#[test]
+fn failed_backfills_are_recorded_and_not_retried() {
+    let mut library = Library::new();
+    library.add_song(Song {
+        id: SongId(1),
+        ..fixtures::song(1, "Missing")
+    });
+
+    let mut failures = BackfillFailures::default();
+    backfill_content_hashes(&mut library, &mut failures);
+    assert_eq!(failures.content_hash.get(&1), Some(&None));
+
+    // The file is still missing, so it's skipped rather than retried
+    failures.content_hash.insert(1, None);
+    let recorded = failures.clone();
+    backfill_content_hashes(&mut library, &mut failures);
+    assert_eq!(failures, recorded);
+    assert_eq!(library.songs[&SongId(1)].content_hash, None);
+}
@@ -0,0 +1,205 @@
+mod fixtures;
+
+use std::fs;
+use std::time::Duration;
+
+use fixtures::{m4b_first_chunk, mp3_fixture, song, write_m4b_fixture};
+use player_core::edit::{edit_song, popm_rating, rate_songs, EditError, MetadataEdit, RatingEdit};
+use player_core::import::read_metadata;
+use player_core::{
+    load_library, save_library, AudioFile, AudioFormat, Library, Paths, Song, SongFilter, SongId,
+    SortOrder,
+};
+
+/// Edits here never refile, so nothing is written under the root
+fn paths() -> Paths {
+    Paths::from_root(std::env::temp_dir().join("player-edit-tests"))
+}
+
+fn song_at(path: std::path::PathBuf, format: AudioFormat) -> Song {
+    Song {
+        id: SongId(1),
+        file: AudioFile { path, format },
+        artist: Some("Old Artist".to_string()),
+        album: Some("Old Album".to_string()),
+        track_number: Some(1),
+        ..song(1, "Old Title")
+    }
+}
+
+fn library_with_song(path: std::path::PathBuf, format: AudioFormat) -> Library {
+    let mut library = Library::new();
+    library.add_song(song_at(path, format));
+    library
+}
+
+#[test]
+fn edit_song_writes_tags_and_updates_library() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("song.mp3");
+    fs::copy(mp3_fixture(), &path).unwrap();
+
+    let mut library = library_with_song(path.clone(), AudioFormat::Mp3);
+    let edit = MetadataEdit {
+        title: Some("New Title".to_string()),
+        album: Some(None),
+        track_number: Some(Some(7)),
+        ..Default::default()
+    };
+
+    let result = edit_song(&mut library, SongId(1), &edit, false, &paths()).unwrap();
+
+    assert_eq!(result.previous_path, path);
+    let song = &library.songs[&SongId(1)];
+    assert_eq!(song.title, "New Title");
+    assert_eq!(song.artist.as_deref(), Some("Old Artist"));
+    assert_eq!(song.album, None);
+    assert_eq!(song.track_number, Some(7));
+
+    let metadata = read_metadata(&path).unwrap().metadata;
+    assert_eq!(metadata.title.as_deref(), Some("New Title"));
+    assert_eq!(metadata.album, None);
+    assert_eq!(metadata.track_number, Some(7));
+}
+
+#[test]
+fn edit_missing_song_returns_error() {
+    let mut library = Library::new();
+    let result = edit_song(
+        &mut library,
+        SongId(42),
+        &MetadataEdit::default(),
+        false,
+        &paths(),
+    );
+
+    assert!(matches!(result, Err(EditError::SongNotFound(SongId(42)))));
+}
+
+#[test]
+fn edit_m4b_song_writes_mp4_tags() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("book.m4b");
+    write_m4b_fixture(&path);
+
+    let mut library = library_with_song(path.clone(), AudioFormat::M4b);
+    let edit = MetadataEdit {
+        title: Some("New Title".to_string()),
+        artist: Some(Some("Narrator".to_string())),
+        track_number: Some(Some(3)),
+        ..Default::default()
+    };
+    edit_song(&mut library, SongId(1), &edit, false, &paths()).unwrap();
+    assert_eq!(library.songs[&SongId(1)].title, "New Title");
+
+    let metadata = read_metadata(&path).unwrap().metadata;
+    assert_eq!(metadata.title.as_deref(), Some("New Title"));
+    assert_eq!(metadata.artist.as_deref(), Some("Narrator"));
+    assert_eq!(metadata.track_number, Some(3));
+    assert_eq!(metadata.duration, Some(Duration::from_secs(90)));
+
+    // The moov grew, so the chunk offset moved along with the audio
+    assert_eq!(m4b_first_chunk(&path), b"audio");
+}
+
+#[test]
+fn refile_onto_existing_file_leaves_tags_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let path = paths.music.join("song.mp3");
+    fs::create_dir_all(&paths.music).unwrap();
+    fs::copy(mp3_fixture(), &path).unwrap();
+
+    let mut library = library_with_song(path.clone(), AudioFormat::Mp3);
+    let edit = MetadataEdit {
+        title: Some("Taken".to_string()),
+        ..Default::default()
+    };
+    let taken = paths.music.join("Old Artist/Old Album/01 - Taken.mp3");
+    fs::create_dir_all(taken.parent().unwrap()).unwrap();
+    fs::write(&taken, b"another song").unwrap();
+
+    let original = fs::read(&path).unwrap();
+    let result = edit_song(&mut library, SongId(1), &edit, true, &paths);
+
+    assert!(matches!(result, Err(EditError::DestinationExists(_))));
+    assert_eq!(fs::read(&path).unwrap(), original);
+    assert_eq!(library.songs[&SongId(1)].title, "Old Title");
+}
+
+#[test]
+fn refile_leaves_songs_outside_the_music_folder_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path().join("Library"));
+    let path = dir.path().join("NAS").join("song.mp3");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::copy(mp3_fixture(), &path).unwrap();
+
+    let mut library = library_with_song(path.clone(), AudioFormat::Mp3);
+    let edit = MetadataEdit {
+        title: Some("New Title".to_string()),
+        ..Default::default()
+    };
+    let result = edit_song(&mut library, SongId(1), &edit, true, &paths).unwrap();
+
+    assert_eq!(result.song.file.path, path);
+    assert!(path.exists());
+    assert!(!paths.music.exists());
+}
+
+#[test]
+fn rate_songs_writes_popm_and_saves() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let path = dir.path().join("song.mp3");
+    fs::copy(mp3_fixture(), &path).unwrap();
+
+    let mut library = library_with_song(path.clone(), AudioFormat::Mp3);
+    let edit = RatingEdit {
+        rating: Some(4),
+        loved: Some(true),
+    };
+    let result = rate_songs(&mut library, &[SongId(1)], edit, true);
+    assert_eq!(result.edited.len(), 1);
+    save_library(&library, &paths).unwrap();
+
+    let reloaded = load_library(&paths).unwrap();
+    let song = reloaded.songs.values().next().unwrap();
+    assert_eq!(song.rating, 4);
+    assert!(song.loved);
+
+    let tag = id3::Tag::read_from_path(&path).unwrap();
+    let popm = tag
+        .frames()
+        .find_map(|frame| frame.content().popularimeter())
+        .unwrap();
+    assert_eq!(popm.rating, popm_rating(4));
+}
+
+#[test]
+fn ratings_filter_and_sort() {
+    let mut library = Library::new();
+    for (id, title, rating, loved) in [
+        (1, "One", 3, false),
+        (2, "Two", 5, false),
+        (3, "Three", 3, true),
+    ] {
+        library.add_song(Song {
+            rating,
+            loved,
+            ..song(id, title)
+        });
+    }
+
+    let titles = |filter| {
+        library
+            .list_filtered(SortOrder::Rating, filter)
+            .into_iter()
+            .map(|song| song.title)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(titles(SongFilter::All), ["Two", "Three", "One"]);
+    assert_eq!(titles(SongFilter::MinRating(4)), ["Two"]);
+    assert_eq!(titles(SongFilter::Loved), ["Three"]);
+    assert!(titles(SongFilter::Unrated).is_empty());
+}
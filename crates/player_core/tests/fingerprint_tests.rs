@@ -0,0 +1,95 @@
+mod fixtures;
+
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use fixtures::mp3_fixture;
+use player_core::fingerprint::{
+    compute_fingerprint, fingerprint_samples, Fingerprint, FingerprintDatabase, FingerprintRecord,
+    MetadataLookup,
+};
+
+const SAMPLE_RATE: u32 = 22050;
+
+/// A sequence of tones, changing pitch every half second
+fn melody(notes: &[f32], gain: f32) -> Vec<f32> {
+    let samples_per_note = SAMPLE_RATE as usize / 2;
+    notes
+        .iter()
+        .flat_map(|frequency| {
+            (0..samples_per_note).map(move |i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                gain * ((2.0 * PI * frequency * t).sin() + 0.5 * (4.0 * PI * frequency * t).sin())
+            })
+        })
+        .collect()
+}
+
+const TUNE: [f32; 16] = [
+    261.6, 293.7, 329.6, 349.2, 392.0, 440.0, 493.9, 523.3, 493.9, 440.0, 392.0, 349.2, 329.6,
+    293.7, 261.6, 196.0,
+];
+const OTHER_TUNE: [f32; 16] = [
+    415.3, 311.1, 466.2, 277.2, 370.0, 415.3, 233.1, 311.1, 554.4, 370.0, 277.2, 466.2, 233.1,
+    554.4, 415.3, 311.1,
+];
+
+#[test]
+fn same_recording_at_different_volume_matches() {
+    let original = fingerprint_samples(&melody(&TUNE, 0.8), SAMPLE_RATE);
+    let quieter = fingerprint_samples(&melody(&TUNE, 0.3), SAMPLE_RATE);
+
+    assert!(!original.is_empty());
+    assert!(original.matches(&quieter));
+}
+
+#[test]
+fn different_recordings_do_not_match() {
+    let original = fingerprint_samples(&melody(&TUNE, 0.8), SAMPLE_RATE);
+    let other = fingerprint_samples(&melody(&OTHER_TUNE, 0.8), SAMPLE_RATE);
+
+    assert!(!original.matches(&other));
+}
+
+#[test]
+fn fingerprint_round_trips_through_hex() {
+    let fingerprint = Fingerprint(vec![0, 1, 0xdeadbeef, u32::MAX]);
+
+    assert_eq!(
+        Fingerprint::from_hex(&fingerprint.to_hex()),
+        Some(fingerprint)
+    );
+    assert_eq!(Fingerprint::from_hex("abc"), None);
+}
+
+#[test]
+fn mp3_fixture_can_be_fingerprinted() {
+    let fingerprint = compute_fingerprint(&mp3_fixture()).unwrap();
+    assert!(fingerprint.matches(&fingerprint));
+}
+
+#[test]
+fn database_suggests_metadata_for_matching_fingerprint() {
+    let mut database = FingerprintDatabase::new();
+    database.insert(FingerprintRecord {
+        fingerprint: fingerprint_samples(&melody(&TUNE, 0.8), SAMPLE_RATE),
+        duration: Duration::from_secs(8),
+        title: Some("Scale".to_string()),
+        artist: Some("Tester".to_string()),
+        album: None,
+        track_number: None,
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("fingerprints.jsonl");
+    database.save(&path).unwrap();
+    let database = FingerprintDatabase::load(&path).unwrap();
+
+    let query = fingerprint_samples(&melody(&TUNE, 0.5), SAMPLE_RATE);
+    let suggestion = database.lookup(&query, Duration::from_secs(9)).unwrap();
+    assert_eq!(suggestion.title.as_deref(), Some("Scale"));
+
+    let other = fingerprint_samples(&melody(&OTHER_TUNE, 0.8), SAMPLE_RATE);
+    assert!(database.lookup(&other, Duration::from_secs(8)).is_none());
+    assert!(database.lookup(&query, Duration::from_secs(20)).is_none());
+}
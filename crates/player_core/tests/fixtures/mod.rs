@@ -1,4 +1,7 @@
 use std::path::PathBuf;
+use std::time::Duration;
+
+use player_core::{AudioFile, AudioFormat, ImportOptions, Library, PlayStats, Song, SongId};
 
 pub fn fixture_path(name: &str) -> PathBuf {
     PathBuf::from(env!("CARGO_MANIFEST_DIR"))
@@ -7,10 +10,89 @@ pub fn fixture_path(name: &str) -> PathBuf {
         .join(name)
 }
 
+// Not every test file uses every fixture
+#[allow(dead_code)]
 pub fn mp3_fixture() -> PathBuf {
     fixture_path("mp3_700KB.mp3")
 }
 
+/// Import options for tests, which skip fingerprinting to stay fast
+#[allow(dead_code)]
+pub fn options() -> ImportOptions {
+    ImportOptions {
+        fingerprint: false,
+        ..Default::default()
+    }
+}
+
+/// The id a song at `/music/{n}.mp3` gets, having no content hash
+#[allow(dead_code)]
+pub fn song_id(n: u64) -> SongId {
+    Library::new().assign_song_id(None, &PathBuf::from(format!("/music/{}.mp3", n)))
+}
+
+/// A three minute song at `/music/{n}.mp3` with no tags besides its title.
+/// Tests set the fields they care about with struct update syntax.
+#[allow(dead_code)]
+pub fn song(n: u64, title: &str) -> Song {
+    Song {
+        id: song_id(n),
+        file: AudioFile {
+            path: PathBuf::from(format!("/music/{}.mp3", n)),
+            format: AudioFormat::Mp3,
+        },
+        title: title.to_string(),
+        artist: None,
+        album: None,
+        track_number: None,
+        duration: Duration::from_secs(180),
+        content_hash: None,
+        fingerprint: None,
+        file_stamp: None,
+        added_at: None,
+        rating: 0,
+        loved: false,
+        stats: PlayStats::default(),
+    }
+}
+
+/// Write a minimal M4B to `path`: 90 seconds long per its `mvhd`, no tags,
+/// and a single chunk offset pointing at its `mdat` payload
+#[allow(dead_code)]
+pub fn write_m4b_fixture(path: &std::path::Path) {
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend(kind);
+        out.extend(payload);
+        out
+    }
+
+    let ftyp = atom(b"ftyp", b"M4B \0\0\0\0M4B isom");
+    let mut mvhd = vec![0u8; 100];
+    mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+    mvhd[16..20].copy_from_slice(&90_000u32.to_be_bytes());
+    let build_moov = |chunk_offset: u32| {
+        let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stco.extend(chunk_offset.to_be_bytes());
+        let stbl = atom(b"stbl", &atom(b"stco", &stco));
+        let trak = atom(b"trak", &atom(b"mdia", &atom(b"minf", &stbl)));
+        atom(b"moov", &[atom(b"mvhd", &mvhd), trak].concat())
+    };
+    let moov_len = build_moov(0).len();
+    let moov = build_moov((ftyp.len() + moov_len + 8) as u32);
+
+    std::fs::write(path, [ftyp, moov, atom(b"mdat", b"audio")].concat()).unwrap();
+}
+
+/// The bytes the first chunk offset of an M4B points at
+#[allow(dead_code)]
+pub fn m4b_first_chunk(path: &std::path::Path) -> Vec<u8> {
+    let bytes = std::fs::read(path).unwrap();
+    let stco = bytes.windows(4).position(|w| w == b"stco").unwrap();
+    let offset = u32::from_be_bytes(bytes[stco + 12..stco + 16].try_into().unwrap()) as usize;
+    bytes[offset..offset + 5].to_vec()
+}
+
 // TODO: Future fixtures needed:
 // - MP3 with full ID3v2.4 tags (title, artist, album, track number, year, genre)
 // - MP3 with ID3v1 tags only
@@ -0,0 +1,106 @@
+mod fixtures;
+
+use std::time::{Duration, Instant};
+
+use fixtures::song;
+use player_core::{
+    append_play, load_history, Library, Paths, PlayOutcome, PlayRecord, PlayTracker, PlaybackState,
+    Song, SortOrder,
+};
+
+fn play(song: &Song, started_at: u64, outcome: PlayOutcome) -> PlayRecord {
+    PlayRecord {
+        song_id: song.id.0,
+        started_at,
+        listened: Duration::from_secs(60),
+        outcome,
+    }
+}
+
+#[test]
+fn tracker_counts_listening_time_and_outcome() {
+    let one = song(1, "One");
+    let two = song(2, "Two");
+    let start = Instant::now();
+    let mut tracker = PlayTracker::new();
+
+    assert!(tracker.song_changed(Some(&one), start).is_none());
+    tracker.state_changed(PlaybackState::Playing, start);
+    tracker.state_changed(PlaybackState::Paused, start + Duration::from_secs(10));
+    tracker.state_changed(PlaybackState::Playing, start + Duration::from_secs(100));
+    let skipped = tracker
+        .song_changed(Some(&two), start + Duration::from_secs(105))
+        .unwrap();
+    assert_eq!(skipped.song_id, one.id.0);
+    assert_eq!(skipped.listened, Duration::from_secs(15));
+    assert_eq!(skipped.outcome, PlayOutcome::Skipped);
+
+    tracker.state_changed(PlaybackState::Playing, start + Duration::from_secs(105));
+    let completed = tracker.finished(start + Duration::from_secs(285)).unwrap();
+    assert_eq!(completed.song_id, two.id.0);
+    assert_eq!(completed.listened, Duration::from_secs(180));
+    assert_eq!(completed.outcome, PlayOutcome::Completed);
+
+    // Stopping after the end has nothing left to record
+    assert!(tracker
+        .song_changed(None, start + Duration::from_secs(290))
+        .is_none());
+}
+
+#[test]
+fn history_round_trips_into_song_stats() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let one = song(1, "One");
+    let two = song(2, "Two");
+
+    append_play(&play(&one, 100, PlayOutcome::Completed), &paths).unwrap();
+    append_play(&play(&two, 200, PlayOutcome::Skipped), &paths).unwrap();
+    append_play(&play(&one, 300, PlayOutcome::Completed), &paths).unwrap();
+
+    let mut library = Library::new();
+    library.add_song(one.clone());
+    library.add_song(two.clone());
+    library.apply_history(&load_history(&paths).unwrap());
+
+    let stats = library.songs[&one.id].stats;
+    assert_eq!(stats.play_count, 2);
+    assert_eq!(stats.skip_count, 0);
+    assert_eq!(stats.last_played, Some(300));
+    assert_eq!(library.songs[&two.id].stats.skip_count, 1);
+
+    // A song rebuilt from its file, as a rescan does, keeps its stats
+    let mut rescanned = one.clone();
+    rescanned.keep_user_data(&library.songs[&one.id]);
+    library.add_song(rescanned);
+    assert_eq!(library.songs[&one.id].stats.play_count, 2);
+}
+
+#[test]
+fn sorts_by_plays_and_recency() {
+    let [one, two, three] = [(1, "One"), (2, "Two"), (3, "Three")].map(|(n, title)| Song {
+        added_at: Some(n),
+        ..song(n, title)
+    });
+    let mut library = Library::new();
+    for song in [&one, &two, &three] {
+        library.add_song(song.clone());
+    }
+    library.apply_history(&[
+        play(&two, 100, PlayOutcome::Completed),
+        play(&two, 150, PlayOutcome::Completed),
+        play(&one, 200, PlayOutcome::Completed),
+        play(&three, 300, PlayOutcome::Skipped),
+    ]);
+
+    let titles = |order| {
+        library
+            .list(order)
+            .into_iter()
+            .map(|song| song.title)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(titles(SortOrder::MostPlayed), ["Two", "One", "Three"]);
+    assert_eq!(titles(SortOrder::RecentlyPlayed), ["Three", "One", "Two"]);
+    assert_eq!(titles(SortOrder::RecentlyAdded), ["Three", "Two", "One"]);
+}
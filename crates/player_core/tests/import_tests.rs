@@ -1,8 +1,21 @@
 mod fixtures;
 
-use fixtures::mp3_fixture;
-use player_core::import::{read_metadata, ImportError};
-use player_core::AudioFormat;
+use std::fs;
+
+use fixtures::{mp3_fixture, options, song, write_m4b_fixture};
+use player_core::edit::{write_tags, MetadataEdit};
+use player_core::import::{
+    import_all_pending, import_all_pending_with_events, import_file_to_library, preview_import,
+    read_metadata, CancelToken, ImportError, ImportOptions, ImportOutcome,
+};
+use player_core::{AudioFile, AudioFormat, DuplicatePolicy, Library, Paths, Song, SongId};
+
+fn pending_import(root: &std::path::Path) -> Paths {
+    let paths = Paths::from_root(root);
+    paths.ensure_directories().unwrap();
+    fs::copy(mp3_fixture(), paths.import.join("song.mp3")).unwrap();
+    paths
+}
 
 #[test]
 fn import_mp3_reads_metadata() {
@@ -28,3 +41,131 @@ fn import_nonexistent_file_returns_error() {
 
     assert!(matches!(result, Err(ImportError::Id3Error(_))));
 }
+
+#[test]
+fn import_all_pending_copies_and_archives() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = pending_import(dir.path());
+    let mut library = Library::new();
+
+    let results = import_all_pending(&mut library, &options(), &paths);
+    let result = results[0].as_ref().unwrap();
+
+    assert!(result.library_path.starts_with(&paths.music));
+    assert!(result.library_path.exists());
+    assert!(result.archived_path.starts_with(&paths.imported));
+    assert_eq!(library.songs.len(), 1);
+}
+
+#[test]
+fn same_recording_gets_same_id_in_separate_libraries() {
+    let import_into_new_library = || {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = pending_import(dir.path());
+        let results = import_all_pending(&mut Library::new(), &options(), &paths);
+        results[0].as_ref().unwrap().song.id
+    };
+
+    let id = import_into_new_library();
+    assert!(id.is_derived());
+    assert_eq!(import_into_new_library(), id);
+}
+
+#[test]
+fn cancelled_import_leaves_files_pending() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = pending_import(dir.path());
+    let mut library = Library::new();
+
+    let cancel = CancelToken::new();
+    cancel.cancel();
+    let results = import_all_pending_with_events(&mut library, &options(), &paths, &cancel, |_| {});
+
+    assert!(results.is_empty());
+    assert!(library.is_empty());
+    assert!(paths.import.join("song.mp3").exists());
+}
+
+#[test]
+fn interrupted_import_reuses_existing_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = pending_import(dir.path());
+
+    // First run copies the file, but the library is never saved
+    let first = import_all_pending(&mut Library::new(), &options(), &paths);
+    let first_path = first[0].as_ref().unwrap().library_path.clone();
+    fs::copy(mp3_fixture(), paths.import.join("song.mp3")).unwrap();
+
+    let mut library = Library::new();
+    let second = import_all_pending(&mut library, &options(), &paths);
+
+    assert_eq!(second[0].as_ref().unwrap().library_path, first_path);
+}
+
+#[test]
+fn preview_import_plans_without_moving_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = pending_import(dir.path());
+    fs::copy(mp3_fixture(), paths.import.join("copy.mp3")).unwrap();
+    let before = fs::read(paths.import.join("song.mp3")).unwrap();
+    let library = Library::new();
+
+    let results = preview_import(&library, &options(), &paths);
+    let outcomes: Vec<ImportOutcome> = results
+        .iter()
+        .map(|(_, result)| result.as_ref().unwrap().outcome)
+        .collect();
+
+    // The second copy is found as a duplicate of the first, as on import
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0], ImportOutcome::Imported);
+    assert!(matches!(outcomes[1], ImportOutcome::Skipped(_)));
+    // A preview never writes to the files, not even a duration tag
+    assert_eq!(fs::read(paths.import.join("song.mp3")).unwrap(), before);
+    assert!(!results[0].1.as_ref().unwrap().library_path.exists());
+    assert!(library.is_empty());
+}
+
+#[test]
+fn better_bitrate_never_replaces_a_different_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    paths.ensure_directories().unwrap();
+
+    // A tiny library copy, so any incoming file has the better bitrate
+    let existing = paths.music.join("Book.mp3");
+    fs::write(&existing, b"mp3").unwrap();
+    let mut library = Library::new();
+    library.add_song(Song {
+        id: SongId(1),
+        file: AudioFile {
+            path: existing.clone(),
+            format: AudioFormat::Mp3,
+        },
+        artist: Some("Narrator".to_string()),
+        duration: std::time::Duration::from_secs(90),
+        ..song(1, "Book")
+    });
+
+    let incoming = paths.import.join("Book.m4b");
+    write_m4b_fixture(&incoming);
+    let file = AudioFile {
+        path: incoming.clone(),
+        format: AudioFormat::M4b,
+    };
+    let edit = MetadataEdit {
+        title: Some("Book".to_string()),
+        artist: Some(Some("Narrator".to_string())),
+        ..Default::default()
+    };
+    write_tags(&file, &edit).unwrap();
+
+    let options = ImportOptions {
+        duplicate_policy: DuplicatePolicy::ReplaceIfBetterBitrate,
+        ..options()
+    };
+    let result = import_file_to_library(&incoming, &library, &options, &paths).unwrap();
+
+    assert!(matches!(result.outcome, ImportOutcome::Skipped(_)));
+    assert_eq!(fs::read(&existing).unwrap(), b"mp3");
+}
@@ -0,0 +1,93 @@
+mod fixtures;
+
+use std::fs;
+
+use fixtures::{mp3_fixture, options};
+use player_core::import::import_all_pending;
+use player_core::journal::{needs_recovery, recover_imports, ImportJournal};
+use player_core::{Library, Paths};
+
+#[test]
+fn unsaved_import_is_recovered_from_journal() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    paths.ensure_directories().unwrap();
+    fs::copy(mp3_fixture(), paths.import.join("song.mp3")).unwrap();
+
+    // The import finishes, but the app dies before the library is saved
+    let results = import_all_pending(&mut Library::new(), &options(), &paths);
+    let imported = results[0].as_ref().unwrap();
+    assert!(needs_recovery(&paths));
+
+    let mut library = Library::new();
+    let report = recover_imports(&mut library, &paths).unwrap();
+
+    assert_eq!(report.completed.len(), 1);
+    assert!(report.adopted.is_empty());
+    assert_eq!(library.songs.len(), 1);
+    assert_eq!(
+        library.songs.values().next().unwrap().file.path,
+        imported.library_path
+    );
+
+    ImportJournal::clear(&paths).unwrap();
+    assert!(!needs_recovery(&paths));
+}
+
+#[test]
+fn finished_copy_without_a_hash_is_recovered() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    paths.ensure_directories().unwrap();
+    let source = paths.import.join("song.mp3");
+    fs::copy(mp3_fixture(), &source).unwrap();
+    let results = import_all_pending(&mut Library::new(), &options(), &paths);
+    let imported = results[0].as_ref().unwrap();
+
+    // The copy finished but the app died before archiving the original. The
+    // song has no content hash, so only the copy's presence says it finished.
+    fs::rename(&imported.archived_path, &source).unwrap();
+    let journal = fs::read_to_string(paths.import_journal()).unwrap();
+    let begin: Vec<String> = journal
+        .lines()
+        .filter_map(|line| {
+            let mut entry: serde_json::Value = serde_json::from_str(line).unwrap();
+            (entry["op"] == "begin").then(|| {
+                entry["song"]
+                    .as_object_mut()
+                    .unwrap()
+                    .remove("content_hash");
+                entry.to_string()
+            })
+        })
+        .collect();
+    fs::write(paths.import_journal(), begin.join("\n") + "\n").unwrap();
+
+    let mut library = Library::new();
+    let report = recover_imports(&mut library, &paths).unwrap();
+
+    assert_eq!(report.completed.len(), 1);
+    assert!(report.rolled_back.is_empty());
+    assert!(report.adopted.is_empty());
+    assert_eq!(library.songs.len(), 1);
+    assert!(!source.exists());
+    assert!(imported.library_path.exists());
+}
+
+#[test]
+fn orphans_in_music_are_adopted_and_partials_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let album = paths.music.join("Artist").join("Album");
+    fs::create_dir_all(&album).unwrap();
+    fs::copy(mp3_fixture(), album.join("01 - Song.mp3")).unwrap();
+    fs::write(album.join(".02 - Other.mp3.partial"), b"truncated").unwrap();
+
+    let mut library = Library::new();
+    let report = recover_imports(&mut library, &paths).unwrap();
+
+    assert_eq!(report.adopted.len(), 1);
+    assert_eq!(report.adopted[0].file.path, album.join("01 - Song.mp3"));
+    assert_eq!(report.removed_partials.len(), 1);
+    assert!(!album.join(".02 - Other.mp3.partial").exists());
+}
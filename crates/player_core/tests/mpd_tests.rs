@@ -0,0 +1,243 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use player_core::{
+    AudioPlayerEvent, MpdServer, MpdSubsystem, PlaybackState, RemoteRequest, RemoteSong,
+    RemoteStatus, RepeatMode,
+};
+
+fn song(id: u64, title: &str, artist: &str) -> RemoteSong {
+    RemoteSong {
+        id,
+        title: title.to_string(),
+        artist: Some(artist.to_string()),
+        album: Some("Album".to_string()),
+        track_number: Some(id as u32),
+        duration: 180.0,
+        file: PathBuf::from(format!("/music/{}.mp3", title)),
+        rating: 0,
+        loved: false,
+        play_count: 0,
+    }
+}
+
+/// A player with a library of three songs, the first one playing
+fn fake_server(password: Option<&str>) -> MpdServer {
+    let library = vec![
+        song(1, "Intro", "Blue"),
+        song(2, "Outro", "Blue"),
+        song(3, "Other", "Green"),
+    ];
+    let status = Arc::new(Mutex::new(RemoteStatus {
+        state: PlaybackState::Playing,
+        song: Some(library[0].clone()),
+        position: 12.5,
+        volume: 0.5,
+        shuffle: false,
+        repeat: RepeatMode::Off,
+        queue: Vec::new(),
+    }));
+
+    MpdServer::start("127.0.0.1:0", password, move |call| {
+        let mut status = status.lock().unwrap();
+        match &call.request {
+            RemoteRequest::Search { .. } => call.respond(&library),
+            RemoteRequest::QueueAdd { ids } => {
+                for id in ids {
+                    let song = library.iter().find(|song| song.id == *id).unwrap();
+                    status.queue.push(song.clone());
+                }
+                call.respond(&status.queue)
+            }
+            RemoteRequest::Pause => {
+                status.state = PlaybackState::Paused;
+                call.respond(&*status)
+            }
+            _ => call.respond(&*status),
+        }
+    })
+    .unwrap()
+}
+
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    fn connect(server: &MpdServer) -> Self {
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut client = Self {
+            reader: BufReader::new(stream.try_clone().unwrap()),
+            writer: stream,
+        };
+        assert_eq!(client.read_line(), "OK MPD 0.23.0");
+        client
+    }
+
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+
+    /// Whether the server hung up, having sent nothing more
+    fn closed(&mut self) -> bool {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(read) => read == 0,
+            // Reset with our unread lines still in its buffer
+            Err(e) => e.kind() == std::io::ErrorKind::ConnectionReset,
+        }
+    }
+
+    /// Send lines and read the response up to its `OK` or `ACK`
+    fn send(&mut self, lines: &[&str]) -> Vec<String> {
+        for line in lines {
+            writeln!(self.writer, "{}", line).unwrap();
+        }
+        let mut response = Vec::new();
+        loop {
+            let line = self.read_line();
+            let done = line == "OK" || line.starts_with("ACK ");
+            response.push(line);
+            if done {
+                return response;
+            }
+        }
+    }
+}
+
+#[test]
+fn status_and_current_song() {
+    let server = fake_server(Some("hunter2"));
+    let mut client = Client::connect(&server);
+
+    assert_eq!(
+        client.send(&["status"]),
+        ["ACK [4@0] {status} you don't have permission for \"status\""]
+    );
+    assert_eq!(client.send(&["password \"hunter2\""]), ["OK"]);
+
+    let status = client.send(&["status"]);
+    for line in [
+        "volume: 50",
+        "repeat: 0",
+        "state: play",
+        "playlistlength: 1",
+        "song: 0",
+        "elapsed: 12.500",
+    ] {
+        assert!(status.iter().any(|l| l == line), "{} missing", line);
+    }
+
+    assert_eq!(
+        client.send(&[
+            "command_list_ok_begin",
+            "currentsong",
+            "pause 1",
+            "command_list_end"
+        ]),
+        [
+            "file: /music/Intro.mp3",
+            "Title: Intro",
+            "Artist: Blue",
+            "Album: Album",
+            "Track: 1",
+            "Time: 180",
+            "duration: 180.000",
+            "Pos: 0",
+            "Id: 0",
+            "list_OK",
+            "list_OK",
+            "OK",
+        ]
+    );
+    assert!(client
+        .send(&["status"])
+        .contains(&"state: pause".to_string()));
+    assert_eq!(
+        client.send(&["frobnicate"]),
+        ["ACK [5@0] {frobnicate} unknown command \"frobnicate\""]
+    );
+}
+
+#[test]
+fn find_and_add_to_the_playlist() {
+    let server = fake_server(None);
+    let mut client = Client::connect(&server);
+
+    let titles = |response: Vec<String>| -> Vec<String> {
+        response
+            .into_iter()
+            .filter(|line| line.starts_with("Title: "))
+            .collect()
+    };
+    assert_eq!(
+        titles(client.send(&["find artist Blue title Outro"])),
+        ["Title: Outro"]
+    );
+    assert_eq!(
+        titles(client.send(&["search any \"o\""])),
+        ["Title: Intro", "Title: Outro", "Title: Other"]
+    );
+    assert_eq!(
+        client.send(&["list artist"]),
+        ["Artist: Blue", "Artist: Green", "OK"]
+    );
+
+    assert_eq!(client.send(&["addid /music/Other.mp3"]), ["Id: 1", "OK"]);
+    assert_eq!(
+        client.send(&["add /music/Missing.mp3"]),
+        ["ACK [50@0] {add} No such song"]
+    );
+    assert_eq!(
+        titles(client.send(&["playlistinfo"])),
+        ["Title: Intro", "Title: Other"]
+    );
+}
+
+#[test]
+fn idle_reports_changes() {
+    let server = fake_server(None);
+    let mut client = Client::connect(&server);
+
+    writeln!(client.writer, "idle player playlist").unwrap();
+    server.changed(MpdSubsystem::Mixer);
+    server.broadcast(&AudioPlayerEvent::StateChanged(PlaybackState::Paused));
+    assert_eq!(client.read_line(), "changed: player");
+    assert_eq!(client.read_line(), "OK");
+
+    // The mixer change waited for the next idle
+    assert_eq!(client.send(&["idle"]), ["changed: mixer", "OK"]);
+
+    writeln!(client.writer, "idle options").unwrap();
+    assert_eq!(client.send(&["noidle"]), ["OK"]);
+}
+
+#[test]
+fn oversized_input_closes_the_connection() {
+    let server = fake_server(Some("hunter2"));
+
+    // Before the password, a line longer than any command
+    let mut client = Client::connect(&server);
+    let _ = writeln!(client.writer, "ping {}", "x".repeat(64 * 1024));
+    assert!(client.closed());
+
+    // A command list that never ends
+    let mut client = Client::connect(&server);
+    let _ = writeln!(client.writer, "command_list_begin");
+    let command = format!("ping {}\n", "x".repeat(4000));
+    for _ in 0..1000 {
+        if client.writer.write_all(command.as_bytes()).is_err() {
+            break;
+        }
+    }
+    assert!(client.closed());
+}
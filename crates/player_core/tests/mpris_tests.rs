@@ -0,0 +1,187 @@
+#![cfg(target_os = "linux")]
+
+mod fixtures;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use player_core::{MediaKeyEvent, MprisServer, RepeatMode, Song, SongId};
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{ObjectPath, OwnedObjectPath, OwnedValue};
+
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+const TRACK_LIST: &str = "org.mpris.MediaPlayer2.TrackList";
+
+/// A session bus of our own, so tests don't touch the desktop's
+struct PrivateBus {
+    daemon: Child,
+    address: String,
+}
+
+impl PrivateBus {
+    /// `None` if there's no `dbus-daemon` to run
+    fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--print-address=1", "--nofork", "--nopidfile"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+
+    fn client(&self) -> Connection {
+        zbus::blocking::connection::Builder::address(self.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+fn start_server(bus: &PrivateBus) -> (MprisServer, Receiver<MediaKeyEvent>) {
+    let (tx, rx) = mpsc::channel();
+    let server = MprisServer::with_address(&bus.address, move |event| {
+        let _ = tx.send(event);
+    })
+    .unwrap();
+    (server, rx)
+}
+
+fn proxy<'a>(client: &Connection, interface: &'a str) -> Proxy<'a> {
+    Proxy::new(
+        client,
+        "org.mpris.MediaPlayer2.player",
+        "/org/mpris/MediaPlayer2",
+        interface,
+    )
+    .unwrap()
+}
+
+fn next_event(events: &Receiver<MediaKeyEvent>) -> MediaKeyEvent {
+    events.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn player_reports_state_and_forwards_requests() {
+    let Some(bus) = PrivateBus::start() else {
+        eprintln!("Skipping: no dbus-daemon");
+        return;
+    };
+    let (mut server, events) = start_server(&bus);
+    let client = bus.client();
+    let player = proxy(&client, PLAYER);
+
+    let intro = Song {
+        id: SongId(7),
+        ..fixtures::song(7, "Intro")
+    };
+    server.set_metadata(Some(&intro)).unwrap();
+    server
+        .set_playback_playing(Some(Duration::from_secs(3)))
+        .unwrap();
+    server.set_repeat(RepeatMode::All).unwrap();
+    server.set_shuffle(true).unwrap();
+
+    let status: String = player.get_property("PlaybackStatus").unwrap();
+    assert_eq!(status, "Playing");
+    let loop_status: String = player.get_property("LoopStatus").unwrap();
+    assert_eq!(loop_status, "Playlist");
+    assert!(player.get_property::<bool>("Shuffle").unwrap());
+    assert!(player.get_property::<i64>("Position").unwrap() >= 3_000_000);
+
+    let metadata: HashMap<String, OwnedValue> = player.get_property("Metadata").unwrap();
+    let title: String = metadata["xesam:title"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(title, "Intro");
+    let track_id: OwnedObjectPath = metadata["mpris:trackid"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(track_id.as_str(), "/org/mpris/MediaPlayer2/Track/7");
+    let url: String = metadata["xesam:url"]
+        .try_clone()
+        .unwrap()
+        .try_into()
+        .unwrap();
+    assert_eq!(url, "file:///music/7.mp3");
+
+    let _: () = player.call("PlayPause", &()).unwrap();
+    assert_eq!(next_event(&events), MediaKeyEvent::Toggle);
+
+    let _: () = player.call("Seek", &(-2_000_000i64)).unwrap();
+    assert_eq!(
+        next_event(&events),
+        MediaKeyEvent::SeekBy {
+            offset: Duration::from_secs(2),
+            forward: false,
+        }
+    );
+
+    player.set_property("LoopStatus", "Track").unwrap();
+    assert_eq!(
+        next_event(&events),
+        MediaKeyEvent::SetRepeat(RepeatMode::One)
+    );
+    player.set_property("Volume", 0.5f64).unwrap();
+    assert_eq!(next_event(&events), MediaKeyEvent::SetVolume(0.5));
+}
+
+#[test]
+fn track_list_and_seeked_signal() {
+    let Some(bus) = PrivateBus::start() else {
+        eprintln!("Skipping: no dbus-daemon");
+        return;
+    };
+    let (mut server, events) = start_server(&bus);
+    let client = bus.client();
+
+    let tracks = [(1, "One"), (2, "Two")].map(|(id, title)| Song {
+        id: SongId(id),
+        ..fixtures::song(id, title)
+    });
+    server.set_metadata(Some(&tracks[0])).unwrap();
+    server.set_tracks(&tracks).unwrap();
+
+    let track_list = proxy(&client, TRACK_LIST);
+    let tracks: Vec<OwnedObjectPath> = track_list.get_property("Tracks").unwrap();
+    assert_eq!(tracks.len(), 2);
+
+    let two = ObjectPath::try_from("/org/mpris/MediaPlayer2/Track/2").unwrap();
+    let _: () = track_list.call("GoTo", &(two,)).unwrap();
+    assert_eq!(next_event(&events), MediaKeyEvent::GoTo(SongId(2)));
+
+    // Listen on a thread, so a missing signal fails the test instead of hanging it
+    let player = proxy(&client, PLAYER);
+    let mut seeked = player.receive_signal("Seeked").unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        if let Some(message) = seeked.next() {
+            let _ = tx.send(message.body().deserialize::<i64>().unwrap());
+        }
+    });
+
+    server.seeked(Duration::from_secs(7)).unwrap();
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 7_000_000);
+}
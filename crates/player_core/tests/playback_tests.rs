@@ -0,0 +1,58 @@
+mod fixtures;
+
+use fixtures::song;
+use player_core::{MediaItem, Queue, RepeatMode};
+
+fn queue(titles: &[&str]) -> Queue {
+    let items = titles
+        .iter()
+        .enumerate()
+        .map(|(n, title)| MediaItem::Song(song(n as u64, title)))
+        .collect();
+    Queue::new(items)
+}
+
+fn title(item: Option<&MediaItem>) -> Option<&str> {
+    match item? {
+        MediaItem::Song(song) => Some(&song.title),
+        MediaItem::Audiobook(book) => Some(&book.title),
+    }
+}
+
+#[test]
+fn advance_follows_repeat_mode() {
+    let mut queue = queue(&["One", "Two"]);
+    assert_eq!(title(queue.select(1)), Some("Two"));
+
+    assert_eq!(title(queue.advance(false, RepeatMode::Off, true)), None);
+    assert_eq!(
+        title(queue.advance(false, RepeatMode::One, true)),
+        Some("Two")
+    );
+    // Skipping moves on even when repeating one song
+    assert_eq!(title(queue.advance(false, RepeatMode::One, false)), None);
+    assert_eq!(
+        title(queue.advance(false, RepeatMode::All, true)),
+        Some("One")
+    );
+    assert_eq!(title(queue.go_back(false)), None);
+    assert_eq!(title(queue.current_item()), Some("One"));
+}
+
+#[test]
+fn shuffle_picks_another_item() {
+    let mut queue = queue(&["One", "Two", "Three"]);
+    queue.select(0);
+    for _ in 0..10 {
+        let before = queue.current;
+        queue.advance(true, RepeatMode::Off, true);
+        assert_ne!(queue.current, before);
+    }
+
+    let mut single = self::queue(&["Only"]);
+    single.select(0);
+    assert_eq!(
+        title(single.advance(true, RepeatMode::Off, true)),
+        Some("Only")
+    );
+}
@@ -0,0 +1,92 @@
+mod fixtures;
+
+use std::fs;
+
+use fixtures::{mp3_fixture, options};
+use player_core::import::{import_all_pending, ImportError};
+use player_core::problems::{
+    edit_problem_tags, force_import_problem, problem_files, ProblemError, ProblemKind,
+};
+use player_core::{Library, MetadataEdit, Paths};
+
+/// Import a copy of the fixture with its tags stripped, which lands it in Problem
+fn import_untagged(root: &std::path::Path) -> Paths {
+    let paths = Paths::from_root(root);
+    paths.ensure_directories().unwrap();
+    let source = paths.import.join("untagged.mp3");
+    fs::copy(mp3_fixture(), &source).unwrap();
+    id3::Tag::remove_from_path(&source).unwrap();
+
+    let results = import_all_pending(&mut Library::new(), &options(), &paths);
+    assert!(matches!(results[0], Err(ImportError::Id3Error(_))));
+    paths
+}
+
+#[test]
+fn failed_import_is_logged_with_reason() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = import_untagged(dir.path());
+
+    let problems = problem_files(&paths).unwrap();
+
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].kind, ProblemKind::UnreadableTags);
+    assert_eq!(problems[0].original_path, paths.import.join("untagged.mp3"));
+    assert!(problems[0].path.starts_with(&paths.problem));
+    assert!(problems[0].path.exists());
+}
+
+#[test]
+fn torn_problem_log_line_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = import_untagged(dir.path());
+    let log = fs::read_to_string(paths.problem_log()).unwrap();
+    fs::write(paths.problem_log(), format!("{{\"path\": \"/Prob\n{}", log)).unwrap();
+
+    assert_eq!(problem_files(&paths).unwrap().len(), 1);
+}
+
+#[test]
+fn edited_problem_file_imports_on_retry() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = import_untagged(dir.path());
+    let problem = problem_files(&paths).unwrap().remove(0);
+
+    let edit = MetadataEdit {
+        title: Some("Fixed".to_string()),
+        artist: Some(Some("Artist".to_string())),
+        ..Default::default()
+    };
+    let retry_path = edit_problem_tags(&problem, &edit, &paths).unwrap();
+    assert!(retry_path.starts_with(&paths.import));
+    assert!(problem_files(&paths).unwrap().is_empty());
+
+    let mut library = Library::new();
+    let results = import_all_pending(&mut library, &options(), &paths);
+
+    assert_eq!(results[0].as_ref().unwrap().song.title, "Fixed");
+}
+
+#[test]
+fn force_import_without_a_duration_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    paths.ensure_directories().unwrap();
+    fs::write(paths.import.join("noise.mp3"), b"not really audio").unwrap();
+    import_all_pending(&mut Library::new(), &options(), &paths);
+    let problem = problem_files(&paths).unwrap().remove(0);
+
+    let edit = MetadataEdit {
+        title: Some("Noise".to_string()),
+        ..Default::default()
+    };
+    let mut library = Library::new();
+    let result = force_import_problem(&problem, &mut library, &edit, &options(), &paths);
+
+    assert!(matches!(
+        result,
+        Err(ProblemError::Import(ImportError::NoDuration(_)))
+    ));
+    assert!(library.is_empty());
+    assert_eq!(problem_files(&paths).unwrap().len(), 1);
+}
@@ -0,0 +1,125 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use player_core::{AudioPlayerEvent, PlaybackState, RemoteRequest, RemoteServer};
+use serde_json::{json, Value};
+
+const TOKEN: &str = "secret";
+
+/// A server that answers every call with the request it parsed
+fn echo_server() -> RemoteServer {
+    RemoteServer::start("127.0.0.1:0", TOKEN, |call| {
+        let answer = format!("{:?}", call.request);
+        match call.request {
+            RemoteRequest::QueueRemove { index } if index > 0 => call.fail("No such entry"),
+            _ => call.respond(answer),
+        }
+    })
+    .unwrap()
+}
+
+/// Send a request and return the status line and body
+fn http(
+    server: &RemoteServer,
+    method: &str,
+    target: &str,
+    token: &str,
+    body: &str,
+) -> (String, String) {
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        target,
+        token,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+fn rpc(server: &RemoteServer, call: Value) -> Value {
+    let (status, body) = http(server, "POST", "/rpc", TOKEN, &call.to_string());
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn requests_without_the_token_are_refused() {
+    let server = echo_server();
+
+    let (status, _) = http(&server, "POST", "/rpc", "wrong", "{}");
+    assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+    let (status, _) = http(&server, "GET", "/events", "", "");
+    assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+}
+
+#[test]
+fn calls_are_answered_by_the_player() {
+    let server = echo_server();
+
+    let response = rpc(
+        &server,
+        json!({"jsonrpc": "2.0", "id": 1, "method": "toggle"}),
+    );
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["result"], "Toggle");
+
+    let response = rpc(
+        &server,
+        json!({"jsonrpc": "2.0", "id": 2, "method": "search", "params": {"query": "blue"}}),
+    );
+    assert_eq!(response["result"], "Search { query: \"blue\", limit: 50 }");
+
+    let response = rpc(
+        &server,
+        json!({"jsonrpc": "2.0", "id": 3, "method": "queue.remove", "params": {"index": 4}}),
+    );
+    assert_eq!(response["error"]["message"], "No such entry");
+
+    let response = rpc(
+        &server,
+        json!({"jsonrpc": "2.0", "id": 4, "method": "explode"}),
+    );
+    assert_eq!(response["error"]["code"], -32601);
+
+    let response = rpc(
+        &server,
+        json!({"jsonrpc": "2.0", "id": 5, "method": "seek"}),
+    );
+    assert_eq!(response["error"]["code"], -32602);
+}
+
+#[test]
+fn events_are_streamed_to_subscribers() {
+    let server = echo_server();
+
+    let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+    write!(stream, "GET /events?token={} HTTP/1.1\r\n\r\n", TOKEN).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while line.trim_end() != ": connected" {
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+    }
+
+    server.broadcast(&AudioPlayerEvent::StateChanged(PlaybackState::Paused));
+
+    let mut event = String::new();
+    while !event.ends_with("\n\n") {
+        reader.read_line(&mut event).unwrap();
+    }
+    assert_eq!(
+        event.trim_start(),
+        "event: state\ndata: {\"state\":\"paused\"}\n\n"
+    );
+}
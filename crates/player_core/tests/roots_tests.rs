@@ -0,0 +1,106 @@
+mod fixtures;
+
+use std::fs;
+
+use fixtures::{mp3_fixture, options};
+use player_core::edit::{rate_songs, RatingEdit};
+use player_core::{rescan_root, Library, LibraryRoot};
+
+#[test]
+fn rescan_indexes_files_in_place() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Artist").join("song.mp3");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::copy(mp3_fixture(), &path).unwrap();
+    let before = fs::read(&path).unwrap();
+
+    let root = LibraryRoot::new(dir.path());
+    let mut library = Library::new();
+    let result = rescan_root(&mut library, &root, &options()).unwrap();
+
+    assert_eq!(result.added.len(), 1);
+    assert_eq!(result.added[0].file.path, path);
+    // Files under a root are never written to
+    assert_eq!(fs::read(&path).unwrap(), before);
+    assert_eq!(library.songs.len(), 1);
+
+    // Nothing changed on disk, so nothing is re-read
+    let result = rescan_root(&mut library, &root, &options()).unwrap();
+    assert!(!result.changed_library());
+}
+
+#[test]
+fn rescan_detects_moved_and_removed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.mp3");
+    fs::copy(mp3_fixture(), &first).unwrap();
+
+    let root = LibraryRoot::new(dir.path());
+    let mut library = Library::new();
+    let added = rescan_root(&mut library, &root, &options()).unwrap().added;
+    let id = added[0].id;
+
+    let moved_path = dir.path().join("Moved").join("first.mp3");
+    fs::create_dir_all(moved_path.parent().unwrap()).unwrap();
+    fs::rename(&first, &moved_path).unwrap();
+
+    let result = rescan_root(&mut library, &root, &options()).unwrap();
+    assert_eq!(result.moved.len(), 1);
+    assert!(result.added.is_empty() && result.removed.is_empty());
+    assert_eq!(library.songs[&id].file.path, moved_path);
+
+    fs::remove_file(&moved_path).unwrap();
+
+    let result = rescan_root(&mut library, &root, &options()).unwrap();
+    assert_eq!(result.removed, vec![id]);
+    assert!(library.is_empty());
+}
+
+#[test]
+fn rescan_keeps_ratings_of_changed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root_dir = dir.path().join("root");
+    fs::create_dir_all(&root_dir).unwrap();
+    fs::copy(mp3_fixture(), root_dir.join("song.mp3")).unwrap();
+
+    let root = LibraryRoot::new(&root_dir);
+    let mut library = Library::new();
+    let id = rescan_root(&mut library, &root, &options()).unwrap().added[0].id;
+    let added_at = library.songs[&id].added_at;
+
+    // Writing the rating to the file's tags changes it, so the rescan re-reads it
+    let edit = RatingEdit {
+        rating: Some(4),
+        loved: Some(true),
+    };
+    rate_songs(&mut library, &[id], edit, true);
+    let result = rescan_root(&mut library, &root, &options()).unwrap();
+
+    assert_eq!(result.updated.len(), 1);
+    assert_eq!(result.updated[0].rating, 4);
+    let song = &library.songs[&id];
+    assert_eq!(song.rating, 4);
+    assert!(song.loved);
+    assert_eq!(song.added_at, added_at);
+}
+
+#[test]
+fn unreadable_root_leaves_library_untouched() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::copy(mp3_fixture(), dir.path().join("song.mp3")).unwrap();
+
+    let root = LibraryRoot::new(dir.path());
+    let mut library = Library::new();
+    rescan_root(&mut library, &root, &options()).unwrap();
+
+    let unmounted = LibraryRoot::new(dir.path().join("missing"));
+    let mut songs_elsewhere = Library::new();
+    for song in library.songs.values() {
+        let mut song = song.clone();
+        song.file.path = unmounted.path.join("song.mp3");
+        songs_elsewhere.add_song(song);
+    }
+
+    assert!(rescan_root(&mut songs_elsewhere, &unmounted, &options()).is_err());
+    assert_eq!(songs_elsewhere.songs.len(), 1);
+}
@@ -0,0 +1,174 @@
+mod fixtures;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use player_core::{
+    flush_queue, is_scrobble, load_queue, log_scrobble, ListenPayload, Paths, PlayOutcome,
+    PlayRecord, ScrobbleError, Scrobbler, Song,
+};
+
+fn play(song: &Song, started_at: u64, listened_secs: u64) -> PlayRecord {
+    PlayRecord {
+        song_id: song.id.0,
+        started_at,
+        listened: Duration::from_secs(listened_secs),
+        outcome: PlayOutcome::Skipped,
+    }
+}
+
+/// Posts each payload to a local HTTP server, the way a network submitter would
+struct StubSubmitter {
+    address: String,
+}
+
+impl Scrobbler for StubSubmitter {
+    fn submit(&mut self, payload: &ListenPayload) -> Result<(), ScrobbleError> {
+        let body = serde_json::to_string(payload)?;
+        let mut stream = TcpStream::connect(&self.address)?;
+        write!(
+            stream,
+            "POST /1/submit-listens HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            self.address,
+            body.len(),
+            body
+        )?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        if status.contains(" 200 ") {
+            Ok(())
+        } else {
+            Err(ScrobbleError::Submit(status.trim().to_string()))
+        }
+    }
+}
+
+/// Answer each request with the next status, returning the bodies received
+fn stub_server(statuses: Vec<&'static str>) -> (String, thread::JoinHandle<Vec<ListenPayload>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let handle = thread::spawn(move || {
+        let mut bodies = Vec::new();
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length: ") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            bodies.push(serde_json::from_slice(&body).unwrap());
+
+            let mut stream = reader.into_inner();
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+        }
+        bodies
+    });
+    (address, handle)
+}
+
+#[test]
+fn scrobbles_after_half_or_four_minutes() {
+    let secs = Duration::from_secs;
+    assert!(is_scrobble(secs(180), secs(90)));
+    assert!(!is_scrobble(secs(180), secs(89)));
+    assert!(is_scrobble(secs(1200), secs(240)));
+    assert!(!is_scrobble(secs(30), secs(30)));
+}
+
+#[test]
+fn plays_are_logged_and_scrobbles_queued() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let [one, two] = [1, 2].map(|n| Song {
+        artist: Some("Artist".to_string()),
+        album: Some("Album".to_string()),
+        track_number: Some(n as u32),
+        duration: Duration::from_secs(200),
+        ..fixtures::song(n, &format!("Song {}", n))
+    });
+
+    assert!(log_scrobble(&one, &play(&one, 1000, 150), &paths)
+        .unwrap()
+        .is_some());
+    assert!(log_scrobble(&two, &play(&two, 1200, 20), &paths)
+        .unwrap()
+        .is_none());
+
+    let log = fs::read_to_string(paths.scrobbler_log()).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines[0], "#AUDIOSCROBBLER/1.1");
+    assert_eq!(lines[3], "Artist\tAlbum\tSong 1\t1\t200\tL\t1000\t");
+    assert_eq!(lines[4], "Artist\tAlbum\tSong 2\t2\t200\tS\t1200\t");
+
+    let queue = load_queue(&paths).unwrap();
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue[0].listened_at, 1000);
+    assert_eq!(queue[0].track_metadata.track_name, "Song 1");
+}
+
+#[test]
+fn plays_ending_together_are_all_queued() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+
+    let threads: Vec<_> = (1..=8)
+        .map(|n| {
+            let paths = paths.clone();
+            thread::spawn(move || {
+                let song = Song {
+                    artist: Some("Artist".to_string()),
+                    ..fixtures::song(n, &format!("Song {}", n))
+                };
+                log_scrobble(&song, &play(&song, n * 1000, 200), &paths).unwrap();
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(load_queue(&paths).unwrap().len(), 8);
+    let log = fs::read_to_string(paths.scrobbler_log()).unwrap();
+    assert_eq!(log.lines().filter(|line| line.starts_with('#')).count(), 3);
+}
+
+#[test]
+fn flush_submits_queue_and_keeps_it_on_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let one = Song {
+        artist: Some("Artist".to_string()),
+        ..fixtures::song(1, "Song 1")
+    };
+    log_scrobble(&one, &play(&one, 1000, 200), &paths).unwrap();
+    log_scrobble(&one, &play(&one, 2000, 200), &paths).unwrap();
+
+    let (address, server) = stub_server(vec!["503 Service Unavailable", "200 OK"]);
+    let mut scrobbler = StubSubmitter { address };
+
+    assert!(matches!(
+        flush_queue(&mut scrobbler, &paths),
+        Err(ScrobbleError::Submit(_))
+    ));
+    assert_eq!(load_queue(&paths).unwrap().len(), 2);
+
+    assert_eq!(flush_queue(&mut scrobbler, &paths).unwrap(), 2);
+    assert!(load_queue(&paths).unwrap().is_empty());
+
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies[1].listen_type, "import");
+    assert_eq!(bodies[1].payload.len(), 2);
+}
@@ -0,0 +1,35 @@
+use std::fs;
+use std::time::Duration;
+
+use player_core::{load_session, save_session, Paths, Session, SessionItem, SortOrder};
+
+#[test]
+fn missing_session_is_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+
+    assert_eq!(load_session(&paths).unwrap(), Session::default());
+}
+
+#[test]
+fn session_round_trips_with_exact_position() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let session = Session {
+        current: Some(SessionItem::Song(3)),
+        // Ten hours into an audiobook
+        position: Duration::new(36_000, 123_456_789),
+        queue: vec![7, 8],
+        sort_order: SortOrder::RecentlyAdded,
+        selected: Some(8),
+    };
+
+    save_session(&session, &paths).unwrap();
+    assert_eq!(load_session(&paths).unwrap(), session);
+
+    // Sessions from older versions lack newer fields
+    fs::write(paths.session(), r#"{"current": {"kind": "song", "id": 7}}"#).unwrap();
+    let session = load_session(&paths).unwrap();
+    assert_eq!(session.current, Some(SessionItem::Song(7)));
+    assert_eq!(session.sort_order, SortOrder::Artist);
+}
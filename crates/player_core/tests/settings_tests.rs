@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use player_core::{Paths, RepeatMode, Settings};
+
+#[test]
+fn paths_use_standard_layout_under_root() {
+    let paths = Paths::from_root("/data/Player");
+
+    assert_eq!(paths.music, PathBuf::from("/data/Player/Music"));
+    assert_eq!(paths.import, PathBuf::from("/data/Player/Import"));
+    assert_eq!(
+        paths.manifest(),
+        PathBuf::from("/data/Player/library.jsonl")
+    );
+}
+
+#[test]
+fn folder_overrides_replace_default_paths() {
+    let settings = Settings {
+        player_root: Some("/data/Player".into()),
+        music_path: Some("/nas/Music".into()),
+        ..Default::default()
+    };
+
+    // PLAYER_ROOT takes precedence over player_root
+    let root = settings.player_root().unwrap();
+    let paths = settings.paths().unwrap();
+
+    assert_eq!(paths.root, root);
+    assert_eq!(paths.music, PathBuf::from("/nas/Music"));
+    assert_eq!(paths.import, root.join("Import"));
+}
+
+#[test]
+fn settings_fill_in_missing_fields() {
+    let settings: Settings = serde_json::from_str(r#"{"playback": {"repeat": "all"}}"#).unwrap();
+
+    assert_eq!(settings.player_root, None);
+    assert_eq!(settings.playback.repeat, RepeatMode::All);
+    assert_eq!(settings.playback.volume, 1.0);
+    assert!(!settings.remote.enabled);
+    assert!(!settings.mpd.enabled);
+}
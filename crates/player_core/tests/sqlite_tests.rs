@@ -0,0 +1,67 @@
+mod fixtures;
+
+use std::time::Duration;
+
+use fixtures::{song, song_id};
+use player_core::{
+    load_library, migrate_jsonl_to_sqlite, save_library, Fingerprint, Library, Paths, Song,
+    SqliteStorage, Storage, StorageBackend,
+};
+
+fn sqlite_paths(root: &std::path::Path) -> Paths {
+    let mut paths = Paths::from_root(root);
+    paths.backend = StorageBackend::Sqlite;
+    paths
+}
+
+#[test]
+fn sqlite_round_trips_edits_and_removals() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = sqlite_paths(dir.path());
+    let mut library = Library::new();
+    library.add_song(song(2, "Two"));
+    let one = Song {
+        artist: Some("A".to_string()),
+        duration: Duration::from_millis(30_500),
+        content_hash: Some("abc".to_string()),
+        ..song(1, "One")
+    };
+    library.add_song(one.clone());
+    save_library(&library, &paths).unwrap();
+
+    library.add_song(Song {
+        title: "One (edited)".to_string(),
+        fingerprint: Some(Fingerprint(vec![7; 32])),
+        ..one
+    });
+    library.remove_song(song_id(2));
+    save_library(&library, &paths).unwrap();
+
+    assert!(paths.database().exists());
+    assert!(!paths.manifest().exists());
+    let loaded = load_library(&paths).unwrap();
+    assert_eq!(loaded.songs.len(), 1);
+    let loaded = &loaded.songs[&song_id(1)];
+    assert_eq!(loaded.title, "One (edited)");
+    assert_eq!(loaded.artist.as_deref(), Some("A"));
+    assert_eq!(loaded.duration, Duration::from_millis(30_500));
+    assert_eq!(loaded.content_hash.as_deref(), Some("abc"));
+    assert_eq!(loaded.fingerprint, Some(Fingerprint(vec![7; 32])));
+}
+
+#[test]
+fn jsonl_library_migrates_to_sqlite_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut library = Library::new();
+    library.add_song(song(1, "One"));
+    library.add_song(song(2, "Two"));
+    save_library(&library, &Paths::from_root(dir.path())).unwrap();
+
+    let paths = sqlite_paths(dir.path());
+    assert_eq!(migrate_jsonl_to_sqlite(&paths).unwrap(), Some(2));
+    assert_eq!(migrate_jsonl_to_sqlite(&paths).unwrap(), None);
+
+    assert!(paths.manifest().exists());
+    let storage = SqliteStorage::open(&paths).unwrap();
+    assert_eq!(storage.load_library().unwrap().songs.len(), 2);
+}
@@ -0,0 +1,237 @@
+mod fixtures;
+
+use std::fs;
+use std::time::Duration;
+
+use fixtures::{fixture_path, song, song_id};
+use player_core::{
+    append_play, compact_library, load_history, load_library, load_session, save_library,
+    save_session, AudiobookId, Fingerprint, Library, LibraryReader, LoadedEntry, Paths,
+    PlayOutcome, PlayRecord, Session, SessionItem, Song, StorageError, MANIFEST_SCHEMA_VERSION,
+};
+
+fn manifest_lines(paths: &Paths) -> Vec<String> {
+    fs::read_to_string(paths.manifest())
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn later_saves_append_only_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    library.add_song(song(1, "One"));
+    library.add_song(song(2, "Two"));
+    save_library(&library, &paths).unwrap();
+    let snapshot_len = manifest_lines(&paths).len();
+
+    library.add_song(song(1, "One (edited)"));
+    library.remove_song(song_id(2));
+    library.add_song(song(3, "Three"));
+    save_library(&library, &paths).unwrap();
+
+    let lines = manifest_lines(&paths);
+    assert_eq!(lines.len(), snapshot_len + 3);
+    assert!(lines[snapshot_len..]
+        .iter()
+        .any(|line| line.contains("\"remove_song\"")));
+
+    let loaded = load_library(&paths).unwrap();
+    assert_eq!(loaded.songs.len(), 2);
+    assert_eq!(loaded.songs[&song_id(1)].title, "One (edited)");
+    assert!(!loaded.songs.contains_key(&song_id(2)));
+}
+
+#[test]
+fn compaction_folds_log_into_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    library.add_song(song(1, "One"));
+    save_library(&library, &paths).unwrap();
+    library.add_song(song(2, "Two"));
+    save_library(&library, &paths).unwrap();
+
+    compact_library(&library, &paths).unwrap();
+
+    let lines = manifest_lines(&paths);
+    assert_eq!(lines.len(), 3);
+    assert!(!lines.iter().any(|line| line.contains("\"put_song\"")));
+    assert_eq!(load_library(&paths).unwrap().songs.len(), 2);
+}
+
+#[test]
+fn fingerprints_are_kept_out_of_the_manifest() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let fingerprint = Fingerprint(vec![0xdeadbeef; 100]);
+    let mut library = Library::new();
+    library.add_song(Song {
+        fingerprint: Some(fingerprint.clone()),
+        ..song(1, "One")
+    });
+    save_library(&library, &paths).unwrap();
+
+    // Editing the song appends it to the manifest without its fingerprint
+    library.songs.get_mut(&song_id(1)).unwrap().rating = 4;
+    save_library(&library, &paths).unwrap();
+
+    assert!(!manifest_lines(&paths)
+        .iter()
+        .any(|line| line.contains("deadbeef")));
+    let side_file = fs::read_to_string(paths.song_fingerprints()).unwrap();
+    assert_eq!(side_file.lines().count(), 1);
+    let loaded = load_library(&paths).unwrap();
+    assert_eq!(loaded.songs[&song_id(1)].fingerprint, Some(fingerprint));
+}
+
+fn by_title<'a>(library: &'a Library, title: &str) -> &'a Song {
+    library
+        .songs
+        .values()
+        .find(|song| song.title == title)
+        .unwrap()
+}
+
+/// Paths whose manifest is a copy of a golden file from `fixtures/manifests`
+fn golden_manifest(root: &std::path::Path, name: &str) -> Paths {
+    let paths = Paths::from_root(root);
+    paths.ensure_directories().unwrap();
+    fs::copy(
+        fixture_path(&format!("manifests/{}", name)),
+        paths.manifest(),
+    )
+    .unwrap();
+    paths
+}
+
+#[test]
+fn v1_manifest_loads_and_is_upgraded_on_save() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = golden_manifest(dir.path(), "library_v1.jsonl");
+
+    let library = load_library(&paths).unwrap();
+    assert_eq!(library.songs.len(), 2);
+    let one = by_title(&library, "One");
+    assert_eq!(one.duration, Duration::from_secs_f64(181.5));
+    assert!(one.id.is_derived());
+    assert_eq!(library.audiobooks[&AudiobookId(1)].chapters.len(), 1);
+
+    save_library(&library, &paths).unwrap();
+    let reader = LibraryReader::open(&paths).unwrap().unwrap();
+    assert_eq!(reader.schema_version(), MANIFEST_SCHEMA_VERSION);
+    assert_eq!(reader.count(), 4);
+
+    // Migrated ids are written back, so they stay the same from now on
+    let reloaded = load_library(&paths).unwrap();
+    assert_eq!(by_title(&reloaded, "One").id, one.id);
+}
+
+#[test]
+fn rekeyed_songs_keep_their_history_and_session() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = golden_manifest(dir.path(), "library_v1.jsonl");
+    let play = PlayRecord {
+        song_id: 1,
+        started_at: 1_700_000_000,
+        listened: Duration::from_secs(181),
+        outcome: PlayOutcome::Completed,
+    };
+    append_play(&play, &paths).unwrap();
+    let session = Session {
+        current: Some(SessionItem::Song(1)),
+        queue: vec![2],
+        selected: Some(2),
+        ..Default::default()
+    };
+    save_session(&session, &paths).unwrap();
+
+    let mut library = load_library(&paths).unwrap();
+    let one = by_title(&library, "One").id;
+    let two = by_title(&library, "Two").id;
+
+    let history = load_history(&paths).unwrap();
+    assert_eq!(history[0].song_id, one.0);
+    library.apply_history(&history);
+    assert_eq!(library.songs[&one].stats.play_count, 1);
+
+    let session = load_session(&paths).unwrap();
+    assert_eq!(session.current, Some(SessionItem::Song(one.0)));
+    assert_eq!(session.queue, [two.0]);
+    assert_eq!(session.selected, Some(two.0));
+
+    // The new ids were saved with the files that refer to them
+    let reloaded = load_library(&paths).unwrap();
+    assert_eq!(by_title(&reloaded, "One").id, one);
+    assert_eq!(load_history(&paths).unwrap()[0].song_id, one.0);
+}
+
+#[test]
+fn v2_manifest_replays_its_log() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = golden_manifest(dir.path(), "library_v2.jsonl");
+
+    let library = load_library(&paths).unwrap();
+
+    assert_eq!(library.songs.len(), 1);
+    let one = by_title(&library, "One (Remastered)");
+    assert_eq!(
+        one.id,
+        Library::new().assign_song_id(Some("3f2a"), &one.file.path)
+    );
+    assert_eq!(library.audiobooks.len(), 1);
+
+    // Fingerprints move from the manifest to their own file on save
+    assert_eq!(one.fingerprint, Some(Fingerprint(vec![1, 2])));
+    save_library(&library, &paths).unwrap();
+    assert!(!fs::read_to_string(paths.manifest())
+        .unwrap()
+        .contains("fingerprint"));
+    let reloaded = load_library(&paths).unwrap();
+    assert_eq!(
+        by_title(&reloaded, "One (Remastered)").fingerprint,
+        Some(Fingerprint(vec![1, 2]))
+    );
+}
+
+#[test]
+fn song_with_zero_duration_is_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = Paths::from_root(dir.path());
+    let mut library = Library::new();
+    library.add_song(song(1, "One"));
+    save_library(&library, &paths).unwrap();
+
+    let mut manifest = fs::read_to_string(paths.manifest()).unwrap();
+    manifest.push_str(&manifest_lines(&paths)[1].replace("\"duration\":180.0", "\"duration\":0.0"));
+    manifest.push('\n');
+    fs::write(paths.manifest(), manifest).unwrap();
+
+    let skipped = LibraryReader::open(&paths)
+        .unwrap()
+        .unwrap()
+        .filter(|entry| matches!(entry, LoadedEntry::Skipped { .. }))
+        .count();
+    assert_eq!(skipped, 1);
+    assert_eq!(load_library(&paths).unwrap().songs.len(), 1);
+}
+
+#[test]
+fn newer_manifest_is_not_read_or_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = golden_manifest(dir.path(), "library_v99.jsonl");
+    let before = fs::read_to_string(paths.manifest()).unwrap();
+
+    assert!(matches!(
+        load_library(&paths),
+        Err(StorageError::NewerSchema { found: 99, .. })
+    ));
+    assert!(matches!(
+        save_library(&Library::new(), &paths),
+        Err(StorageError::NewerSchema { found: 99, .. })
+    ));
+    assert_eq!(fs::read_to_string(paths.manifest()).unwrap(), before);
+}
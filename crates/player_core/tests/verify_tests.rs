@@ -0,0 +1,81 @@
+mod fixtures;
+
+use std::fs;
+
+use fixtures::mp3_fixture;
+use player_core::import::{import_all_pending, ImportOptions};
+use player_core::verify::{apply_fix, verify_library, IntegrityFix, IntegrityIssue, VerifyOptions};
+use player_core::{Library, Paths};
+
+fn imported_library(root: &std::path::Path) -> (Library, Paths) {
+    let paths = Paths::from_root(root);
+    paths.ensure_directories().unwrap();
+    fs::copy(mp3_fixture(), paths.import.join("song.mp3")).unwrap();
+
+    let mut library = Library::new();
+    let options = ImportOptions {
+        fingerprint: false,
+        ..Default::default()
+    };
+    import_all_pending(&mut library, &options, &paths);
+    (library, paths)
+}
+
+#[test]
+fn clean_library_has_no_issues() {
+    let dir = tempfile::tempdir().unwrap();
+    let (library, paths) = imported_library(dir.path());
+
+    let report = verify_library(&library, &paths, VerifyOptions::default());
+
+    assert_eq!(report.songs_checked, 1);
+    assert!(report.is_clean(), "{:?}", report.issues);
+}
+
+#[test]
+fn moved_file_is_relinked_by_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut library, paths) = imported_library(dir.path());
+    let song = library.songs.values().next().unwrap().clone();
+    let moved = paths.music.join("moved.mp3");
+    fs::rename(&song.file.path, &moved).unwrap();
+
+    let report = verify_library(&library, &paths, VerifyOptions::default());
+
+    assert_eq!(
+        report.issues,
+        vec![IntegrityIssue::MissingFile {
+            song_id: song.id,
+            path: song.file.path.clone(),
+            relink_to: Some(moved.clone()),
+        }]
+    );
+
+    let fix = &report.issues[0].fixes()[0];
+    assert!(matches!(fix, IntegrityFix::Relink { .. }));
+    apply_fix(&mut library, fix).unwrap();
+
+    assert_eq!(library.songs[&song.id].file.path, moved);
+    assert!(verify_library(&library, &paths, VerifyOptions::default()).is_clean());
+}
+
+#[test]
+fn untracked_file_is_reported_and_imported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut library, paths) = imported_library(dir.path());
+    let orphan = paths.music.join("orphan.mp3");
+    fs::copy(mp3_fixture(), &orphan).unwrap();
+
+    let report = verify_library(&library, &paths, VerifyOptions::default());
+    assert_eq!(
+        report.issues,
+        vec![IntegrityIssue::Orphan {
+            path: orphan.clone()
+        }]
+    );
+
+    apply_fix(&mut library, &report.issues[0].fixes()[0]).unwrap();
+
+    assert_eq!(library.songs.len(), 2);
+    assert!(verify_library(&library, &paths, VerifyOptions::default()).is_clean());
+}
@@ -0,0 +1,36 @@
+use std::fs;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use player_core::watcher::{file_sizes, ImportWatcher};
+
+#[test]
+fn watcher_fires_once_new_files_settle() {
+    let dir = tempfile::tempdir().unwrap();
+    let (sender, receiver) = mpsc::channel();
+
+    let _watcher = ImportWatcher::new(dir.path(), Duration::from_millis(200), move || {
+        let _ = sender.send(());
+    })
+    .unwrap();
+
+    fs::create_dir(dir.path().join("Album")).unwrap();
+    fs::write(dir.path().join("Album").join("song.mp3"), b"audio").unwrap();
+
+    receiver
+        .recv_timeout(Duration::from_secs(10))
+        .expect("watcher should report settled files");
+}
+
+#[test]
+fn file_sizes_lists_nested_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("Artist")).unwrap();
+    fs::write(dir.path().join("Artist").join("a.mp3"), b"12345").unwrap();
+    fs::write(dir.path().join("b.mp3"), b"1").unwrap();
+
+    let sizes = file_sizes(dir.path());
+
+    assert_eq!(sizes.len(), 2);
+    assert_eq!(sizes[&dir.path().join("Artist").join("a.mp3")], 5);
+}
@@ -0,0 +1,457 @@
+//! A terminal front-end for playing the library, e.g. over SSH. Uses the same
+//! library, settings and listening history as the `player` app, with the list
+//! view's key bindings.
+
+use std::io;
+use std::process::ExitCode;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use player_core::{
+    append_play, load_history, load_library, log_scrobble, rate_songs, save_library, AudioEngine,
+    AudioPlayerEvent, Library, MediaItem, Paths, PlayRecord, PlayTracker, PlaybackState, Queue,
+    RatingEdit, RepeatMode, Settings, Song, SongId, SortOrder, MAX_RATING,
+};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+
+/// How often to check for the end of a song while waiting for keys
+const TICK: Duration = Duration::from_millis(250);
+
+/// Rows moved by page up and page down
+const PAGE_SIZE: usize = 10;
+
+const HELP: &str = "j/k move  enter play  space pause  n/p next/prev  s shuffle  r repeat  o sort  0-5 rate  l love  q quit";
+
+fn main() -> ExitCode {
+    let settings = match Settings::load() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load settings: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let paths = match settings.paths() {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut library = match load_library(&paths) {
+        Ok(library) => library,
+        Err(e) => {
+            eprintln!("Failed to load library: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match load_history(&paths) {
+        Ok(history) => library.apply_history(&history),
+        Err(e) => eprintln!("Failed to load listening history: {}", e),
+    }
+
+    let mut engine = match AudioEngine::new() {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Failed to open audio output: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    engine.set_volume(settings.playback.volume);
+
+    let mut app = App::new(settings, paths, library, engine);
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+    app.stop();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct App {
+    settings: Settings,
+    paths: Paths,
+    library: Library,
+    /// The library as listed, in `sort_order`
+    songs: Vec<Song>,
+    sort_order: SortOrder,
+    table: TableState,
+    engine: AudioEngine,
+    engine_events: mpsc::Receiver<AudioPlayerEvent>,
+    /// The songs listed when playback last started from the list
+    queue: Queue,
+    /// Turns engine events into plays for the listening history
+    play_tracker: PlayTracker,
+    status_message: Option<String>,
+    should_quit: bool,
+}
+
+impl App {
+    fn new(settings: Settings, paths: Paths, library: Library, mut engine: AudioEngine) -> Self {
+        let sort_order = SortOrder::default();
+        let songs = library.list(sort_order);
+        let mut table = TableState::default();
+        table.select((!songs.is_empty()).then_some(0));
+
+        Self {
+            settings,
+            paths,
+            library,
+            songs,
+            sort_order,
+            table,
+            engine_events: engine.subscribe(),
+            engine,
+            queue: Queue::default(),
+            play_tracker: PlayTracker::new(),
+            status_message: None,
+            should_quit: false,
+        }
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.should_quit {
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(TICK)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+            self.engine.check_and_handle_finished();
+            self.handle_engine_events();
+        }
+        Ok(())
+    }
+
+    /// Stop playback, recording the song that was playing
+    fn stop(&mut self) {
+        self.engine.stop();
+        self.handle_engine_events();
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        self.status_message = None;
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.should_quit = true,
+            KeyCode::Char('j') | KeyCode::Down => self.select_by(1),
+            KeyCode::Char('k') | KeyCode::Up => self.select_by(-1),
+            KeyCode::PageDown => self.select_by(PAGE_SIZE as isize),
+            KeyCode::PageUp => self.select_by(-(PAGE_SIZE as isize)),
+            KeyCode::Char('g') | KeyCode::Home => self.select_index(0),
+            KeyCode::Char('G') | KeyCode::End => {
+                self.select_index(self.songs.len().saturating_sub(1))
+            }
+            KeyCode::Enter => self.play_selected(),
+            KeyCode::Char(' ') => self.engine.toggle_playback(),
+            KeyCode::Char('n') | KeyCode::Right => self.skip_next(),
+            KeyCode::Char('p') | KeyCode::Left => self.skip_previous(),
+            KeyCode::Char('s') => self.toggle_shuffle(),
+            KeyCode::Char('r') => self.toggle_repeat(),
+            KeyCode::Char('o') => self.cycle_sort_order(),
+            KeyCode::Char('l') => {
+                if let Some(song) = self.selected_song() {
+                    let loved = !song.loved;
+                    self.rate_selected(RatingEdit {
+                        rating: None,
+                        loved: Some(loved),
+                    });
+                }
+            }
+            KeyCode::Char(c @ '0'..='5') => {
+                let stars = c.to_digit(10).unwrap_or(0) as u8;
+                self.rate_selected(RatingEdit {
+                    rating: Some(stars.min(MAX_RATING)),
+                    loved: None,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // ========================================================================
+    // Selection
+    // ========================================================================
+
+    fn selected_song(&self) -> Option<&Song> {
+        self.songs.get(self.table.selected()?)
+    }
+
+    fn select_by(&mut self, delta: isize) {
+        let current = self.table.selected().unwrap_or(0);
+        self.select_index(current.saturating_add_signed(delta));
+    }
+
+    fn select_index(&mut self, index: usize) {
+        if self.songs.is_empty() {
+            self.table.select(None);
+        } else {
+            self.table.select(Some(index.min(self.songs.len() - 1)));
+        }
+    }
+
+    /// List the library again, keeping the selected song selected
+    fn relist(&mut self) {
+        let selected_id = self.selected_song().map(|song| song.id);
+        self.songs = self.library.list(self.sort_order);
+        let index = selected_id
+            .and_then(|id| self.songs.iter().position(|song| song.id == id))
+            .unwrap_or(0);
+        self.select_index(index);
+    }
+
+    fn cycle_sort_order(&mut self) {
+        let ix = SortOrder::ALL
+            .iter()
+            .position(|order| *order == self.sort_order)
+            .unwrap_or(0);
+        self.sort_order = SortOrder::ALL[(ix + 1) % SortOrder::ALL.len()];
+        self.relist();
+    }
+
+    // ========================================================================
+    // Playback
+    // ========================================================================
+
+    fn play_selected(&mut self) {
+        let Some(index) = self.table.selected() else {
+            return;
+        };
+        self.queue = Queue::new(self.songs.iter().cloned().map(MediaItem::Song).collect());
+        let item = self.queue.select(index).cloned();
+        self.play_item(item);
+    }
+
+    fn play_item(&mut self, item: Option<MediaItem>) {
+        let Some(MediaItem::Song(song)) = item else {
+            return;
+        };
+        if let Err(e) = self.engine.play_song(song) {
+            self.status_message = Some(format!("Failed to play song: {}", e));
+        }
+    }
+
+    fn skip_next(&mut self) {
+        let next = self
+            .queue
+            .advance(
+                self.settings.playback.shuffle,
+                self.settings.playback.repeat,
+                false,
+            )
+            .cloned();
+        self.play_item(next);
+    }
+
+    fn skip_previous(&mut self) {
+        if self.engine.position() > Duration::from_secs(3) {
+            let current = self.engine.current_song().cloned().map(MediaItem::Song);
+            self.play_item(current);
+        } else {
+            let previous = self.queue.go_back(self.settings.playback.shuffle).cloned();
+            self.play_item(previous);
+        }
+    }
+
+    fn toggle_shuffle(&mut self) {
+        self.settings.playback.shuffle = !self.settings.playback.shuffle;
+        self.save_settings();
+    }
+
+    fn toggle_repeat(&mut self) {
+        self.settings.playback.repeat = match self.settings.playback.repeat {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        };
+        self.save_settings();
+    }
+
+    fn save_settings(&mut self) {
+        if let Err(e) = self.settings.save() {
+            self.status_message = Some(format!("Failed to save settings: {}", e));
+        }
+    }
+
+    fn handle_engine_events(&mut self) {
+        while let Ok(event) = self.engine_events.try_recv() {
+            match event {
+                AudioPlayerEvent::StateChanged(state) => {
+                    self.play_tracker.state_changed(state, Instant::now());
+                }
+                AudioPlayerEvent::SongChanged(song) => {
+                    if let Some(record) = self
+                        .play_tracker
+                        .song_changed(song.as_deref(), Instant::now())
+                    {
+                        self.record_play(record);
+                    }
+                }
+                AudioPlayerEvent::PlaybackFinished => {
+                    if let Some(record) = self.play_tracker.finished(Instant::now()) {
+                        self.record_play(record);
+                    }
+                    let next = self
+                        .queue
+                        .advance(
+                            self.settings.playback.shuffle,
+                            self.settings.playback.repeat,
+                            true,
+                        )
+                        .cloned();
+                    self.play_item(next);
+                }
+            }
+        }
+    }
+
+    /// Count a finished play towards the song's stats and add it to the listening history
+    fn record_play(&mut self, record: PlayRecord) {
+        self.library.record_play(&record);
+        if let Err(e) = append_play(&record, &self.paths) {
+            self.status_message = Some(format!("Failed to record play: {}", e));
+        }
+        if self.settings.scrobbling {
+            if let Some(song) = self.library.songs.get(&SongId(record.song_id)) {
+                if let Err(e) = log_scrobble(song, &record, &self.paths) {
+                    self.status_message = Some(format!("Failed to log scrobble: {}", e));
+                }
+            }
+        }
+        self.relist();
+    }
+
+    fn rate_selected(&mut self, edit: RatingEdit) {
+        let Some(id) = self.selected_song().map(|song| song.id) else {
+            return;
+        };
+        let write_to_tags = self.settings.write_ratings_to_tags;
+        let result = rate_songs(&mut self.library, &[id], edit, write_to_tags);
+        if let Some(failure) = result.failed.first() {
+            self.status_message = Some(format!("Failed to rate song: {}", failure.error));
+        } else if let Err(e) = save_library(&self.library, &self.paths) {
+            self.status_message = Some(format!("Failed to save library: {}", e));
+        }
+        self.relist();
+    }
+
+    // ========================================================================
+    // Drawing
+    // ========================================================================
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, now_playing_area, status_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let playing_id = self.engine.current_song().map(|song| song.id);
+        let rows = self.songs.iter().map(|song| {
+            let row = Row::new([
+                song.title.clone(),
+                song.artist.clone().unwrap_or_default(),
+                song.album.clone().unwrap_or_default(),
+                format_rating(song),
+                format_duration(song.duration),
+            ]);
+            if Some(song.id) == playing_id {
+                row.add_modifier(Modifier::BOLD).cyan()
+            } else {
+                row
+            }
+        });
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(35),
+                Constraint::Percentage(25),
+                Constraint::Percentage(25),
+                Constraint::Length(7),
+                Constraint::Length(6),
+            ],
+        )
+        .header(
+            Row::new(["Title", "Artist", "Album", "Rating", "Time"]).add_modifier(Modifier::BOLD),
+        )
+        .row_highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(table, list_area, &mut self.table);
+
+        frame.render_widget(self.now_playing_line(), now_playing_area);
+
+        let status = match &self.status_message {
+            Some(message) => Line::from(message.as_str()).red(),
+            None => Line::from(HELP).dim(),
+        };
+        frame.render_widget(status, status_area);
+    }
+
+    fn now_playing_line(&self) -> Line<'_> {
+        let playback = &self.settings.playback;
+        let mut spans = match self.engine.current_song() {
+            Some(song) => {
+                let icon = match self.engine.state() {
+                    PlaybackState::Playing => "▶",
+                    PlaybackState::Paused | PlaybackState::Stopped => "⏸",
+                };
+                let mut spans = vec![
+                    Span::raw(format!("{} ", icon)),
+                    Span::raw(song.title.clone()).bold(),
+                ];
+                if let Some(artist) = &song.artist {
+                    spans.push(Span::raw(format!(" — {}", artist)));
+                }
+                spans.push(Span::raw(format!(
+                    "  {} / {}",
+                    format_duration(self.engine.position()),
+                    format_duration(song.duration)
+                )));
+                spans
+            }
+            None => vec![Span::raw("Not playing").dim()],
+        };
+
+        let repeat = match playback.repeat {
+            RepeatMode::Off => "Off",
+            RepeatMode::All => "All",
+            RepeatMode::One => "One",
+        };
+        spans.push(
+            Span::raw(format!(
+                "   Shuffle: {}  Repeat: {}  Sort: {}",
+                if playback.shuffle { "On" } else { "Off" },
+                repeat,
+                self.sort_order.label()
+            ))
+            .dim(),
+        );
+        Line::from(spans)
+    }
+}
+
+fn format_rating(song: &Song) -> String {
+    let stars = "★".repeat(song.rating as usize);
+    if song.loved {
+        format!("♥ {}", stars)
+    } else {
+        stars
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
@@ -1,3 +1,7 @@
 mod ui;
 
-pub use ui::{init, ListView, ListViewEvent};
+pub use ui::{
+    init, BackupsView, BackupsViewEvent, IntegrityView, IntegrityViewEvent, ListView,
+    ListViewEvent, ProblemAction, ProblemsView, ProblemsViewEvent, TagEditTarget, TagEditor,
+    TagEditorEvent,
+};
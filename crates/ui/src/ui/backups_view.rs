@@ -0,0 +1,160 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gpui::{
+    actions, div, prelude::*, px, rems, App, Context, EventEmitter, FocusHandle, Focusable,
+    IntoElement, KeyBinding, Render, SharedString, Window,
+};
+use gpuikit::layout::{h_stack, v_stack};
+use gpuikit::theme::{ActiveTheme, Themeable};
+use player_core::Backup;
+
+actions!(backups_view, [Close, Export]);
+
+pub fn init(cx: &mut App) {
+    cx.bind_keys([
+        KeyBinding::new("escape", Close, Some("BackupsView")),
+        KeyBinding::new("cmd-e", Export, Some("BackupsView")),
+    ]);
+}
+
+/// Lists the automatic backups of the library, newest first, each of which can
+/// be restored, and offers an export of the whole library
+pub struct BackupsView {
+    backups: Vec<Backup>,
+    focus_handle: FocusHandle,
+}
+
+pub enum BackupsViewEvent {
+    Restore(Backup),
+    Export,
+    Close,
+}
+
+impl EventEmitter<BackupsViewEvent> for BackupsView {}
+
+impl Focusable for BackupsView {
+    fn focus_handle(&self, _cx: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl BackupsView {
+    pub fn new(backups: Vec<Backup>, cx: &mut Context<Self>) -> Self {
+        Self {
+            backups,
+            focus_handle: cx.focus_handle(),
+        }
+    }
+
+    pub fn focus(&self, window: &mut Window, cx: &mut Context<Self>) {
+        self.focus_handle.focus(window);
+        cx.notify();
+    }
+
+    pub fn set_backups(&mut self, backups: Vec<Backup>, cx: &mut Context<Self>) {
+        self.backups = backups;
+        cx.notify();
+    }
+
+    fn export(&mut self, _: &Export, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(BackupsViewEvent::Export);
+    }
+
+    fn close(&mut self, _: &Close, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(BackupsViewEvent::Close);
+    }
+}
+
+/// How long ago a backup was made, e.g. "5 min ago"
+fn describe_age(created_at: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let seconds = now.saturating_sub(created_at) / 1000;
+
+    match seconds {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", seconds / 60),
+        3600..86400 => format!("{} h ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+impl Render for BackupsView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let theme = cx.theme();
+
+        let heading: SharedString = match self.backups.len() {
+            0 => "Backups: none yet".into(),
+            n => format!("Backups ({})", n).into(),
+        };
+
+        v_stack()
+            .key_context("BackupsView")
+            .id("backups-view")
+            .track_focus(&self.focus_handle)
+            .on_action(cx.listener(Self::close))
+            .on_action(cx.listener(Self::export))
+            .w_full()
+            .gap(rems(0.25))
+            .px(rems(0.75))
+            .py(rems(0.5))
+            .bg(theme.surface())
+            .border_t_1()
+            .border_color(theme.border())
+            .child(div().text_sm().text_color(theme.fg()).child(heading))
+            .child(
+                v_stack()
+                    .id("backup-list")
+                    .max_h(px(160.0))
+                    .overflow_y_scroll()
+                    .children(self.backups.iter().enumerate().map(|(ix, backup)| {
+                        let restore = backup.clone();
+
+                        h_stack()
+                            .id(ix)
+                            .h(px(20.0))
+                            .items_center()
+                            .gap(rems(0.5))
+                            .child(
+                                div()
+                                    .w(rems(7.0))
+                                    .text_xs()
+                                    .text_color(theme.fg())
+                                    .child(describe_age(backup.created_at)),
+                            )
+                            .child(
+                                div()
+                                    .flex_1()
+                                    .text_xs()
+                                    .text_color(theme.fg_disabled())
+                                    .overflow_hidden()
+                                    .whitespace_nowrap()
+                                    .child(backup.files().join(", ")),
+                            )
+                            .child(
+                                div()
+                                    .id("restore")
+                                    .px(rems(0.25))
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .border_1()
+                                    .border_color(theme.border())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .child("Restore")
+                                    .on_click(cx.listener(move |_this, _event, _window, cx| {
+                                        cx.emit(BackupsViewEvent::Restore(restore.clone()));
+                                    })),
+                            )
+                    })),
+            )
+            .child(
+                div()
+                    .text_xs()
+                    .text_color(theme.fg_disabled())
+                    .child("cmd-e to export the library, esc to close"),
+            )
+    }
+}
@@ -0,0 +1,195 @@
+use gpui::{
+    actions, div, prelude::*, px, rems, App, Context, EventEmitter, FocusHandle, Focusable,
+    IntoElement, KeyBinding, Render, SharedString, Window,
+};
+use gpuikit::layout::{h_stack, v_stack};
+use gpuikit::theme::{ActiveTheme, Themeable};
+use player_core::{IntegrityFix, IntegrityIssue, IntegrityReport, Library};
+
+actions!(integrity_view, [Close, FixAll]);
+
+pub fn init(cx: &mut App) {
+    cx.bind_keys([
+        KeyBinding::new("escape", Close, Some("IntegrityView")),
+        KeyBinding::new("cmd-enter", FixAll, Some("IntegrityView")),
+    ]);
+}
+
+struct IssueRow {
+    description: SharedString,
+    fixes: Vec<IntegrityFix>,
+}
+
+/// Lists the problems found by a library check, each with the fixes that apply to it.
+/// Issues are removed from the list as they are fixed.
+pub struct IntegrityView {
+    songs_checked: usize,
+    rows: Vec<IssueRow>,
+    focus_handle: FocusHandle,
+}
+
+pub enum IntegrityViewEvent {
+    ApplyFixes(Vec<IntegrityFix>),
+    Close,
+}
+
+impl EventEmitter<IntegrityViewEvent> for IntegrityView {}
+
+impl Focusable for IntegrityView {
+    fn focus_handle(&self, _cx: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl IntegrityView {
+    pub fn new(report: IntegrityReport, library: &Library, cx: &mut Context<Self>) -> Self {
+        let rows = report
+            .issues
+            .iter()
+            .map(|issue| IssueRow {
+                description: describe(issue, library).into(),
+                fixes: issue.fixes(),
+            })
+            .collect();
+
+        Self {
+            songs_checked: report.songs_checked,
+            rows,
+            focus_handle: cx.focus_handle(),
+        }
+    }
+
+    pub fn focus(&self, window: &mut Window, cx: &mut Context<Self>) {
+        self.focus_handle.focus(window);
+        cx.notify();
+    }
+
+    fn apply(&mut self, ix: usize, fix: IntegrityFix, cx: &mut Context<Self>) {
+        self.rows.remove(ix);
+        cx.emit(IntegrityViewEvent::ApplyFixes(vec![fix]));
+        cx.notify();
+    }
+
+    /// Apply the recommended fix for every issue
+    fn fix_all(&mut self, _: &FixAll, _window: &mut Window, cx: &mut Context<Self>) {
+        let fixes: Vec<IntegrityFix> = self
+            .rows
+            .drain(..)
+            .filter_map(|row| row.fixes.into_iter().next())
+            .collect();
+        if !fixes.is_empty() {
+            cx.emit(IntegrityViewEvent::ApplyFixes(fixes));
+        }
+        cx.notify();
+    }
+
+    fn close(&mut self, _: &Close, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(IntegrityViewEvent::Close);
+    }
+}
+
+fn describe(issue: &IntegrityIssue, library: &Library) -> String {
+    let title = |id| {
+        library
+            .songs
+            .get(id)
+            .map(|song| song.title.clone())
+            .unwrap_or_else(|| "Unknown song".to_string())
+    };
+
+    match issue {
+        IntegrityIssue::MissingFile {
+            song_id,
+            path,
+            relink_to,
+        } => match relink_to {
+            Some(new_path) => format!("{}: file moved to {}", title(song_id), new_path.display()),
+            None => format!("{}: file missing ({})", title(song_id), path.display()),
+        },
+        IntegrityIssue::Orphan { path } => format!("Not in library: {}", path.display()),
+        IntegrityIssue::DurationMismatch {
+            song_id,
+            stored,
+            decoded,
+        } => format!(
+            "{}: duration is {}s, file is {}s",
+            title(song_id),
+            stored.as_secs(),
+            decoded.as_secs()
+        ),
+        IntegrityIssue::StaleTags { song_id, fields } => {
+            format!("{}: {} changed on disk", title(song_id), fields.join(", "))
+        }
+    }
+}
+
+impl Render for IntegrityView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let theme = cx.theme();
+
+        let heading: SharedString = match self.rows.len() {
+            0 => format!("Library Check: {} songs, no issues", self.songs_checked).into(),
+            1 => format!("Library Check: {} songs, 1 issue", self.songs_checked).into(),
+            n => format!("Library Check: {} songs, {} issues", self.songs_checked, n).into(),
+        };
+
+        v_stack()
+            .key_context("IntegrityView")
+            .id("integrity-view")
+            .track_focus(&self.focus_handle)
+            .on_action(cx.listener(Self::close))
+            .on_action(cx.listener(Self::fix_all))
+            .w_full()
+            .gap(rems(0.25))
+            .px(rems(0.75))
+            .py(rems(0.5))
+            .bg(theme.surface())
+            .border_t_1()
+            .border_color(theme.border())
+            .child(div().text_sm().text_color(theme.fg()).child(heading))
+            .child(
+                v_stack()
+                    .id("integrity-issues")
+                    .max_h(px(160.0))
+                    .overflow_y_scroll()
+                    .children(self.rows.iter().enumerate().map(|(ix, row)| {
+                        h_stack()
+                            .id(ix)
+                            .h(px(20.0))
+                            .items_center()
+                            .gap(rems(0.5))
+                            .child(
+                                div()
+                                    .flex_1()
+                                    .text_xs()
+                                    .text_color(theme.fg())
+                                    .overflow_hidden()
+                                    .whitespace_nowrap()
+                                    .child(row.description.clone()),
+                            )
+                            .children(row.fixes.iter().enumerate().map(|(fix_ix, fix)| {
+                                let fix = fix.clone();
+                                div()
+                                    .id(fix_ix)
+                                    .px(rems(0.25))
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .border_1()
+                                    .border_color(theme.border())
+                                    .cursor_pointer()
+                                    .hover(|s| s.text_color(theme.fg()))
+                                    .child(fix.label())
+                                    .on_click(cx.listener(move |this, _event, _window, cx| {
+                                        this.apply(ix, fix.clone(), cx);
+                                    }))
+                            }))
+                    })),
+            )
+            .child(
+                div()
+                    .text_xs()
+                    .text_color(theme.fg_disabled())
+                    .child("cmd-enter to apply recommended fixes, esc to close"),
+            )
+    }
+}
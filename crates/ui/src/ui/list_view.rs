@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::ops::Range;
 
 use gpui::{
@@ -7,7 +8,7 @@ use gpui::{
 };
 use gpuikit::layout::{h_stack, v_stack};
 use gpuikit::theme::{ActiveTheme, Themeable};
-use player_core::{Library, Song, SongId, SortOrder};
+use player_core::{Library, RatingEdit, Song, SongFilter, SongId, SortOrder, MAX_RATING};
 
 actions!(
     list_view,
@@ -20,6 +21,14 @@ actions!(
         PageUp,
         PlaySelected,
         TogglePlayback,
+        EditSelected,
+        ClearRating,
+        RateOne,
+        RateTwo,
+        RateThree,
+        RateFour,
+        RateFive,
+        ToggleLoved,
     ]
 );
 
@@ -37,6 +46,14 @@ pub fn init(cx: &mut App) {
         KeyBinding::new("pageup", PageUp, Some("ListView")),
         KeyBinding::new("enter", PlaySelected, Some("ListView")),
         KeyBinding::new("space", TogglePlayback, Some("ListView")),
+        KeyBinding::new("cmd-i", EditSelected, Some("ListView")),
+        KeyBinding::new("0", ClearRating, Some("ListView")),
+        KeyBinding::new("1", RateOne, Some("ListView")),
+        KeyBinding::new("2", RateTwo, Some("ListView")),
+        KeyBinding::new("3", RateThree, Some("ListView")),
+        KeyBinding::new("4", RateFour, Some("ListView")),
+        KeyBinding::new("5", RateFive, Some("ListView")),
+        KeyBinding::new("l", ToggleLoved, Some("ListView")),
     ]);
 }
 
@@ -44,8 +61,11 @@ pub struct ListView {
     library: Entity<Library>,
     scroll_handle: UniformListScrollHandle,
     sort_order: SortOrder,
+    filter: SongFilter,
     playing_song_id: Option<SongId>,
     selected_index: Option<usize>,
+    /// Songs added to the selection with cmd-click, alongside `selected_index`
+    marked_song_ids: HashSet<SongId>,
     focus_handle: FocusHandle,
 }
 
@@ -54,6 +74,8 @@ pub enum ListViewEvent {
     SongDoubleClicked(Song),
     PlaySelected(Song),
     TogglePlayback,
+    EditSelected(Vec<Song>),
+    RateSelected(Vec<Song>, RatingEdit),
 }
 
 impl EventEmitter<ListViewEvent> for ListView {}
@@ -70,8 +92,10 @@ impl ListView {
             library,
             scroll_handle: UniformListScrollHandle::new(),
             sort_order: SortOrder::default(),
+            filter: SongFilter::default(),
             playing_song_id: None,
             selected_index: None,
+            marked_song_ids: HashSet::new(),
             focus_handle: cx.focus_handle(),
         }
     }
@@ -81,12 +105,42 @@ impl ListView {
         self
     }
 
+    /// Re-sort the list, keeping the selected song selected
+    pub fn set_sort_order(&mut self, sort_order: SortOrder, cx: &mut Context<Self>) {
+        self.relist(|this| this.sort_order = sort_order, cx);
+    }
+
+    /// Show only the songs `filter` lets through, keeping the selected song
+    /// selected if it's still shown
+    pub fn set_filter(&mut self, filter: SongFilter, cx: &mut Context<Self>) {
+        self.relist(|this| this.filter = filter, cx);
+    }
+
+    fn relist(&mut self, change: impl FnOnce(&mut Self), cx: &mut Context<Self>) {
+        let selected_id = self.selected_song(cx).map(|song| song.id);
+        change(self);
+        self.marked_song_ids.clear();
+
+        let library = self.library.read(cx);
+        self.selected_index = None;
+        if let Some(selected_id) = selected_id {
+            let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
+            self.selected_index = songs.iter().position(|s| s.id == selected_id);
+            if let Some(index) = self.selected_index {
+                self.scroll_handle
+                    .scroll_to_item(index, ScrollStrategy::Center);
+            }
+        }
+
+        cx.notify();
+    }
+
     pub fn set_playing_song(&mut self, song_id: Option<SongId>, cx: &mut Context<Self>) {
         self.playing_song_id = song_id;
 
         if let Some(song_id) = song_id {
             let library = self.library.read(cx);
-            let songs: Vec<Song> = library.list(self.sort_order);
+            let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
             if let Some(index) = songs.iter().position(|s| s.id == song_id) {
                 self.selected_index = Some(index);
                 self.scroll_handle
@@ -100,7 +154,7 @@ impl ListView {
     pub fn next_song(&self, cx: &App) -> Option<Song> {
         let playing_id = self.playing_song_id?;
         let library = self.library.read(cx);
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
 
         let current_index = songs.iter().position(|s| s.id == playing_id)?;
         songs.get(current_index + 1).cloned()
@@ -108,13 +162,13 @@ impl ListView {
 
     pub fn first_song(&self, cx: &App) -> Option<Song> {
         let library = self.library.read(cx);
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
         songs.first().cloned()
     }
 
     pub fn random_song(&self, cx: &App) -> Option<Song> {
         let library = self.library.read(cx);
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
 
         if songs.is_empty() {
             return None;
@@ -141,7 +195,7 @@ impl ListView {
     pub fn previous_song(&self, cx: &App) -> Option<Song> {
         let playing_id = self.playing_song_id?;
         let library = self.library.read(cx);
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
 
         let current_index = songs.iter().position(|s| s.id == playing_id)?;
         if current_index > 0 {
@@ -151,26 +205,72 @@ impl ListView {
         }
     }
 
+    /// Up to `limit` songs from the playing song on, or from the top if
+    /// nothing in the list is playing
+    pub fn upcoming_songs(&self, limit: usize, cx: &App) -> Vec<Song> {
+        let library = self.library.read(cx);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
+        let start = self
+            .playing_song_id
+            .and_then(|playing_id| songs.iter().position(|s| s.id == playing_id))
+            .unwrap_or(0);
+        songs.into_iter().skip(start).take(limit).collect()
+    }
+
     pub fn focus(&self, window: &mut Window, cx: &mut Context<Self>) {
         self.focus_handle.focus(window);
         cx.notify();
     }
 
     fn song_count(&self, cx: &App) -> usize {
-        self.library.read(cx).list(self.sort_order).len()
+        self.library
+            .read(cx)
+            .list_filtered(self.sort_order, self.filter)
+            .len()
     }
 
     fn get_song_at_index(&self, index: usize, cx: &App) -> Option<Song> {
         let library = self.library.read(cx);
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
         songs.get(index).cloned()
     }
 
+    pub fn selected_song_id(&self, cx: &App) -> Option<SongId> {
+        self.selected_song(cx).map(|song| song.id)
+    }
+
+    /// Select `song_id` if it's in the list, without playing it
+    pub fn select_song(&mut self, song_id: SongId, cx: &mut Context<Self>) {
+        let library = self.library.read(cx);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
+        if let Some(index) = songs.iter().position(|s| s.id == song_id) {
+            self.selected_index = Some(index);
+            self.marked_song_ids.clear();
+            self.scroll_handle
+                .scroll_to_item(index, ScrollStrategy::Center);
+            cx.notify();
+        }
+    }
+
     fn selected_song(&self, cx: &App) -> Option<Song> {
         let index = self.selected_index?;
         self.get_song_at_index(index, cx)
     }
 
+    /// All selected songs: the cursor row plus any cmd-clicked rows, in list order
+    pub fn selected_songs(&self, cx: &App) -> Vec<Song> {
+        let library = self.library.read(cx);
+        library
+            .list_filtered(self.sort_order, self.filter)
+            .into_iter()
+            .enumerate()
+            .filter(|(ix, song)| {
+                self.selected_index == Some(*ix) || self.marked_song_ids.contains(&song.id)
+            })
+            .map(|(_, song)| song)
+            .collect()
+    }
+
     fn select_next(&mut self, _: &SelectNext, _window: &mut Window, cx: &mut Context<Self>) {
         let count = self.song_count(cx);
         if count == 0 {
@@ -267,8 +367,63 @@ impl ListView {
         cx.emit(ListViewEvent::TogglePlayback);
     }
 
+    fn edit_selected(&mut self, _: &EditSelected, _window: &mut Window, cx: &mut Context<Self>) {
+        let songs = self.selected_songs(cx);
+        if !songs.is_empty() {
+            cx.emit(ListViewEvent::EditSelected(songs));
+        }
+    }
+
+    fn clear_rating(&mut self, _: &ClearRating, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(0, cx);
+    }
+
+    fn rate_one(&mut self, _: &RateOne, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(1, cx);
+    }
+
+    fn rate_two(&mut self, _: &RateTwo, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(2, cx);
+    }
+
+    fn rate_three(&mut self, _: &RateThree, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(3, cx);
+    }
+
+    fn rate_four(&mut self, _: &RateFour, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(4, cx);
+    }
+
+    fn rate_five(&mut self, _: &RateFive, _window: &mut Window, cx: &mut Context<Self>) {
+        self.rate_selected(5, cx);
+    }
+
+    fn rate_selected(&mut self, rating: u8, cx: &mut Context<Self>) {
+        let songs = self.selected_songs(cx);
+        if !songs.is_empty() {
+            let edit = RatingEdit {
+                rating: Some(rating),
+                loved: None,
+            };
+            cx.emit(ListViewEvent::RateSelected(songs, edit));
+        }
+    }
+
+    /// Love every selected song, or unlove them if they're all loved already
+    fn toggle_loved(&mut self, _: &ToggleLoved, _window: &mut Window, cx: &mut Context<Self>) {
+        let songs = self.selected_songs(cx);
+        if !songs.is_empty() {
+            let edit = RatingEdit {
+                rating: None,
+                loved: Some(!songs.iter().all(|song| song.loved)),
+            };
+            cx.emit(ListViewEvent::RateSelected(songs, edit));
+        }
+    }
+
     fn select_index(&mut self, index: usize, cx: &mut Context<Self>) {
         self.selected_index = Some(index);
+        self.marked_song_ids.clear();
         self.scroll_handle
             .scroll_to_item(index, ScrollStrategy::Center);
 
@@ -285,10 +440,11 @@ impl Render for ListView {
         let theme = cx.theme();
         let library = self.library.read(cx);
 
-        let songs: Vec<Song> = library.list(self.sort_order);
+        let songs: Vec<Song> = library.list_filtered(self.sort_order, self.filter);
         let song_count = songs.len();
         let playing_song_id = self.playing_song_id;
         let selected_index = self.selected_index;
+        let marked_song_ids = self.marked_song_ids.clone();
 
         let header_text_color = theme.fg_muted();
 
@@ -304,6 +460,14 @@ impl Render for ListView {
             .on_action(cx.listener(Self::page_up))
             .on_action(cx.listener(Self::play_selected))
             .on_action(cx.listener(Self::toggle_playback))
+            .on_action(cx.listener(Self::edit_selected))
+            .on_action(cx.listener(Self::clear_rating))
+            .on_action(cx.listener(Self::rate_one))
+            .on_action(cx.listener(Self::rate_two))
+            .on_action(cx.listener(Self::rate_three))
+            .on_action(cx.listener(Self::rate_four))
+            .on_action(cx.listener(Self::rate_five))
+            .on_action(cx.listener(Self::toggle_loved))
             .size_full()
             .child(
                 h_stack()
@@ -338,6 +502,14 @@ impl Render for ListView {
                             .overflow_hidden()
                             .child("Time"),
                     )
+                    .child(
+                        div()
+                            .w(rems(4.5))
+                            .text_xs()
+                            .text_color(header_text_color)
+                            .overflow_hidden()
+                            .child("Rating"),
+                    )
                     .child(
                         div()
                             .w(rems(10.0))
@@ -378,7 +550,8 @@ impl Render for ListView {
                                         .into();
 
                                     let is_playing = playing_song_id == Some(song.id);
-                                    let is_selected = selected_index == Some(ix);
+                                    let is_selected = selected_index == Some(ix)
+                                        || marked_song_ids.contains(&song.id);
 
                                     let bg_color = if is_playing {
                                         theme.accent_bg()
@@ -436,6 +609,15 @@ impl Render for ListView {
                                                     .whitespace_nowrap()
                                                     .child(format_duration(song.duration)),
                                             )
+                                            .child(
+                                                div()
+                                                    .w(rems(4.5))
+                                                    .text_xs()
+                                                    .text_color(theme.fg_muted())
+                                                    .overflow_hidden()
+                                                    .whitespace_nowrap()
+                                                    .child(format_rating(song.rating, song.loved)),
+                                            )
                                             .child(
                                                 div()
                                                     .w(rems(10.0))
@@ -456,9 +638,23 @@ impl Render for ListView {
                                             )
                                             .on_click(cx.listener(
                                                 move |this, event: &gpui::ClickEvent, window, cx| {
-                                                    this.selected_index = Some(ix);
                                                     this.focus_handle.focus(window);
 
+                                                    if event.modifiers().platform {
+                                                        if !this
+                                                            .marked_song_ids
+                                                            .remove(&song_for_click.id)
+                                                        {
+                                                            this.marked_song_ids
+                                                                .insert(song_for_click.id);
+                                                        }
+                                                        cx.notify();
+                                                        return;
+                                                    }
+
+                                                    this.selected_index = Some(ix);
+                                                    this.marked_song_ids.clear();
+
                                                     if event.click_count() >= 2 {
                                                         cx.emit(ListViewEvent::SongDoubleClicked(
                                                             song_for_click.clone(),
@@ -491,3 +687,13 @@ fn format_duration(duration: std::time::Duration) -> String {
     let seconds = total_seconds % 60;
     format!("{}:{:02}", minutes, seconds)
 }
+
+/// Filled stars for the rating, e.g. "★★★", with a heart for loved songs
+fn format_rating(rating: u8, loved: bool) -> String {
+    let stars = "★".repeat(rating.min(MAX_RATING) as usize);
+    if loved {
+        format!("♥ {}", stars)
+    } else {
+        stars
+    }
+}
@@ -1,3 +1,19 @@
+mod backups_view;
+mod integrity_view;
 mod list_view;
+mod problems_view;
+mod tag_editor;
 
-pub use list_view::{init, ListView, ListViewEvent};
+pub use backups_view::{BackupsView, BackupsViewEvent};
+pub use integrity_view::{IntegrityView, IntegrityViewEvent};
+pub use list_view::{ListView, ListViewEvent};
+pub use problems_view::{ProblemAction, ProblemsView, ProblemsViewEvent};
+pub use tag_editor::{TagEditTarget, TagEditor, TagEditorEvent};
+
+pub fn init(cx: &mut gpui::App) {
+    backups_view::init(cx);
+    integrity_view::init(cx);
+    list_view::init(cx);
+    problems_view::init(cx);
+    tag_editor::init(cx);
+}
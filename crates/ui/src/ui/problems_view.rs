@@ -0,0 +1,205 @@
+use gpui::{
+    actions, div, prelude::*, px, rems, App, Context, EventEmitter, FocusHandle, Focusable,
+    IntoElement, KeyBinding, Render, SharedString, Window,
+};
+use gpuikit::layout::{h_stack, v_stack};
+use gpuikit::theme::{ActiveTheme, Themeable};
+use player_core::{ProblemFile, ProblemKind};
+
+actions!(problems_view, [Close]);
+
+pub fn init(cx: &mut App) {
+    cx.bind_keys([KeyBinding::new("escape", Close, Some("ProblemsView"))]);
+}
+
+/// Something to do with a file that failed to import
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProblemAction {
+    /// Move the file back to Import to try again
+    Retry,
+    /// Decode the file to find its duration, then retry
+    RepairDuration,
+    /// Write new tags to the file, then retry
+    EditTags,
+    Delete,
+    /// Import the file with manually entered metadata
+    ForceImport,
+}
+
+impl ProblemAction {
+    fn label(&self) -> &'static str {
+        match self {
+            ProblemAction::Retry => "Retry",
+            ProblemAction::RepairDuration => "Repair",
+            ProblemAction::EditTags => "Edit Tags",
+            ProblemAction::Delete => "Delete",
+            ProblemAction::ForceImport => "Force Import",
+        }
+    }
+
+    /// The actions that make sense for a kind of problem
+    fn for_kind(kind: ProblemKind) -> &'static [ProblemAction] {
+        match kind {
+            ProblemKind::NoDuration | ProblemKind::Unknown => &[
+                ProblemAction::RepairDuration,
+                ProblemAction::Retry,
+                ProblemAction::EditTags,
+                ProblemAction::ForceImport,
+                ProblemAction::Delete,
+            ],
+            ProblemKind::UnreadableTags => &[
+                ProblemAction::EditTags,
+                ProblemAction::Retry,
+                ProblemAction::ForceImport,
+                ProblemAction::Delete,
+            ],
+            ProblemKind::UnknownFormat => &[ProblemAction::Retry, ProblemAction::Delete],
+        }
+    }
+}
+
+/// Lists the files in the Problem folder with why each one failed to import.
+/// Files are removed from the list once an action is taken on them.
+pub struct ProblemsView {
+    problems: Vec<ProblemFile>,
+    focus_handle: FocusHandle,
+}
+
+pub enum ProblemsViewEvent {
+    Action {
+        problem: ProblemFile,
+        action: ProblemAction,
+    },
+    Close,
+}
+
+impl EventEmitter<ProblemsViewEvent> for ProblemsView {}
+
+impl Focusable for ProblemsView {
+    fn focus_handle(&self, _cx: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl ProblemsView {
+    pub fn new(problems: Vec<ProblemFile>, cx: &mut Context<Self>) -> Self {
+        Self {
+            problems,
+            focus_handle: cx.focus_handle(),
+        }
+    }
+
+    pub fn focus(&self, window: &mut Window, cx: &mut Context<Self>) {
+        self.focus_handle.focus(window);
+        cx.notify();
+    }
+
+    pub fn set_problems(&mut self, problems: Vec<ProblemFile>, cx: &mut Context<Self>) {
+        self.problems = problems;
+        cx.notify();
+    }
+
+    fn take_action(&mut self, ix: usize, action: ProblemAction, cx: &mut Context<Self>) {
+        let problem = self.problems.remove(ix);
+        cx.emit(ProblemsViewEvent::Action { problem, action });
+        cx.notify();
+    }
+
+    fn close(&mut self, _: &Close, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(ProblemsViewEvent::Close);
+    }
+}
+
+impl Render for ProblemsView {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let theme = cx.theme();
+
+        let heading: SharedString = match self.problems.len() {
+            0 => "Problem Files: none".into(),
+            n => format!("Problem Files ({})", n).into(),
+        };
+
+        v_stack()
+            .key_context("ProblemsView")
+            .id("problems-view")
+            .track_focus(&self.focus_handle)
+            .on_action(cx.listener(Self::close))
+            .w_full()
+            .gap(rems(0.25))
+            .px(rems(0.75))
+            .py(rems(0.5))
+            .bg(theme.surface())
+            .border_t_1()
+            .border_color(theme.border())
+            .child(div().text_sm().text_color(theme.fg()).child(heading))
+            .child(
+                v_stack()
+                    .id("problem-files")
+                    .max_h(px(160.0))
+                    .overflow_y_scroll()
+                    .children(self.problems.iter().enumerate().map(|(ix, problem)| {
+                        let file_name = problem
+                            .path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string();
+
+                        h_stack()
+                            .id(ix)
+                            .h(px(20.0))
+                            .items_center()
+                            .gap(rems(0.5))
+                            .child(
+                                div()
+                                    .w(rems(7.0))
+                                    .text_xs()
+                                    .text_color(theme.fg_muted())
+                                    .child(problem.kind.label()),
+                            )
+                            .child(
+                                h_stack()
+                                    .flex_1()
+                                    .gap(rems(0.5))
+                                    .text_xs()
+                                    .overflow_hidden()
+                                    .whitespace_nowrap()
+                                    .child(div().text_color(theme.fg()).child(file_name))
+                                    .child(
+                                        div()
+                                            .text_color(theme.fg_disabled())
+                                            .child(problem.message.clone()),
+                                    ),
+                            )
+                            .children(
+                                ProblemAction::for_kind(problem.kind)
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(action_ix, &action)| {
+                                        div()
+                                            .id(action_ix)
+                                            .px(rems(0.25))
+                                            .text_xs()
+                                            .text_color(theme.fg_muted())
+                                            .border_1()
+                                            .border_color(theme.border())
+                                            .cursor_pointer()
+                                            .hover(|s| s.text_color(theme.fg()))
+                                            .child(action.label())
+                                            .on_click(cx.listener(
+                                                move |this, _event, _window, cx| {
+                                                    this.take_action(ix, action, cx);
+                                                },
+                                            ))
+                                    }),
+                            )
+                    })),
+            )
+            .child(
+                div()
+                    .text_xs()
+                    .text_color(theme.fg_disabled())
+                    .child("esc to close"),
+            )
+    }
+}
@@ -0,0 +1,369 @@
+use gpui::{
+    actions, div, prelude::*, px, rems, App, Context, EventEmitter, FocusHandle, Focusable,
+    IntoElement, KeyBinding, KeyDownEvent, Render, SharedString, Window,
+};
+use gpuikit::layout::{h_stack, v_stack};
+use gpuikit::theme::{ActiveTheme, Themeable};
+use player_core::{read_metadata, MetadataEdit, ProblemFile, Song, SongId};
+
+actions!(
+    tag_editor,
+    [
+        Confirm,
+        Cancel,
+        NextField,
+        PreviousField,
+        DeleteBackward,
+        InsertSpace,
+        ToggleRefile,
+    ]
+);
+
+pub fn init(cx: &mut App) {
+    cx.bind_keys([
+        KeyBinding::new("enter", Confirm, Some("TagEditor")),
+        KeyBinding::new("escape", Cancel, Some("TagEditor")),
+        KeyBinding::new("tab", NextField, Some("TagEditor")),
+        KeyBinding::new("shift-tab", PreviousField, Some("TagEditor")),
+        KeyBinding::new("backspace", DeleteBackward, Some("TagEditor")),
+        KeyBinding::new("space", InsertSpace, Some("TagEditor")),
+        KeyBinding::new("cmd-r", ToggleRefile, Some("TagEditor")),
+    ]);
+}
+
+const FIELD_LABELS: [&str; 4] = ["Title", "Artist", "Album", "Track"];
+
+const TITLE: usize = 0;
+const ARTIST: usize = 1;
+const ALBUM: usize = 2;
+const TRACK: usize = 3;
+
+/// What a tag edit applies to
+#[derive(Clone)]
+pub enum TagEditTarget {
+    Songs(Vec<SongId>),
+    /// A file that failed to import, either retried with the new tags or
+    /// force-imported with them
+    ProblemFile {
+        problem: ProblemFile,
+        force_import: bool,
+    },
+}
+
+/// Edits the tags of one or more songs, or of a file that failed to import.
+///
+/// When editing several songs, fields whose values differ between them start
+/// out empty and are only changed if something is typed into them.
+pub struct TagEditor {
+    target: TagEditTarget,
+    values: [String; 4],
+    /// The shared starting value of each field, or `None` if the songs disagree
+    original: [Option<String>; 4],
+    active_field: usize,
+    refile: bool,
+    focus_handle: FocusHandle,
+}
+
+pub enum TagEditorEvent {
+    Save {
+        target: TagEditTarget,
+        edit: MetadataEdit,
+        refile: bool,
+    },
+    Cancel,
+}
+
+impl EventEmitter<TagEditorEvent> for TagEditor {}
+
+impl Focusable for TagEditor {
+    fn focus_handle(&self, _cx: &App) -> FocusHandle {
+        self.focus_handle.clone()
+    }
+}
+
+impl TagEditor {
+    pub fn new(songs: Vec<Song>, cx: &mut Context<Self>) -> Self {
+        let field_value = |song: &Song, field: usize| match field {
+            TITLE => song.title.clone(),
+            ARTIST => song.artist.clone().unwrap_or_default(),
+            ALBUM => song.album.clone().unwrap_or_default(),
+            _ => song.track_number.map(|n| n.to_string()).unwrap_or_default(),
+        };
+
+        let original: [Option<String>; 4] = std::array::from_fn(|field| {
+            let first = songs.first().map(|song| field_value(song, field))?;
+            songs
+                .iter()
+                .all(|song| field_value(song, field) == first)
+                .then_some(first)
+        });
+
+        Self {
+            target: TagEditTarget::Songs(songs.iter().map(|song| song.id).collect()),
+            values: original.clone().map(Option::unwrap_or_default),
+            original,
+            active_field: 0,
+            refile: true,
+            focus_handle: cx.focus_handle(),
+        }
+    }
+
+    /// Edit the tags of a problem file, starting from whatever tags it has
+    pub fn for_problem(problem: ProblemFile, force_import: bool, cx: &mut Context<Self>) -> Self {
+        let metadata = read_metadata(&problem.path)
+            .map(|imported| imported.metadata)
+            .unwrap_or_default();
+        let original = [
+            metadata.title.unwrap_or_default(),
+            metadata
+                .artist
+                .or(metadata.album_artist)
+                .unwrap_or_default(),
+            metadata.album.unwrap_or_default(),
+            metadata
+                .track_number
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+        ];
+
+        Self {
+            target: TagEditTarget::ProblemFile {
+                problem,
+                force_import,
+            },
+            values: original.clone(),
+            original: original.map(Some),
+            active_field: 0,
+            refile: false,
+            focus_handle: cx.focus_handle(),
+        }
+    }
+
+    pub fn focus(&self, window: &mut Window, cx: &mut Context<Self>) {
+        self.focus_handle.focus(window);
+        cx.notify();
+    }
+
+    fn is_changed(&self, field: usize) -> bool {
+        match &self.original[field] {
+            Some(original) => self.values[field] != *original,
+            None => !self.values[field].is_empty(),
+        }
+    }
+
+    fn optional_value(&self, field: usize) -> Option<Option<String>> {
+        if !self.is_changed(field) {
+            return None;
+        }
+        let value = self.values[field].trim();
+        Some((!value.is_empty()).then(|| value.to_string()))
+    }
+
+    /// Build the edit from the fields that were changed
+    fn build_edit(&self) -> MetadataEdit {
+        let title = self
+            .optional_value(TITLE)
+            .flatten()
+            .filter(|title| !title.is_empty());
+
+        let track_number = self.optional_value(TRACK).and_then(|value| match value {
+            Some(value) => value.parse::<u32>().ok().map(Some),
+            None => Some(None),
+        });
+
+        MetadataEdit {
+            title,
+            artist: self.optional_value(ARTIST),
+            album: self.optional_value(ALBUM),
+            track_number,
+        }
+    }
+
+    fn confirm(&mut self, _: &Confirm, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(TagEditorEvent::Save {
+            target: self.target.clone(),
+            edit: self.build_edit(),
+            refile: self.refile,
+        });
+    }
+
+    fn cancel(&mut self, _: &Cancel, _window: &mut Window, cx: &mut Context<Self>) {
+        cx.emit(TagEditorEvent::Cancel);
+    }
+
+    fn next_field(&mut self, _: &NextField, _window: &mut Window, cx: &mut Context<Self>) {
+        self.active_field = (self.active_field + 1) % FIELD_LABELS.len();
+        cx.notify();
+    }
+
+    fn previous_field(&mut self, _: &PreviousField, _window: &mut Window, cx: &mut Context<Self>) {
+        self.active_field = (self.active_field + FIELD_LABELS.len() - 1) % FIELD_LABELS.len();
+        cx.notify();
+    }
+
+    fn delete_backward(
+        &mut self,
+        _: &DeleteBackward,
+        _window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        self.values[self.active_field].pop();
+        cx.notify();
+    }
+
+    fn insert_space(&mut self, _: &InsertSpace, _window: &mut Window, cx: &mut Context<Self>) {
+        self.values[self.active_field].push(' ');
+        cx.notify();
+    }
+
+    fn toggle_refile(&mut self, _: &ToggleRefile, _window: &mut Window, cx: &mut Context<Self>) {
+        self.refile = !self.refile;
+        cx.notify();
+    }
+
+    fn handle_key_down(
+        &mut self,
+        event: &KeyDownEvent,
+        _window: &mut Window,
+        cx: &mut Context<Self>,
+    ) {
+        let keystroke = &event.keystroke;
+        if keystroke.modifiers.platform || keystroke.modifiers.control {
+            return;
+        }
+
+        if let Some(key_char) = &keystroke.key_char {
+            if self.active_field == TRACK && !key_char.chars().all(|c| c.is_ascii_digit()) {
+                return;
+            }
+            self.values[self.active_field].push_str(key_char);
+            cx.stop_propagation();
+            cx.notify();
+        }
+    }
+}
+
+impl Render for TagEditor {
+    fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let theme = cx.theme();
+        let heading: SharedString = match &self.target {
+            TagEditTarget::Songs(song_ids) if song_ids.len() == 1 => "Edit Tags".into(),
+            TagEditTarget::Songs(song_ids) => {
+                format!("Edit Tags ({} songs)", song_ids.len()).into()
+            }
+            TagEditTarget::ProblemFile {
+                problem,
+                force_import,
+            } => {
+                let file_name = problem
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                if *force_import {
+                    format!("Force Import: {}", file_name).into()
+                } else {
+                    format!("Edit Tags and Retry: {}", file_name).into()
+                }
+            }
+        };
+        let is_songs = matches!(self.target, TagEditTarget::Songs(_));
+
+        v_stack()
+            .key_context("TagEditor")
+            .id("tag-editor")
+            .track_focus(&self.focus_handle)
+            .on_action(cx.listener(Self::confirm))
+            .on_action(cx.listener(Self::cancel))
+            .on_action(cx.listener(Self::next_field))
+            .on_action(cx.listener(Self::previous_field))
+            .on_action(cx.listener(Self::delete_backward))
+            .on_action(cx.listener(Self::insert_space))
+            .on_action(cx.listener(Self::toggle_refile))
+            .on_key_down(cx.listener(Self::handle_key_down))
+            .w_full()
+            .gap(rems(0.25))
+            .px(rems(0.75))
+            .py(rems(0.5))
+            .bg(theme.surface())
+            .border_t_1()
+            .border_color(theme.border())
+            .child(div().text_sm().text_color(theme.fg()).child(heading))
+            .children(FIELD_LABELS.iter().enumerate().map(|(ix, label)| {
+                let is_active = ix == self.active_field;
+                let value = &self.values[ix];
+                let placeholder = self.original[ix].is_none() && value.is_empty();
+
+                h_stack()
+                    .id(ix)
+                    .h(px(20.0))
+                    .items_center()
+                    .gap(rems(0.5))
+                    .child(
+                        div()
+                            .w(rems(4.0))
+                            .text_xs()
+                            .text_color(theme.fg_muted())
+                            .child(*label),
+                    )
+                    .child(
+                        div()
+                            .flex_1()
+                            .px(rems(0.25))
+                            .text_xs()
+                            .overflow_hidden()
+                            .whitespace_nowrap()
+                            .border_1()
+                            .border_color(if is_active {
+                                theme.accent()
+                            } else {
+                                theme.border()
+                            })
+                            .bg(theme.bg())
+                            .map(|el| {
+                                if placeholder {
+                                    el.text_color(theme.fg_disabled()).child("Mixed")
+                                } else if is_active {
+                                    el.text_color(theme.fg()).child(format!("{}▏", value))
+                                } else {
+                                    el.text_color(theme.fg()).child(value.clone())
+                                }
+                            }),
+                    )
+                    .on_click(cx.listener(move |this, _event, window, cx| {
+                        this.active_field = ix;
+                        this.focus(window, cx);
+                    }))
+            }))
+            .child(
+                h_stack()
+                    .items_center()
+                    .justify_between()
+                    .child(div().when(is_songs, |el| {
+                        el.child(
+                            div()
+                                .id("refile-toggle")
+                                .text_xs()
+                                .text_color(theme.fg_muted())
+                                .cursor_pointer()
+                                .hover(|s| s.text_color(theme.fg()))
+                                .on_click(cx.listener(|this, _event, _window, cx| {
+                                    this.refile = !this.refile;
+                                    cx.notify();
+                                }))
+                                .child(if self.refile {
+                                    "[x] Move files to match new tags"
+                                } else {
+                                    "[ ] Move files to match new tags"
+                                }),
+                        )
+                    }))
+                    .child(
+                        div()
+                            .text_xs()
+                            .text_color(theme.fg_disabled())
+                            .child("enter to save, esc to cancel"),
+                    ),
+            )
+    }
+}